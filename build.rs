@@ -0,0 +1,12 @@
+fn main() {
+    // The ABI self-check compiles a C description of the types to compare against
+    #[cfg(feature = "abi-check")]
+    {
+        println!("cargo:rerun-if-changed=src/abi_check.c");
+        cc::Build::new().file("src/abi_check.c").std("c11").compile("luaffi_abi_check");
+    }
+
+    // Header scanning outside build scripts preprocesses for the target luaffi was built for
+    #[cfg(feature = "cdefgen")]
+    println!("cargo:rustc-env=LUAFFI_TARGET={}", std::env::var("TARGET").unwrap());
+}
@@ -0,0 +1,79 @@
+/* Sizes, alignments and member offsets as the C compiler lays them out, for comparison with
+ * luaffi's own answers. The sample declarations are also handed to ffi.cdef as text */
+#ifndef _WIN32
+/* The POSIX typedefs are hidden in strict C11 mode */
+#define _DEFAULT_SOURCE
+#define _XOPEN_SOURCE 700
+#endif
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <wchar.h>
+#ifndef _WIN32
+#include <sys/types.h>
+#endif
+
+#define SAMPLES                                                                                  \
+    struct luaffi_abi_mixed { char c; double d; long l; short s; long long ll; float f; };        \
+    struct luaffi_abi_nested { char tag; struct luaffi_abi_mixed inner; int values[3]; void *p; }; \
+    struct luaffi_abi_bits { unsigned a : 3; unsigned b : 7; char c; unsigned d : 20; };          \
+    union luaffi_abi_union { char c; double d; int i[3]; };                                       \
+    enum luaffi_abi_enum { LUAFFI_ABI_A, LUAFFI_ABI_B = 1000 };                                   \
+    struct luaffi_abi_tail { double d; char c; };                                                 \
+    struct luaffi_abi_enums { char c; enum luaffi_abi_enum e; size_t n; };
+
+SAMPLES
+
+#define STRING(...) #__VA_ARGS__
+#define EXPAND(...) STRING(__VA_ARGS__)
+
+struct luaffi_abi_entry {
+    const char *type;
+    const char *member;
+    size_t size;
+    size_t align;
+    size_t offset;
+};
+
+#define TYPE(t) {#t, NULL, sizeof(t), _Alignof(t), 0}
+#define MEMBER(t, m) {#t, #m, sizeof(((t *)0)->m), 0, offsetof(t, m)}
+
+static const struct luaffi_abi_entry entries[] = {
+    TYPE(bool), TYPE(char), TYPE(unsigned char), TYPE(short), TYPE(unsigned short),
+    TYPE(int), TYPE(unsigned int), TYPE(long), TYPE(unsigned long), TYPE(long long),
+    TYPE(unsigned long long), TYPE(float), TYPE(double), TYPE(void *), TYPE(size_t),
+    TYPE(int8_t), TYPE(int16_t), TYPE(int32_t), TYPE(int64_t),
+    TYPE(uint8_t), TYPE(uint16_t), TYPE(uint32_t), TYPE(uint64_t),
+    TYPE(wchar_t),
+#ifndef _WIN32
+    TYPE(ssize_t), TYPE(ino_t), TYPE(dev_t), TYPE(gid_t), TYPE(mode_t), TYPE(nlink_t),
+    TYPE(uid_t), TYPE(off_t), TYPE(pid_t), TYPE(useconds_t), TYPE(suseconds_t),
+    TYPE(blksize_t), TYPE(blkcnt_t), TYPE(time_t),
+#endif
+    TYPE(struct luaffi_abi_mixed),
+    MEMBER(struct luaffi_abi_mixed, d), MEMBER(struct luaffi_abi_mixed, l),
+    MEMBER(struct luaffi_abi_mixed, s), MEMBER(struct luaffi_abi_mixed, ll),
+    MEMBER(struct luaffi_abi_mixed, f),
+    TYPE(struct luaffi_abi_nested),
+    MEMBER(struct luaffi_abi_nested, inner), MEMBER(struct luaffi_abi_nested, values),
+    MEMBER(struct luaffi_abi_nested, p),
+    TYPE(struct luaffi_abi_bits), MEMBER(struct luaffi_abi_bits, c),
+    TYPE(union luaffi_abi_union),
+    TYPE(enum luaffi_abi_enum),
+    TYPE(struct luaffi_abi_tail),
+    TYPE(struct luaffi_abi_enums),
+    MEMBER(struct luaffi_abi_enums, e), MEMBER(struct luaffi_abi_enums, n),
+};
+
+size_t luaffi_abi_count(void) {
+    return sizeof(entries) / sizeof(entries[0]);
+}
+
+const struct luaffi_abi_entry *luaffi_abi_entry(size_t i) {
+    return &entries[i];
+}
+
+const char *luaffi_abi_samples(void) {
+    return EXPAND(SAMPLES);
+}
@@ -0,0 +1,69 @@
+// Compare luaffi's type layouts with those of the platform's C compiler
+
+use std::ffi::{CStr, c_char};
+
+use crate::ctype::CType;
+use crate::ffi_ops;
+use crate::parser;
+
+#[repr(C)]
+struct Entry {
+    type_name: *const c_char,
+    member: *const c_char,
+    size: usize,
+    align: usize,
+    offset: usize,
+}
+
+unsafe extern "C" {
+    fn luaffi_abi_count() -> usize;
+    fn luaffi_abi_entry(i: usize) -> *const Entry;
+    fn luaffi_abi_samples() -> *const c_char;
+}
+
+/// Check the size and alignment of the builtin types and the layout of a set of sample
+/// declarations against the C compiler the crate was built with. Each difference is reported
+/// as one line, e.g. `sizeof(long): C says 4, luaffi says 8`
+pub fn verify() -> Result<(), Vec<String>> {
+    let samples = unsafe { CStr::from_ptr(luaffi_abi_samples()) }.to_string_lossy();
+    parser::parse_cdef(&samples, Default::default())
+        .map_err(|e| vec![format!("Failed to declare the sample types: {}", e)])?;
+
+    let mut differences = Vec::new();
+    let mut compare = |what: String, c: usize, ours: usize| {
+        if c != ours {
+            differences.push(format!("{}: C says {}, luaffi says {}", what, c, ours));
+        }
+    };
+    for i in 0..unsafe { luaffi_abi_count() } {
+        let entry = unsafe { &*luaffi_abi_entry(i) };
+        let type_name = unsafe { CStr::from_ptr(entry.type_name) }.to_string_lossy();
+        let ctype = match ffi_ops::lookup_type(&type_name) {
+            Ok(ctype) => ctype,
+            Err(e) => {
+                compare(format!("{} ({})", type_name, e), 1, 0);
+                continue;
+            }
+        };
+
+        if entry.member.is_null() {
+            compare(format!("sizeof({})", type_name), entry.size, ctype.size());
+            compare(format!("alignof({})", type_name), entry.align, ctype.alignment());
+            continue;
+        }
+        let member = unsafe { CStr::from_ptr(entry.member) }.to_string_lossy();
+        let field = match ctype.resolved() {
+            CType::Struct(_, fields) | CType::Union(_, fields) => fields.iter().find(|f| f.name == member),
+            _ => None,
+        };
+        match field {
+            Some(field) => {
+                compare(format!("offsetof({}, {})", type_name, member), entry.offset, field.offset);
+                compare(format!("sizeof(({}){{}}.{})", type_name, member), entry.size, field.ctype.size());
+            }
+            None => compare(format!("{}.{} (no such member)", type_name, member), 1, 0),
+        }
+    }
+
+    if differences.is_empty() { Ok(()) } else { Err(differences) }
+}
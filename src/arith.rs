@@ -0,0 +1,302 @@
+// Arithmetic and comparisons on scalar and pointer cdata, mixed freely with Lua numbers
+
+use std::cmp::Ordering;
+
+use mlua::prelude::*;
+
+use crate::cdata::{integer_value, CData, Ownership};
+use crate::ctype::CType;
+use crate::ffi_ops;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Arith {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    #[cfg(any(feature = "lua53", feature = "lua54", feature = "luau"))]
+    IDiv,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Compare {
+    Eq,
+    Lt,
+    Le,
+}
+
+// One side of an operator
+enum Operand {
+    // Integer value, whether C promotes the operation to `uint64_t`, and whether it is a 64-bit
+    // integer cdata, which makes the operation follow C rather than Lua
+    Int(i128, bool, bool),
+    Float(f64),
+    // Address, pointed-to type and the bounds of the buffer it points into, if known
+    Ptr(usize, CType, Option<(usize, usize)>),
+}
+
+fn operand(value: &LuaValue) -> LuaResult<Operand> {
+    // Without an integer subtype, integral numbers stand in for integers
+    let value = match value {
+        LuaValue::Number(_) if cfg!(any(feature = "lua53", feature = "lua54")) => value.clone(),
+        value => ffi_ops::integral(value.clone()),
+    };
+    match &value {
+        LuaValue::Integer(i) => Ok(Operand::Int(*i as i128, false, false)),
+        LuaValue::Number(n) => Ok(Operand::Float(*n)),
+        LuaValue::UserData(ud) => {
+            let cdata = ud.borrow::<CData>()?;
+            match cdata.ctype.resolved() {
+                CType::Ptr(target) | CType::Array(target, _) | CType::VLA(target) => {
+                    Ok(Operand::Ptr(cdata.as_ptr() as usize, (**target).clone(), cdata.bounds()))
+                }
+                // Boxed floating point values compute as Lua numbers
+                CType::Float | CType::Double if cdata.is_null() => Err(unsupported(&value)),
+                CType::Float => Ok(Operand::Float(unsafe { (cdata.ptr as *const f32).read_unaligned() } as f64)),
+                CType::Double => Ok(Operand::Float(unsafe { (cdata.ptr as *const f64).read_unaligned() })),
+                // Narrower integers, enums and bools compute as the Lua numbers they read as
+                CType::Bool if !cdata.is_null() => Ok(Operand::Int(unsafe { *cdata.ptr } as i128, false, false)),
+                _ => match ffi_ops::cdata_integer(&cdata) {
+                    Some(i) => {
+                        let wide = cdata.ctype.size() == 8;
+                        Ok(Operand::Int(i, wide && !ffi_ops::is_signed(&cdata.ctype), wide))
+                    }
+                    None => Err(unsupported(&value)),
+                },
+            }
+        }
+        _ => Err(unsupported(&value)),
+    }
+}
+
+/// The metatype handler for `event` of the first operand without built-in arithmetic that has
+/// one; built-in operations take precedence, as in LuaJIT
+pub(crate) fn overload(lua: &Lua, event: LuaMetaMethod, operands: &[&LuaValue]) -> LuaResult<Option<LuaFunction>> {
+    for value in operands {
+        let LuaValue::UserData(ud) = value else {
+            continue;
+        };
+        if operand(value).is_ok() {
+            continue;
+        }
+        let Ok(cdata) = ud.borrow::<CData>() else {
+            continue;
+        };
+        match ffi_ops::metamethod(lua, &cdata.ctype, event.name())? {
+            Some(LuaValue::Function(handler)) => return Ok(Some(handler)),
+            Some(_) => {
+                return Err(LuaError::RuntimeError(format!(
+                    "Metamethod {} of '{}' is not a function",
+                    event.name(),
+                    cdata.ctype
+                )));
+            }
+            None => {}
+        }
+    }
+    Ok(None)
+}
+
+fn unsupported(value: &LuaValue) -> LuaError {
+    LuaError::RuntimeError(format!("attempt to perform arithmetic on a {} value", ffi_ops::describe(value)))
+}
+
+fn number(operand: &Operand) -> LuaResult<f64> {
+    match operand {
+        Operand::Int(i, _, _) => Ok(*i as f64),
+        Operand::Float(n) => Ok(*n),
+        Operand::Ptr(_, _, _) => Err(LuaError::RuntimeError(
+            "Cannot mix pointers and floating point numbers".to_string(),
+        )),
+    }
+}
+
+/// Apply a binary operator. With a 64-bit integer cdata on either side, integers follow C:
+/// the operation is done in `uint64_t` if either side is one and in `int64_t` otherwise,
+/// wrapping on overflow, with `/` and `%` truncating and `//` flooring. Narrower integers
+/// compute like Lua integers, so `/` and `^` give floating point results. Non-integral numbers
+/// switch to floating point; pointers move by whole elements
+pub(crate) fn arith(lua: &Lua, op: Arith, a: LuaValue, b: LuaValue) -> LuaResult<LuaValue> {
+    match (operand(&a)?, operand(&b)?) {
+        (Operand::Ptr(address, target, bounds), Operand::Int(i, _, _)) if matches!(op, Arith::Add | Arith::Sub) => {
+            let step = if op == Arith::Sub { -i } else { i };
+            offset_pointer(lua, address, target, bounds, step)
+        }
+        (Operand::Int(i, _, _), Operand::Ptr(address, target, bounds)) if op == Arith::Add => {
+            offset_pointer(lua, address, target, bounds, i)
+        }
+        (Operand::Ptr(x, target, _), Operand::Ptr(y, other, _)) if op == Arith::Sub => {
+            if target.unqualified() != other.unqualified() {
+                return Err(LuaError::RuntimeError(format!(
+                    "Cannot subtract pointers to different types: {} and {}",
+                    target, other
+                )));
+            }
+            let size = element_size(&target)?;
+            Ok(LuaValue::Integer((x as isize).wrapping_sub(y as isize) as i64 / size as i64))
+        }
+        (Operand::Ptr(_, _, _), _) | (_, Operand::Ptr(_, _, _)) => Err(LuaError::RuntimeError(
+            "Invalid pointer arithmetic: only pointer + integer, pointer - integer and pointer - pointer are allowed"
+                .to_string(),
+        )),
+        (Operand::Int(x, ux, cx), Operand::Int(y, uy, cy)) if cx || cy => integer_arith(lua, op, x, y, ux || uy),
+        (Operand::Int(x, _, _), Operand::Int(y, _, _)) => lua_integer_arith(lua, op, x as i64, y as i64),
+        (x, y) => Ok(LuaValue::Number(float_arith(op, number(&x)?, number(&y)?))),
+    }
+}
+
+/// Negate a 64-bit integer cdata, keeping its signedness
+pub(crate) fn negate(lua: &Lua, value: LuaValue) -> LuaResult<LuaValue> {
+    match operand(&value)? {
+        Operand::Int(i, unsigned, _) => integer_arith(lua, Arith::Sub, 0, i, unsigned),
+        Operand::Float(n) => Ok(LuaValue::Number(-n)),
+        Operand::Ptr(_, _, _) => Err(unsupported(&value)),
+    }
+}
+
+/// Compare two operands with the same promotions as arithmetic; pointers compare by address
+pub(crate) fn compare(op: Compare, a: &LuaValue, b: &LuaValue) -> LuaResult<bool> {
+    let ordering = match (operand(a)?, operand(b)?) {
+        (Operand::Int(x, ux, _), Operand::Int(y, uy, _)) if ux || uy => (x as u64).cmp(&(y as u64)),
+        (Operand::Int(x, _, _), Operand::Int(y, _, _)) => x.cmp(&y),
+        (Operand::Ptr(x, _, _), Operand::Ptr(y, _, _)) => x.cmp(&y),
+        (Operand::Ptr(x, _, _), Operand::Int(y, _, _)) => (x as u64).cmp(&(y as u64)),
+        (Operand::Int(x, _, _), Operand::Ptr(y, _, _)) => (x as u64).cmp(&(y as u64)),
+        (x, y) => match number(&x)?.partial_cmp(&number(&y)?) {
+            Some(ordering) => ordering,
+            // NaN is unordered
+            None => return Ok(false),
+        },
+    };
+    Ok(match op {
+        Compare::Eq => ordering == Ordering::Equal,
+        Compare::Lt => ordering == Ordering::Less,
+        Compare::Le => ordering != Ordering::Greater,
+    })
+}
+
+fn element_size(target: &CType) -> LuaResult<usize> {
+    match target.size() {
+        0 => Err(LuaError::RuntimeError(format!(
+            "Cannot perform arithmetic on a pointer to {}",
+            target
+        ))),
+        size => Ok(size),
+    }
+}
+
+// The pointer `count` elements on; one into a buffer of known size stays checked against it
+fn offset_pointer(
+    lua: &Lua,
+    address: usize,
+    target: CType,
+    bounds: Option<(usize, usize)>,
+    count: i128,
+) -> LuaResult<LuaValue> {
+    let step = element_size(&target)? as isize;
+    let address = (address as isize).wrapping_add((count as isize).wrapping_mul(step));
+    let cdata = CData::from_ptr(CType::Ptr(Box::new(target)), address as *mut u8, Ownership::Borrowed);
+    let cdata = match bounds {
+        Some(bounds) => cdata.with_bounds(bounds),
+        None => cdata,
+    };
+    lua.create_userdata(cdata).map(LuaValue::UserData)
+}
+
+fn integer_arith(lua: &Lua, op: Arith, x: i128, y: i128, unsigned: bool) -> LuaResult<LuaValue> {
+    let division_by_zero = || LuaError::RuntimeError("Integer division by zero".to_string());
+    let value = if unsigned {
+        let (x, y) = (x as u64, y as u64);
+        (match op {
+            Arith::Add => x.wrapping_add(y),
+            Arith::Sub => x.wrapping_sub(y),
+            Arith::Mul => x.wrapping_mul(y),
+            Arith::Div => x.checked_div(y).ok_or_else(division_by_zero)?,
+            #[cfg(any(feature = "lua53", feature = "lua54", feature = "luau"))]
+            Arith::IDiv => x.checked_div(y).ok_or_else(division_by_zero)?,
+            Arith::Mod => x.checked_rem(y).ok_or_else(division_by_zero)?,
+            Arith::Pow => wrapping_pow(x, y),
+        }) as i128
+    } else {
+        let (x, y) = (x as i64, y as i64);
+        (match op {
+            Arith::Add => x.wrapping_add(y),
+            Arith::Sub => x.wrapping_sub(y),
+            Arith::Mul => x.wrapping_mul(y),
+            Arith::Div if y == 0 => return Err(division_by_zero()),
+            Arith::Div => x.wrapping_div(y),
+            #[cfg(any(feature = "lua53", feature = "lua54", feature = "luau"))]
+            Arith::IDiv if y == 0 => return Err(division_by_zero()),
+            #[cfg(any(feature = "lua53", feature = "lua54", feature = "luau"))]
+            Arith::IDiv => floor_div(x, y),
+            Arith::Mod if y == 0 => return Err(division_by_zero()),
+            Arith::Mod => x.wrapping_rem(y),
+            Arith::Pow if y >= 0 => wrapping_pow(x as u64, y as u64) as i64,
+            // Negative powers truncate toward zero like integer division
+            Arith::Pow => match x {
+                0 => return Err(division_by_zero()),
+                1 => 1,
+                -1 if y % 2 == 0 => 1,
+                -1 => -1,
+                _ => 0,
+            },
+        }) as i128
+    };
+    let ctype = if unsigned { CType::UInt64 } else { CType::Int64 };
+    integer_value(lua, &ctype, value)
+}
+
+// Operators on integers as Lua applies them: `/` and `^` in floating point, `//` and `%`
+// rounding toward negative infinity, the rest wrapping
+fn lua_integer_arith(lua: &Lua, op: Arith, x: i64, y: i64) -> LuaResult<LuaValue> {
+    let value = match op {
+        Arith::Add => x.wrapping_add(y),
+        Arith::Sub => x.wrapping_sub(y),
+        Arith::Mul => x.wrapping_mul(y),
+        Arith::Div | Arith::Pow => return Ok(LuaValue::Number(float_arith(op, x as f64, y as f64))),
+        #[cfg(any(feature = "lua53", feature = "lua54", feature = "luau"))]
+        Arith::IDiv if y == 0 => {
+            return Err(LuaError::RuntimeError("Integer division by zero".to_string()));
+        }
+        #[cfg(any(feature = "lua53", feature = "lua54", feature = "luau"))]
+        Arith::IDiv => floor_div(x, y),
+        Arith::Mod if y == 0 => {
+            return Err(LuaError::RuntimeError("Integer division by zero".to_string()));
+        }
+        Arith::Mod => x.wrapping_sub(floor_div(x, y).wrapping_mul(y)),
+    };
+    integer_value(lua, &CType::Int64, value as i128)
+}
+
+fn floor_div(x: i64, y: i64) -> i64 {
+    let quotient = x.wrapping_div(y);
+    if x.wrapping_rem(y) != 0 && (x < 0) != (y < 0) { quotient - 1 } else { quotient }
+}
+
+fn wrapping_pow(mut base: u64, mut exponent: u64) -> u64 {
+    let mut result = 1u64;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+    result
+}
+
+// Floating point operators follow Lua, e.g. `%` takes the sign of the divisor
+fn float_arith(op: Arith, x: f64, y: f64) -> f64 {
+    match op {
+        Arith::Add => x + y,
+        Arith::Sub => x - y,
+        Arith::Mul => x * y,
+        Arith::Div => x / y,
+        Arith::Mod => x - (x / y).floor() * y,
+        Arith::Pow => x.powf(y),
+        #[cfg(any(feature = "lua53", feature = "lua54", feature = "luau"))]
+        Arith::IDiv => (x / y).floor(),
+    }
+}
@@ -0,0 +1,581 @@
+use std::ffi::c_void;
+use std::mem::size_of;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libffi::low::{self, CodePtr, ffi_abi, ffi_cif, ffi_type};
+use libffi::middle::Type;
+use mlua::prelude::*;
+
+use crate::callback;
+use crate::cdata::{create_owned, CData, CFunction, read_ctype_value};
+use crate::ctype::{CType, CallConv};
+use crate::error::{ErrorKind, FfiError};
+use crate::ffi_ops::{self, write_value_to_ptr};
+use crate::parser::SignalHandling;
+use crate::syserr;
+
+/// Call the C function at `fn_ptr` with the prototype `ctype` (a `CType::Function`),
+/// converting the Lua arguments and the return value
+pub fn call_function(
+    lua: &Lua,
+    fn_ptr: *mut c_void,
+    ctype: &CType,
+    convention: CallConv,
+    args: LuaMultiValue,
+) -> LuaResult<LuaValue> {
+    let mut call = PreparedCall::new(lua, fn_ptr, ctype, convention, args)?;
+    unsafe { call.invoke() };
+    callback::take_error()?;
+    call.result(lua)
+}
+
+// A call with its arguments converted and its CIF built, ready to run on any thread
+struct PreparedCall {
+    fn_ptr: *mut c_void,
+    cif: ffi_cif,
+    ret: CType,
+    // The libffi types must outlive the CIF built from them
+    _ret_ffi_type: Type,
+    _arg_ffi_types: Vec<Type>,
+    _arg_ffi_ptrs: Vec<*mut ffi_type>,
+    _storage: Vec<Vec<u64>>,
+    arg_values: Vec<*mut c_void>,
+    // Temporaries such as wide copies of strings, kept alive until the call returns
+    _temporaries: Vec<LuaAnyUserData>,
+    result: Vec<u64>,
+    // errno as the call left it on the thread that ran it
+    errno: Option<i32>,
+}
+
+impl PreparedCall {
+    fn new(lua: &Lua, fn_ptr: *mut c_void, ctype: &CType, convention: CallConv, args: LuaMultiValue) -> LuaResult<Self> {
+        let CType::Function(ret, params) = ctype.resolved() else {
+            return Err(LuaError::RuntimeError("Not a function type".to_string()));
+        };
+        if fn_ptr.is_null() {
+            return Err(LuaError::RuntimeError("Attempt to call a NULL function pointer".to_string()));
+        }
+        // Deliver callbacks queued by foreign threads while the C side waits for them
+        callback::pump(lua)?;
+
+        let variadic = params.last() == Some(&CType::VarArgs);
+        let fixed = if variadic { &params[..params.len() - 1] } else { &params[..] };
+        if args.len() < fixed.len() || (!variadic && args.len() > fixed.len()) {
+            return Err(LuaError::RuntimeError(format!(
+                "Wrong number of arguments for function call: expected {}, got {}",
+                fixed.len(),
+                args.len()
+            )));
+        }
+
+        // Extra arguments of a variadic call take their type from the Lua value
+        let mut arg_types = fixed.to_vec();
+        for arg in args.iter().skip(fixed.len()) {
+            arg_types.push(vararg_type(arg)?);
+        }
+
+        // Each argument gets its own 8-byte aligned slot, at least as large as a register
+        let mut storage = Vec::with_capacity(args.len());
+        let mut temporaries = Vec::new();
+        for (arg, arg_type) in args.into_iter().zip(&arg_types) {
+            let mut slot = vec![0u64; arg_type.size().max(size_of::<u64>()).div_ceil(size_of::<u64>())];
+            write_argument(lua, slot.as_mut_ptr() as *mut u8, arg_type, arg, &mut temporaries)?;
+            storage.push(slot);
+        }
+        let arg_values: Vec<*mut c_void> = storage.iter_mut().map(|slot| slot.as_mut_ptr() as *mut c_void).collect();
+
+        let ret_ffi_type = ffi_type_of(ret)?;
+        let arg_ffi_types = arg_types.iter().map(ffi_type_of).collect::<LuaResult<Vec<_>>>()?;
+        let mut arg_ffi_ptrs: Vec<*mut ffi_type> = arg_ffi_types.iter().map(Type::as_raw_ptr).collect();
+
+        let mut cif: ffi_cif = Default::default();
+        let prepared = unsafe {
+            if variadic {
+                low::prep_cif_var(
+                    &mut cif,
+                    abi_of(convention),
+                    fixed.len(),
+                    arg_ffi_ptrs.len(),
+                    ret_ffi_type.as_raw_ptr(),
+                    arg_ffi_ptrs.as_mut_ptr(),
+                )
+            } else {
+                low::prep_cif(
+                    &mut cif,
+                    abi_of(convention),
+                    arg_ffi_ptrs.len(),
+                    ret_ffi_type.as_raw_ptr(),
+                    arg_ffi_ptrs.as_mut_ptr(),
+                )
+            }
+        };
+        prepared.map_err(|e| LuaError::RuntimeError(format!("Failed to prepare function call: {:?}", e)))?;
+
+        // libffi widens small integer results to a full register
+        let result = vec![0u64; ret.size().max(size_of::<u64>()).div_ceil(size_of::<u64>())];
+        Ok(PreparedCall {
+            fn_ptr,
+            cif,
+            ret: (**ret).clone(),
+            _ret_ffi_type: ret_ffi_type,
+            _arg_ffi_types: arg_ffi_types,
+            _arg_ffi_ptrs: arg_ffi_ptrs,
+            _storage: storage,
+            arg_values,
+            _temporaries: temporaries,
+            result,
+            errno: None,
+        })
+    }
+
+    // Run the C function. Touches no Lua value, so any thread may do it while the owner waits
+    unsafe fn invoke(&mut self) {
+        unsafe {
+            libffi::raw::ffi_call(
+                &mut self.cif,
+                Some(*CodePtr::from_ptr(self.fn_ptr).as_fun()),
+                self.result.as_mut_ptr() as *mut c_void,
+                self.arg_values.as_mut_ptr(),
+            );
+        }
+    }
+
+    // Run the C function, guarded against signals as `signals` asks, and note the errno it
+    // leaves. Touches no Lua value either
+    unsafe fn invoke_with(&mut self, signals: Option<SignalHandling>) -> std::io::Result<()> {
+        match signals {
+            None => unsafe { self.invoke() },
+            #[cfg(unix)]
+            Some(SignalHandling::Retry) => loop {
+                let _ = syserr::errno(Some(0));
+                unsafe { self.invoke() };
+                if !self.failed() || syserr::errno(None).ok() != Some(libc::EINTR) {
+                    break;
+                }
+            },
+            #[cfg(unix)]
+            Some(SignalHandling::Mask) => {
+                let previous = block_signals()?;
+                unsafe { self.invoke() };
+                unsafe { libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut()) };
+            }
+            #[cfg(not(unix))]
+            Some(_) => unsafe { self.invoke() },
+        }
+        self.errno = syserr::errno(None).ok();
+        Ok(())
+    }
+
+    // Whether the result reports a failure that errno may explain: -1 from a signed integer
+    // or NULL. A function returning nothing cannot report one
+    #[cfg(unix)]
+    fn failed(&self) -> bool {
+        let value = unsafe { (self.result.as_ptr() as *const u8).add(return_offset(&self.ret)) };
+        unsafe {
+            match self.ret.resolved() {
+                CType::Ptr(_) => (value as *const *const u8).read_unaligned().is_null(),
+                ret => match ret.integer_kind() {
+                    Some((1, true)) => (value as *const i8).read_unaligned() == -1,
+                    Some((2, true)) => (value as *const i16).read_unaligned() == -1,
+                    Some((4, true)) => (value as *const i32).read_unaligned() == -1,
+                    Some((8, true)) => (value as *const i64).read_unaligned() == -1,
+                    _ => false,
+                },
+            }
+        }
+    }
+
+    fn result(&mut self, lua: &Lua) -> LuaResult<LuaValue> {
+        read_return_value(lua, self.result.as_mut_ptr() as *mut u8, &self.ret)
+    }
+}
+
+/// A C function to call: a declared library function or a function pointer cdata
+pub(crate) struct Callee {
+    pub ptr: *mut c_void,
+    /// The prototype, a `CType::Function`
+    pub ctype: CType,
+    pub convention: CallConv,
+    /// How a declared function's calls deal with signals
+    pub signals: Option<SignalHandling>,
+    /// The symbol, or the type of a function pointer, for messages
+    pub name: String,
+}
+
+impl Callee {
+    pub(crate) fn of(func: &LuaAnyUserData) -> LuaResult<Self> {
+        if let Ok(func) = func.borrow::<CFunction>() {
+            let Some(ctype) = &func.ctype else {
+                return Err(LuaError::RuntimeError(format!("Missing declaration for symbol '{}'", func.name)));
+            };
+            return Ok(Callee {
+                ptr: func.ptr,
+                ctype: ctype.clone(),
+                convention: func.convention,
+                signals: func.signals,
+                name: func.name.clone(),
+            });
+        }
+        let cdata = func.borrow::<CData>()?;
+        let ctype = match cdata.ctype.resolved() {
+            CType::Ptr(inner) if matches!(inner.resolved(), CType::Function(_, _)) => (**inner).clone(),
+            function @ CType::Function(_, _) => function.clone(),
+            _ => return Err(LuaError::RuntimeError(format!("Not a function pointer: {}", cdata.ctype))),
+        };
+        Ok(Callee {
+            ptr: cdata.as_ptr() as *mut c_void,
+            ctype,
+            convention: CallConv::C,
+            signals: None,
+            name: cdata.ctype.to_string(),
+        })
+    }
+}
+
+// Raw pointer to a prepared call, handed to the worker thread that runs it
+struct SendCall(*mut PreparedCall);
+
+unsafe impl Send for SendCall {}
+
+// Calls that timed out, with their arguments, until their worker threads finish with them
+#[derive(Default)]
+struct Abandoned(Vec<(mpsc::Receiver<std::io::Result<()>>, SendCall, LuaMultiValue)>);
+
+// Release the calls whose workers have returned since they were abandoned
+fn reap_abandoned(lua: &Lua) {
+    let finished = match lua.app_data_mut::<Abandoned>() {
+        Some(mut abandoned) => {
+            let (finished, running) = std::mem::take(&mut abandoned.0)
+                .into_iter()
+                .partition(|(done, _, _)| !matches!(done.try_recv(), Err(mpsc::TryRecvError::Empty)));
+            abandoned.0 = running;
+            finished
+        }
+        None => Vec::new(),
+    };
+    for (_, call, _) in finished {
+        drop(unsafe { Box::from_raw(call.0) });
+    }
+}
+
+/// Call a C function on a worker thread, raising an error if it has not returned after
+/// `timeout`. The owning thread keeps running callbacks the function makes meanwhile, and
+/// gets the errno the function left. A call that times out is abandoned with its thread; its
+/// arguments, including the cdata and strings it points into, stay alive until it returns or
+/// the Lua state is closed, which must not happen while it may still touch them
+pub fn call_with_timeout(lua: &Lua, func: LuaAnyUserData, timeout: Duration, args: LuaMultiValue) -> LuaResult<LuaValue> {
+    reap_abandoned(lua);
+    let Callee { ptr: fn_ptr, ctype, convention, signals, name } = Callee::of(&func)?;
+
+    let kept = args.clone();
+    let call = Box::into_raw(Box::new(PreparedCall::new(lua, fn_ptr, &ctype, convention, args)?));
+    let (done, finished) = mpsc::channel();
+    let worker = SendCall(call);
+    thread::spawn(move || {
+        let worker = worker;
+        let _ = done.send(unsafe { (*worker.0).invoke_with(signals) });
+    });
+
+    let deadline = Instant::now() + timeout;
+    let mut callback_error = None;
+    let invoked = loop {
+        // Callbacks from the worker queue up for this thread; run them while waiting
+        if let Err(e) = callback::pump(lua) {
+            callback_error.get_or_insert(e);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            if lua.app_data_ref::<Abandoned>().is_none() {
+                lua.set_app_data(Abandoned::default());
+            }
+            if let Some(mut abandoned) = lua.app_data_mut::<Abandoned>() {
+                abandoned.0.push((finished, SendCall(call), kept));
+            }
+            let message = format!("Call of '{}' did not return within {} ms", name, timeout.as_millis());
+            return Err(FfiError::new(ErrorKind::Runtime, message).with_symbol(&name).into());
+        }
+        match finished.recv_timeout((deadline - now).min(PUMP_INTERVAL)) {
+            Ok(invoked) => break invoked,
+            Err(RecvTimeoutError::Disconnected) => break Ok(()),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    };
+    let mut call = unsafe { Box::from_raw(call) };
+    if let Some(errno) = call.errno {
+        syserr::errno(Some(errno))?;
+    }
+    callback::pump(lua)?;
+    if let Some(e) = callback_error {
+        return Err(e);
+    }
+    invoked.map_err(LuaError::external)?;
+    call.result(lua)
+}
+
+/// How often a thread waiting for workers runs the callbacks they queued
+pub(crate) const PUMP_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Call a C function of a loaded library; only declared functions can be called
+pub fn call_symbol(lua: &Lua, func: &CFunction, args: LuaMultiValue) -> LuaResult<LuaValue> {
+    let Some(ctype) = &func.ctype else {
+        return Err(LuaError::RuntimeError(format!(
+            "Missing declaration for symbol '{}'",
+            func.name
+        )));
+    };
+    let mut call = PreparedCall::new(lua, func.ptr, ctype, func.convention, args)?;
+    unsafe { call.invoke_with(func.signals) }.map_err(LuaError::external)?;
+    callback::take_error()?;
+    call.result(lua)
+}
+
+// Signals that report a fault of the running code rather than an outside event; blocking them
+// makes a crash in the call undefined, and on Linux kills the process past any handler
+#[cfg(unix)]
+const SYNCHRONOUS_SIGNALS: [libc::c_int; 5] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGFPE, libc::SIGILL, libc::SIGTRAP];
+
+// Block every asynchronous signal on this thread, returning the mask to restore
+#[cfg(unix)]
+fn block_signals() -> std::io::Result<libc::sigset_t> {
+    unsafe {
+        let mut all = std::mem::zeroed();
+        let mut previous = std::mem::zeroed();
+        libc::sigfillset(&mut all);
+        // SIGKILL and SIGSTOP cannot be blocked anyway
+        for signal in SYNCHRONOUS_SIGNALS.into_iter().chain([libc::SIGKILL, libc::SIGSTOP]) {
+            libc::sigdelset(&mut all, signal);
+        }
+        let code = libc::pthread_sigmask(libc::SIG_BLOCK, &all, &mut previous);
+        if code != 0 {
+            return Err(std::io::Error::from_raw_os_error(code));
+        }
+        Ok(previous)
+    }
+}
+
+/// The zero value of the return type of the prototype `ctype`, standing in for the result of a
+/// function that is missing
+pub fn zero_result(lua: &Lua, ctype: &CType) -> LuaResult<LuaValue> {
+    let CType::Function(ret, _) = ctype.resolved() else {
+        return Err(LuaError::RuntimeError("Not a function type".to_string()));
+    };
+    let mut result = vec![0u64; ret.size().max(size_of::<u64>()).div_ceil(size_of::<u64>())];
+    read_return_value(lua, result.as_mut_ptr() as *mut u8, ret)
+}
+
+// Only 32-bit Windows has distinct stdcall/fastcall ABIs
+#[cfg(all(windows, target_arch = "x86"))]
+pub(crate) fn abi_of(convention: CallConv) -> ffi_abi {
+    match convention {
+        CallConv::C => low::ffi_abi_FFI_DEFAULT_ABI,
+        CallConv::Stdcall => libffi::raw::ffi_abi_FFI_STDCALL,
+        CallConv::Fastcall => libffi::raw::ffi_abi_FFI_FASTCALL,
+    }
+}
+
+#[cfg(not(all(windows, target_arch = "x86")))]
+pub(crate) fn abi_of(_convention: CallConv) -> ffi_abi {
+    low::ffi_abi_FFI_DEFAULT_ABI
+}
+
+// Default argument promotions for values passed through `...`
+fn vararg_type(value: &LuaValue) -> LuaResult<CType> {
+    let ctype = match value {
+        LuaValue::Integer(i) if i32::try_from(*i).is_ok() => CType::Int,
+        LuaValue::Integer(_) => CType::LongLong,
+        LuaValue::Number(_) => CType::Double,
+        LuaValue::Boolean(_) => CType::Int,
+        LuaValue::String(_) => CType::Ptr(Box::new(CType::Const(Box::new(CType::Char)))),
+        LuaValue::Nil => CType::Ptr(Box::new(CType::Void)),
+        LuaValue::Function(_) => {
+            return Err(LuaError::RuntimeError(
+                "Cannot pass a Lua function as a variadic argument (use ffi.cast to a function pointer)".to_string(),
+            ));
+        }
+        LuaValue::UserData(ud) => {
+            if ud.is::<CFunction>() {
+                return Ok(CType::Ptr(Box::new(CType::Void)));
+            }
+            let cdata = ud.borrow::<CData>()?;
+            match cdata.ctype.resolved() {
+                CType::Float => CType::Double,
+                CType::Bool | CType::Char | CType::UChar | CType::Short | CType::UShort
+                | CType::Int8 | CType::UInt8 | CType::Int16 | CType::UInt16 => CType::Int,
+                CType::Array(inner, _) | CType::VLA(inner) => CType::Ptr(inner.clone()),
+                function @ CType::Function(_, _) => CType::Ptr(Box::new(function.clone())),
+                ctype => ctype.clone(),
+            }
+        }
+        _ => {
+            return Err(LuaError::RuntimeError(format!(
+                "Cannot pass {} as a variadic argument",
+                value.type_name()
+            )));
+        }
+    };
+    Ok(ctype)
+}
+
+// Convert one argument into its C representation
+fn write_argument(
+    lua: &Lua,
+    ptr: *mut u8,
+    ctype: &CType,
+    value: LuaValue,
+    temporaries: &mut Vec<LuaAnyUserData>,
+) -> LuaResult<()> {
+    // Lua strings passed as `const wchar_t*` go through a wide copy
+    if let LuaValue::String(text) = &value
+        && let CType::Ptr(inner) = ctype.resolved()
+        && inner.is_wchar()
+        && inner.is_const()
+    {
+        let wide = ffi_ops::to_wide(lua, &text.as_bytes())?;
+        unsafe { (ptr as *mut *mut u8).write_unaligned(wide.borrow::<CData>()?.as_ptr()) };
+        temporaries.push(wide);
+        return Ok(());
+    }
+
+    let value = callback::convert_function(lua, ctype, value)?;
+    let LuaValue::UserData(ud) = &value else {
+        return write_scalar(ptr, ctype, value);
+    };
+
+    if let Ok(func) = ud.borrow::<CFunction>() {
+        unsafe { *(ptr as *mut *mut c_void) = func.ptr };
+        return Ok(());
+    }
+
+    let cdata = ud.borrow::<CData>()?;
+    cdata.check_live()?;
+    match ctype.resolved() {
+        // Aggregates are passed by value
+        CType::Struct(_, _) | CType::Union(_, _) => {
+            if cdata.size < ctype.size() {
+                return Err(LuaError::RuntimeError("Argument type mismatch".to_string()));
+            }
+            cdata.check_not_null()?;
+            unsafe { std::ptr::copy_nonoverlapping(cdata.ptr, ptr, ctype.size()) };
+            Ok(())
+        }
+        CType::Ptr(_) | CType::Function(_, _) => write_value_to_ptr(ptr, ctype, value.clone()),
+        // Scalar cdata are converted through their Lua value
+        _ => {
+            cdata.check_not_null()?;
+            let scalar = read_ctype_value(lua, cdata.ptr, &cdata.ctype)?;
+            write_scalar(ptr, ctype, scalar)
+        }
+    }
+}
+
+// Booleans promote to int the way `_Bool` does, e.g. when passed through `...`
+fn write_scalar(ptr: *mut u8, ctype: &CType, value: LuaValue) -> LuaResult<()> {
+    match value {
+        LuaValue::Boolean(b) if matches!(ctype.resolved(), CType::Int) => {
+            write_value_to_ptr(ptr, ctype, LuaValue::Integer(i64::from(b)))
+        }
+        value => write_value_to_ptr(ptr, ctype, value),
+    }
+}
+
+fn read_return_value(lua: &Lua, ptr: *mut u8, ctype: &CType) -> LuaResult<LuaValue> {
+    match ctype.resolved() {
+        CType::Void => Ok(LuaValue::Nil),
+        CType::Struct(_, _) | CType::Union(_, _) => {
+            let cdata = CData::new(ctype.clone(), ctype.size());
+            unsafe { std::ptr::copy_nonoverlapping(ptr, cdata.ptr, ctype.size()) };
+            create_owned(lua, cdata).map(LuaValue::UserData)
+        }
+        _ => read_ctype_value(lua, unsafe { ptr.add(return_offset(ctype)) }, ctype),
+    }
+}
+
+/// Where a scalar result of `ctype` starts in the buffer libffi returned it in: small integers
+/// sit in the low-order end of the register-sized value libffi widens them to (`ffi_arg`, the
+/// size of a pointer)
+pub(crate) fn return_offset(ctype: &CType) -> usize {
+    match ctype.resolved() {
+        CType::Float | CType::Double | CType::Struct(_, _) | CType::Union(_, _) => 0,
+        _ if cfg!(target_endian = "big") => size_of::<usize>().saturating_sub(ctype.size()),
+        _ => 0,
+    }
+}
+
+// Describe a C type to libffi
+pub(crate) fn ffi_type_of(ctype: &CType) -> LuaResult<Type> {
+    let ffi_type = match ctype {
+        CType::Void => Type::void(),
+        CType::Bool | CType::UChar | CType::UInt8 => Type::u8(),
+        CType::Char | CType::Int8 => Type::i8(),
+        CType::Short | CType::Int16 => Type::i16(),
+        CType::UShort | CType::UInt16 => Type::u16(),
+        CType::Int | CType::Int32 => Type::i32(),
+        CType::UInt | CType::UInt32 => Type::u32(),
+        CType::Long => Type::c_long(),
+        CType::ULong => Type::c_ulong(),
+        CType::SSizeT => Type::isize(),
+        CType::SizeT => Type::usize(),
+        CType::LongLong | CType::Int64 => Type::i64(),
+        CType::ULongLong | CType::UInt64 => Type::u64(),
+        CType::Float => Type::f32(),
+        CType::Double => Type::f64(),
+        CType::Ptr(_) | CType::Function(_, _) | CType::Array(_, _) | CType::VLA(_) => Type::pointer(),
+        // Byte-swapped integers are passed as they are stored
+        CType::Typedef(_, inner) | CType::Const(inner) | CType::Enum(_, _, inner) | CType::Swapped(inner) => {
+            ffi_type_of(inner)?
+        }
+        CType::Struct(name, fields) => {
+            if fields.is_empty() {
+                return Err(LuaError::RuntimeError(format!(
+                    "Cannot pass incomplete struct {} by value",
+                    name
+                )));
+            }
+            let mut elements = Vec::new();
+            for field in fields {
+                push_ffi_elements(&mut elements, &field.ctype)?;
+            }
+            Type::structure(elements)
+        }
+        CType::Union(_, _) => {
+            return Err(LuaError::RuntimeError(
+                "Passing unions by value is not supported".to_string(),
+            ));
+        }
+        CType::Packed(_, _) => {
+            return Err(LuaError::RuntimeError(
+                "Passing packed structs by value is not supported".to_string(),
+            ));
+        }
+        CType::Bitfield(_, _, _) => {
+            return Err(LuaError::RuntimeError(
+                "Passing structs with bit-fields by value is not supported".to_string(),
+            ));
+        }
+        CType::VarArgs => {
+            return Err(LuaError::RuntimeError("Unexpected '...' in argument list".to_string()));
+        }
+        // POSIX typedefs are integers of the target's width and signedness
+        #[cfg(unix)]
+        _ => match ctype.posix_integer() {
+            Some((size, _, signed)) => ffi_type_of(&CType::integer(size, signed).unwrap_or(CType::UInt64))?,
+            None => Type::u64(),
+        },
+    };
+    Ok(ffi_type)
+}
+
+// Arrays inside structs are laid out as repeated elements
+fn push_ffi_elements(elements: &mut Vec<Type>, ctype: &CType) -> LuaResult<()> {
+    match ctype.resolved() {
+        CType::Array(inner, count) => {
+            for _ in 0..*count {
+                push_ffi_elements(elements, inner)?;
+            }
+        }
+        _ => elements.push(ffi_type_of(ctype)?),
+    }
+    Ok(())
+}
@@ -0,0 +1,345 @@
+// Lua functions exposed to C as function pointers
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::c_void;
+use std::mem::size_of;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, ThreadId};
+
+use libffi::low::{self, ffi_cif, ffi_closure, ffi_type};
+use libffi::middle::Type;
+use mlua::prelude::*;
+
+use crate::call::{abi_of, ffi_type_of};
+use crate::cdata::{create_owned, CData, read_ctype_value};
+use crate::ctype::{CType, CallConv};
+use crate::ffi_ops::write_value_to_ptr;
+
+thread_local! {
+    // Error raised by a callback, reported once the C call that invoked it returns
+    static CALLBACK_ERROR: RefCell<Option<LuaError>> = const { RefCell::new(None) };
+}
+
+// Callbacks of one Lua state
+#[derive(Default)]
+struct Callbacks {
+    // Keyed by code address
+    active: HashMap<usize, Callback>,
+    // Functions converted implicitly reuse their callback, keyed by function and type
+    by_function: HashMap<(usize, String), usize>,
+    queue: Arc<CallbackQueue>,
+}
+
+// Invocations from foreign threads, waiting to be run by the thread owning the Lua state
+#[derive(Default)]
+struct CallbackQueue {
+    pending: Mutex<VecDeque<PendingCall>>,
+}
+
+struct PendingCall {
+    state: Arc<CallbackState>,
+    args: Vec<Vec<u64>>,
+    // Set when the C caller is waiting for a return value
+    reply: Option<Arc<Reply>>,
+}
+
+#[derive(Default)]
+struct Reply {
+    result: Mutex<Option<Vec<u64>>>,
+    ready: Condvar,
+}
+
+struct Callback {
+    closure: *mut ffi_closure,
+    state: Arc<CallbackState>,
+}
+
+impl Drop for Callback {
+    fn drop(&mut self) {
+        unsafe { low::closure_free(self.closure) };
+    }
+}
+
+struct CallbackState {
+    // The CIF refers to the libffi types, which must live as long as the closure
+    _cif: Box<ffi_cif>,
+    _ffi_types: (Type, Vec<Type>, Vec<*mut ffi_type>),
+    ret: CType,
+    params: Vec<CType>,
+    func: RefCell<LuaFunction>,
+    lua: WeakLua,
+    owner: ThreadId,
+    queue: Arc<CallbackQueue>,
+}
+
+// Foreign threads only read the signature and queue calls; the Lua function and state are
+// only used on the owning thread, which also drops the queued calls
+unsafe impl Send for CallbackState {}
+unsafe impl Sync for CallbackState {}
+
+impl CallbackState {
+    // Run the Lua function with C arguments, storing its result for the C caller
+    fn invoke(&self, result: *mut u8, args: &[*mut u8]) -> LuaResult<()> {
+        let lua = self
+            .lua
+            .try_upgrade()
+            .ok_or_else(|| LuaError::RuntimeError("Callback invoked after its Lua state was closed".to_string()))?;
+        let mut values = LuaMultiValue::new();
+        for (param, &arg) in self.params.iter().zip(args) {
+            values.push_back(read_argument(&lua, arg, param)?);
+        }
+        let func = self.func.borrow().clone();
+        let value: LuaValue = func.call(values)?;
+        write_result(&lua, result, &self.ret, value)
+    }
+
+    fn result_slot(&self) -> Vec<u64> {
+        vec![0u64; self.ret.size().max(size_of::<u64>()).div_ceil(size_of::<u64>())]
+    }
+}
+
+unsafe extern "C" fn trampoline(_cif: &ffi_cif, result: &mut u8, args: *const *const c_void, state: &CallbackState) {
+    let result = result as *mut u8;
+    let result_size = state.ret.size().max(size_of::<usize>());
+    let args: Vec<*mut u8> = (0..state.params.len()).map(|i| unsafe { *args.add(i) as *mut u8 }).collect();
+
+    if thread::current().id() == state.owner {
+        if let Err(e) = state.invoke(result, &args) {
+            unsafe { std::ptr::write_bytes(result, 0, result_size) };
+            CALLBACK_ERROR.with(|error| {
+                error.borrow_mut().get_or_insert(e);
+            });
+        }
+        return;
+    }
+
+    // Foreign threads must not touch the Lua state: queue the call with copies of the arguments
+    let args = state
+        .params
+        .iter()
+        .zip(&args)
+        .map(|(param, &arg)| {
+            let mut copy = vec![0u64; param.size().max(1).div_ceil(size_of::<u64>())];
+            unsafe { std::ptr::copy_nonoverlapping(arg, copy.as_mut_ptr() as *mut u8, param.size()) };
+            copy
+        })
+        .collect();
+    let reply = (!matches!(state.ret.resolved(), CType::Void)).then(|| Arc::new(Reply::default()));
+    let state_arc = unsafe {
+        Arc::increment_strong_count(state as *const CallbackState);
+        Arc::from_raw(state as *const CallbackState)
+    };
+    state
+        .queue
+        .pending
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push_back(PendingCall { state: state_arc, args, reply: reply.clone() });
+
+    // Callbacks returning a value block until the owning thread has run them
+    if let Some(reply) = reply {
+        let mut value = reply.result.lock().unwrap_or_else(PoisonError::into_inner);
+        while value.is_none() {
+            value = reply.ready.wait(value).unwrap_or_else(PoisonError::into_inner);
+        }
+        if let Some(value) = value.as_ref() {
+            unsafe { std::ptr::copy_nonoverlapping(value.as_ptr() as *const u8, result, result_size) };
+        }
+    }
+}
+
+fn read_argument(lua: &Lua, ptr: *mut u8, ctype: &CType) -> LuaResult<LuaValue> {
+    match ctype.resolved() {
+        // Aggregates passed by value only live for the duration of the call
+        CType::Struct(_, _) | CType::Union(_, _) => {
+            let cdata = CData::new(ctype.clone(), ctype.size());
+            unsafe { std::ptr::copy_nonoverlapping(ptr, cdata.ptr, ctype.size()) };
+            create_owned(lua, cdata).map(LuaValue::UserData)
+        }
+        _ => read_ctype_value(lua, ptr, ctype),
+    }
+}
+
+// libffi expects integer results narrower than a register to be widened to one
+fn write_result(lua: &Lua, result: *mut u8, ctype: &CType, value: LuaValue) -> LuaResult<()> {
+    let resolved = ctype.resolved();
+    if matches!(resolved, CType::Void) {
+        return Ok(());
+    }
+    let widen = ctype.size() < size_of::<usize>()
+        && !matches!(
+            resolved,
+            CType::Float | CType::Double | CType::Struct(_, _) | CType::Union(_, _) | CType::Array(_, _)
+        );
+    if !widen {
+        return write_value_to_ptr(result, ctype, value);
+    }
+
+    let mut slot = 0u64;
+    write_value_to_ptr(&mut slot as *mut u64 as *mut u8, ctype, value)?;
+    let widened = match read_ctype_value(lua, &mut slot as *mut u64 as *mut u8, ctype)? {
+        LuaValue::Integer(i) => i as isize,
+        LuaValue::Boolean(b) => b as isize,
+        _ => 0,
+    };
+    unsafe { *(result as *mut isize) = widened };
+    Ok(())
+}
+
+// Return and parameter types of a function pointer type
+fn signature(ctype: &CType) -> Option<(&CType, &[CType])> {
+    let function = match ctype.resolved() {
+        CType::Ptr(inner) => inner.resolved(),
+        function => function,
+    };
+    match function {
+        CType::Function(ret, params) => Some((ret, params)),
+        _ => None,
+    }
+}
+
+fn with_callbacks<R>(lua: &Lua, f: impl FnOnce(&mut Callbacks) -> R) -> R {
+    if lua.app_data_ref::<Callbacks>().is_none() {
+        lua.set_app_data(Callbacks::default());
+    }
+    let mut callbacks = lua.app_data_mut::<Callbacks>().expect("callback registry");
+    f(&mut callbacks)
+}
+
+/// Create a C function pointer of type `ctype` that calls `func`. It stays valid until
+/// freed with `cb:free()`
+pub fn create(lua: &Lua, ctype: &CType, func: LuaFunction) -> LuaResult<*mut c_void> {
+    let Some((ret, params)) = signature(ctype) else {
+        return Err(LuaError::RuntimeError(format!("Not a function pointer type: {}", ctype)));
+    };
+    if params.last() == Some(&CType::VarArgs) {
+        return Err(LuaError::RuntimeError("Variadic callbacks are not supported".to_string()));
+    }
+
+    let ret_type = ffi_type_of(ret)?;
+    let arg_types = params.iter().map(ffi_type_of).collect::<LuaResult<Vec<_>>>()?;
+    let mut arg_ptrs: Vec<*mut ffi_type> = arg_types.iter().map(Type::as_raw_ptr).collect();
+    let mut cif: Box<ffi_cif> = Box::default();
+    unsafe {
+        low::prep_cif(
+            &mut *cif,
+            abi_of(CallConv::C),
+            arg_ptrs.len(),
+            ret_type.as_raw_ptr(),
+            arg_ptrs.as_mut_ptr(),
+        )
+    }
+    .map_err(|e| LuaError::RuntimeError(format!("Failed to prepare callback: {:?}", e)))?;
+    let cif_ptr: *mut ffi_cif = &mut *cif;
+
+    let state = Arc::new(CallbackState {
+        _cif: cif,
+        _ffi_types: (ret_type, arg_types, arg_ptrs),
+        ret: ret.clone(),
+        params: params.to_vec(),
+        func: RefCell::new(func),
+        lua: lua.weak(),
+        owner: thread::current().id(),
+        queue: with_callbacks(lua, |callbacks| callbacks.queue.clone()),
+    });
+
+    let (closure, code) = low::closure_alloc();
+    if closure.is_null() {
+        return Err(LuaError::RuntimeError("Failed to allocate callback".to_string()));
+    }
+    let callback = Callback { closure, state };
+    unsafe { low::prep_closure(closure, cif_ptr, trampoline, Arc::as_ptr(&callback.state), code) }
+        .map_err(|e| LuaError::RuntimeError(format!("Failed to prepare callback: {:?}", e)))?;
+
+    let code = code.as_mut_ptr();
+    with_callbacks(lua, |callbacks| callbacks.active.insert(code as usize, callback));
+    Ok(code)
+}
+
+/// Convert a Lua function passed where a function pointer is expected into the address of
+/// a callback; other values are returned unchanged
+pub fn convert_function(lua: &Lua, ctype: &CType, value: LuaValue) -> LuaResult<LuaValue> {
+    let LuaValue::Function(func) = value else {
+        return Ok(value);
+    };
+    if signature(ctype).is_none() {
+        return Ok(LuaValue::Function(func));
+    }
+
+    let key = (func.to_pointer() as usize, ctype.to_string());
+    let cached = with_callbacks(lua, |callbacks| callbacks.by_function.get(&key).copied());
+    let code = match cached {
+        Some(code) => code,
+        None => {
+            let code = create(lua, ctype, func)? as usize;
+            with_callbacks(lua, |callbacks| callbacks.by_function.insert(key, code));
+            code
+        }
+    };
+    Ok(LuaValue::Integer(code as i64))
+}
+
+/// Methods of callback cdata: `cb:free()` releases the callback and `cb:set(func)` replaces
+/// the Lua function it calls
+pub fn method(lua: &Lua, name: &str) -> LuaResult<LuaValue> {
+    let method = match name {
+        "free" => lua.create_function(|lua, cd: LuaAnyUserData| {
+            let mut cdata = cd.borrow_mut::<CData>()?;
+            let code = cdata.as_ptr() as usize;
+            let callback = with_callbacks(lua, |callbacks| {
+                callbacks.by_function.retain(|_, cached| *cached != code);
+                callbacks.active.remove(&code)
+            });
+            if callback.is_none() {
+                return Err(LuaError::RuntimeError("Not a callback".to_string()));
+            }
+            // Calls through this cdata now fail instead of jumping to freed code
+            cdata.ptr = std::ptr::null_mut();
+            Ok(())
+        })?,
+        "set" => lua.create_function(|lua, (cd, func): (LuaAnyUserData, LuaFunction)| {
+            let code = cd.borrow::<CData>()?.as_ptr() as usize;
+            with_callbacks(lua, |callbacks| {
+                callbacks.by_function.retain(|_, cached| *cached != code);
+                let callback = callbacks
+                    .active
+                    .get(&code)
+                    .ok_or_else(|| LuaError::RuntimeError("Not a callback".to_string()))?;
+                *callback.state.func.borrow_mut() = func;
+                Ok(())
+            })
+        })?,
+        _ => return Err(LuaError::RuntimeError(format!("Unknown callback method: {}", name))),
+    };
+    Ok(LuaValue::Function(method))
+}
+
+/// Run the callback invocations queued by foreign threads, returning how many ran
+pub fn pump(lua: &Lua) -> LuaResult<usize> {
+    let Some(queue) = lua.app_data_ref::<Callbacks>().map(|callbacks| callbacks.queue.clone()) else {
+        return Ok(0);
+    };
+    let pending: Vec<PendingCall> = queue.pending.lock().unwrap_or_else(PoisonError::into_inner).drain(..).collect();
+
+    let count = pending.len();
+    let mut first_error = None;
+    for mut call in pending {
+        let mut result = call.state.result_slot();
+        let args: Vec<*mut u8> = call.args.iter_mut().map(|arg| arg.as_mut_ptr() as *mut u8).collect();
+        if let Err(e) = call.state.invoke(result.as_mut_ptr() as *mut u8, &args) {
+            result.fill(0);
+            first_error.get_or_insert(e);
+        }
+        if let Some(reply) = call.reply {
+            *reply.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+            reply.ready.notify_all();
+        }
+    }
+    first_error.map_or(Ok(count), Err)
+}
+
+/// Report an error raised by a callback during the C call that just returned
+pub fn take_error() -> LuaResult<()> {
+    CALLBACK_ERROR.with(|error| error.borrow_mut().take()).map_or(Ok(()), Err)
+}
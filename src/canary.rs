@@ -0,0 +1,80 @@
+// Canary bytes around the memory cdata own, for catching out-of-bounds writes in debug builds
+
+use std::alloc::{self, Layout};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+const CANARY: u8 = 0xFD;
+const GUARD: usize = 16;
+
+// Live blocks by the address handed out, with the layout asked for
+static LIVE: Mutex<BTreeMap<usize, Layout>> = Mutex::new(BTreeMap::new());
+
+// Bytes of canary before a block: at least `GUARD`, and keeping the block aligned
+fn guard(layout: Layout) -> usize {
+    GUARD.max(layout.align())
+}
+
+fn padded(layout: Layout) -> Layout {
+    Layout::from_size_align(layout.size() + 2 * guard(layout), layout.align()).expect("Invalid layout")
+}
+
+/// Allocate zeroed memory for `layout` between two runs of canary bytes
+pub(crate) fn alloc(layout: Layout) -> *mut u8 {
+    let padded = padded(layout);
+    let base = unsafe { alloc::alloc(padded) };
+    if base.is_null() {
+        alloc::handle_alloc_error(padded);
+    }
+    let guard = guard(layout);
+    unsafe {
+        std::ptr::write_bytes(base, CANARY, guard);
+        std::ptr::write_bytes(base.add(guard), 0, layout.size());
+        std::ptr::write_bytes(base.add(guard + layout.size()), CANARY, guard);
+    }
+    let ptr = unsafe { base.add(guard) };
+    LIVE.lock().unwrap().insert(ptr as usize, layout);
+    ptr
+}
+
+/// Free a block from `alloc`. Damaged canaries abort the process, like a corrupted heap does
+pub(crate) fn dealloc(ptr: *mut u8, layout: Layout) {
+    LIVE.lock().unwrap().remove(&(ptr as usize));
+    if let Err(message) = check(ptr, layout) {
+        eprintln!("luaffi: {}", message);
+        std::process::abort();
+    }
+    unsafe { alloc::dealloc(ptr.sub(guard(layout)), padded(layout)) };
+}
+
+/// Verify the canaries of one block
+pub(crate) fn check(ptr: *const u8, layout: Layout) -> Result<(), String> {
+    let guard = guard(layout);
+    let damaged = |start: *const u8| {
+        unsafe { std::slice::from_raw_parts(start, guard) }.iter().filter(|&&b| b != CANARY).count()
+    };
+    let (before, after) = unsafe { (damaged(ptr.sub(guard)), damaged(ptr.add(layout.size()))) };
+    if before == 0 && after == 0 {
+        return Ok(());
+    }
+    Err(format!(
+        "Canary bytes of the {}-byte block at {:p} were overwritten ({} before it, {} after it)",
+        layout.size(),
+        ptr,
+        before,
+        after
+    ))
+}
+
+/// Verify the canaries of every live block, returning how many were checked
+pub fn check_all() -> Result<usize, String> {
+    let live = LIVE.lock().unwrap();
+    let damaged: Vec<String> = live
+        .iter()
+        .filter_map(|(&ptr, &layout)| check(ptr as *const u8, layout).err())
+        .collect();
+    if !damaged.is_empty() {
+        return Err(damaged.join("\n"));
+    }
+    Ok(live.len())
+}
@@ -1,79 +1,122 @@
+use std::alloc::Layout;
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::ffi::{c_long, c_ulong};
+use std::os::raw::c_int;
 use std::ptr;
+use std::sync::Mutex;
 
 use mlua::prelude::*;
 
-use crate::ctype::CType;
-use crate::dylib::DynamicLibrary;
+use crate::arith::{self, Arith, Compare};
+use crate::call;
+use crate::callback;
+use crate::config::{self, AllocatorHooks};
+use crate::ctype::{CType, CallConv, TypeHandle};
+use crate::dylib::{host, DynamicLibrary};
+use crate::error::{ErrorKind, FfiError};
+use crate::ffi_ops;
+use crate::parser::{MissingSymbol, SignalHandling};
+use crate::symbols;
 
 // Helper function to read a value from memory as a Lua value
+// (POSIX typedef widths vary per platform, so some casts are no-ops on a given target)
 #[inline]
-fn read_ctype_value(lua: &Lua, ptr: *mut u8, ctype: &CType) -> LuaResult<LuaValue> {
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn read_ctype_value(lua: &Lua, ptr: *mut u8, ctype: &CType) -> LuaResult<LuaValue> {
     unsafe {
         match ctype {
             // Basic integer types
-            CType::Int => Ok(LuaValue::Integer(*(ptr as *const i32) as i64)),
-            CType::UInt => Ok(LuaValue::Integer(*(ptr as *const u32) as i64)),
-            CType::Long => Ok(LuaValue::Integer(*(ptr as *const isize) as i64)),
-            CType::ULong => Ok(LuaValue::Integer(*(ptr as *const usize) as i64)),
-            CType::LongLong => Ok(LuaValue::Integer(*(ptr as *const i64))),
-            CType::ULongLong => Ok(LuaValue::Integer(*(ptr as *const u64) as i64)),
+            CType::Int => Ok(LuaValue::Integer((ptr as *const i32).read_unaligned() as i64)),
+            CType::UInt => Ok(LuaValue::Integer((ptr as *const u32).read_unaligned() as i64)),
+            CType::Long => integer_value(lua, ctype, (ptr as *const c_long).read_unaligned() as i128),
+            CType::ULong => integer_value(lua, ctype, (ptr as *const c_ulong).read_unaligned() as i128),
+            CType::LongLong => integer_value(lua, ctype, (ptr as *const i64).read_unaligned() as i128),
+            CType::ULongLong => integer_value(lua, ctype, (ptr as *const u64).read_unaligned() as i128),
             
             // Character types
-            CType::Char => Ok(LuaValue::Integer(*(ptr as *const i8) as i64)),
-            CType::UChar => Ok(LuaValue::Integer(*(ptr as *const u8) as i64)),
+            CType::Char => Ok(LuaValue::Integer((ptr as *const i8).read_unaligned() as i64)),
+            CType::UChar => Ok(LuaValue::Integer((ptr as *const u8).read_unaligned() as i64)),
             
             // Short types
-            CType::Short => Ok(LuaValue::Integer(*(ptr as *const i16) as i64)),
-            CType::UShort => Ok(LuaValue::Integer(*(ptr as *const u16) as i64)),
+            CType::Short => Ok(LuaValue::Integer((ptr as *const i16).read_unaligned() as i64)),
+            CType::UShort => Ok(LuaValue::Integer((ptr as *const u16).read_unaligned() as i64)),
             
             // Fixed-width integer types
-            CType::Int8 => Ok(LuaValue::Integer(*(ptr as *const i8) as i64)),
-            CType::Int16 => Ok(LuaValue::Integer(*(ptr as *const i16) as i64)),
-            CType::Int32 => Ok(LuaValue::Integer(*(ptr as *const i32) as i64)),
-            CType::Int64 => Ok(LuaValue::Integer(*(ptr as *const i64))),
-            CType::UInt8 => Ok(LuaValue::Integer(*(ptr as *const u8) as i64)),
-            CType::UInt16 => Ok(LuaValue::Integer(*(ptr as *const u16) as i64)),
-            CType::UInt32 => Ok(LuaValue::Integer(*(ptr as *const u32) as i64)),
-            CType::UInt64 => Ok(LuaValue::Integer(*(ptr as *const u64) as i64)),
+            CType::Int8 => Ok(LuaValue::Integer((ptr as *const i8).read_unaligned() as i64)),
+            CType::Int16 => Ok(LuaValue::Integer((ptr as *const i16).read_unaligned() as i64)),
+            CType::Int32 => Ok(LuaValue::Integer((ptr as *const i32).read_unaligned() as i64)),
+            CType::Int64 => integer_value(lua, ctype, (ptr as *const i64).read_unaligned() as i128),
+            CType::UInt8 => Ok(LuaValue::Integer((ptr as *const u8).read_unaligned() as i64)),
+            CType::UInt16 => Ok(LuaValue::Integer((ptr as *const u16).read_unaligned() as i64)),
+            CType::UInt32 => Ok(LuaValue::Integer((ptr as *const u32).read_unaligned() as i64)),
+            CType::UInt64 => integer_value(lua, ctype, (ptr as *const u64).read_unaligned() as i128),
             
             // Size types
-            CType::SizeT => Ok(LuaValue::Integer(*(ptr as *const usize) as i64)),
-            CType::SSizeT => Ok(LuaValue::Integer(*(ptr as *const isize) as i64)),
+            CType::SizeT => integer_value(lua, ctype, (ptr as *const usize).read_unaligned() as i128),
+            CType::SSizeT => integer_value(lua, ctype, (ptr as *const isize).read_unaligned() as i128),
             
             // Floating point types
-            CType::Float => Ok(LuaValue::Number(*(ptr as *const f32) as f64)),
-            CType::Double => Ok(LuaValue::Number(*(ptr as *const f64))),
+            CType::Float => Ok(LuaValue::Number((ptr as *const f32).read_unaligned() as f64)),
+            CType::Double => Ok(LuaValue::Number((ptr as *const f64).read_unaligned())),
             
-            // Boolean type
-            CType::Bool => Ok(LuaValue::Boolean(*(ptr as *const bool))),
+            // `_Bool` is one byte; any non-zero byte written by C code reads as true
+            CType::Bool => Ok(LuaValue::Boolean(ptr.read_unaligned() != 0)),
+            
+            // Enums are stored as their underlying integer type
+            CType::Enum(_, _, repr) => read_ctype_value(lua, ptr, repr),
             
             // POSIX types (Unix only)
             #[cfg(unix)]
-            CType::InoT => Ok(LuaValue::Integer(*(ptr as *const libc::ino_t) as i64)),
+            CType::InoT => integer_value(lua, ctype, (ptr as *const libc::ino_t).read_unaligned() as i128),
             #[cfg(unix)]
-            CType::DevT => Ok(LuaValue::Integer(*(ptr as *const libc::dev_t) as i64)),
+            CType::DevT => integer_value(lua, ctype, (ptr as *const libc::dev_t).read_unaligned() as i128),
             #[cfg(unix)]
-            CType::GidT => Ok(LuaValue::Integer(*(ptr as *const libc::gid_t) as i64)),
+            CType::GidT => Ok(LuaValue::Integer((ptr as *const libc::gid_t).read_unaligned() as i64)),
             #[cfg(unix)]
-            CType::ModeT => Ok(LuaValue::Integer(*(ptr as *const libc::mode_t) as i64)),
+            CType::ModeT => Ok(LuaValue::Integer((ptr as *const libc::mode_t).read_unaligned() as i64)),
             #[cfg(unix)]
-            CType::NlinkT => Ok(LuaValue::Integer(*(ptr as *const libc::nlink_t) as i64)),
+            CType::NlinkT => integer_value(lua, ctype, (ptr as *const libc::nlink_t).read_unaligned() as i128),
             #[cfg(unix)]
-            CType::UidT => Ok(LuaValue::Integer(*(ptr as *const libc::uid_t) as i64)),
+            CType::UidT => Ok(LuaValue::Integer((ptr as *const libc::uid_t).read_unaligned() as i64)),
             #[cfg(unix)]
-            CType::OffT => Ok(LuaValue::Integer(*(ptr as *const libc::off_t) as i64)),
+            CType::OffT => integer_value(lua, ctype, (ptr as *const libc::off_t).read_unaligned() as i128),
             #[cfg(unix)]
-            CType::PidT => Ok(LuaValue::Integer(*(ptr as *const libc::pid_t) as i64)),
+            CType::PidT => Ok(LuaValue::Integer((ptr as *const libc::pid_t).read_unaligned() as i64)),
             #[cfg(unix)]
-            CType::UsecondsT => Ok(LuaValue::Integer(*(ptr as *const libc::useconds_t) as i64)),
+            CType::UsecondsT => Ok(LuaValue::Integer((ptr as *const libc::useconds_t).read_unaligned() as i64)),
             #[cfg(unix)]
-            CType::SusecondsT => Ok(LuaValue::Integer(*(ptr as *const libc::suseconds_t) as i64)),
+            CType::SusecondsT => integer_value(lua, ctype, (ptr as *const libc::suseconds_t).read_unaligned() as i128),
             #[cfg(unix)]
-            CType::BlksizeT => Ok(LuaValue::Integer(*(ptr as *const libc::blksize_t) as i64)),
+            CType::BlksizeT => integer_value(lua, ctype, (ptr as *const libc::blksize_t).read_unaligned() as i128),
             #[cfg(unix)]
-            CType::BlkcntT => Ok(LuaValue::Integer(*(ptr as *const libc::blkcnt_t) as i64)),
+            CType::BlkcntT => integer_value(lua, ctype, (ptr as *const libc::blkcnt_t).read_unaligned() as i128),
             #[cfg(unix)]
-            CType::TimeT => Ok(LuaValue::Integer(*(ptr as *const libc::time_t) as i64)),
+            CType::TimeT => integer_value(lua, ctype, (ptr as *const libc::time_t).read_unaligned() as i128),
+            
+            // Typedefs and qualified types read as their underlying type
+            CType::Typedef(_, inner) | CType::Const(inner) => read_ctype_value(lua, ptr, inner),
+
+            CType::Swapped(inner) => {
+                let mut bytes = [0u8; 8];
+                let size = inner.size();
+                ptr::copy_nonoverlapping(ptr, bytes.as_mut_ptr(), size);
+                bytes[..size].reverse();
+                read_ctype_value(lua, bytes.as_mut_ptr(), inner)
+            }
+
+            CType::Bitfield(inner, shift, width) => {
+                let bits = ffi_ops::read_bits(ptr, *shift, *width);
+                match inner.resolved() {
+                    CType::Bool => Ok(LuaValue::Boolean(bits != 0)),
+                    // Sign-extend from the top bit of the field
+                    base if ffi_ops::is_signed(base) && *width < 64 => {
+                        let unused = 64 - *width as u32;
+                        Ok(LuaValue::Integer(((bits << unused) as i64) >> unused))
+                    }
+                    _ => integer_value(lua, inner, bits as i128),
+                }
+            }
             
             CType::VLA(_) => {
                 // VLA should be converted to Array before reaching here
@@ -82,87 +125,333 @@ fn read_ctype_value(lua: &Lua, ptr: *mut u8, ctype: &CType) -> LuaResult<LuaValu
                 ))
             }
             
+            // Pointer cdata refer to the address stored at this location
+            CType::Ptr(_) | CType::Function(_, _) => {
+                let cdata = CData::from_ptr(ctype.clone(), (ptr as *const *mut u8).read_unaligned(), Ownership::Foreign);
+                lua.create_userdata(cdata).map(LuaValue::UserData)
+            }
+            
             _ => {
-                // For complex types (Ptr, Array, Struct, Union, etc.), return as CData userdata
-                let cdata = CData::from_ptr(ctype.clone(), ptr, false);
-                lua.create_userdata(cdata).map(|ud| LuaValue::UserData(ud))
+                // For aggregate types (Array, Struct, Union), return as CData userdata
+                let cdata = CData::from_ptr(ctype.clone(), ptr, Ownership::Borrowed);
+                lua.create_userdata(cdata).map(LuaValue::UserData)
             }
         }
     }
 }
 
-// Small buffer optimization - avoid heap allocation for small objects
-const SMALL_BUFFER_SIZE: usize = 64;
+// Read a field or element through the handle its parent type shares; aggregates and pointers
+// become cdata holding that handle instead of a copy of the type
+pub(crate) fn read_member(lua: &Lua, ptr: *mut u8, handle: Option<&TypeHandle>) -> LuaResult<LuaValue> {
+    let handle = handle.ok_or_else(|| LuaError::RuntimeError("Missing member type".to_string()))?;
+    let cdata = match handle.resolved() {
+        CType::Array(_, _) | CType::Struct(_, _) | CType::Union(_, _) => {
+            CData::from_ptr(handle.clone(), ptr, Ownership::Borrowed)
+        }
+        // Pointers stored in memory have unknown provenance
+        CType::Ptr(_) => {
+            CData::from_ptr(handle.clone(), unsafe { (ptr as *const *mut u8).read_unaligned() }, Ownership::Foreign)
+        }
+        _ => return read_ctype_value(lua, ptr, handle),
+    };
+    lua.create_userdata(cdata).map(LuaValue::UserData)
+}
+
+// Largest magnitude the Lua version holds exactly as a number
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+const EXACT_INTEGERS: std::ops::RangeInclusive<i128> = i64::MIN as i128..=i64::MAX as i128;
+#[cfg(not(any(feature = "lua53", feature = "lua54")))]
+const EXACT_INTEGERS: std::ops::RangeInclusive<i128> = -(1 << 53)..=1 << 53;
+
+// 64-bit integers become Lua integers when the Lua version represents them exactly; others,
+// e.g. a `uint64_t` above `i64::MAX` or an `int64_t` beyond 2^53 on Lua 5.1, stay boxed as cdata
+pub(crate) fn integer_value(lua: &Lua, ctype: &CType, value: i128) -> LuaResult<LuaValue> {
+    if EXACT_INTEGERS.contains(&value) {
+        return Ok(LuaValue::Integer(value as i64));
+    }
+    // Like LuaJIT, boxes are `int64_t` or `uint64_t` whatever the source type, and equal
+    // values share one userdata so boxed integers work as table keys
+    let signed = value <= i64::MAX as i128 && ctype.resolved().integer_kind().is_none_or(|(_, signed)| signed);
+    let ctype = CType::integer(size_of::<u64>(), signed).expect("64-bit integer type");
+    let key = format!("{}:{}", ctype, value);
+    let cache = boxed_integers(lua)?;
+    if let LuaValue::UserData(boxed) = cache.raw_get(key.as_str())? {
+        return Ok(LuaValue::UserData(boxed));
+    }
+    let cdata = CData::new(ctype, size_of::<u64>());
+    unsafe { (cdata.ptr as *mut u64).write_unaligned(value as u64) };
+    let boxed = lua.create_userdata(cdata)?;
+    cache.raw_set(key, &boxed)?;
+    Ok(LuaValue::UserData(boxed))
+}
+
+// Weak-valued table of the boxed integers alive in this Lua state
+fn boxed_integers(lua: &Lua) -> LuaResult<LuaTable> {
+    const KEY: &str = "ffi_boxed_integers";
+    if let LuaValue::Table(cache) = lua.named_registry_value(KEY)? {
+        return Ok(cache);
+    }
+    let cache = lua.create_table()?;
+    cache.set_metatable(Some(lua.create_table_from([("__mode", "v")])?))?;
+    lua.set_named_registry_value(KEY, &cache)?;
+    Ok(cache)
+}
+
+/// Create the userdata for a cdata that owns its memory, counting the allocation towards the
+/// next garbage collection step
+pub(crate) fn create_owned(lua: &Lua, cdata: CData) -> LuaResult<LuaAnyUserData> {
+    let size = cdata.size;
+    let userdata = lua.create_userdata(cdata)?;
+    report_allocation(lua, size)?;
+    Ok(userdata)
+}
+
+// Bytes allocated outside the Lua heap that the collector has not been told about yet
+#[derive(Default)]
+struct ExternalDebt(Cell<usize>);
+
+const DEBT_STEP: usize = 1024;
+
+// The collector only sees the small userdata headers, so big buffers would never trigger a
+// cycle; each whole KiB allocated outside the heap drives an incremental step of that size
+fn report_allocation(lua: &Lua, bytes: usize) -> LuaResult<()> {
+    if lua.app_data_ref::<ExternalDebt>().is_none() {
+        lua.set_app_data(ExternalDebt::default());
+    }
+    let kbytes = {
+        let debt = lua.app_data_ref::<ExternalDebt>().expect("external memory debt");
+        let total = debt.0.get().saturating_add(bytes);
+        debt.0.set(total % DEBT_STEP);
+        total / DEBT_STEP
+    };
+    if kbytes > 0 {
+        lua.gc_step_kbytes(kbytes.min(c_int::MAX as usize) as c_int)?;
+    }
+    Ok(())
+}
+
+/// Forget memory released before the collector was told about it
+pub(crate) fn report_release(lua: &Lua, bytes: usize) {
+    if let Some(debt) = lua.app_data_ref::<ExternalDebt>() {
+        debt.0.set(debt.0.get().saturating_sub(bytes));
+    }
+}
 
-#[derive(Clone)]
 pub struct CData {
-    pub ctype: CType,
+    pub ctype: TypeHandle,
     pub ptr: *mut u8,
-    pub owned: bool,
     pub size: usize,
-    // Small buffer optimization: store small data inline
-    small_buffer: Option<Box<[u8; SMALL_BUFFER_SIZE]>>,
+    pub ownership: Ownership,
+    // Allocation owned by this cdata and released on drop
+    layout: Option<Layout>,
+    // Hooks the allocation came from, if not the global allocator
+    allocator: Option<AllocatorHooks>,
+    // Start and length of the buffer `ptr` points into when another cdata owns it, e.g. for a
+    // pointer cast from an array, so accesses through it are checked like those of the owner
+    bounds: Option<(usize, usize)>,
+    // Created by `ffi.out`, so `:get()` reads the value back
+    out_param: bool,
 }
 
+/// Who is responsible for the memory a cdata holds or points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ownership {
+    /// Allocated by `ffi.new` and released with the cdata
+    Lua,
+    /// Part of, or derived from, memory another cdata owns, e.g. a field view or `ffi.cast`
+    /// of an array; it must never be freed through this cdata
+    Borrowed,
+    /// Handed out by C code, e.g. a returned pointer; C decides when it is freed
+    Foreign,
+    /// Foreign memory whose finalizer from `ffi.gc` this cdata runs
+    Finalized,
+    /// Released early with `ffi.free`; the cdata reads as NULL and accessing it errors
+    Freed,
+}
+
+// Zeroed storage for owned cdata from the allocator hooks of the running Lua state, or else
+// the global allocator, fenced with canary bytes when the `canaries` feature is on
+fn allocate(layout: Layout, allocator: Option<AllocatorHooks>) -> *mut u8 {
+    if let Some(hooks) = allocator {
+        let ptr = unsafe { (hooks.alloc)(layout) };
+        if ptr.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        // Hooks may hand back recycled memory; elements a constructor leaves out read as zero
+        unsafe { std::ptr::write_bytes(ptr, 0, layout.size()) };
+        return ptr;
+    }
+    #[cfg(feature = "canaries")]
+    return crate::canary::alloc(layout);
+    #[cfg(not(feature = "canaries"))]
+    {
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        ptr
+    }
+}
+
+fn deallocate(ptr: *mut u8, layout: Layout, allocator: Option<AllocatorHooks>) {
+    if let Some(hooks) = allocator {
+        return unsafe { (hooks.dealloc)(ptr, layout) };
+    }
+    #[cfg(feature = "canaries")]
+    crate::canary::dealloc(ptr, layout);
+    #[cfg(not(feature = "canaries"))]
+    unsafe {
+        std::alloc::dealloc(ptr, layout)
+    };
+}
+
+// Addresses of foreign memory claimed by a finalizer, so no two cdata free the same block
+static FINALIZED: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+
 impl CData {
+    /// Allocate zeroed storage of `size` bytes, aligned for `ctype`
     #[inline]
-    pub fn new(ctype: CType, size: usize) -> Self {
-        // Use small buffer optimization for objects <= 64 bytes
-        if size <= SMALL_BUFFER_SIZE && size > 0 {
-            let mut buffer = Box::new([0u8; SMALL_BUFFER_SIZE]);
-            let ptr = buffer.as_mut_ptr();
-            Self {
-                ctype,
-                ptr,
-                owned: true,
-                size,
-                small_buffer: Some(buffer),
-            }
-        } else if size > 0 {
-            let layout = std::alloc::Layout::from_size_align(size, ctype.alignment())
-                .expect("Invalid layout");
-            // Use alloc instead of alloc_zeroed for better performance when initialization is not needed
-            let ptr = unsafe { std::alloc::alloc(layout) };
-            Self {
-                ctype,
-                ptr,
-                owned: true,
-                size,
-                small_buffer: None,
-            }
-        } else {
-            Self {
+    pub fn new(ctype: impl Into<TypeHandle>, size: usize) -> Self {
+        let ctype = ctype.into();
+        if size == 0 {
+            return Self {
                 ctype,
                 ptr: ptr::null_mut(),
-                owned: false,
                 size: 0,
-                small_buffer: None,
-            }
+                ownership: Ownership::Lua,
+                layout: None,
+                allocator: None,
+                bounds: None,
+                out_param: false,
+            };
+        }
+        let layout = Layout::from_size_align(size, ctype.alignment().max(1)).expect("Invalid layout");
+        let allocator = config::allocator();
+        Self {
+            ctype,
+            ptr: allocate(layout, allocator),
+            size,
+            ownership: Ownership::Lua,
+            layout: Some(layout),
+            allocator,
+            bounds: None,
+            out_param: false,
         }
     }
 
     pub fn new_null_ptr() -> Self {
         Self {
-            ctype: CType::Ptr(Box::new(CType::Void)),
+            ctype: CType::Ptr(Box::new(CType::Void)).into(),
             ptr: ptr::null_mut(),
-            owned: false,
             size: std::mem::size_of::<*const ()>(),
-            small_buffer: None,
+            ownership: Ownership::Foreign,
+            layout: None,
+            allocator: None,
+            bounds: None,
+            out_param: false,
         }
     }
 
+    /// Wrap memory at `ptr` that the cdata does not allocate itself
     #[inline]
-    pub fn from_ptr(ctype: CType, ptr: *mut u8, owned: bool) -> Self {
+    pub fn from_ptr(ctype: impl Into<TypeHandle>, ptr: *mut u8, ownership: Ownership) -> Self {
+        let ctype = ctype.into();
         let size = ctype.size();
-        Self {
-            ctype,
-            ptr,
-            owned,
-            size,
-            small_buffer: None,
+        Self { ctype, ptr, size, ownership, layout: None, allocator: None, bounds: None, out_param: false }
+    }
+
+    /// Ownership of a cdata derived from this one, e.g. by `ffi.cast`: foreign memory stays
+    /// foreign until a finalizer claims it, anything else is borrowed
+    pub fn derived_ownership(&self) -> Ownership {
+        match self.ownership {
+            Ownership::Foreign => Ownership::Foreign,
+            _ => Ownership::Borrowed,
         }
     }
 
+    /// Claim foreign memory for a finalizer, or give the claim back when `claim` is false
+    pub fn set_finalized(&mut self, claim: bool) -> LuaResult<()> {
+        let what = match self.ownership {
+            Ownership::Foreign if claim => {
+                if !self.ptr.is_null() && !FINALIZED.lock().unwrap().insert(self.ptr as usize) {
+                    return Err(LuaError::RuntimeError(
+                        "Memory already has a finalizer attached through another cdata".to_string(),
+                    ));
+                }
+                self.ownership = Ownership::Finalized;
+                return Ok(());
+            }
+            Ownership::Finalized if !claim => {
+                FINALIZED.lock().unwrap().remove(&(self.ptr as usize));
+                self.ownership = Ownership::Foreign;
+                return Ok(());
+            }
+            Ownership::Foreign | Ownership::Finalized => return Ok(()),
+            Ownership::Lua => "memory owned by Lua, which is freed automatically",
+            Ownership::Borrowed => "borrowed memory, which belongs to another cdata",
+            Ownership::Freed => "memory that has already been freed",
+        };
+        Err(LuaError::RuntimeError(format!("Cannot attach a finalizer to {}", what)))
+    }
+
+    // Whether `key` names a field or element of the cdata itself, which a metatype cannot override
+    fn has_member(&self, key: &LuaValue) -> bool {
+        match (self.ctype.resolved(), ffi_ops::integral(key.clone())) {
+            (CType::Struct(_, fields) | CType::Union(_, fields), LuaValue::String(name)) => {
+                fields.iter().any(|field| *name.as_bytes() == *field.name.as_bytes())
+            }
+            (CType::Array(_, _) | CType::Ptr(_) | CType::VLA(_), LuaValue::Integer(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Fail if the cdata has been released with `ffi.free`
+    #[inline]
+    pub fn check_live(&self) -> LuaResult<()> {
+        if self.ownership == Ownership::Freed {
+            let message = format!("Attempt to use freed '{}'", self.ctype);
+            return Err(FfiError::new(ErrorKind::UseAfterFree, message).with_type(&self.ctype).into());
+        }
+        Ok(())
+    }
+
+    /// Fail if the memory the cdata refers to is at NULL, before anything reads or writes it
+    #[inline]
+    pub fn check_not_null(&self) -> LuaResult<()> {
+        if self.ptr.is_null() {
+            let message = format!("Attempt to dereference a NULL '{}'", self.ctype);
+            return Err(FfiError::new(ErrorKind::NullPointer, message).with_type(&self.ctype).into());
+        }
+        Ok(())
+    }
+
+    /// Fail if writes past either end of the memory this cdata owns damaged its canaries
+    #[cfg(feature = "canaries")]
+    pub fn check_canaries(&self) -> LuaResult<()> {
+        match self.layout {
+            // Memory from allocator hooks has no canaries
+            Some(layout) if self.allocator.is_none() => {
+                crate::canary::check(self.ptr, layout).map_err(LuaError::RuntimeError)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Release owned memory and any finalizer claim now, leaving the cdata as a NULL that
+    /// refuses further access
+    pub fn release(&mut self) {
+        if let Some(layout) = self.layout.take() {
+            deallocate(self.ptr, layout, self.allocator);
+        }
+        if self.ownership == Ownership::Finalized {
+            FINALIZED.lock().unwrap().remove(&(self.ptr as usize));
+        }
+        self.ptr = ptr::null_mut();
+        self.size = 0;
+        self.bounds = None;
+        self.ownership = Ownership::Freed;
+    }
+
     #[inline]
     pub fn as_ptr(&self) -> *mut u8 {
         self.ptr
@@ -172,290 +461,629 @@ impl CData {
     pub fn is_null(&self) -> bool {
         self.ptr.is_null()
     }
+
+    /// Remember the buffer, as start address and length, that the cdata's address points into
+    pub fn with_bounds(mut self, bounds: (usize, usize)) -> Self {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    /// Mark a one-element array as an out-parameter from `ffi.out`, which has a `get` method
+    pub fn into_out_param(mut self) -> Self {
+        self.out_param = true;
+        self
+    }
+
+    /// Start and length of the memory of known size the cdata's address points into: its own
+    /// allocation, or the buffer of another cdata a view remembers
+    pub fn bounds(&self) -> Option<(usize, usize)> {
+        self.layout.map(|layout| (self.ptr as usize, layout.size())).or(self.bounds)
+    }
+
+    /// Bytes of known memory from the cdata's address on, when known
+    pub fn extent(&self) -> Option<usize> {
+        let (start, len) = self.bounds()?;
+        let address = self.ptr as usize;
+        Some(if address < start { 0 } else { (start + len).saturating_sub(address) })
+    }
+
+    /// Check that `len` bytes at `offset` lie inside the memory of known size the cdata's
+    /// address points into; memory the cdata merely points to has no known size and is not
+    /// checked
+    pub fn check_bounds(&self, offset: isize, len: usize) -> LuaResult<()> {
+        self.check_live()?;
+        let Some((start, size)) = self.bounds() else {
+            return Ok(());
+        };
+        let inside = (self.ptr as usize).checked_add_signed(offset).is_some_and(|address| {
+            address >= start && address.checked_add(len).is_some_and(|end| end <= start + size)
+        });
+        if !inside {
+            let message = format!(
+                "Access of {} bytes at offset {} is outside the {}-byte '{}' object",
+                len,
+                offset,
+                self.extent().unwrap_or(0),
+                self.ctype
+            );
+            return Err(FfiError::new(ErrorKind::OutOfBounds, message).with_type(&self.ctype).into());
+        }
+        Ok(())
+    }
+
+    // Address of element `index` of an array or pointer cdata, checked against array bounds
+    // and the cdata's own allocation
+    fn element(&self, index: i64, elem_type: &CType) -> LuaResult<*mut u8> {
+        self.check_not_null()?;
+        if let CType::Array(_, count) = self.ctype.resolved()
+            && *count > 0
+            && !(0..*count as i64).contains(&index)
+        {
+            return Err(LuaError::RuntimeError(format!(
+                "Index {} out of bounds for '{}'",
+                index, self.ctype
+            )));
+        }
+        let elem_size = elem_type.size();
+        let offset = (index as isize).wrapping_mul(elem_size as isize);
+        self.check_bounds(offset, elem_size)?;
+        Ok(self.ptr.wrapping_offset(offset))
+    }
+}
+
+// Owned cdata clone their storage; others share the memory they refer to
+impl Clone for CData {
+    fn clone(&self) -> Self {
+        let Some(layout) = self.layout else {
+            // A copy never inherits the claim of a finalizer
+            let ownership = match self.ownership {
+                Ownership::Finalized => Ownership::Borrowed,
+                ownership => ownership,
+            };
+            return Self { layout: None, ctype: self.ctype.clone(), ownership, ..*self };
+        };
+        let ptr = allocate(layout, self.allocator);
+        unsafe { ptr::copy_nonoverlapping(self.ptr, ptr, layout.size()) };
+        Self {
+            ctype: self.ctype.clone(),
+            ptr,
+            size: self.size,
+            ownership: Ownership::Lua,
+            layout: Some(layout),
+            allocator: self.allocator,
+            bounds: None,
+            out_param: self.out_param,
+        }
+    }
 }
 
 impl Drop for CData {
     fn drop(&mut self) {
-        // If we're using small_buffer, it will be dropped automatically
-        // Only deallocate if we're using heap-allocated memory
-        if self.owned && !self.ptr.is_null() && self.size > 0 && self.small_buffer.is_none() {
-            let layout = std::alloc::Layout::from_size_align(self.size, self.ctype.alignment())
-                .expect("Invalid layout");
-            unsafe {
-                std::alloc::dealloc(self.ptr, layout);
-            }
-        }
+        self.release();
     }
 }
 
 impl LuaUserData for CData {
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
-        methods.add_meta_method(
+        methods.add_meta_function(
             LuaMetaMethod::Index,
-            |_lua, this, key: LuaValue| match key {
-                LuaValue::String(s) => {
-                    let field_name = s.to_str()?;
-                    match &this.ctype {
-                        CType::Struct(_, fields) | CType::Union(_, fields) => {
-                            for field in fields {
-                                if field_name == field.name.as_str() {
-                                    let field_ptr = unsafe { this.ptr.add(field.offset) };
-                                    return read_ctype_value(_lua, field_ptr, &field.ctype);
-                                }
-                            }
-                            Err(LuaError::RuntimeError(format!(
-                                "Unknown field: {}",
-                                field_name
-                            )))
-                        }
-                        _ => Err(LuaError::RuntimeError("Not a struct or union".to_string())),
-                    }
-                }
-                LuaValue::Integer(i) => {
-                    match &this.ctype {
-                        CType::Array(elem_type, _) | CType::Ptr(elem_type) | CType::VLA(elem_type) => {
-                            let elem_size = elem_type.size();
-                            let offset = i as usize * elem_size;
-                            let elem_ptr = unsafe { this.ptr.add(offset) };
-                            read_ctype_value(_lua, elem_ptr, elem_type)
-                        }
-                        _ => Err(LuaError::RuntimeError(
-                            "Not an array or pointer".to_string(),
-                        )),
+            |lua, (ud, key): (LuaAnyUserData, LuaValue)| {
+                let _active = config::enter(lua);
+                let this = ud.borrow::<CData>()?;
+                this.check_live()?;
+                // Keys that are not members go to the metatype before any built-in method
+                if !this.has_member(&key)
+                    && let Some(handler) = ffi_ops::metamethod(lua, &this.ctype, "__index")?
+                {
+                    drop(this);
+                    let value = match handler {
+                        LuaValue::Function(handler) => return handler.call((ud, key)),
+                        LuaValue::Table(methods) => methods.get(key.clone())?,
+                        _ => LuaValue::Nil,
+                    };
+                    if !value.is_nil() {
+                        return Ok(value);
                     }
+                    return index_member(lua, &*ud.borrow::<CData>()?, key);
                 }
-                _ => Err(LuaError::RuntimeError("Invalid index type".to_string())),
+                index_member(lua, &this, key)
             },
         );
 
-        methods.add_meta_method_mut(
+        methods.add_meta_function(
             LuaMetaMethod::NewIndex,
-            |_lua, this, (key, value): (LuaValue, LuaValue)| {
-                match key {
-                    LuaValue::String(s) => {
-                        // Field assignment for structs/unions
-                        let field_name = s.to_str()?;
-                        match &this.ctype {
-                            CType::Struct(_, fields) | CType::Union(_, fields) => {
-                                for field in fields {
-                                    if field_name == field.name.as_str() {
-                                        let field_ptr = unsafe { this.ptr.add(field.offset) };
-                                        write_value_to_ptr(field_ptr, &field.ctype, value)?;
-                                        return Ok(());
-                                    }
-                                }
-                                Err(LuaError::RuntimeError(format!(
-                                    "Unknown field: {}",
-                                    field_name
-                                )))
-                            }
-                            _ => Err(LuaError::RuntimeError("Not a struct or union".to_string())),
+            |lua, (ud, key, value): (LuaAnyUserData, LuaValue, LuaValue)| {
+                let _active = config::enter(lua);
+                let this = ud.borrow::<CData>()?;
+                this.check_live()?;
+                if !this.has_member(&key)
+                    && let Some(handler) = ffi_ops::metamethod(lua, &this.ctype, "__newindex")?
+                {
+                    drop(this);
+                    return match handler {
+                        LuaValue::Function(handler) => handler.call((ud, key, value)),
+                        LuaValue::Table(target) => target.set(key, value),
+                        _ => Ok(()),
+                    };
+                }
+                new_index_member(lua, &this, key, value)
+            },
+        );
+
+        // Cdata compare by address, so `ptr == ffi.nullptr` tests for NULL, and scalars by
+        // value. Lua never consults __eq when one side is nil; use ffi.isnull for that
+        methods.add_meta_function(LuaMetaMethod::Eq, |lua, (a, b): (LuaAnyUserData, LuaAnyUserData)| {
+            let (a_value, b_value) = (LuaValue::UserData(a.clone()), LuaValue::UserData(b.clone()));
+            if let Some(handler) = arith::overload(lua, LuaMetaMethod::Eq, &[&a_value, &b_value])? {
+                return Ok(truthy(&handler.call((a, b))?));
+            }
+            if let Ok(equal) = arith::compare(Compare::Eq, &a_value, &b_value) {
+                return Ok(equal);
+            }
+            let address = |ud: &LuaAnyUserData| match ud.borrow::<CFunction>() {
+                Ok(func) => Some(func.ptr as *mut u8),
+                Err(_) => ud.borrow::<CData>().ok().map(|cdata| cdata.as_ptr()),
+            };
+            Ok(address(&a).is_some() && address(&a) == address(&b))
+        });
+
+        // Scalars and pointers mix with Lua numbers, e.g. `len < 4096`, `point.x * 2 + 1` or
+        // `ptr + 1`; other cdata use the operators of their metatype
+        for (metamethod, op) in [
+            (LuaMetaMethod::Add, Arith::Add),
+            (LuaMetaMethod::Sub, Arith::Sub),
+            (LuaMetaMethod::Mul, Arith::Mul),
+            (LuaMetaMethod::Div, Arith::Div),
+            (LuaMetaMethod::Mod, Arith::Mod),
+            (LuaMetaMethod::Pow, Arith::Pow),
+            #[cfg(any(feature = "lua53", feature = "lua54", feature = "luau"))]
+            (LuaMetaMethod::IDiv, Arith::IDiv),
+        ] {
+            methods.add_meta_function(metamethod, move |lua, (a, b): (LuaValue, LuaValue)| {
+                let _active = config::enter(lua);
+                match arith::overload(lua, metamethod, &[&a, &b])? {
+                    Some(handler) => handler.call((a, b)),
+                    None => arith::arith(lua, op, a, b),
+                }
+            });
+        }
+        methods.add_meta_function(LuaMetaMethod::Unm, |lua, value: LuaValue| {
+            let _active = config::enter(lua);
+            match arith::overload(lua, LuaMetaMethod::Unm, &[&value])? {
+                Some(handler) => handler.call((value.clone(), value)),
+                None => arith::negate(lua, value),
+            }
+        });
+        for (metamethod, op) in [(LuaMetaMethod::Lt, Compare::Lt), (LuaMetaMethod::Le, Compare::Le)] {
+            methods.add_meta_function(metamethod, move |lua, (a, b): (LuaValue, LuaValue)| {
+                match arith::overload(lua, metamethod, &[&a, &b])? {
+                    Some(handler) => Ok(truthy(&handler.call((a, b))?)),
+                    None => arith::compare(op, &a, &b),
+                }
+            });
+        }
+
+        methods.add_meta_function(LuaMetaMethod::Len, |lua, ud: LuaAnyUserData| {
+            let this = ud.borrow::<CData>()?;
+            match this.ctype.resolved() {
+                CType::Array(_, count) => Ok(LuaValue::Integer(*count as i64)),
+                CType::VLA(_) => {
+                    // VLA should have been converted to Array, this shouldn't happen
+                    Err(LuaError::RuntimeError("VLA must be instantiated with size".to_string()))
+                }
+                _ => match ffi_ops::metamethod(lua, &this.ctype, LuaMetaMethod::Len.name())? {
+                    Some(LuaValue::Function(handler)) => {
+                        drop(this);
+                        handler.call(ud)
+                    }
+                    _ => Err(LuaError::RuntimeError("Not an array".to_string())),
+                },
+            }
+        });
+
+        // Function pointers are callable once bound to an address
+        methods.add_meta_function(LuaMetaMethod::Call, |lua, (ud, args): (LuaAnyUserData, LuaMultiValue)| {
+            let _active = config::enter(lua);
+            let this = ud.borrow::<CData>()?;
+            match this.ctype.resolved() {
+                CType::Ptr(inner) if matches!(inner.resolved(), CType::Function(_, _)) => {
+                    call::call_function(lua, this.ptr as *mut libc::c_void, inner, CallConv::C, args)
+                }
+                CType::Function(_, _) => {
+                    call::call_function(lua, this.ptr as *mut libc::c_void, &this.ctype, CallConv::C, args)
+                }
+                // A metatype's __call may return several values
+                _ => match ffi_ops::metamethod(lua, &this.ctype, LuaMetaMethod::Call.name())? {
+                    Some(LuaValue::Function(handler)) => {
+                        drop(this);
+                        return handler.call((ud, args));
+                    }
+                    _ => Err(LuaError::RuntimeError("Not a function pointer".to_string())),
+                },
+            }
+            .and_then(|value| value.into_lua_multi(lua))
+        });
+    }
+}
+
+// Lua truthiness of a metamethod result
+fn truthy(value: &LuaValue) -> bool {
+    !matches!(value, LuaValue::Nil | LuaValue::Boolean(false))
+}
+
+// Fields, elements and built-in methods of a cdata
+fn index_member(lua: &Lua, this: &CData, key: LuaValue) -> LuaResult<LuaValue> {
+    match ffi_ops::integral(key) {
+        LuaValue::String(s) => {
+            let field_name = s.to_str()?;
+            match this.ctype.resolved() {
+                CType::Struct(_, fields) | CType::Union(_, fields) => {
+                    for (i, field) in fields.iter().enumerate() {
+                        if field_name == field.name.as_str() {
+                            this.check_not_null()?;
+                            let field_ptr = unsafe { this.ptr.add(field.offset) };
+                            return read_member(lua, field_ptr, this.ctype.member(i));
                         }
                     }
-                    LuaValue::Integer(i) => {
-                        // Array/pointer element assignment
-                        match &this.ctype {
-                            CType::Array(elem_type, _) | CType::Ptr(elem_type) | CType::VLA(elem_type) => {
-                                let elem_size = elem_type.size();
-                                let offset = i as usize * elem_size;
-                                let elem_ptr = unsafe { this.ptr.add(offset) };
-                                write_value_to_ptr(elem_ptr, elem_type, value)?;
-                                Ok(())
+                    // Fields shadow the `free` method, which goes with `ffi.free`
+                    if field_name == "free" && config::exported("free") {
+                        return free_method(lua);
+                    }
+                    Err(LuaError::RuntimeError(format!(
+                        "Unknown field: {}",
+                        field_name
+                    )))
+                }
+                CType::Ptr(inner) if matches!(inner.resolved(), CType::Function(_, _)) => {
+                    callback::method(lua, &field_name)
+                }
+                // One-element arrays from ffi.out read their value back with `:get()`
+                CType::Array(_, 1) if this.out_param && field_name == "get" => {
+                    let get = lua.create_function(|lua, cd: LuaAnyUserData| {
+                        let this = cd.borrow::<CData>()?;
+                        match this.ctype.resolved() {
+                            CType::Array(elem_type, _) if this.out_param => {
+                                this.check_live()?;
+                                this.check_not_null()?;
+                                this.check_bounds(0, elem_type.size())?;
+                                read_ctype_value(lua, this.ptr, elem_type)
                             }
-                            _ => Err(LuaError::RuntimeError(
-                                "Not an array or pointer".to_string(),
-                            )),
+                            _ => Err(LuaError::RuntimeError("Not an out-parameter".to_string())),
                         }
-                    }
-                    _ => Err(LuaError::RuntimeError("Invalid index type".to_string())),
+                    })?;
+                    Ok(LuaValue::Function(get))
                 }
-            },
-        );
+                _ if field_name == "free" && config::exported("free") => free_method(lua),
+                _ => Err(LuaError::RuntimeError("Not a struct or union".to_string())),
+            }
+        }
+        LuaValue::Integer(i) => {
+            match this.ctype.resolved() {
+                CType::Array(elem_type, _) | CType::Ptr(elem_type) | CType::VLA(elem_type) => {
+                    let elem_ptr = this.element(i, elem_type)?;
+                    read_member(lua, elem_ptr, this.ctype.member(0))
+                }
+                _ => Err(LuaError::RuntimeError(
+                    "Not an array or pointer".to_string(),
+                )),
+            }
+        }
+        _ => Err(LuaError::RuntimeError("Invalid index type".to_string())),
+    }
+}
 
-        methods.add_meta_method(LuaMetaMethod::Len, |_lua, this, ()| match &this.ctype {
-            CType::Array(_, count) => Ok(*count),
-            CType::VLA(_) => {
-                // VLA should have been converted to Array, this shouldn't happen
-                Err(LuaError::RuntimeError("VLA must be instantiated with size".to_string()))
+// Assign a field or element of a cdata
+fn new_index_member(lua: &Lua, this: &CData, key: LuaValue, value: LuaValue) -> LuaResult<()> {
+    match ffi_ops::integral(key) {
+        LuaValue::String(s) => {
+            // Field assignment for structs/unions
+            let field_name = s.to_str()?;
+            match this.ctype.resolved() {
+                CType::Struct(_, fields) | CType::Union(_, fields) => {
+                    for field in fields {
+                        if field_name == field.name.as_str() {
+                            if field.ctype.is_const() || this.ctype.is_const() {
+                                let message = format!("Cannot assign to const field: {}", field_name);
+                                return Err(FfiError::new(ErrorKind::ReadOnly, message).with_type(&this.ctype).into());
+                            }
+                            this.check_not_null()?;
+                            let field_ptr = unsafe { this.ptr.add(field.offset) };
+                            let value = callback::convert_function(lua, &field.ctype, value)?;
+                            ffi_ops::write_member_value(field_ptr, &field.ctype, value, &field.name)?;
+                            return Ok(());
+                        }
+                    }
+                    Err(LuaError::RuntimeError(format!(
+                        "Unknown field: {}",
+                        field_name
+                    )))
+                }
+                _ => Err(LuaError::RuntimeError("Not a struct or union".to_string())),
             }
-            _ => Err(LuaError::RuntimeError("Not an array".to_string())),
-        });
+        }
+        LuaValue::Integer(i) => {
+            // Array/pointer element assignment
+            match this.ctype.resolved() {
+                CType::Array(elem_type, _) | CType::Ptr(elem_type) | CType::VLA(elem_type) => {
+                    if elem_type.is_const() {
+                        let error = FfiError::new(ErrorKind::ReadOnly, "Cannot write through a pointer to const");
+                        return Err(error.with_type(&this.ctype).into());
+                    }
+                    // Elements of a const array are const, unlike the target of a const pointer
+                    if this.ctype.is_const() && !matches!(this.ctype.resolved(), CType::Ptr(_)) {
+                        let message = format!("Cannot assign to element of const '{}'", this.ctype);
+                        return Err(FfiError::new(ErrorKind::ReadOnly, message).with_type(&this.ctype).into());
+                    }
+                    let elem_ptr = this.element(i, elem_type)?;
+                    let value = callback::convert_function(lua, elem_type, value)?;
+                    ffi_ops::write_member_value(elem_ptr, elem_type, value, &format!("[{}]", i))?;
+                    Ok(())
+                }
+                _ => Err(LuaError::RuntimeError(
+                    "Not an array or pointer".to_string(),
+                )),
+            }
+        }
+        _ => Err(LuaError::RuntimeError("Invalid index type".to_string())),
     }
 }
 
+// `cd:free()`, the method form of `ffi.free`
+fn free_method(lua: &Lua) -> LuaResult<LuaValue> {
+    let free = lua.create_function(|lua, cd: LuaAnyUserData| ffi_ops::free_cdata(lua, cd))?;
+    Ok(LuaValue::Function(free))
+}
+
 pub struct CFunction {
-    _ptr: *mut libc::c_void,
+    pub ptr: *mut libc::c_void,
     pub name: String,
+    // Prototype and calling convention from cdef, if the function has been declared
+    pub ctype: Option<CType>,
+    pub convention: CallConv,
+    pub signals: Option<SignalHandling>,
 }
 
 impl LuaUserData for CFunction {
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
-        methods.add_meta_method(LuaMetaMethod::Call, |_lua, this, _args: LuaMultiValue| -> LuaResult<LuaValue> {
-            Err(LuaError::RuntimeError(format!(
-                "C function call not yet fully implemented for '{}'",
-                this.name
-            )))
+        methods.add_meta_method(LuaMetaMethod::Call, |lua, this, args: LuaMultiValue| {
+            let _active = config::enter(lua);
+            call::call_symbol(lua, this, args)
         });
     }
 }
 
 pub struct CLib {
+    // None for a library made only of registered host functions
     handle: Option<DynamicLibrary>,
-    _name: String,
+    name: String,
 }
 
 impl CLib {
     pub fn load(name: &str) -> Result<Self, String> {
-        let lib = DynamicLibrary::load(name)?;
+        let handle = if host::is_registered(name) { None } else { Some(DynamicLibrary::load(name)?) };
         Ok(Self {
-            handle: Some(lib),
-            _name: name.to_string(),
+            handle,
+            name: name.to_string(),
         })
     }
 
     pub fn load_default() -> Result<Self, String> {
-        let lib = DynamicLibrary::load_default()?;
+        let handle = match DynamicLibrary::load_default() {
+            Ok(lib) => Some(lib),
+            // Without dynamic linking, the default library only has registered host functions
+            Err(_) if cfg!(feature = "wasm") => None,
+            Err(e) => return Err(e),
+        };
         Ok(Self {
-            handle: Some(lib),
-            _name: "C".to_string(),
+            handle,
+            name: "C".to_string(),
         })
     }
 
     pub fn get_symbol(&self, name: &str) -> Option<*mut libc::c_void> {
-        self.handle.as_ref()?.get_symbol(name)
+        host::symbol(&self.name, name).or_else(|| self.handle.as_ref()?.get_symbol(name))
+    }
+
+    fn exported_symbols(&self) -> Vec<String> {
+        let mut exports = self.handle.as_ref().map(DynamicLibrary::exported_symbols).unwrap_or_default();
+        exports.extend(host::symbols(&self.name));
+        exports
+    }
+
+    /// Look up a C++ function by its demangled name, e.g. `ns::add(int, int)`
+    #[cfg(feature = "demangle")]
+    fn get_demangled_symbol(&self, name: &str) -> Option<*mut libc::c_void> {
+        let exports = self.exported_symbols();
+        self.get_symbol(symbols::find_demangled(&exports, name)?)
+    }
+
+    #[cfg(not(feature = "demangle"))]
+    fn get_demangled_symbol(&self, _name: &str) -> Option<*mut libc::c_void> {
+        None
+    }
+
+    // Address of `name`, also trying the decorated and demangled names it may be exported under
+    fn resolve(&self, name: &str, declaration: Option<&(CType, CallConv)>) -> Option<*mut libc::c_void> {
+        self.get_symbol(name)
+            .or_else(|| {
+                // 32-bit Windows exports stdcall/fastcall functions under decorated names
+                if !cfg!(all(windows, target_arch = "x86")) {
+                    return None;
+                }
+                let (ctype, convention) = declaration?;
+                self.get_symbol(&decorated_name(name, ctype, *convention)?)
+            })
+            .or_else(|| self.get_demangled_symbol(name))
+    }
+
+    /// Address of the symbol `name` as a pointer cdata: a pointer to the declared prototype if
+    /// `name` is a declared function, and `void*` otherwise
+    pub fn address(&self, name: &str) -> LuaResult<CData> {
+        let declaration = ffi_ops::lookup_symbol(name);
+        let ptr = self.resolve(name, declaration.as_ref()).ok_or_else(|| self.symbol_not_found(name))?;
+        let target = match declaration {
+            Some((function @ CType::Function(_, _), _)) => function,
+            _ => CType::Void,
+        };
+        Ok(CData::from_ptr(CType::Ptr(Box::new(target)), ptr as *mut u8, Ownership::Foreign))
+    }
+
+    // Result of the declared parameterless function `name` a library reports its version with,
+    // such as `const char *zlibVersion(void)`; strings are converted and NULL becomes nil
+    fn call_version_function(&self, lua: &Lua, name: &str) -> LuaResult<LuaValue> {
+        let declaration = ffi_ops::lookup_symbol(name);
+        let ptr = self.resolve(name, declaration.as_ref()).ok_or_else(|| self.symbol_not_found(name))?;
+        let (ctype, convention) = match declaration {
+            Some((ctype, convention)) if matches!(&ctype, CType::Function(_, params) if params.is_empty()) => {
+                (ctype, convention)
+            }
+            _ => {
+                let message = format!("Version symbol '{}' must be declared as a function without parameters", name);
+                return Err(FfiError::new(ErrorKind::Runtime, message).with_symbol(name).into());
+            }
+        };
+        let result = call::call_function(lua, ptr, &ctype, convention, LuaMultiValue::new())?;
+        match &result {
+            LuaValue::UserData(ud) if ud.borrow::<CData>().is_ok_and(|cdata| cdata.is_null()) => Ok(LuaValue::Nil),
+            LuaValue::UserData(ud) => match ffi_ops::cdata_to_string(lua, ud.clone(), None) {
+                Ok(version) => Ok(LuaValue::String(version)),
+                Err(_) => Ok(result),
+            },
+            _ => Ok(result),
+        }
+    }
+
+    // Error for a failed lookup, listing C++ symbols the name may have been mangled into
+    fn symbol_not_found(&self, name: &str) -> LuaError {
+        let exports = self.exported_symbols();
+        let candidates = symbols::cpp_candidates(&exports, name);
+        if candidates.is_empty() {
+            let hints = symbols::suggestions(name, exports.iter().map(String::as_str));
+            let message = format!("Symbol not found: {}{}", name, symbols::did_you_mean(&hints));
+            return FfiError::new(ErrorKind::SymbolNotFound, message).with_symbol(name).into();
+        }
+
+        let shown: Vec<String> = candidates.iter().take(5).map(|symbol| symbols::display_name(symbol)).collect();
+        let more = match candidates.len() - shown.len() {
+            0 => String::new(),
+            n => format!(" and {} more", n),
+        };
+        let message = format!("Symbol not found: {} (C++ candidates: {}{})", name, shown.join(", "), more);
+        FfiError::new(ErrorKind::SymbolNotFound, message).with_symbol(name).into()
+    }
+}
+
+// `lib:has(name)`, `lib:path()`, `lib:handle()` and `lib:version([symbol])`
+fn lib_method(lua: &Lua, name: &str) -> LuaResult<Option<LuaFunction>> {
+    let method = match name {
+        "has" => lua.create_function(|lua, (lib, name): (LuaUserDataRef<CLib>, String)| {
+            let _active = config::enter(lua);
+            Ok(lib.resolve(&name, ffi_ops::lookup_symbol(&name).as_ref()).is_some())
+        })?,
+        "path" => lua.create_function(|_, lib: LuaUserDataRef<CLib>| {
+            Ok(lib.handle.as_ref().and_then(DynamicLibrary::path))
+        })?,
+        "handle" => lua.create_function(|lua, lib: LuaUserDataRef<CLib>| match &lib.handle {
+            Some(handle) => {
+                let ctype = CType::Ptr(Box::new(CType::Void));
+                let handle = CData::from_ptr(ctype, handle.handle() as *mut u8, Ownership::Foreign);
+                lua.create_userdata(handle).map(LuaValue::UserData)
+            }
+            None => Ok(LuaValue::Nil),
+        })?,
+        "version" => lua.create_function(|lua, (lib, symbol): (LuaUserDataRef<CLib>, Option<String>)| {
+            let _active = config::enter(lua);
+            match symbol {
+                Some(symbol) => lib.call_version_function(lua, &symbol),
+                None => lib.handle.as_ref().and_then(DynamicLibrary::version).into_lua(lua),
+            }
+        })?,
+        _ => return Ok(None),
+    };
+    Ok(Some(method))
+}
+
+// Stand-in for an optional function `lib` lacks: calls raise a catchable error or return zero
+fn missing_stub(lua: &Lua, lib: &str, name: String, ctype: CType, missing: MissingSymbol) -> LuaResult<LuaFunction> {
+    let lib = lib.to_string();
+    lua.create_function(move |lua, _: LuaMultiValue| match missing {
+        MissingSymbol::Zero => call::zero_result(lua, &ctype),
+        MissingSymbol::Raise => {
+            let message = format!("Optional function '{}' is not available in library '{}'", name, lib);
+            Err(FfiError::new(ErrorKind::SymbolNotFound, message).with_symbol(&name).into())
+        }
+    })
+}
+
+/// Decorated symbol name of a stdcall (`_name@N`) or fastcall (`@name@N`) function,
+/// where N is the number of bytes of arguments, each padded to 4 bytes
+fn decorated_name(name: &str, ctype: &CType, convention: CallConv) -> Option<String> {
+    let CType::Function(_, params) = ctype.resolved() else {
+        return None;
+    };
+    let arg_bytes: usize = params.iter().map(|param| (param.size() + 3) & !3).sum();
+    match convention {
+        CallConv::C => None,
+        CallConv::Stdcall => Some(format!("_{}@{}", name, arg_bytes)),
+        CallConv::Fastcall => Some(format!("@{}@{}", name, arg_bytes)),
     }
 }
 
 impl LuaUserData for CLib {
     fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
         methods.add_meta_method(LuaMetaMethod::Index, |lua, this, name: String| {
-            if let Some(sym) = this.get_symbol(&name) {
+            let _active = config::enter(lua);
+            let declaration = ffi_ops::lookup_symbol(&name);
+            let sym = this.resolve(&name, declaration.as_ref());
+
+            if let Some(sym) = sym {
                 // Return a callable function wrapper
+                let (ctype, convention) = declaration.unzip();
                 let cfunc = CFunction {
-                    _ptr: sym,
-                    name: name.clone(),
+                    ptr: sym,
+                    ctype,
+                    convention: convention.unwrap_or_default(),
+                    signals: ffi_ops::lookup_signals(&name),
+                    name,
                 };
                 lua.create_userdata(cfunc)
-                    .map(|ud| LuaValue::UserData(ud))
+                    .map(LuaValue::UserData)
+            } else if let (Some((ctype, _)), Some(missing)) = (declaration, ffi_ops::lookup_optional(&name)) {
+                missing_stub(lua, &this.name, name, ctype, missing).map(LuaValue::Function)
+            } else if let Some(method) = lib_method(lua, &name)? {
+                // Symbols shadow the methods
+                Ok(LuaValue::Function(method))
+            } else if config::nil_for_missing_symbols() {
+                Ok(LuaValue::Nil)
             } else {
-                Err(LuaError::RuntimeError(format!(
-                    "Symbol not found: {}",
-                    name
-                )))
+                Err(this.symbol_not_found(&name))
             }
         });
     }
 }
 
-// Improved macro with better error messages
-macro_rules! write_numeric {
-    ($ptr:expr, $ty:ty, $value:expr) => {{
-        let val = match $value {
-            LuaValue::Integer(i) => i as $ty,
-            LuaValue::Number(n) => n as $ty,
-            _ => return Err(LuaError::RuntimeError(
-                format!("Expected number for {} type", stringify!($ty))
-            )),
-        };
-        *($ptr as *mut $ty) = val;
-    }};
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-// Improved write function with better type safety and error handling
-#[inline]
-fn write_value_to_ptr(ptr: *mut u8, ctype: &CType, value: LuaValue) -> LuaResult<()> {
-    unsafe {
-        match ctype {
-            // Basic integer types
-            CType::Int => write_numeric!(ptr, i32, value),
-            CType::UInt => write_numeric!(ptr, u32, value),
-            CType::Long => write_numeric!(ptr, isize, value),
-            CType::ULong => write_numeric!(ptr, usize, value),
-            CType::LongLong => write_numeric!(ptr, i64, value),
-            CType::ULongLong => write_numeric!(ptr, u64, value),
-            
-            // Character types
-            CType::Char => write_numeric!(ptr, i8, value),
-            CType::UChar => write_numeric!(ptr, u8, value),
-            
-            // Short types
-            CType::Short => write_numeric!(ptr, i16, value),
-            CType::UShort => write_numeric!(ptr, u16, value),
-            
-            // Fixed-width integer types
-            CType::Int8 => write_numeric!(ptr, i8, value),
-            CType::Int16 => write_numeric!(ptr, i16, value),
-            CType::Int32 => write_numeric!(ptr, i32, value),
-            CType::Int64 => write_numeric!(ptr, i64, value),
-            CType::UInt8 => write_numeric!(ptr, u8, value),
-            CType::UInt16 => write_numeric!(ptr, u16, value),
-            CType::UInt32 => write_numeric!(ptr, u32, value),
-            CType::UInt64 => write_numeric!(ptr, u64, value),
-            
-            // Size types
-            CType::SizeT => write_numeric!(ptr, usize, value),
-            CType::SSizeT => write_numeric!(ptr, isize, value),
-            
-            // Floating point types
-            CType::Float => write_numeric!(ptr, f32, value),
-            CType::Double => write_numeric!(ptr, f64, value),
-            
-            // Boolean type
-            CType::Bool => {
-                let val = match value {
-                    LuaValue::Boolean(b) => b,
-                    LuaValue::Integer(i) => i != 0,
-                    _ => return Err(LuaError::RuntimeError("Expected boolean or integer".to_string())),
-                };
-                *(ptr as *mut bool) = val;
-            }
-            
-            // POSIX types (Unix only)
-            #[cfg(unix)]
-            CType::InoT => write_numeric!(ptr, libc::ino_t, value),
-            #[cfg(unix)]
-            CType::DevT => write_numeric!(ptr, libc::dev_t, value),
-            #[cfg(unix)]
-            CType::GidT => write_numeric!(ptr, libc::gid_t, value),
-            #[cfg(unix)]
-            CType::ModeT => write_numeric!(ptr, libc::mode_t, value),
-            #[cfg(unix)]
-            CType::NlinkT => write_numeric!(ptr, libc::nlink_t, value),
-            #[cfg(unix)]
-            CType::UidT => write_numeric!(ptr, libc::uid_t, value),
-            #[cfg(unix)]
-            CType::OffT => write_numeric!(ptr, libc::off_t, value),
-            #[cfg(unix)]
-            CType::PidT => write_numeric!(ptr, libc::pid_t, value),
-            #[cfg(unix)]
-            CType::UsecondsT => write_numeric!(ptr, libc::useconds_t, value),
-            #[cfg(unix)]
-            CType::SusecondsT => write_numeric!(ptr, libc::suseconds_t, value),
-            #[cfg(unix)]
-            CType::BlksizeT => write_numeric!(ptr, libc::blksize_t, value),
-            #[cfg(unix)]
-            CType::BlkcntT => write_numeric!(ptr, libc::blkcnt_t, value),
-            #[cfg(unix)]
-            CType::TimeT => write_numeric!(ptr, libc::time_t, value),
-            
-            // Pointer type
-            CType::Ptr(_) => {
-                match value {
-                    LuaValue::Integer(i) => *(ptr as *mut usize) = i as usize,
-                    LuaValue::UserData(ud) => {
-                        let cdata = ud.borrow::<CData>()?;
-                        *(ptr as *mut *mut u8) = cdata.as_ptr();
-                    }
-                    _ => return Err(LuaError::RuntimeError(
-                        "Expected pointer value (integer or cdata)".to_string()
-                    )),
-                }
-            }
-            
-            _ => return Err(LuaError::RuntimeError(
-                format!("Cannot assign value to type: {:?}", ctype)
-            )),
-        }
+    #[test]
+    fn test_decorated_name() {
+        let ctype = CType::Function(
+            Box::new(CType::Int),
+            vec![CType::Ptr(Box::new(CType::Void)), CType::UInt, CType::Char, CType::Double],
+        );
+        let ptr_size = std::mem::size_of::<*const ()>();
+        let arg_bytes = ((ptr_size + 3) & !3) + 4 + 4 + 8;
+
+        assert_eq!(decorated_name("Sleep", &ctype, CallConv::C), None);
+        assert_eq!(
+            decorated_name("Sleep", &ctype, CallConv::Stdcall),
+            Some(format!("_Sleep@{}", arg_bytes))
+        );
+        assert_eq!(
+            decorated_name("Sleep", &ctype, CallConv::Fastcall),
+            Some(format!("@Sleep@{}", arg_bytes))
+        );
+        assert_eq!(decorated_name("Sleep", &CType::Int, CallConv::Stdcall), None);
     }
-    Ok(())
 }
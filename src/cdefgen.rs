@@ -0,0 +1,261 @@
+// Generate `ffi.cdef` declarations from C headers at build time
+
+use std::path::{Path, PathBuf};
+
+use crate::parser::{self, CdefOptions};
+
+/// Declarations generated from C headers, ready to be embedded and passed to `luaffi::cdef`
+#[derive(Debug, Clone, Default)]
+pub struct Bindings {
+    /// Declarations luaffi understands, one per line
+    pub cdef: String,
+    /// Declarations that were left out, with the reason
+    pub skipped: Vec<String>,
+}
+
+impl Bindings {
+    /// Write the declarations to `path`, e.g. in `OUT_DIR` for `include_str!`
+    pub fn write(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, &self.cdef)
+    }
+}
+
+/// Runs the C preprocessor over headers and keeps the declarations they make themselves (not
+/// those of the headers they include), so a `build.rs` can generate a module's cdefs:
+///
+/// ```ignore
+/// let bindings = luaffi::cdefgen::Builder::new().header("mylib.h").allow("mylib_").generate()?;
+/// bindings.write(Path::new(&env::var("OUT_DIR")?).join("mylib.cdef"))?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    headers: Vec<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+    defines: Vec<(String, Option<String>)>,
+    allow: Vec<String>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a header whose declarations are generated
+    pub fn header(mut self, path: impl AsRef<Path>) -> Self {
+        self.headers.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add a directory to the include path
+    pub fn include(mut self, dir: impl AsRef<Path>) -> Self {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Define a preprocessor macro
+    pub fn define(mut self, name: &str, value: Option<&str>) -> Self {
+        self.defines.push((name.to_string(), value.map(str::to_string)));
+        self
+    }
+
+    /// Only keep declarations of names starting with `prefix`. Without any, everything the
+    /// headers declare is kept
+    pub fn allow(mut self, prefix: &str) -> Self {
+        self.allow.push(prefix.to_string());
+        self
+    }
+
+    /// Preprocess the headers with the C compiler `cc` finds and collect their declarations
+    pub fn generate(&self) -> Result<Bindings, String> {
+        let mut source = String::new();
+        let mut defines = String::new();
+        for header in &self.headers {
+            let path = header.canonicalize().map_err(|e| format!("{}: {}", header.display(), e))?;
+            source.push_str(&format!("#include \"{}\"\n", path.display()));
+            let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            defines.push_str(&constant_defines(&text, &self.allow));
+        }
+
+        let dir = std::env::temp_dir().join(format!("luaffi-cdefgen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let wrapper = dir.join("cdefgen.c");
+        std::fs::write(&wrapper, source).map_err(|e| e.to_string())?;
+
+        let mut build = cc::Build::new();
+        build.file(&wrapper).cargo_metadata(false).warnings(false);
+        // Outside a build script, preprocess for the target luaffi was built for
+        if std::env::var_os("TARGET").is_none() {
+            build.target(env!("LUAFFI_TARGET")).host(env!("LUAFFI_TARGET")).opt_level(0);
+        }
+        for dir in &self.include_dirs {
+            build.include(dir);
+        }
+        for (name, value) in &self.defines {
+            build.define(name, value.as_deref());
+        }
+        let expanded = build.try_expand().map_err(|e| e.to_string());
+        let _ = std::fs::remove_dir_all(&dir);
+        let expanded = String::from_utf8_lossy(&expanded?).into_owned();
+
+        let mut bindings = scan(&expanded, &self.headers, &self.allow);
+        bindings.cdef.push_str(&defines);
+        Ok(bindings)
+    }
+}
+
+/// Extract the declarations of preprocessed C `source` that come from one of `headers`,
+/// keeping those of names starting with a prefix in `allow` (all if it is empty). Each kept
+/// declaration is checked with the cdef parser, so the result always loads
+pub fn scan(source: &str, headers: &[PathBuf], allow: &[String]) -> Bindings {
+    let names: Vec<String> = headers
+        .iter()
+        .filter_map(|header| Some(header.file_name()?.to_string_lossy().into_owned()))
+        .collect();
+
+    // Line markers (`# 12 "file.h"` or `#line 12 "file.h"`) say where the text came from
+    let mut own = String::new();
+    let mut in_header = false;
+    for line in source.lines() {
+        let trimmed = line.trim_start();
+        if let Some(marker) = trimmed.strip_prefix('#') {
+            if let Some(file) = marker.split('"').nth(1) {
+                let file = file.replace("\\\\", "/").replace('\\', "/");
+                in_header = names.iter().any(|name| file.rsplit('/').next() == Some(name.as_str()));
+            }
+            continue;
+        }
+        if in_header {
+            own.push_str(line);
+            own.push('\n');
+        }
+    }
+
+    let mut bindings = Bindings::default();
+    let strict = CdefOptions { strict: true, ..Default::default() };
+    for declaration in declarations(&own) {
+        let declaration = declaration.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(name) = declared_name(&declaration)
+            && !allow.is_empty()
+            && !allow.iter().any(|prefix| name.starts_with(prefix.as_str()))
+        {
+            continue;
+        }
+        match parser::parse_cdef(&declaration, strict) {
+            Ok(()) => {
+                bindings.cdef.push_str(&declaration);
+                bindings.cdef.push('\n');
+            }
+            Err(e) => bindings.skipped.push(format!("{}: {}", declaration, e)),
+        }
+    }
+    bindings
+}
+
+// Top-level declarations, without function definitions
+fn declarations(source: &str) -> Vec<String> {
+    let mut declarations = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    let mut body = false;
+    for c in source.chars() {
+        match c {
+            '{' | '(' | '[' => {
+                // A brace right after a parameter list opens a function body
+                if c == '{' && depth == 0 && current.trim_end().ends_with(')') {
+                    body = true;
+                }
+                depth += 1;
+            }
+            '}' | ')' | ']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if !body {
+            current.push(c);
+        }
+        if depth == 0 && (c == ';' || (body && c == '}')) {
+            if !body && !current.trim().is_empty() {
+                declarations.push(current.trim().to_string());
+            }
+            current.clear();
+            body = false;
+        }
+    }
+    declarations
+}
+
+// Name declared by a declaration: the function, variable or typedef name, or the tag of a
+// lone `struct X { ... };`
+fn declared_name(declaration: &str) -> Option<String> {
+    // Skip the bodies of structs, unions and enums, and attributes
+    let mut outer = String::new();
+    let mut depth = 0;
+    for c in declaration.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth == 0 => outer.push(c),
+            _ => {}
+        }
+    }
+    for word in ["__attribute__", "__attribute", "__declspec", "__asm__", "__asm"] {
+        while let Some(start) = outer.find(word) {
+            let end = balanced_end(&outer[start + word.len()..]).map_or(start + word.len(), |end| start + word.len() + end);
+            outer.replace_range(start..end, " ");
+        }
+    }
+
+    let outer = outer.trim_end_matches(';');
+    let head = match outer.find('(') {
+        // `int (*name)(...)` or `int name(...)`
+        Some(open) if outer[open + 1..].trim_start().starts_with(['*', '^']) => {
+            &outer[open + 1..open + 1 + outer[open + 1..].find(')')?]
+        }
+        Some(open) => &outer[..open],
+        None => outer.split('[').next()?,
+    };
+    let name = head.trim_end().rsplit(|c: char| !(c.is_alphanumeric() || c == '_')).next()?;
+    match name {
+        "" | "struct" | "union" | "enum" => None,
+        name => Some(name.to_string()),
+    }
+}
+
+// Length of a leading parenthesized group, e.g. `((packed))`
+fn balanced_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return Some(i + 1),
+            ')' => depth -= 1,
+            c if depth == 0 && !c.is_whitespace() => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+// `#define NAME value` lines of allowed names; the cdef parser keeps those with constant values
+fn constant_defines(header: &str, allow: &[String]) -> String {
+    let mut defines = String::new();
+    for line in header.lines() {
+        let Some(rest) = line.trim_start().strip_prefix('#') else {
+            continue;
+        };
+        let Some(rest) = rest.trim_start().strip_prefix("define") else {
+            continue;
+        };
+        let mut parts = rest.trim().splitn(2, char::is_whitespace);
+        let (Some(name), Some(value)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let is_object_like = name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !is_object_like || value.trim().is_empty() || value.trim_end().ends_with('\\') {
+            continue;
+        }
+        if allow.is_empty() || allow.iter().any(|prefix| name.starts_with(prefix.as_str())) {
+            defines.push_str(&format!("#define {} {}\n", name, value.trim()));
+        }
+    }
+    defines
+}
@@ -0,0 +1,217 @@
+// Per-state configuration of the module, for embedders building it with `lua_module_with_config`
+
+use std::alloc::Layout;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use mlua::prelude::*;
+
+use crate::error::{ErrorKind, FfiError};
+pub use crate::ffi_ops::Registry;
+
+/// Which libraries `ffi.load` may open
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum SandboxPolicy {
+    /// Any library the dynamic loader finds
+    #[default]
+    Unrestricted,
+    /// None; `ffi.load` always fails and only `ffi.C` is available
+    NoLoad,
+    /// Only these names, compared with the argument of `ffi.load` as written
+    Allow(Vec<String>),
+}
+
+impl SandboxPolicy {
+    fn permits(&self, name: &str) -> bool {
+        match self {
+            SandboxPolicy::Unrestricted => true,
+            SandboxPolicy::NoLoad => false,
+            SandboxPolicy::Allow(names) => names.iter().any(|allowed| allowed == name),
+        }
+    }
+}
+
+/// Replacement for the global allocator behind the memory cdata own, e.g. to account for it
+/// or to place it in an arena. Memory from `alloc` must be aligned for the layout, and null
+/// when it cannot be provided (it is zeroed before use); `dealloc` receives the layout it was
+/// allocated with
+#[derive(Clone, Copy, Debug)]
+pub struct AllocatorHooks {
+    pub alloc: unsafe fn(Layout) -> *mut u8,
+    pub dealloc: unsafe fn(*mut u8, Layout),
+}
+
+/// How the module behaves in one Lua state. `FfiConfig::default()` is what `lua_module` uses
+#[derive(Clone, Debug, Default)]
+pub struct FfiConfig {
+    safe: bool,
+    sandbox: SandboxPolicy,
+    default_library: Option<String>,
+    allocator: Option<AllocatorHooks>,
+    registry: Option<Registry>,
+    omitted: Vec<String>,
+    nil_for_missing_symbols: bool,
+    // Toggled at run time by `ffi.strictconv` and `ffi.strictalign`
+    strict_conversions: Cell<bool>,
+    strict_alignment: Cell<bool>,
+}
+
+impl FfiConfig {
+    /// In safe mode, cdata cannot be made to point at arbitrary addresses: casting a nonzero
+    /// number to a pointer, writing one into a pointer and undumping pointers all fail
+    pub fn safe(mut self, safe: bool) -> Self {
+        self.safe = safe;
+        self
+    }
+
+    pub fn sandbox(mut self, policy: SandboxPolicy) -> Self {
+        self.sandbox = policy;
+        self
+    }
+
+    /// Library `ffi.C` binds to instead of the process's own symbols
+    pub fn default_library(mut self, name: impl Into<String>) -> Self {
+        self.default_library = Some(name.into());
+        self
+    }
+
+    pub fn allocator(mut self, hooks: AllocatorHooks) -> Self {
+        self.allocator = Some(hooks);
+        self
+    }
+
+    /// Keep the declarations of `ffi.cdef` in `registry` instead of the process-wide one.
+    /// Lookups still see what `luaffi::cdef` declared globally
+    pub fn registry(mut self, registry: Registry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Make `lib.name` evaluate to nil rather than raise when a library has no symbol `name`,
+    /// so optional functions can be probed without `pcall`
+    pub fn nil_for_missing_symbols(mut self, enabled: bool) -> Self {
+        self.nil_for_missing_symbols = enabled;
+        self
+    }
+
+    /// Start with strict numeric conversions on, as after `ffi.strictconv(true)`
+    pub fn strict_conversions(self, enabled: bool) -> Self {
+        self.strict_conversions.set(enabled);
+        self
+    }
+
+    /// Start with alignment checks of casts on, as after `ffi.strictalign(true)`
+    pub fn strict_alignment(self, enabled: bool) -> Self {
+        self.strict_alignment.set(enabled);
+        self
+    }
+
+    /// Leave these functions out of the module, e.g. `["load", "C", "cast"]` for a plugin host.
+    /// Without `C` no default library is opened, and without `free` cdata have no `:free()`
+    pub fn without(mut self, names: &[&str]) -> Self {
+        self.omitted.extend(names.iter().map(|name| name.to_string()));
+        self
+    }
+
+    pub(crate) fn omitted(&self) -> &[String] {
+        &self.omitted
+    }
+
+    pub(crate) fn exports(&self, name: &str) -> bool {
+        !self.omitted.iter().any(|omitted| omitted == name)
+    }
+
+    pub(crate) fn library(&self) -> Option<&str> {
+        self.default_library.as_deref()
+    }
+}
+
+// The configuration of a Lua state, kept as its app data
+struct StateConfig(Rc<FfiConfig>);
+
+pub(crate) fn install(lua: &Lua, config: FfiConfig) {
+    lua.set_app_data(StateConfig(Rc::new(config)));
+}
+
+thread_local! {
+    // Configuration of the Lua state whose FFI function or metamethod is running on this thread
+    static ACTIVE: RefCell<Option<Rc<FfiConfig>>> = const { RefCell::new(None) };
+}
+
+/// Restores the previously active configuration when dropped
+pub(crate) struct Active(Option<Rc<FfiConfig>>);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        ACTIVE.set(self.0.take());
+    }
+}
+
+/// Make the configuration of `lua` apply to this thread until the guard is dropped. Every
+/// entry point from Lua calls this, since the code below it has no Lua state at hand
+pub(crate) fn enter(lua: &Lua) -> Active {
+    let config = lua.app_data_ref::<StateConfig>().map(|config| config.0.clone());
+    Active(ACTIVE.replace(config))
+}
+
+fn with_active<R>(f: impl FnOnce(&FfiConfig) -> R, default: R) -> R {
+    ACTIVE.with_borrow(|config| config.as_deref().map_or(default, f))
+}
+
+/// Whether the module of the running state has the function `name`
+pub(crate) fn exported(name: &str) -> bool {
+    with_active(|config| config.exports(name), true)
+}
+
+pub(crate) fn nil_for_missing_symbols() -> bool {
+    with_active(|config| config.nil_for_missing_symbols, false)
+}
+
+/// Whether numeric writes in the running state reject values the target type cannot
+/// represent exactly instead of truncating them
+pub(crate) fn strict_conversions() -> bool {
+    with_active(|config| config.strict_conversions.get(), false)
+}
+
+/// Turn strict numeric conversions of `lua` on or off, returning the previous setting
+pub(crate) fn set_strict_conversions(lua: &Lua, enabled: bool) -> bool {
+    lua.app_data_ref::<StateConfig>().is_some_and(|config| config.0.strict_conversions.replace(enabled))
+}
+
+/// Whether casts in the running state reject addresses misaligned for the type they would be
+/// accessed as
+pub(crate) fn strict_alignment() -> bool {
+    with_active(|config| config.strict_alignment.get(), false)
+}
+
+/// Turn alignment checks of casts in `lua` on or off, returning the previous setting
+pub(crate) fn set_strict_alignment(lua: &Lua, enabled: bool) -> bool {
+    lua.app_data_ref::<StateConfig>().is_some_and(|config| config.0.strict_alignment.replace(enabled))
+}
+
+pub(crate) fn allocator() -> Option<AllocatorHooks> {
+    with_active(|config| config.allocator, None)
+}
+
+/// Registry of the running state, when it has one of its own
+pub(crate) fn registry() -> Option<Registry> {
+    with_active(|config| config.registry.clone(), None)
+}
+
+/// Fail in safe mode when a pointer would be made from the number `address`
+pub(crate) fn check_address(address: usize) -> LuaResult<()> {
+    if address != 0 && with_active(|config| config.safe, false) {
+        let message = format!("Cannot make a pointer from address 0x{:x} in safe mode", address);
+        return Err(FfiError::new(ErrorKind::NotPermitted, message).into());
+    }
+    Ok(())
+}
+
+/// Fail when the sandbox policy of the running state does not let `ffi.load` open `name`
+pub(crate) fn check_load(name: &str) -> LuaResult<()> {
+    if !with_active(|config| config.sandbox.permits(name), true) {
+        let message = format!("Loading library '{}' is not permitted", name);
+        return Err(FfiError::new(ErrorKind::NotPermitted, message).into());
+    }
+    Ok(())
+}
@@ -1,4 +1,8 @@
+use std::fmt;
+use std::ffi::c_long;
 use std::mem::{align_of, size_of};
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
 
 /// C type representation with size and alignment information
 #[derive(Debug, Clone, PartialEq)]
@@ -69,8 +73,23 @@ pub enum CType {
     VLA(Box<CType>), // Variable Length Array - size determined at runtime
     Struct(String, Vec<CField>),
     Union(String, Vec<CField>),
+    Enum(String, Vec<(String, i64)>, Box<CType>), // Enumerators in declaration order and the underlying integer type
     Function(Box<CType>, Vec<CType>),
+    VarArgs, // Trailing `...` in a function parameter list
     Typedef(String, Box<CType>),
+    Const(Box<CType>), // const-qualified type, read-only through cdata
+    Packed(usize, Box<CType>), // Struct or union with member alignment capped at N bytes
+    Bitfield(Box<CType>, usize, usize), // Bit-field member: bit offset from the field offset and width in bits
+    Swapped(Box<CType>), // Integer stored in the opposite byte order to the host's
+}
+
+/// Calling convention of a declared function; only 32-bit Windows distinguishes them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CallConv {
+    #[default]
+    C,
+    Stdcall,
+    Fastcall,
 }
 
 /// Struct/union field with name, type and offset
@@ -82,27 +101,33 @@ pub struct CField {
 }
 
 impl CType {
-    /// Get the alignment requirement for this type
+    /// Get the alignment requirement for this type. Types whose alignment varies between
+    /// targets (`long`, `long long` and `double` are 4-byte aligned on 32-bit x86) take the
+    /// target's own
     #[inline]
     pub fn alignment(&self) -> usize {
         match self {
-            CType::Bool => align_of::<bool>(),
-            CType::Char | CType::UChar | CType::Int8 | CType::UInt8 => 1,
-            CType::Short | CType::UShort | CType::Int16 | CType::UInt16 => 2,
-            CType::Int | CType::UInt | CType::Int32 | CType::UInt32 | CType::Float => 4,
-            CType::Long | CType::ULong | CType::LongLong | CType::ULongLong 
-            | CType::Int64 | CType::UInt64 | CType::Double => 8,
+            // C `_Bool` is one byte
+            CType::Bool | CType::Char | CType::UChar | CType::Int8 | CType::UInt8 => 1,
+            CType::Short | CType::UShort | CType::Int16 | CType::UInt16 => align_of::<i16>(),
+            CType::Int | CType::UInt | CType::Int32 | CType::UInt32 => align_of::<i32>(),
+            CType::Long | CType::ULong => align_of::<c_long>(),
+            CType::LongLong | CType::ULongLong | CType::Int64 | CType::UInt64 => align_of::<i64>(),
+            CType::Float => align_of::<f32>(),
+            CType::Double => align_of::<f64>(),
             CType::SizeT | CType::SSizeT => align_of::<usize>(),
-            CType::Void => 1,
+            CType::Void | CType::VarArgs => 1,
             CType::Ptr(_) | CType::Function(_, _) => align_of::<*const ()>(),
-            CType::Array(inner, _) | CType::VLA(inner) | CType::Typedef(_, inner) => inner.alignment(),
+            CType::Array(inner, _) | CType::VLA(inner) | CType::Typedef(_, inner) | CType::Const(inner)
+            | CType::Enum(_, _, inner) | CType::Bitfield(inner, _, _) | CType::Swapped(inner) => inner.alignment(),
             CType::Struct(_, fields) | CType::Union(_, fields) => fields
                 .iter()
                 .map(|f| f.ctype.alignment())
                 .max()
                 .unwrap_or(1),
+            CType::Packed(pack, inner) => inner.alignment().min(*pack),
             #[cfg(unix)]
-            _ => 8,
+            _ => self.posix_integer().map_or(1, |(_, align, _)| align),
         }
     }
 
@@ -110,20 +135,23 @@ impl CType {
     #[inline]
     pub fn size(&self) -> usize {
         match self {
-            CType::Bool => size_of::<bool>(),
-            CType::Char | CType::UChar | CType::Int8 | CType::UInt8 => 1,
+            CType::Bool | CType::Char | CType::UChar | CType::Int8 | CType::UInt8 => 1,
             CType::Short | CType::UShort | CType::Int16 | CType::UInt16 => 2,
             CType::Int | CType::UInt | CType::Int32 | CType::UInt32 => 4,
-            CType::Long | CType::ULong => size_of::<isize>(),
+            CType::Enum(_, _, repr) => repr.size(),
+            // `long` is 4 bytes on 64-bit Windows and all 32-bit targets
+            CType::Long | CType::ULong => size_of::<c_long>(),
             CType::LongLong | CType::ULongLong | CType::Int64 | CType::UInt64 => 8,
             CType::SizeT | CType::SSizeT => size_of::<usize>(),
             #[cfg(unix)]
-            CType::InoT | CType::DevT | CType::GidT | CType::ModeT | CType::NlinkT 
-            | CType::UidT | CType::OffT | CType::PidT | CType::UsecondsT 
-            | CType::SusecondsT | CType::BlksizeT | CType::BlkcntT | CType::TimeT => 8,
+            CType::InoT | CType::DevT | CType::GidT | CType::ModeT | CType::NlinkT
+            | CType::UidT | CType::OffT | CType::PidT | CType::UsecondsT
+            | CType::SusecondsT | CType::BlksizeT | CType::BlkcntT | CType::TimeT => {
+                self.posix_integer().map_or(0, |(size, _, _)| size)
+            }
             CType::Float => 4,
             CType::Double => 8,
-            CType::Void => 0,
+            CType::Void | CType::VarArgs => 0,
             CType::Ptr(_) | CType::Function(_, _) => size_of::<*const ()>(),
             CType::Array(inner, count) => inner.size() * count,
             CType::VLA(_) => 0, // Size unknown at type definition time
@@ -141,8 +169,428 @@ impl CType {
                 let align = self.alignment();
                 (max_end + align - 1) & !(align - 1)
             }
-            CType::Union(_, fields) => fields.iter().map(|f| f.ctype.size()).max().unwrap_or(0),
-            CType::Typedef(_, inner) => inner.size(),
+            CType::Union(_, fields) => {
+                let size = fields.iter().map(|f| f.ctype.size()).max().unwrap_or(0);
+                size.next_multiple_of(self.alignment())
+            }
+            CType::Typedef(_, inner) | CType::Const(inner) | CType::Swapped(inner) => inner.size(),
+            // Bytes spanned by the bits, which may share storage with neighbouring bit-fields
+            CType::Bitfield(_, shift, width) => (shift + width).div_ceil(8),
+            // Packed members end without the natural trailing padding
+            CType::Packed(_, inner) => match inner.as_ref() {
+                CType::Struct(_, fields) | CType::Union(_, fields) => {
+                    let end = fields.iter().map(|f| f.offset + f.ctype.size()).max().unwrap_or(0);
+                    end.next_multiple_of(self.alignment())
+                }
+                inner => inner.size(),
+            },
+        }
+    }
+
+    /// Size, alignment and signedness of a POSIX typedef, as defined by the target's libc
+    #[cfg(unix)]
+    pub fn posix_integer(&self) -> Option<(usize, usize, bool)> {
+        fn of<T: TryFrom<i8>>() -> (usize, usize, bool) {
+            (size_of::<T>(), align_of::<T>(), T::try_from(-1).is_ok())
+        }
+        Some(match self {
+            CType::InoT => of::<libc::ino_t>(),
+            CType::DevT => of::<libc::dev_t>(),
+            CType::GidT => of::<libc::gid_t>(),
+            CType::ModeT => of::<libc::mode_t>(),
+            CType::NlinkT => of::<libc::nlink_t>(),
+            CType::UidT => of::<libc::uid_t>(),
+            CType::OffT => of::<libc::off_t>(),
+            CType::PidT => of::<libc::pid_t>(),
+            CType::UsecondsT => of::<libc::useconds_t>(),
+            CType::SusecondsT => of::<libc::suseconds_t>(),
+            CType::BlksizeT => of::<libc::blksize_t>(),
+            CType::BlkcntT => of::<libc::blkcnt_t>(),
+            CType::TimeT => of::<libc::time_t>(),
+            _ => return None,
+        })
+    }
+
+    /// Strip typedef wrappers, qualifiers and packing down to the underlying type
+    #[inline]
+    pub fn resolved(&self) -> &CType {
+        match self {
+            CType::Typedef(_, inner) | CType::Const(inner) | CType::Packed(_, inner) => inner.resolved(),
+            ctype => ctype,
+        }
+    }
+
+    /// Strip a top-level const qualifier
+    #[inline]
+    pub fn unqualified(&self) -> &CType {
+        match self {
+            CType::Const(inner) => inner,
+            ctype => ctype,
+        }
+    }
+
+    /// Check whether this is `wchar_t`, possibly qualified or behind further typedefs
+    pub fn is_wchar(&self) -> bool {
+        match self {
+            CType::Typedef(name, inner) => name == "wchar_t" || inner.is_wchar(),
+            CType::Const(inner) => inner.is_wchar(),
+            _ => false,
+        }
+    }
+
+    /// The platform's `wchar_t`: UTF-16 units on Windows, UTF-32 code points elsewhere
+    pub fn wchar() -> CType {
+        let repr = if cfg!(windows) {
+            CType::UInt16
+        } else if cfg!(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "arm"))) {
+            CType::UInt32
+        } else {
+            CType::Int32
+        };
+        CType::Typedef("wchar_t".to_string(), Box::new(repr))
+    }
+
+    /// The fixed byte-order integer types, e.g. `le_uint32` or `be_int16`. Those in the host's
+    /// own order are plain typedefs; the others are stored byte-swapped
+    pub fn byte_order(name: &str) -> Option<CType> {
+        let (big, integer) = match name.split_at_checked(3)? {
+            ("le_", integer) => (false, integer),
+            ("be_", integer) => (true, integer),
+            _ => return None,
+        };
+        let repr = match integer {
+            "int16" => CType::Int16,
+            "int32" => CType::Int32,
+            "int64" => CType::Int64,
+            "uint16" => CType::UInt16,
+            "uint32" => CType::UInt32,
+            "uint64" => CType::UInt64,
+            _ => return None,
+        };
+        let repr = if big == cfg!(target_endian = "big") { repr } else { CType::Swapped(Box::new(repr)) };
+        Some(CType::Typedef(name.to_string(), Box::new(repr)))
+    }
+
+    /// Check whether values of this type are read-only, looking through typedefs
+    pub fn is_const(&self) -> bool {
+        match self {
+            CType::Const(_) => true,
+            CType::Typedef(_, inner) | CType::Bitfield(inner, _, _) => inner.is_const(),
+            _ => false,
+        }
+    }
+
+    /// The type of a read-only view of a value of this type: a pointer to const for a pointer,
+    /// the const-qualified type for anything else
+    pub fn read_only(&self) -> CType {
+        let constant = |ctype: &CType| {
+            if ctype.is_const() { ctype.clone() } else { CType::Const(Box::new(ctype.clone())) }
+        };
+        match self {
+            CType::Ptr(inner) => CType::Ptr(Box::new(constant(inner))),
+            ctype => constant(ctype),
+        }
+    }
+
+    /// This type with const qualifiers removed, also from array elements and below typedefs,
+    /// as a copy of an object does not inherit them
+    pub fn without_const(&self) -> CType {
+        match self {
+            CType::Const(inner) => inner.without_const(),
+            CType::Typedef(name, inner) => CType::Typedef(name.clone(), Box::new(inner.without_const())),
+            CType::Array(elem, count) => CType::Array(Box::new(elem.without_const()), *count),
+            ctype => ctype.clone(),
+        }
+    }
+
+    /// Check whether two types denote the same C type: typedefs are looked through, tagged
+    /// types compare by tag and integer types of equal size and signedness (`int`/`int32_t`)
+    /// are interchangeable
+    pub fn same_type(&self, other: &CType) -> bool {
+        match (self, other) {
+            (CType::Typedef(_, a) | CType::Packed(_, a), _) => a.same_type(other),
+            (_, CType::Typedef(_, b) | CType::Packed(_, b)) => self.same_type(b),
+            (CType::Const(a), CType::Const(b)) | (CType::Ptr(a), CType::Ptr(b)) | (CType::VLA(a), CType::VLA(b)) => {
+                a.same_type(b)
+            }
+            (CType::Array(a, n), CType::Array(b, m)) => n == m && a.same_type(b),
+            (CType::Struct(a, fields_a), CType::Struct(b, fields_b))
+            | (CType::Union(a, fields_a), CType::Union(b, fields_b)) => {
+                // Forward declarations and complete definitions share the tag
+                if a.is_empty() && b.is_empty() { fields_a == fields_b } else { a == b }
+            }
+            (CType::Enum(a, values_a, _), CType::Enum(b, values_b, _)) => {
+                if a.is_empty() && b.is_empty() { values_a == values_b } else { a == b }
+            }
+            (CType::Function(ret_a, params_a), CType::Function(ret_b, params_b)) => {
+                ret_a.same_type(ret_b)
+                    && params_a.len() == params_b.len()
+                    && params_a.iter().zip(params_b).all(|(a, b)| a.same_type(b))
+            }
+            _ => match (self.integer_kind(), other.integer_kind()) {
+                (Some(a), Some(b)) => a == b,
+                _ => self == other,
+            },
+        }
+    }
+
+    /// Size and signedness of the standard integer types
+    pub fn integer_kind(&self) -> Option<(usize, bool)> {
+        let signed = match self {
+            CType::Char | CType::Short | CType::Int | CType::Long | CType::LongLong | CType::SSizeT
+            | CType::Int8 | CType::Int16 | CType::Int32 | CType::Int64 => true,
+            CType::UChar | CType::UShort | CType::UInt | CType::ULong | CType::ULongLong | CType::SizeT
+            | CType::UInt8 | CType::UInt16 | CType::UInt32 | CType::UInt64 => false,
+            _ => return None,
+        };
+        Some((self.size(), signed))
+    }
+
+    /// Size and signedness of any integer type: the standard ones and, on Unix, the POSIX
+    /// typedefs as the target's libc defines them
+    pub fn integer_layout(&self) -> Option<(usize, bool)> {
+        #[cfg(unix)]
+        if let Some((size, _, signed)) = self.posix_integer() {
+            return Some((size, signed));
+        }
+        self.integer_kind()
+    }
+
+    /// Fixed-width integer type of `size` bytes and the given signedness
+    pub fn integer(size: usize, signed: bool) -> Option<CType> {
+        Some(match (size, signed) {
+            (1, true) => CType::Int8,
+            (2, true) => CType::Int16,
+            (4, true) => CType::Int32,
+            (8, true) => CType::Int64,
+            (1, false) => CType::UInt8,
+            (2, false) => CType::UInt16,
+            (4, false) => CType::UInt32,
+            (8, false) => CType::UInt64,
+            _ => return None,
+        })
+    }
+
+    /// Check whether a value of type `source` converts implicitly to a pointer to this type:
+    /// pointers to the same type (arrays decay and objects convert to their address), or any
+    /// pointer to or from `void*`. Discarding `const` is never implicit
+    pub fn accepts_pointer_from(&self, source: &CType) -> bool {
+        let source_target = match source.resolved() {
+            CType::Ptr(inner) | CType::Array(inner, _) | CType::VLA(inner) => inner,
+            _ => source,
+        };
+        if source_target.is_const() && !self.is_const() {
+            return false;
+        }
+        matches!(self.resolved(), CType::Void)
+            || matches!(source_target.resolved(), CType::Void)
+            || self.resolved().same_type(source_target.resolved())
+    }
+
+    /// C spelling of a declaration of `declarator` with this type, e.g. `int (*name)[4]`
+    fn declaration(&self, declarator: &str) -> String {
+        match self {
+            CType::Ptr(inner) => match inner.unqualified().resolved_typedefs() {
+                CType::Array(_, _) | CType::VLA(_) | CType::Function(_, _) => {
+                    inner.declaration(&format!("(*{})", declarator))
+                }
+                _ => inner.declaration(&format!("*{}", declarator)),
+            },
+            CType::Array(inner, count) => inner.declaration(&format!("{}[{}]", declarator, count)),
+            CType::VLA(inner) => inner.declaration(&format!("{}[?]", declarator)),
+            CType::Function(ret, params) => {
+                let params = if params.is_empty() {
+                    "void".to_string()
+                } else {
+                    params.iter().map(|param| param.declaration("")).collect::<Vec<_>>().join(", ")
+                };
+                ret.declaration(&format!("{}({})", declarator, params))
+            }
+            CType::Const(inner) if matches!(**inner, CType::Ptr(_)) => {
+                inner.declaration(&format!("const {}", declarator))
+            }
+            CType::Const(inner) => format!("const {}", inner.declaration(declarator)),
+            CType::Bitfield(inner, _, width) => format!("{} : {}", inner.declaration(declarator), width),
+            _ => {
+                let name = self.base_name();
+                if declarator.is_empty() { name } else { format!("{} {}", name, declarator) }
+            }
         }
     }
+
+    // Only typedefs are looked through, so `const` on pointer targets is kept
+    fn resolved_typedefs(&self) -> &CType {
+        match self {
+            CType::Typedef(_, inner) => inner.resolved_typedefs(),
+            ctype => ctype,
+        }
+    }
+
+    // Name of a type that is not built from a declarator
+    fn base_name(&self) -> String {
+        let name = match self {
+            CType::Bool => "bool",
+            CType::Char => "char",
+            CType::UChar => "unsigned char",
+            CType::Short => "short",
+            CType::UShort => "unsigned short",
+            CType::Int => "int",
+            CType::UInt => "unsigned int",
+            CType::Long => "long",
+            CType::ULong => "unsigned long",
+            CType::LongLong => "long long",
+            CType::ULongLong => "unsigned long long",
+            CType::Int8 => "int8_t",
+            CType::Int16 => "int16_t",
+            CType::Int32 => "int32_t",
+            CType::Int64 => "int64_t",
+            CType::UInt8 => "uint8_t",
+            CType::UInt16 => "uint16_t",
+            CType::UInt32 => "uint32_t",
+            CType::UInt64 => "uint64_t",
+            #[cfg(unix)]
+            CType::InoT => "ino_t",
+            #[cfg(unix)]
+            CType::DevT => "dev_t",
+            #[cfg(unix)]
+            CType::GidT => "gid_t",
+            #[cfg(unix)]
+            CType::ModeT => "mode_t",
+            #[cfg(unix)]
+            CType::NlinkT => "nlink_t",
+            #[cfg(unix)]
+            CType::UidT => "uid_t",
+            #[cfg(unix)]
+            CType::OffT => "off_t",
+            #[cfg(unix)]
+            CType::PidT => "pid_t",
+            #[cfg(unix)]
+            CType::UsecondsT => "useconds_t",
+            #[cfg(unix)]
+            CType::SusecondsT => "suseconds_t",
+            #[cfg(unix)]
+            CType::BlksizeT => "blksize_t",
+            #[cfg(unix)]
+            CType::BlkcntT => "blkcnt_t",
+            #[cfg(unix)]
+            CType::TimeT => "time_t",
+            CType::SizeT => "size_t",
+            CType::SSizeT => "ssize_t",
+            CType::Float => "float",
+            CType::Double => "double",
+            CType::Void => "void",
+            CType::VarArgs => "...",
+            CType::Typedef(name, _) => name,
+            CType::Packed(_, inner) | CType::Swapped(inner) => return inner.base_name(),
+            CType::Struct(name, _) | CType::Union(name, _) | CType::Enum(name, _, _) => {
+                let kind = match self {
+                    CType::Struct(_, _) => "struct",
+                    CType::Union(_, _) => "union",
+                    _ => "enum",
+                };
+                if name.is_empty() {
+                    return self.anonymous_body(kind);
+                }
+                return format!("{} {}", kind, name);
+            }
+            CType::Ptr(_) | CType::Array(_, _) | CType::VLA(_) | CType::Function(_, _) | CType::Const(_)
+            | CType::Bitfield(_, _, _) => {
+                return self.declaration("");
+            }
+        };
+        name.to_string()
+    }
+
+    // An anonymous type is spelled with its body, e.g. `struct { int x; int y; }`, which type
+    // strings accept back
+    fn anonymous_body(&self, kind: &str) -> String {
+        let members: Vec<String> = match self {
+            CType::Struct(_, fields) | CType::Union(_, fields) => {
+                fields.iter().map(|field| format!("{}; ", field.ctype.declaration(&field.name))).collect()
+            }
+            CType::Enum(_, values, _) => {
+                let values: Vec<String> = values.iter().map(|(name, value)| format!("{} = {}", name, value)).collect();
+                vec![format!("{} ", values.join(", "))]
+            }
+            _ => Vec::new(),
+        };
+        let underlying = match self {
+            CType::Enum(_, _, underlying) if **underlying != CType::Int => format!(" : {}", underlying),
+            _ => String::new(),
+        };
+        format!("{}{} {{ {}}}", kind, underlying, members.concat())
+    }
+
+    /// Find the enumerator name for a value, looking through typedefs
+    pub fn enum_name(&self, value: i64) -> Option<&str> {
+        match self {
+            CType::Enum(_, values, _) => values
+                .iter()
+                .find(|(_, v)| *v == value)
+                .map(|(name, _)| name.as_str()),
+            CType::Typedef(_, inner) | CType::Const(inner) | CType::Packed(_, inner) => inner.enum_name(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.declaration(""))
+    }
+}
+
+/// Shared handle to a type. The handles of its fields or element type are built on first use
+/// and shared by every value of the type, so member views don't copy the type
+#[derive(Debug, Clone)]
+pub struct TypeHandle(Arc<TypeNode>);
+
+#[derive(Debug)]
+struct TypeNode {
+    ctype: CType,
+    members: OnceLock<Box<[TypeHandle]>>,
+}
+
+impl TypeHandle {
+    pub fn new(ctype: CType) -> Self {
+        TypeHandle(Arc::new(TypeNode { ctype, members: OnceLock::new() }))
+    }
+
+    /// Handle of the `index`th field of a struct or union, or of the element type (index 0)
+    /// of an array or pointer. Members of a const object are const as well
+    pub fn member(&self, index: usize) -> Option<&TypeHandle> {
+        let constant = |ctype: &CType| {
+            if self.0.ctype.is_const() && !ctype.is_const() { CType::Const(Box::new(ctype.clone())) } else { ctype.clone() }
+        };
+        let members = self.0.members.get_or_init(|| match self.0.ctype.resolved() {
+            CType::Struct(_, fields) | CType::Union(_, fields) => {
+                fields.iter().map(|field| TypeHandle::new(constant(&field.ctype))).collect()
+            }
+            CType::Array(inner, _) | CType::VLA(inner) => Box::new([TypeHandle::new(constant(inner))]),
+            // A const pointer may still point to mutable memory
+            CType::Ptr(inner) => Box::new([TypeHandle::new((**inner).clone())]),
+            _ => Box::new([]),
+        });
+        members.get(index)
+    }
+}
+
+impl Deref for TypeHandle {
+    type Target = CType;
+
+    fn deref(&self) -> &CType {
+        &self.0.ctype
+    }
+}
+
+impl From<CType> for TypeHandle {
+    fn from(ctype: CType) -> Self {
+        TypeHandle::new(ctype)
+    }
+}
+
+impl fmt::Display for TypeHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.ctype.fmt(f)
+    }
 }
@@ -9,13 +9,18 @@ use libc::{RTLD_LAZY, dlclose, dlerror, dlopen, dlsym};
 #[cfg(windows)]
 use windows_sys::Win32::Foundation::FreeLibrary;
 #[cfg(windows)]
-use windows_sys::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress, LoadLibraryA};
+use windows_sys::Win32::System::LibraryLoader::{
+    GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT, GetModuleFileNameA,
+    GetModuleHandleA, GetModuleHandleExA, GetProcAddress, LoadLibraryA,
+};
 
 pub struct DynamicLibrary {
     #[cfg(unix)]
     handle: *mut libc::c_void,
     #[cfg(windows)]
     handle: isize, // HMODULE on Windows
+    // The default library searches every loaded object
+    global: bool,
 }
 
 impl DynamicLibrary {
@@ -23,24 +28,18 @@ impl DynamicLibrary {
     pub fn load(name: &str) -> Result<Self, String> {
         #[cfg(unix)]
         {
-            let c_name = CString::new(name).map_err(|e| e.to_string())?;
-            let handle = unsafe { dlopen(c_name.as_ptr(), RTLD_LAZY) };
-
-            if handle.is_null() {
-                let error_msg = unsafe {
-                    let err_ptr = dlerror();
-                    if !err_ptr.is_null() {
-                        std::ffi::CStr::from_ptr(err_ptr)
-                            .to_string_lossy()
-                            .into_owned()
-                    } else {
-                        format!("Failed to load library: {}", name)
+            let mut first_error = None;
+            for candidate in mobile::candidates(name) {
+                match open(&candidate)? {
+                    Ok(handle) => return Ok(Self { handle, global: false }),
+                    Err(error) => {
+                        first_error.get_or_insert(error);
                     }
-                };
-                return Err(error_msg);
+                }
             }
-
-            Ok(Self { handle })
+            let error = first_error.unwrap_or_else(|| format!("Failed to load library: {}", name));
+            // A fallback stands in for the main program, so it searches every loaded object
+            mobile::fallback(error).map(|handle| Self { handle, global: true })
         }
 
         #[cfg(windows)]
@@ -52,7 +51,7 @@ impl DynamicLibrary {
                 return Err(format!("Failed to load library: {}", name));
             }
 
-            Ok(Self { handle })
+            Ok(Self { handle, global: false })
         }
     }
 
@@ -67,7 +66,7 @@ impl DynamicLibrary {
                 return Err("Failed to load default C library".to_string());
             }
 
-            Ok(Self { handle })
+            Ok(Self { handle, global: true })
         }
 
         #[cfg(windows)]
@@ -84,10 +83,11 @@ impl DynamicLibrary {
                 }
                 return Ok(Self {
                     handle: main_handle,
+                    global: true,
                 });
             }
 
-            Ok(Self { handle: handle })
+            Ok(Self { handle: handle, global: true })
         }
     }
 
@@ -113,6 +113,410 @@ impl DynamicLibrary {
             }
         }
     }
+
+    /// The raw handle from dlopen or LoadLibrary
+    pub fn handle(&self) -> *mut libc::c_void {
+        #[cfg(unix)]
+        return self.handle;
+        #[cfg(windows)]
+        return self.handle as *mut libc::c_void;
+    }
+
+    /// Path of the file the library was loaded from, as the loader resolved it. Where the
+    /// loader cannot tell, as for the default library on Unix, the main program's path stands
+    /// in for libraries that search every loaded object
+    pub fn path(&self) -> Option<String> {
+        #[cfg(target_os = "linux")]
+        unsafe {
+            let mut map: *mut elf::LinkMap = ptr::null_mut();
+            let info = &mut map as *mut *mut elf::LinkMap as *mut libc::c_void;
+            if !self.global && libc::dlinfo(self.handle, libc::RTLD_DI_LINKMAP, info) == 0 && !map.is_null() {
+                let name = std::ffi::CStr::from_ptr((*map).l_name).to_string_lossy();
+                if !name.is_empty() {
+                    return Some(name.into_owned());
+                }
+            }
+        }
+
+        #[cfg(windows)]
+        {
+            let mut buffer = [0u8; 32768];
+            let len = unsafe {
+                GetModuleFileNameA(self.handle as *mut libc::c_void, buffer.as_mut_ptr(), buffer.len() as u32)
+            } as usize;
+            if len > 0 && len < buffer.len() {
+                return Some(String::from_utf8_lossy(&buffer[..len]).into_owned());
+            }
+        }
+
+        if !self.global {
+            return None;
+        }
+        std::env::current_exe().ok().map(|path| path.to_string_lossy().into_owned())
+    }
+
+    /// Version of the library as far as the platform records one: the file version resource
+    /// on Windows, and elsewhere the numbers the file name carries after following symlinks,
+    /// e.g. `1.2.13` for `libz.so.1` or `6` for `libm.so.6`
+    pub fn version(&self) -> Option<String> {
+        if self.global {
+            return None;
+        }
+        let path = self.path()?;
+
+        #[cfg(windows)]
+        {
+            file_version(&path)
+        }
+
+        #[cfg(not(windows))]
+        {
+            let path = std::fs::canonicalize(&path).map_or(path, |path| path.to_string_lossy().into_owned());
+            let name = path.rsplit('/').next()?;
+            version_of_file_name(name)
+        }
+    }
+
+    /// Names of the functions and variables exported by the library. Only ELF platforms
+    /// support enumerating symbols; elsewhere the list is empty
+    pub fn exported_symbols(&self) -> Vec<String> {
+        let mut symbols = Vec::new();
+
+        #[cfg(target_os = "linux")]
+        unsafe {
+            let mut map: *mut elf::LinkMap = ptr::null_mut();
+            let info = &mut map as *mut *mut elf::LinkMap as *mut libc::c_void;
+            if libc::dlinfo(self.handle, libc::RTLD_DI_LINKMAP, info) != 0 {
+                return symbols;
+            }
+            while !map.is_null() {
+                elf::collect_exports(&*map, &mut symbols);
+                if !self.global {
+                    break;
+                }
+                map = (*map).l_next;
+            }
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        let _ = self.global;
+
+        symbols
+    }
+}
+
+// Dotted numbers of a shared object's file name: after `.so.` (`libz.so.1.2.13`) or before
+// `.dylib` (`libz.1.2.13.dylib`)
+#[cfg(not(windows))]
+fn version_of_file_name(name: &str) -> Option<String> {
+    let version = match name.split_once(".so.") {
+        Some((_, version)) => version,
+        None => {
+            let stem = name.strip_suffix(".dylib")?;
+            &stem[stem.find('.')? + 1..]
+        }
+    };
+    let numeric = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    version.split('.').all(numeric).then(|| version.to_string())
+}
+
+// `major.minor.build.revision` from the fixed file version of a module's version resource
+#[cfg(windows)]
+fn file_version(path: &str) -> Option<String> {
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileVersionInfoA, GetFileVersionInfoSizeA, VS_FIXEDFILEINFO, VerQueryValueA,
+    };
+
+    let path = CString::new(path).ok()?;
+    let size = unsafe { GetFileVersionInfoSizeA(path.as_ptr() as *const u8, ptr::null_mut()) };
+    if size == 0 {
+        return None;
+    }
+    let mut data = vec![0u8; size as usize];
+    if unsafe { GetFileVersionInfoA(path.as_ptr() as *const u8, 0, size, data.as_mut_ptr() as *mut _) } == 0 {
+        return None;
+    }
+    let mut info: *mut libc::c_void = ptr::null_mut();
+    let mut len = 0u32;
+    let root = b"\\\0";
+    if unsafe { VerQueryValueA(data.as_ptr() as *const _, root.as_ptr(), &mut info, &mut len) } == 0
+        || info.is_null()
+        || (len as usize) < std::mem::size_of::<VS_FIXEDFILEINFO>()
+    {
+        return None;
+    }
+    let info = unsafe { &*(info as *const VS_FIXEDFILEINFO) };
+    Some(format!(
+        "{}.{}.{}.{}",
+        info.dwFileVersionMS >> 16,
+        info.dwFileVersionMS & 0xFFFF,
+        info.dwFileVersionLS >> 16,
+        info.dwFileVersionLS & 0xFFFF
+    ))
+}
+
+/// Path of the loaded module containing `address`, with the name of the exported symbol at or
+/// before it where the platform can tell (dladdr on Unix; Windows only knows the module)
+pub fn module_of(address: *const libc::c_void) -> Option<(String, Option<String>)> {
+    #[cfg(unix)]
+    {
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        if unsafe { libc::dladdr(address, &mut info) } == 0 || info.dli_fname.is_null() {
+            return None;
+        }
+        let text = |name: *const std::ffi::c_char| unsafe { std::ffi::CStr::from_ptr(name) }.to_string_lossy().into_owned();
+        let symbol = (!info.dli_sname.is_null()).then(|| text(info.dli_sname));
+        Some((text(info.dli_fname), symbol))
+    }
+
+    #[cfg(windows)]
+    {
+        let mut module = ptr::null_mut();
+        let flags = GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT;
+        if unsafe { GetModuleHandleExA(flags, address as *const u8, &mut module) } == 0 {
+            return None;
+        }
+        let mut buffer = [0u8; 32768];
+        let len = unsafe { GetModuleFileNameA(module, buffer.as_mut_ptr(), buffer.len() as u32) } as usize;
+        (len > 0 && len < buffer.len()).then(|| (String::from_utf8_lossy(&buffer[..len]).into_owned(), None))
+    }
+}
+
+// Functions registered from Rust, by library name. WebAssembly has no dynamic linker of its
+// own and Emscripten only emulates dlopen for side modules, so embedders compiled into the
+// main module expose their host functions here instead. Only the `wasm` feature can register any
+pub mod host {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{LazyLock, Mutex};
+
+    static LIBRARIES: LazyLock<Mutex<HashMap<String, BTreeMap<String, usize>>>> = LazyLock::new(Default::default);
+
+    /// Make `symbols` available through `ffi.load(name)`, or through `ffi.C` when `name` is
+    /// "C". Registering a library again adds to its symbols, replacing those of the same name
+    #[cfg(feature = "wasm")]
+    pub fn register_library(name: &str, symbols: &[(&str, *const std::ffi::c_void)]) {
+        let mut libraries = LIBRARIES.lock().unwrap();
+        let library = libraries.entry(name.to_string()).or_default();
+        for (symbol, address) in symbols {
+            library.insert(symbol.to_string(), *address as usize);
+        }
+    }
+
+    pub fn is_registered(library: &str) -> bool {
+        LIBRARIES.lock().unwrap().contains_key(library)
+    }
+
+    pub fn symbol(library: &str, name: &str) -> Option<*mut libc::c_void> {
+        let address = *LIBRARIES.lock().unwrap().get(library)?.get(name)?;
+        Some(address as *mut libc::c_void)
+    }
+
+    pub fn symbols(library: &str) -> Vec<String> {
+        let libraries = LIBRARIES.lock().unwrap();
+        libraries.get(library).map(|symbols| symbols.keys().cloned().collect()).unwrap_or_default()
+    }
+}
+
+/// dlopen one name; the outer error is for names that cannot be passed to C at all
+#[cfg(unix)]
+fn open(name: &str) -> Result<Result<*mut libc::c_void, String>, String> {
+    let c_name = CString::new(name).map_err(|e| e.to_string())?;
+    let handle = unsafe { dlopen(c_name.as_ptr(), RTLD_LAZY) };
+    if !handle.is_null() {
+        return Ok(Ok(handle));
+    }
+    let err_ptr = unsafe { dlerror() };
+    if err_ptr.is_null() {
+        Ok(Err(format!("Failed to load library: {}", name)))
+    } else {
+        Ok(Err(unsafe { std::ffi::CStr::from_ptr(err_ptr) }.to_string_lossy().into_owned()))
+    }
+}
+
+// Loader quirks of the mobile platforms. Android confines dlopen to the app's own libraries
+// and the public system libraries, which are only found by their bare soname, and releases
+// before 6.0 do not search the app's library directory at all. iOS apps may not load their
+// own dynamic libraries, so C code is linked statically into the main executable instead
+#[cfg(unix)]
+mod mobile {
+    /// The names to try for a library, in order
+    #[cfg(not(target_os = "android"))]
+    pub fn candidates(name: &str) -> Vec<String> {
+        vec![name.to_string()]
+    }
+
+    #[cfg(target_os = "android")]
+    pub fn candidates(name: &str) -> Vec<String> {
+        let mut candidates = vec![name.to_string()];
+        let mut push = |candidate: String| {
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        };
+
+        // Public system libraries are only visible by soname, never by path
+        let file = name.rsplit_once('/').map_or(name, |(_, file)| file);
+        // Short names such as "z" mean "libz.so"; Android sonames carry no version suffix
+        let mut soname = match file.find(".so") {
+            Some(end) => file[..end + 3].to_string(),
+            None => format!("{}.so", file),
+        };
+        if !soname.starts_with("lib") && !file.contains('.') {
+            soname.insert_str(0, "lib");
+        }
+        if name.contains('/') {
+            push(file.to_string());
+        }
+        push(soname.clone());
+        if !name.contains('/')
+            && let Some(dir) = library_dir()
+        {
+            push(format!("{}/{}", dir, soname));
+        }
+        candidates
+    }
+
+    // The directory luaffi itself was loaded from, which holds the app's other native libraries
+    #[cfg(target_os = "android")]
+    fn library_dir() -> Option<String> {
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        let address = library_dir as *const () as *const libc::c_void;
+        if unsafe { libc::dladdr(address, &mut info) } == 0 || info.dli_fname.is_null() {
+            return None;
+        }
+        let path = unsafe { std::ffi::CStr::from_ptr(info.dli_fname) }.to_string_lossy();
+        path.rsplit_once('/').map(|(dir, _)| dir.to_string())
+    }
+
+    /// Handle for a library none of the candidates could load, or the error to report
+    #[cfg(target_os = "ios")]
+    pub fn fallback(error: String) -> Result<*mut libc::c_void, String> {
+        // Statically linked libraries resolve from the main executable
+        let handle = unsafe { libc::dlopen(std::ptr::null(), libc::RTLD_LAZY) };
+        if handle.is_null() { Err(error) } else { Ok(handle) }
+    }
+
+    #[cfg(target_os = "android")]
+    pub fn fallback(error: String) -> Result<*mut libc::c_void, String> {
+        if error.contains("not accessible for the namespace") {
+            return Err(format!(
+                "{} (Android only allows loading the app's own libraries and public system libraries)",
+                error
+            ));
+        }
+        Err(error)
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn fallback(error: String) -> Result<*mut libc::c_void, String> {
+        Err(error)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod elf {
+    use std::ffi::{CStr, c_char};
+    use std::mem::size_of;
+
+    // Leading fields of glibc's `struct link_map`, as returned by RTLD_DI_LINKMAP
+    #[repr(C)]
+    pub struct LinkMap {
+        pub l_addr: usize,
+        pub l_name: *const c_char,
+        pub l_ld: *const Dyn,
+        pub l_next: *mut LinkMap,
+        pub l_prev: *mut LinkMap,
+    }
+
+    #[repr(C)]
+    pub struct Dyn {
+        pub d_tag: isize,
+        pub d_val: usize,
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    type Sym = libc::Elf64_Sym;
+    #[cfg(target_pointer_width = "32")]
+    type Sym = libc::Elf32_Sym;
+
+    const DT_NULL: isize = 0;
+    const DT_HASH: isize = 4;
+    const DT_STRTAB: isize = 5;
+    const DT_SYMTAB: isize = 6;
+    const DT_GNU_HASH: isize = 0x6fff_fef5;
+
+    /// Append the defined global functions and objects of one loaded object
+    pub unsafe fn collect_exports(map: &LinkMap, symbols: &mut Vec<String>) {
+        if map.l_ld.is_null() {
+            return;
+        }
+
+        let (mut strtab, mut symtab, mut hash, mut gnu_hash) = (0, 0, 0, 0);
+        let mut entry = map.l_ld;
+        unsafe {
+            while (*entry).d_tag != DT_NULL {
+                let value = (*entry).d_val;
+                match (*entry).d_tag {
+                    DT_STRTAB => strtab = value,
+                    DT_SYMTAB => symtab = value,
+                    DT_HASH => hash = value,
+                    DT_GNU_HASH => gnu_hash = value,
+                    _ => {}
+                }
+                entry = entry.add(1);
+            }
+        }
+
+        // glibc relocates the dynamic section in place; other loaders leave offsets
+        let relocate = |addr: usize| if addr != 0 && addr < map.l_addr { addr + map.l_addr } else { addr };
+        let (strtab, symtab) = (relocate(strtab), relocate(symtab));
+        if strtab == 0 || symtab == 0 {
+            return;
+        }
+
+        let count = unsafe {
+            if hash != 0 {
+                // nchain equals the number of symbols
+                *(relocate(hash) as *const u32).add(1) as usize
+            } else if gnu_hash != 0 {
+                gnu_hash_symbol_count(relocate(gnu_hash) as *const u32)
+            } else {
+                return;
+            }
+        };
+
+        for i in 0..count {
+            let sym = unsafe { &*(symtab as *const Sym).add(i) };
+            let kind = sym.st_info & 0xf; // STT_OBJECT = 1, STT_FUNC = 2
+            let binding = sym.st_info >> 4; // STB_GLOBAL = 1, STB_WEAK = 2
+            if sym.st_shndx == 0 || !matches!(kind, 1 | 2) || !matches!(binding, 1 | 2) {
+                continue;
+            }
+            let name = unsafe { CStr::from_ptr((strtab + sym.st_name as usize) as *const c_char) };
+            symbols.push(name.to_string_lossy().into_owned());
+        }
+    }
+
+    // DT_GNU_HASH does not store the symbol count; follow the chain of the last bucket to its end
+    unsafe fn gnu_hash_symbol_count(table: *const u32) -> usize {
+        unsafe {
+            let nbuckets = *table as usize;
+            let symoffset = *table.add(1) as usize;
+            let bloom_words = *table.add(2) as usize;
+            let buckets = table.add(4 + bloom_words * size_of::<usize>() / 4);
+            let chains = buckets.add(nbuckets);
+
+            let last = (0..nbuckets).map(|i| *buckets.add(i) as usize).max().unwrap_or(0);
+            if last < symoffset {
+                return symoffset;
+            }
+            let mut index = last;
+            while *chains.add(index - symoffset) & 1 == 0 {
+                index += 1;
+            }
+            index + 1
+        }
+    }
 }
 
 impl Drop for DynamicLibrary {
@@ -135,3 +539,18 @@ impl Drop for DynamicLibrary {
 // Ensure the type is Send and Sync for multi-threaded use
 unsafe impl Send for DynamicLibrary {}
 unsafe impl Sync for DynamicLibrary {}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_of_file_name() {
+        assert_eq!(version_of_file_name("libz.so.1.2.13").as_deref(), Some("1.2.13"));
+        assert_eq!(version_of_file_name("libm.so.6").as_deref(), Some("6"));
+        assert_eq!(version_of_file_name("libz.1.2.11.dylib").as_deref(), Some("1.2.11"));
+        assert_eq!(version_of_file_name("libfoo.so"), None);
+        assert_eq!(version_of_file_name("libfoo.dylib"), None);
+        assert_eq!(version_of_file_name("libfoo.so.1.beta"), None);
+    }
+}
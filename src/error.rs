@@ -0,0 +1,151 @@
+// Structured FFI errors, so Lua handlers can tell failures apart without matching messages
+
+use std::cell::RefCell;
+use std::fmt;
+
+use mlua::prelude::*;
+
+/// What an FFI error is about, as `err:kind()` names it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Any error raised without a more specific kind, including those from outside the FFI
+    Runtime,
+    SymbolNotFound,
+    LibraryNotFound,
+    UnknownType,
+    ParseError,
+    NullPointer,
+    OutOfBounds,
+    UseAfterFree,
+    ReadOnly,
+    /// Refused by the configuration of the Lua state, e.g. its sandbox policy or safe mode
+    NotPermitted,
+}
+
+impl ErrorKind {
+    /// The name Lua sees, e.g. `SYMBOL_NOT_FOUND`
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Runtime => "RUNTIME",
+            ErrorKind::SymbolNotFound => "SYMBOL_NOT_FOUND",
+            ErrorKind::LibraryNotFound => "LIBRARY_NOT_FOUND",
+            ErrorKind::UnknownType => "UNKNOWN_TYPE",
+            ErrorKind::ParseError => "PARSE_ERROR",
+            ErrorKind::NullPointer => "NULL_POINTER",
+            ErrorKind::OutOfBounds => "OUT_OF_BOUNDS",
+            ErrorKind::UseAfterFree => "USE_AFTER_FREE",
+            ErrorKind::ReadOnly => "READ_ONLY",
+            ErrorKind::NotPermitted => "NOT_PERMITTED",
+        }
+    }
+
+    /// Stable numeric code, 0 for `Runtime`
+    pub fn code(self) -> i64 {
+        self as i64
+    }
+}
+
+/// An error raised by the FFI, carried inside `LuaError::ExternalError`. Its message is the
+/// error's text, so code matching on `tostring(err)` keeps working
+#[derive(Clone, Debug)]
+pub struct FfiError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// The C type involved, as written by `tostring(ffi.typeof(...))`
+    pub ctype: Option<String>,
+    /// The symbol that was looked up
+    pub symbol: Option<String>,
+}
+
+impl FfiError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into(), ctype: None, symbol: None }
+    }
+
+    pub fn with_type(mut self, ctype: impl fmt::Display) -> Self {
+        self.ctype = Some(ctype.to_string());
+        self
+    }
+
+    pub fn with_symbol(mut self, symbol: impl Into<String>) -> Self {
+        self.symbol = Some(symbol.into());
+        self
+    }
+
+    /// The `FfiError` a Lua error was raised with, looking through callback and context wrappers
+    pub fn find(error: &LuaError) -> Option<&FfiError> {
+        match error {
+            LuaError::CallbackError { cause, .. } | LuaError::WithContext { cause, .. } => Self::find(cause),
+            LuaError::ExternalError(error) => error.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FfiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for FfiError {}
+
+thread_local! {
+    // The last FFI error raised on this thread, like `errno`
+    static LAST_ERROR: RefCell<Option<FfiError>> = const { RefCell::new(None) };
+}
+
+impl From<FfiError> for LuaError {
+    fn from(error: FfiError) -> Self {
+        LAST_ERROR.set(Some(error.clone()));
+        LuaError::external(error)
+    }
+}
+
+/// The last FFI error raised on this thread, if not cleared since
+pub fn last_error() -> Option<FfiError> {
+    LAST_ERROR.with_borrow(Clone::clone)
+}
+
+/// Forget the last FFI error, returning it
+pub fn clear_error() -> Option<FfiError> {
+    LAST_ERROR.take()
+}
+
+// The text of an error without the traceback a callback error adds
+fn root_message(error: &LuaError) -> String {
+    match error {
+        LuaError::CallbackError { cause, .. } | LuaError::WithContext { cause, .. } => root_message(cause),
+        LuaError::RuntimeError(message) => message.clone(),
+        error => error.to_string(),
+    }
+}
+
+/// Give the errors mlua raises in `lua` the `kind`, `code`, `message`, `ctype` and `symbol`
+/// methods. mlua hands Lua every Rust error as a userdata of one metatable, which has no
+/// `__index` of its own, so the methods go there
+pub(crate) fn install_methods(lua: &Lua) -> LuaResult<()> {
+    fn method<R: IntoLua + 'static>(lua: &Lua, f: fn(&LuaError) -> R) -> LuaResult<LuaFunction> {
+        lua.create_function(move |_, error: LuaValue| match error {
+            LuaValue::Error(error) => Ok(f(&error)),
+            other => Err(LuaError::RuntimeError(format!("Expected an error, got {}", other.type_name()))),
+        })
+    }
+
+    let methods = lua.create_table()?;
+    methods.set("kind", method(lua, |error| FfiError::find(error).map_or(ErrorKind::Runtime, |e| e.kind).name())?)?;
+    methods.set("code", method(lua, |error| FfiError::find(error).map_or(ErrorKind::Runtime, |e| e.kind).code())?)?;
+    methods.set("message", method(lua, root_message)?)?;
+    methods.set("ctype", method(lua, |error| FfiError::find(error).and_then(|e| e.ctype.clone()))?)?;
+    methods.set("symbol", method(lua, |error| FfiError::find(error).and_then(|e| e.symbol.clone()))?)?;
+
+    let sample = LuaValue::Error(Box::new(LuaError::RuntimeError(String::new())));
+    unsafe {
+        lua.exec_raw::<()>((sample, methods), |state| {
+            if mlua::ffi::lua_getmetatable(state, 1) != 0 {
+                mlua::ffi::lua_pushvalue(state, 2);
+                mlua::ffi::lua_setfield(state, -2, c"__index".as_ptr());
+            }
+        })
+    }
+}
@@ -1,12 +1,19 @@
-use std::ffi::CStr;
-use std::sync::{RwLock, OnceLock};
+use std::cell::RefCell;
+use std::ffi::{CStr, c_char};
+use std::sync::{Arc, RwLock, OnceLock};
 use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::rc::Rc;
 
 use mlua::prelude::*;
 use phf::phf_map;
 
-use crate::cdata::CData;
-use crate::ctype::CType;
+use crate::callback;
+use crate::config;
+use crate::cdata::{create_owned, integer_value, read_member, report_release, CData, CFunction, Ownership};
+use crate::ctype::{CField, CType, CallConv};
+use crate::error::{ErrorKind, FfiError};
+use crate::parser;
 
 // Static perfect hash map for basic type lookups (zero overhead)
 static BASIC_TYPES: phf::Map<&'static str, CType> = phf_map! {
@@ -32,20 +39,387 @@ static BASIC_TYPES: phf::Map<&'static str, CType> = phf_map! {
     "uint64_t" => CType::UInt64,
     "size_t" => CType::SizeT,
     "ssize_t" => CType::SSizeT,
+    "uintptr_t" => CType::SizeT,
+    "intptr_t" => CType::SSizeT,
 };
 
-// Global type registry for storing parsed types (using RwLock for better concurrent read performance)
-static TYPE_REGISTRY: OnceLock<RwLock<HashMap<String, CType>>> = OnceLock::new();
+// Types, enumerators and function prototypes declared with cdef
+#[derive(Debug, Default)]
+struct Declarations {
+    types: HashMap<String, CType>,
+    // Named integer constants (enumerators) usable in constant expressions
+    constants: HashMap<String, i64>,
+    // Declared C functions, giving library symbols their prototype
+    symbols: HashMap<String, (CType, CallConv)>,
+    // Declared functions that may be missing from their library
+    optional: HashMap<String, parser::MissingSymbol>,
+    // Declared functions whose calls guard against signals
+    signals: HashMap<String, parser::SignalHandling>,
+    // Structs made with `ffi.extend`, by tag, with the struct they start with
+    bases: HashMap<String, CType>,
+}
+
+/// A set of declarations made with `ffi.cdef`. Clones share it, so Lua states configured with
+/// clones of one registry see each other's declarations
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<RwLock<Declarations>>);
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// Global registry, shared by every Lua state without one of its own (using RwLock for better
+// concurrent read performance)
+static GLOBAL_REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+fn global_registry() -> &'static Registry {
+    GLOBAL_REGISTRY.get_or_init(Registry::new)
+}
+
+// Declarations go to the registry of the running Lua state
+fn declare(f: impl FnOnce(&mut Declarations)) {
+    match config::registry() {
+        Some(registry) => f(&mut registry.0.write().unwrap()),
+        None => f(&mut global_registry().0.write().unwrap()),
+    }
+}
+
+// Look a declaration up in the registry of the running Lua state, then in the global one
+fn lookup<T: Clone>(name: &str, table: fn(&Declarations) -> &HashMap<String, T>) -> Option<T> {
+    let find = |registry: &Registry| table(&registry.0.read().unwrap()).get(name).cloned();
+    config::registry().and_then(|registry| find(&registry)).or_else(|| find(global_registry()))
+}
+
 pub fn register_type(name: String, ctype: CType) {
-    TYPE_REGISTRY.get_or_init(|| RwLock::new(HashMap::new())).write().unwrap().insert(name, ctype);
+    declare(|declarations| {
+        declarations.types.insert(name, ctype);
+    });
 }
 
 #[inline]
 fn lookup_registered_type(name: &str) -> Option<CType> {
-    TYPE_REGISTRY.get_or_init(|| RwLock::new(HashMap::new())).read().unwrap().get(name).cloned()
+    lookup(name, |declarations| &declarations.types)
 }
-pub fn new_cdata(lua: &Lua, type_name: &str, init: Option<LuaValue>) -> LuaResult<LuaAnyUserData> {
-    let ctype = lookup_type(type_name)?;
+
+/// Spelling of `ctype` shared by all equal types, provided it parses back to the same type
+pub fn canonical_type_name(ctype: &CType) -> Option<String> {
+    let name = ctype.to_string();
+    lookup_type(&name).is_ok_and(|parsed| parsed == *ctype).then_some(name)
+}
+
+/// Build a type from a template in which each `$` takes the next argument and `$N` the `N`th:
+/// type names and cdata splice in their type, integers their value (e.g. an array size) and
+/// other strings an identifier (e.g. a field name). Returns a name the type can be looked up by
+pub fn substitute_type(template: &str, args: LuaMultiValue) -> LuaResult<String> {
+    enum Argument {
+        Type(CType),
+        Text(String),
+    }
+    let args = args
+        .into_iter()
+        .map(|arg| match integral(arg) {
+            LuaValue::Integer(i) => Ok(Argument::Text(i.to_string())),
+            LuaValue::String(s) => {
+                let text = s.to_str()?.to_string();
+                match lookup_type(&text) {
+                    Ok(ctype) => Ok(Argument::Type(ctype)),
+                    Err(_) if is_identifier(&text) => Ok(Argument::Text(text)),
+                    Err(e) => Err(e),
+                }
+            }
+            LuaValue::UserData(ud) => Ok(Argument::Type((*ud.borrow::<CData>()?.ctype).clone())),
+            other => Err(LuaError::RuntimeError(format!(
+                "Cannot substitute a {} into a type",
+                other.type_name()
+            ))),
+        })
+        .collect::<LuaResult<Vec<_>>>()?;
+
+    // Parse with placeholders for the types, and also spell them out for the returned name
+    let (mut parsed, mut spelled, mut types) = (String::new(), String::new(), Vec::new());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(at) = rest.find('$') {
+        parsed.push_str(&rest[..at]);
+        spelled.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let index = if digits > 0 {
+            let number: usize = rest[..digits].parse().unwrap_or(0);
+            rest = &rest[digits..];
+            number.checked_sub(1)
+        } else {
+            next += 1;
+            Some(next - 1)
+        };
+        match index.and_then(|index| args.get(index)) {
+            Some(Argument::Type(ctype)) => {
+                parsed.push_str(&format!(" {}{} ", parser::PARAMETER_PREFIX, types.len()));
+                spelled.push_str(&format!(" {} ", ctype));
+                types.push(ctype.clone());
+            }
+            Some(Argument::Text(text)) => {
+                parsed.push_str(text);
+                spelled.push_str(text);
+            }
+            None => {
+                return Err(LuaError::RuntimeError(format!(
+                    "Type template '{}' has no argument #{}",
+                    template,
+                    index.map_or(0, |index| index + 1)
+                )));
+            }
+        }
+    }
+    parsed.push_str(rest);
+    spelled.push_str(rest);
+
+    let ctype = parser::parse_type_string_with(&parsed, types).map_err(LuaError::RuntimeError)?;
+    if let Some(name) = canonical_type_name(&ctype) {
+        return Ok(name);
+    }
+    // Types spelled with a declarator, such as function pointers, don't splice in as text
+    let spelled = spelled.split_whitespace().collect::<Vec<_>>().join(" ");
+    match lookup_type(&spelled) {
+        Ok(parsed) if parsed.same_type(&ctype) => Ok(spelled),
+        _ => Err(LuaError::RuntimeError(format!("Type {} has no name it can be looked up by", ctype))),
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Names of all basic and registered types, e.g. for suggestions on typos
+pub fn type_names() -> Vec<String> {
+    let mut names: Vec<String> = BASIC_TYPES.keys().map(|name| name.to_string()).collect();
+    let private = config::registry();
+    for registry in private.iter().chain([global_registry()]) {
+        names.extend(registry.0.read().unwrap().types.keys().cloned());
+    }
+    names
+}
+
+pub fn register_constant(name: String, value: i64) {
+    declare(|declarations| {
+        declarations.constants.insert(name, value);
+    });
+}
+
+#[inline]
+pub fn lookup_constant(name: &str) -> Option<i64> {
+    lookup(name, |declarations| &declarations.constants)
+}
+
+/// Read the options table of `ffi.cdef(src, {pack = 1, abi = "stdcall", strict = true})`
+pub fn cdef_options(table: LuaTable) -> LuaResult<parser::CdefOptions> {
+    let mut options = parser::CdefOptions::default();
+    for pair in table.pairs::<String, LuaValue>() {
+        let (key, value) = pair?;
+        match (key.as_str(), integral(value)) {
+            ("pack", LuaValue::Integer(pack)) if matches!(pack, 1 | 2 | 4 | 8 | 16) => {
+                options.pack = Some(pack as usize);
+            }
+            ("pack", _) => {
+                return Err(LuaError::RuntimeError("cdef option 'pack' must be 1, 2, 4, 8 or 16".to_string()));
+            }
+            ("abi", LuaValue::String(abi)) => {
+                options.convention = match &*abi.to_str()? {
+                    "cdecl" | "default" => CallConv::C,
+                    "stdcall" => CallConv::Stdcall,
+                    "fastcall" => CallConv::Fastcall,
+                    abi => {
+                        return Err(LuaError::RuntimeError(format!("Unknown cdef abi: {}", abi)));
+                    }
+                };
+            }
+            ("strict", LuaValue::Boolean(strict)) => options.strict = strict,
+            // "default" follows GCC and Clang, "short" picks the smallest type that fits and an
+            // integer type name fixes it, e.g. "int" as MSVC does
+            ("enum", LuaValue::String(repr)) => {
+                options.enums = match &*repr.to_str()? {
+                    "default" => parser::EnumRepr::Int,
+                    "short" | "packed" => parser::EnumRepr::Short,
+                    type_name => match lookup_type(type_name)?.resolved().integer_kind() {
+                        Some((size, signed)) => parser::EnumRepr::Fixed(size, signed),
+                        None => {
+                            return Err(LuaError::RuntimeError(format!(
+                                "cdef option 'enum' must be an integer type: {}",
+                                type_name
+                            )));
+                        }
+                    },
+                };
+            }
+            ("bitfields", LuaValue::String(layout)) => {
+                options.bitfields = match &*layout.to_str()? {
+                    "gcc" | "clang" => parser::BitfieldLayout::Gcc,
+                    "msvc" => parser::BitfieldLayout::Msvc,
+                    layout => {
+                        return Err(LuaError::RuntimeError(format!("Unknown cdef bit-field layout: {}", layout)));
+                    }
+                };
+            }
+            // Missing functions of the block become stubs that raise when called, or return zero
+            ("optional", LuaValue::Boolean(optional)) => {
+                options.optional = optional.then_some(parser::MissingSymbol::Raise);
+            }
+            ("optional", LuaValue::String(missing)) => {
+                options.optional = match &*missing.to_str()? {
+                    "error" => Some(parser::MissingSymbol::Raise),
+                    "zero" => Some(parser::MissingSymbol::Zero),
+                    missing => {
+                        return Err(LuaError::RuntimeError(format!("Unknown cdef optional behaviour: {}", missing)));
+                    }
+                };
+            }
+            // Functions of the block retry when interrupted, or run with signals blocked
+            ("signals", LuaValue::String(handling)) => {
+                options.signals = match &*handling.to_str()? {
+                    _ if cfg!(not(unix)) => {
+                        return Err(LuaError::RuntimeError("cdef option 'signals' requires a Unix platform".to_string()));
+                    }
+                    "retry" => Some(parser::SignalHandling::Retry),
+                    "mask" => Some(parser::SignalHandling::Mask),
+                    handling => {
+                        return Err(LuaError::RuntimeError(format!("Unknown cdef signal handling: {}", handling)));
+                    }
+                };
+            }
+            (key @ ("abi" | "strict" | "enum" | "bitfields" | "optional" | "signals"), value) => {
+                return Err(LuaError::RuntimeError(format!(
+                    "Invalid value for cdef option '{}': {}",
+                    key,
+                    value.type_name()
+                )));
+            }
+            (key, _) => return Err(LuaError::RuntimeError(format!("Unknown cdef option: {}", key))),
+        }
+    }
+    Ok(options)
+}
+
+pub fn register_symbol(
+    name: String,
+    ctype: CType,
+    convention: CallConv,
+    optional: Option<parser::MissingSymbol>,
+    signals: Option<parser::SignalHandling>,
+) {
+    declare(|declarations| {
+        // Redeclaring a function without the attribute or option makes it required again, and
+        // its calls plain
+        match optional {
+            Some(missing) => declarations.optional.insert(name.clone(), missing),
+            None => declarations.optional.remove(&name),
+        };
+        match signals {
+            Some(handling) => declarations.signals.insert(name.clone(), handling),
+            None => declarations.signals.remove(&name),
+        };
+        declarations.symbols.insert(name, (ctype, convention));
+    });
+}
+
+#[inline]
+pub fn lookup_symbol(name: &str) -> Option<(CType, CallConv)> {
+    lookup(name, |declarations| &declarations.symbols)
+}
+
+/// How the declared function `name` behaves when missing, if it was declared optional
+#[inline]
+pub fn lookup_optional(name: &str) -> Option<parser::MissingSymbol> {
+    lookup(name, |declarations| &declarations.optional)
+}
+
+/// How calls of the declared function `name` deal with signals, if it was declared with them
+#[inline]
+pub fn lookup_signals(name: &str) -> Option<parser::SignalHandling> {
+    lookup(name, |declarations| &declarations.signals)
+}
+
+/// Define the struct in `code` with the struct `base_name` at its start, as `ffi.extend` does:
+/// the base's fields become its own unless the options ask for a member holding the base
+pub fn extend(lua: &Lua, base_name: &str, code: &str, options: Option<LuaTable>) -> LuaResult<()> {
+    let base = lookup_type(base_name)?;
+    match base.resolved() {
+        CType::Struct(_, fields) if !fields.is_empty() => {}
+        CType::Struct(_, _) => return Err(LuaError::RuntimeError(format!("Cannot extend incomplete type '{}'", base))),
+        _ => return Err(LuaError::RuntimeError(format!("Cannot extend '{}', which is not a struct", base))),
+    }
+
+    // `flatten` and `field` choose how the base is embedded, the rest are cdef options
+    let cdef = lua.create_table()?;
+    let (mut flatten, mut field) = (None, None);
+    if let Some(options) = options {
+        for pair in options.pairs::<String, LuaValue>() {
+            match pair? {
+                (key, value) if key == "flatten" => flatten = Some(bool::from_lua(value, lua)?),
+                (key, value) if key == "field" => field = Some(String::from_lua(value, lua)?),
+                (key, value) => cdef.set(key, value)?,
+            }
+        }
+    }
+    let member = match (flatten.unwrap_or(field.is_none()), field) {
+        (true, Some(_)) => {
+            return Err(LuaError::RuntimeError("ffi.extend cannot flatten a base with a field name".to_string()));
+        }
+        (true, None) => {
+            // Flattened fields keep the alignment they have in the base
+            if base.alignment() < base.resolved().alignment() {
+                return Err(LuaError::RuntimeError(format!("Cannot flatten packed struct '{}'", base)));
+            }
+            None
+        }
+        (false, field) => Some(field.unwrap_or_else(|| "base".to_string())),
+    };
+
+    let derived = parser::parse_extension(code, base.clone(), member, cdef_options(cdef)?)
+        .map_err(|e| FfiError::new(ErrorKind::ParseError, format!("Failed to extend '{}': {}", base, e)))?;
+    if let CType::Struct(name, _) = derived.resolved()
+        && !name.is_empty()
+    {
+        declare(|declarations| {
+            declarations.bases.insert(format!("struct {}", name), base);
+        });
+    }
+    Ok(())
+}
+
+// Whether the struct `source` starts with `target` through `ffi.extend`, directly or further up
+fn derives_from(source: &CType, target: &CType) -> bool {
+    let mut source = source.resolved().clone();
+    while let CType::Struct(name, _) = &source
+        && let Some(base) = lookup(&format!("struct {}", name), |declarations| &declarations.bases)
+    {
+        if base.resolved().same_type(target.resolved()) {
+            return true;
+        }
+        source = base.resolved().clone();
+    }
+    false
+}
+
+/// Create a cdata of `type_name`. A variable-length array takes its element count from `init`
+/// and its contents from `vla_init`, which other types do not accept
+pub fn new_cdata(
+    lua: &Lua,
+    type_name: &str,
+    init: Option<LuaValue>,
+    vla_init: Option<LuaValue>,
+) -> LuaResult<LuaAnyUserData> {
+    let mut ctype = lookup_type(type_name)?;
+
+    // `T[]` takes its length from the initializer: the elements of a table, or the bytes of a
+    // string and its terminating NUL
+    if let CType::Array(elem_type, 0) = &ctype
+        && let Some(count) = initializer_length(elem_type, init.as_ref())
+    {
+        ctype = CType::Array(elem_type.clone(), count);
+    }
     
     // Handle VLA: extract size from init parameter
     let (actual_ctype, size, actual_init) = match &ctype {
@@ -75,7 +449,13 @@ pub fn new_cdata(lua: &Lua, type_name: &str, init: Option<LuaValue>) -> LuaResul
             let total_size = elem_size * count;
             // Convert VLA to Array with actual size
             let array_type = CType::Array(elem_type.clone(), count);
-            (array_type, total_size, None)
+            (array_type, total_size, vla_init)
+        }
+        _ if vla_init.is_some() => {
+            return Err(LuaError::RuntimeError(format!(
+                "Only variable-length arrays take an initializer after their size, not '{}'",
+                ctype
+            )));
         }
         _ => {
             let size = ctype.size();
@@ -83,6 +463,24 @@ pub fn new_cdata(lua: &Lua, type_name: &str, init: Option<LuaValue>) -> LuaResul
         }
     };
 
+    // Pointer cdata hold the address itself, so `int *p = arr` yields the first element
+    if let CType::Ptr(target) = actual_ctype.resolved() {
+        let mut address: *mut u8 = std::ptr::null_mut();
+        let mut ownership = Ownership::Foreign;
+        if let Some(init_value) = actual_init {
+            if let LuaValue::Integer(i) = integral(init_value.clone()) {
+                check_pointer_address(&actual_ctype, target, i)?;
+            }
+            if let LuaValue::UserData(ud) = &init_value
+                && let Ok(source) = ud.borrow::<CData>()
+            {
+                ownership = source.derived_ownership();
+            }
+            write_value_to_ptr(&mut address as *mut *mut u8 as *mut u8, &actual_ctype, init_value)?;
+        }
+        return lua.create_userdata(CData::from_ptr(actual_ctype, address, ownership));
+    }
+
     let mut cdata = CData::new(actual_ctype, size);
 
     // Initialize the memory if init value is provided
@@ -90,121 +488,645 @@ pub fn new_cdata(lua: &Lua, type_name: &str, init: Option<LuaValue>) -> LuaResul
         initialize_cdata(&mut cdata, init_value)?;
     }
 
-    lua.create_userdata(cdata)
+    create_owned(lua, cdata)
+}
+
+// `ffi.new("T*", address)` makes a pointer holding `address`, not storage for a T, so numbers
+// that cannot be the address of a T are more likely that mistake; `ffi.cast` takes any
+fn check_pointer_address(ctype: &CType, target: &CType, address: i64) -> LuaResult<()> {
+    const NULL_PAGE: i64 = 4096;
+    let align = match target.resolved() {
+        CType::Function(_, _) => 1,
+        target => target.alignment().max(1),
+    };
+    let problem = if address < 0 {
+        "is negative".to_string()
+    } else if usize::try_from(address).is_err() {
+        "does not fit in a pointer".to_string()
+    } else if address > 0 && address < NULL_PAGE {
+        "lies in the null page".to_string()
+    } else if !(address as usize).is_multiple_of(align) {
+        format!("is not aligned to {} bytes", align)
+    } else {
+        return Ok(());
+    };
+    let message = format!(
+        "Cannot make a '{}' holding address {}: it {}. ffi.new(\"{}\", x) creates a pointer to address x; \
+         allocate a value with ffi.new(\"{}[1]\", {{x}}) or use ffi.cast for unchecked addresses",
+        ctype, address, problem, ctype, target
+    );
+    Err(FfiError::new(ErrorKind::Runtime, message).with_type(ctype).into())
+}
+
+// Number of elements an initializer gives an array of unspecified length
+fn initializer_length(elem_type: &CType, init: Option<&LuaValue>) -> Option<usize> {
+    match init? {
+        LuaValue::Table(table) => Some(table.raw_len()),
+        LuaValue::String(s) if elem_type.is_wchar() => Some(wide_units(&s.as_bytes()).len() + 1),
+        LuaValue::String(s) if elem_type.size() == 1 && elem_type.resolved().integer_kind().is_some() => {
+            Some(s.as_bytes().len() + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Allocate a one-element `T[1]` to receive a C out-parameter; it decays to `T*` when
+/// passed to a function, and `:get()` reads the value back
+pub fn new_out(lua: &Lua, type_name: &str, init: Option<LuaValue>) -> LuaResult<LuaAnyUserData> {
+    let ctype = lookup_type(type_name)?;
+    if matches!(ctype.resolved(), CType::Void | CType::VLA(_) | CType::Function(_, _)) || ctype.size() == 0 {
+        return Err(LuaError::RuntimeError(format!("Cannot create an out-parameter of type '{}'", ctype)));
+    }
+
+    let cdata = CData::new(CType::Array(Box::new(ctype.clone()), 1), ctype.size()).into_out_param();
+    if let Some(init_value) = init {
+        write_value_to_ptr(cdata.ptr, &ctype, init_value)?;
+    }
+    create_owned(lua, cdata)
+}
+
+/// The raw bytes of a cdata's value: the memory of an object or array, or the address held by
+/// a pointer, in host byte order
+pub fn dump(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<LuaString> {
+    let cdata = cdata.borrow::<CData>()?;
+    cdata.check_live()?;
+    match cdata.ctype.resolved() {
+        CType::Ptr(_) | CType::Function(_, _) => lua.create_string((cdata.ptr as usize).to_ne_bytes()),
+        _ if cdata.is_null() => Err(LuaError::RuntimeError("NULL pointer".to_string())),
+        _ => {
+            cdata.check_bounds(0, cdata.size)?;
+            lua.create_string(unsafe { std::slice::from_raw_parts(cdata.ptr, cdata.size) })
+        }
+    }
+}
+
+/// Rebuild a cdata of `type_name` from bytes produced by `dump`. The length must match the
+/// size of the type; for a variable-length array (`T[?]`) it sets the element count
+pub fn undump(lua: &Lua, type_name: &str, bytes: &[u8]) -> LuaResult<LuaAnyUserData> {
+    let ctype = lookup_type(type_name)?;
+    let mismatch = |size: usize| {
+        LuaError::RuntimeError(format!(
+            "Cannot undump {} bytes into '{}', which has {}",
+            bytes.len(),
+            ctype,
+            size
+        ))
+    };
+    let ctype = match &ctype {
+        CType::VLA(elem) if elem.size() > 0 && bytes.len().is_multiple_of(elem.size()) => {
+            CType::Array(elem.clone(), bytes.len() / elem.size())
+        }
+        CType::VLA(elem) => return Err(mismatch(elem.size())),
+        _ if matches!(ctype.resolved(), CType::Void | CType::Function(_, _)) => {
+            return Err(LuaError::RuntimeError(format!("Cannot undump a value of type '{}'", ctype)));
+        }
+        _ if bytes.len() != ctype.size() => return Err(mismatch(ctype.size())),
+        _ => ctype.clone(),
+    };
+
+    // Addresses cannot be vouched for, so restored pointers belong to C
+    if let CType::Ptr(_) = ctype.resolved() {
+        let address = usize::from_ne_bytes(bytes.try_into().map_err(|_| mismatch(ctype.size()))?);
+        config::check_address(address)?;
+        return lua.create_userdata(CData::from_ptr(ctype, address as *mut u8, Ownership::Foreign));
+    }
+    let cdata = CData::new(ctype, bytes.len());
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), cdata.ptr, bytes.len()) };
+    create_owned(lua, cdata)
+}
+
+// Bit-fields are addressed as bit offsets from the start of their storage, counted from the
+// least significant bit of the first byte on little-endian targets and from the most
+// significant one on big-endian targets, as GCC and MSVC allocate them
+const BIG_ENDIAN: bool = cfg!(target_endian = "big");
+
+fn bit_span(shift: usize, width: usize) -> (usize, u128) {
+    let mask = if width >= 64 { u64::MAX as u128 } else { (1u128 << width) - 1 };
+    ((shift + width).div_ceil(8), mask)
+}
+
+// Storage bytes as one integer and the position of the field's lowest bit in it
+fn load_bits(ptr: *const u8, shift: usize, width: usize, big_endian: bool) -> (u128, usize) {
+    let (len, _) = bit_span(shift, width);
+    let byte = |i: usize| unsafe { *ptr.add(i) } as u128;
+    if big_endian {
+        ((0..len).fold(0, |raw, i| raw << 8 | byte(i)), 8 * len - shift - width)
+    } else {
+        ((0..len).fold(0, |raw, i| raw | byte(i) << (8 * i)), shift)
+    }
+}
+
+/// Read the `width` bits starting `shift` bits after `ptr`
+pub(crate) fn read_bits(ptr: *const u8, shift: usize, width: usize) -> u64 {
+    read_bits_ordered(ptr, shift, width, BIG_ENDIAN)
 }
 
-// Macro for writing numeric values
+fn read_bits_ordered(ptr: *const u8, shift: usize, width: usize, big_endian: bool) -> u64 {
+    let (_, mask) = bit_span(shift, width);
+    let (raw, low) = load_bits(ptr, shift, width, big_endian);
+    ((raw >> low) & mask) as u64
+}
+
+/// Replace the `width` bits starting `shift` bits after `ptr`, leaving the others untouched
+pub(crate) fn write_bits(ptr: *mut u8, shift: usize, width: usize, value: u64) {
+    write_bits_ordered(ptr, shift, width, value, BIG_ENDIAN)
+}
+
+fn write_bits_ordered(ptr: *mut u8, shift: usize, width: usize, value: u64, big_endian: bool) {
+    let (len, mask) = bit_span(shift, width);
+    let (raw, low) = load_bits(ptr, shift, width, big_endian);
+    let raw = (raw & !(mask << low)) | ((value as u128 & mask) << low);
+    for i in 0..len {
+        let byte = if big_endian { raw >> (8 * (len - 1 - i)) } else { raw >> (8 * i) };
+        unsafe { *ptr.add(i) = byte as u8 };
+    }
+}
+
+/// Whether integer (or enum) values of this type are signed
+pub(crate) fn is_signed(ctype: &CType) -> bool {
+    match ctype.resolved() {
+        CType::Enum(_, _, repr) | CType::Swapped(repr) => is_signed(repr),
+        ctype => ctype.integer_layout().is_some_and(|(_, signed)| signed),
+    }
+}
+
+/// Value of a Lua integer or of an integer cdata, e.g. a boxed `uint64_t` above `i64::MAX`
+pub(crate) fn wide_integer(value: &LuaValue) -> Option<i128> {
+    match value {
+        LuaValue::Integer(i) => Some(*i as i128),
+        LuaValue::UserData(ud) => cdata_integer(&*ud.borrow::<CData>().ok()?),
+        _ => None,
+    }
+}
+
+/// Value of an integer or enum cdata
+pub(crate) fn cdata_integer(cdata: &CData) -> Option<i128> {
+    let (ctype, swapped) = match cdata.ctype.resolved() {
+        CType::Enum(_, _, repr) => (repr.resolved(), false),
+        CType::Swapped(repr) => (repr.resolved(), true),
+        ctype => (ctype, false),
+    };
+    let (size, signed) = ctype.integer_layout()?;
+    if cdata.ptr.is_null() || cdata.size < size {
+        return None;
+    }
+    if swapped {
+        let mut bytes = [0u8; 8];
+        unsafe { std::ptr::copy_nonoverlapping(cdata.ptr, bytes.as_mut_ptr(), size) };
+        bytes[..size].reverse();
+        return Some(unsafe { read_integer(bytes.as_ptr(), size, signed) });
+    }
+    Some(unsafe { read_integer(cdata.ptr, size, signed) })
+}
+
+/// Read a `size`-byte integer of the given signedness
+pub(crate) unsafe fn read_integer(ptr: *const u8, size: usize, signed: bool) -> i128 {
+    unsafe {
+        match (size, signed) {
+            (1, true) => (ptr as *const i8).read_unaligned() as i128,
+            (2, true) => (ptr as *const i16).read_unaligned() as i128,
+            (4, true) => (ptr as *const i32).read_unaligned() as i128,
+            (_, true) => (ptr as *const i64).read_unaligned() as i128,
+            (1, false) => ptr.read_unaligned() as i128,
+            (2, false) => (ptr as *const u16).read_unaligned() as i128,
+            (4, false) => (ptr as *const u32).read_unaligned() as i128,
+            (_, false) => (ptr as *const u64).read_unaligned() as i128,
+        }
+    }
+}
+
+// Whether `value` survives being stored in a `width`-bit field of integer type `ctype`
+fn fits_bits(value: i128, ctype: &CType, width: usize) -> bool {
+    if is_signed(ctype) {
+        let half = 1i128 << (width - 1);
+        (-half..half).contains(&value)
+    } else {
+        (0..1i128 << width).contains(&value)
+    }
+}
+
+// Floats stored in a bit-field saturate at the field's range the way `as` does for whole
+// integer types: NaN becomes 0 and infinities clamp to the minimum or maximum
+fn clamp_bits(n: f64, ctype: &CType, width: usize) -> u64 {
+    let (min, max) = if is_signed(ctype) {
+        (-(1i128 << (width - 1)), (1i128 << (width - 1)) - 1)
+    } else {
+        (0, (1i128 << width) - 1)
+    };
+    (n as i128).clamp(min, max) as u64
+}
+
+// Macro for writing numeric values; outside strict mode `as` defines the float edge cases:
+// fractions truncate toward zero, out-of-range values and infinities saturate and NaN becomes 0
 macro_rules! write_numeric {
-    ($ptr:expr, $ty:ty, $value:expr) => {{
+    ($ptr:expr, $ty:ty, $value:expr, $ctype:expr, $strict:expr) => {{
+        let strict = $strict;
         let val = match $value {
-            LuaValue::Integer(i) => i as $ty,
+            LuaValue::Number(n) if strict => {
+                <$ty as Numeric>::exact_number(n).ok_or_else(|| not_representable(n, $ctype))?
+            }
             LuaValue::Number(n) => n as $ty,
-            _ => return Err(LuaError::RuntimeError(
-                format!("Expected number for {} type", stringify!($ty))
-            )),
+            value => match wide_integer(&value) {
+                Some(i) if strict => {
+                    <$ty as Numeric>::exact_integer(i).ok_or_else(|| not_representable(i, $ctype))?
+                }
+                Some(i) => i as $ty,
+                None => return Err(expected("number", &value)),
+            },
         };
-        *($ptr as *mut $ty) = val;
+        ($ptr as *mut $ty).write_unaligned(val);
     }};
 }
 
+// Exact conversions of Lua numbers used by strict mode
+trait Numeric: Sized {
+    fn exact_integer(i: i128) -> Option<Self>;
+    fn exact_number(n: f64) -> Option<Self>;
+}
+
+macro_rules! integer_numeric {
+    ($($ty:ty),*) => {$(
+        impl Numeric for $ty {
+            fn exact_integer(i: i128) -> Option<Self> {
+                <$ty>::try_from(i).ok()
+            }
+
+            // i128 holds every value of the 64-bit types, so the range check is exact
+            fn exact_number(n: f64) -> Option<Self> {
+                if n.fract() != 0.0 {
+                    return None;
+                }
+                <$ty>::try_from(n as i128).ok()
+            }
+        }
+    )*};
+}
+
+integer_numeric!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! float_numeric {
+    ($($ty:ty),*) => {$(
+        impl Numeric for $ty {
+            fn exact_integer(i: i128) -> Option<Self> {
+                Some(i as $ty)
+            }
+
+            // Finite values must not overflow to infinity
+            fn exact_number(n: f64) -> Option<Self> {
+                let val = n as $ty;
+                (val.is_finite() || !n.is_finite()).then_some(val)
+            }
+        }
+    )*};
+}
+
+float_numeric!(f32, f64);
+
+fn not_representable(value: impl std::fmt::Display, ctype: &CType) -> LuaError {
+    LuaError::RuntimeError(format!("{} is not representable as {}", value, ctype))
+}
+
+/// Lua 5.1/5.2, LuaJIT and Luau only have floating point numbers, so integral numbers are
+/// accepted wherever an integer index or address is expected
+pub(crate) fn integral(value: LuaValue) -> LuaValue {
+    match value {
+        LuaValue::Number(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+            LuaValue::Integer(n as i64)
+        }
+        value => value,
+    }
+}
+
+// Luau vectors initialize the leading elements or fields, e.g. `float[3]` or `struct { float x, y, z; }`
+#[cfg(feature = "luau")]
+fn vector_value(value: &LuaValue) -> Option<impl Iterator<Item = LuaValue>> {
+    let LuaValue::Vector(vector) = value else {
+        return None;
+    };
+    Some([vector.x(), vector.y(), vector.z()].into_iter().map(|component| LuaValue::Number(component as f64)))
+}
+
+#[cfg(not(feature = "luau"))]
+fn vector_value(_value: &LuaValue) -> Option<std::iter::Empty<LuaValue>> {
+    None
+}
+
 // Write a Lua value to memory at the given pointer
-fn write_value_to_ptr(ptr: *mut u8, ctype: &CType, value: LuaValue) -> LuaResult<()> {
+pub(crate) fn write_value_to_ptr(ptr: *mut u8, ctype: &CType, value: LuaValue) -> LuaResult<()> {
+    write_value(ptr, ctype, value, &mut String::new())
+}
+
+// Write a value, initializing aggregates member by member. `path` names the member being
+// written (e.g. `items[2].name`) so that errors say where a nested initializer failed
+fn write_value(ptr: *mut u8, ctype: &CType, value: LuaValue, path: &mut String) -> LuaResult<()> {
+    let fail = |path: &str, message: &str| LuaError::RuntimeError(at_path(path, message));
+
+    // An aggregate cdata of the same type initializes a member by copy, whatever the const
+    // qualifiers of either side
+    if let LuaValue::UserData(ud) = &value
+        && matches!(ctype, CType::Array(_, _) | CType::Struct(_, _) | CType::Union(_, _))
+        && let Ok(source) = ud.borrow::<CData>()
+    {
+        source.check_live()?;
+        let compatible = ctype.without_const().same_type(&source.ctype.without_const());
+        if !compatible || source.size < ctype.size() || source.is_null() {
+            return Err(fail(path, &format!("Cannot convert '{}' to '{}'", source.ctype, ctype)));
+        }
+        unsafe { std::ptr::copy(source.as_ptr(), ptr, ctype.size()) };
+        return Ok(());
+    }
+
+    unsafe {
+        match ctype {
+            // Typedef, const (initialization) or packing - unwrap and write to the underlying type
+            CType::Typedef(_, inner_type) | CType::Const(inner_type) | CType::Packed(_, inner_type) => {
+                write_value(ptr, inner_type, value, path)
+            }
+
+            CType::Swapped(inner_type) => {
+                write_value(ptr, inner_type, value, path)?;
+                std::slice::from_raw_parts_mut(ptr, inner_type.size()).reverse();
+                Ok(())
+            }
+
+            // Array type - initialize from table
+            CType::Array(elem_type, count) => {
+                let elem_size = elem_type.size();
+                match value {
+                    LuaValue::Table(table) => {
+                        for i in 0..*count {
+                            // Lua tables are 1-indexed; errors from an `__index` name the element
+                            let elem_value = table.get::<LuaValue>(i + 1).map_err(|error| {
+                                fail(&format!("{}[{}]", path, i), &error.to_string())
+                            })?;
+                            if !elem_value.is_nil() {
+                                write_member(ptr.add(i * elem_size), elem_type, elem_value, path, &format!("[{}]", i))?;
+                            }
+                        }
+                        Ok(())
+                    }
+                    // Wide strings convert from UTF-8, with a terminator if there is room
+                    LuaValue::String(s) if elem_type.is_wchar() => {
+                        let mut units = wide_units(&s.as_bytes());
+                        if units.len() < *count {
+                            units.push(0);
+                        }
+                        units.truncate(*count);
+                        write_wide_units(ptr, &units);
+                        Ok(())
+                    }
+                    LuaValue::String(s) => {
+                        // String initialization for char (byte) arrays
+                        if elem_size != 1 || !matches!(elem_type.resolved(), CType::Char | CType::UChar | CType::Int8 | CType::UInt8) {
+                            return Err(fail(path, &format!("expected table for {}, got string", ctype)));
+                        }
+                        let bytes = s.as_bytes();
+                        let copy_len = (*count).min(bytes.len());
+                        std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, copy_len);
+                        // Null-terminate if there's space
+                        if copy_len < *count {
+                            *ptr.add(copy_len) = 0;
+                        }
+                        Ok(())
+                    }
+                    value => {
+                        let Some(components) = vector_value(&value) else {
+                            let what = if elem_size == 1 { "table or string" } else { "table" };
+                            return Err(fail(path, &expected_message(what, &value)));
+                        };
+                        for (i, component) in components.take(*count).enumerate() {
+                            write_member(ptr.add(i * elem_size), elem_type, component, path, &format!("[{}]", i))?;
+                        }
+                        Ok(())
+                    }
+                }
+            }
+
+            // Struct type - initialize from table
+            CType::Struct(_, fields) => {
+                if let LuaValue::Table(table) = value {
+                    write_fields(ptr, ctype, fields, table, path)
+                } else if let Some(components) = vector_value(&value) {
+                    for (field, component) in fields.iter().zip(components) {
+                        write_member(ptr.add(field.offset), &field.ctype, component, path, &field.name)?;
+                    }
+                    Ok(())
+                } else {
+                    Err(fail(path, &expected_message("table", &value)))
+                }
+            }
+
+            // Union type - initialize from table (typically first field or named field)
+            CType::Union(_, fields) => {
+                if let LuaValue::Table(table) = value {
+                    write_fields(ptr, ctype, fields, table, path)
+                } else {
+                    Err(fail(path, &expected_message("table", &value)))
+                }
+            }
+
+            _ => write_scalar(ptr, ctype, value, config::strict_conversions()).map_err(|error| match error {
+                LuaError::RuntimeError(message) => fail(path, &message),
+                error => error,
+            }),
+        }
+    }
+}
+
+/// Assign to one field (`name`) or element (`[i]`) of a cdata, naming it in errors
+pub(crate) fn write_member_value(ptr: *mut u8, ctype: &CType, value: LuaValue, step: &str) -> LuaResult<()> {
+    write_member(ptr, ctype, value, &mut String::new(), step)
+}
+
+// Write one member of an aggregate, extending `path` with `.field` or `[index]` meanwhile
+fn write_member(ptr: *mut u8, ctype: &CType, value: LuaValue, path: &mut String, step: &str) -> LuaResult<()> {
+    let len = path.len();
+    if !path.is_empty() && !step.starts_with('[') {
+        path.push('.');
+    }
+    path.push_str(step);
+    let result = write_value(ptr, ctype, value, path);
+    path.truncate(len);
+    result
+}
+
+// Error for a value of the wrong kind, e.g. "expected number, got string"
+fn expected(what: &str, value: &LuaValue) -> LuaError {
+    LuaError::RuntimeError(expected_message(what, value))
+}
+
+fn expected_message(what: &str, value: &LuaValue) -> String {
+    format!("expected {}, got {}", what, describe(value))
+}
+
+/// Kind of a value for error messages, e.g. `number` or `cdata<struct point>`
+pub(crate) fn describe(value: &LuaValue) -> String {
+    match value {
+        LuaValue::UserData(ud) => match ud.borrow::<CData>() {
+            Ok(cdata) => format!("cdata<{}>", cdata.ctype),
+            Err(_) => "userdata".to_string(),
+        },
+        LuaValue::Integer(_) => "number".to_string(),
+        value => value.type_name().to_string(),
+    }
+}
+
+// Prefix an error message with the member it concerns, if any
+fn at_path(path: &str, message: &str) -> String {
+    match path {
+        "" => message.to_string(),
+        path if path.starts_with('[') => format!("element '{}': {}", path, message),
+        path => format!("field '{}': {}", path, message),
+    }
+}
+
+// Write a scalar through any typedef, const, packing or byte-order wrapper around its type
+fn write_converted(ptr: *mut u8, ctype: &CType, value: LuaValue, strict: bool) -> LuaResult<()> {
+    match ctype {
+        CType::Typedef(_, inner) | CType::Const(inner) | CType::Packed(_, inner) => {
+            write_converted(ptr, inner, value, strict)
+        }
+        CType::Swapped(inner) => {
+            write_converted(ptr, inner, value, strict)?;
+            unsafe { std::slice::from_raw_parts_mut(ptr, inner.size()).reverse() };
+            Ok(())
+        }
+        _ => write_scalar(ptr, ctype, value, strict),
+    }
+}
+
+// Write a scalar (or pointer) value
+fn write_scalar(ptr: *mut u8, ctype: &CType, value: LuaValue, strict: bool) -> LuaResult<()> {
     unsafe {
         match ctype {
             // Basic integer types
-            CType::Int => write_numeric!(ptr, i32, value),
-            CType::UInt => write_numeric!(ptr, u32, value),
-            CType::Long => write_numeric!(ptr, isize, value),
-            CType::ULong => write_numeric!(ptr, usize, value),
-            CType::LongLong => write_numeric!(ptr, i64, value),
-            CType::ULongLong => write_numeric!(ptr, u64, value),
+            CType::Int => write_numeric!(ptr, i32, value, ctype, strict),
+            CType::UInt => write_numeric!(ptr, u32, value, ctype, strict),
+            CType::Long => write_numeric!(ptr, std::ffi::c_long, value, ctype, strict),
+            CType::ULong => write_numeric!(ptr, std::ffi::c_ulong, value, ctype, strict),
+            CType::LongLong => write_numeric!(ptr, i64, value, ctype, strict),
+            CType::ULongLong => write_numeric!(ptr, u64, value, ctype, strict),
             
             // Character types
-            CType::Char => write_numeric!(ptr, i8, value),
-            CType::UChar => write_numeric!(ptr, u8, value),
+            CType::Char => write_numeric!(ptr, i8, value, ctype, strict),
+            CType::UChar => write_numeric!(ptr, u8, value, ctype, strict),
             
             // Short types
-            CType::Short => write_numeric!(ptr, i16, value),
-            CType::UShort => write_numeric!(ptr, u16, value),
+            CType::Short => write_numeric!(ptr, i16, value, ctype, strict),
+            CType::UShort => write_numeric!(ptr, u16, value, ctype, strict),
             
             // Fixed-width integer types
-            CType::Int8 => write_numeric!(ptr, i8, value),
-            CType::Int16 => write_numeric!(ptr, i16, value),
-            CType::Int32 => write_numeric!(ptr, i32, value),
-            CType::Int64 => write_numeric!(ptr, i64, value),
-            CType::UInt8 => write_numeric!(ptr, u8, value),
-            CType::UInt16 => write_numeric!(ptr, u16, value),
-            CType::UInt32 => write_numeric!(ptr, u32, value),
-            CType::UInt64 => write_numeric!(ptr, u64, value),
+            CType::Int8 => write_numeric!(ptr, i8, value, ctype, strict),
+            CType::Int16 => write_numeric!(ptr, i16, value, ctype, strict),
+            CType::Int32 => write_numeric!(ptr, i32, value, ctype, strict),
+            CType::Int64 => write_numeric!(ptr, i64, value, ctype, strict),
+            CType::UInt8 => write_numeric!(ptr, u8, value, ctype, strict),
+            CType::UInt16 => write_numeric!(ptr, u16, value, ctype, strict),
+            CType::UInt32 => write_numeric!(ptr, u32, value, ctype, strict),
+            CType::UInt64 => write_numeric!(ptr, u64, value, ctype, strict),
             
             // Size types
-            CType::SizeT => write_numeric!(ptr, usize, value),
-            CType::SSizeT => write_numeric!(ptr, isize, value),
+            CType::SizeT => write_numeric!(ptr, usize, value, ctype, strict),
+            CType::SSizeT => write_numeric!(ptr, isize, value, ctype, strict),
             
             // Floating point types
-            CType::Float => write_numeric!(ptr, f32, value),
-            CType::Double => write_numeric!(ptr, f64, value),
+            CType::Float => write_numeric!(ptr, f32, value, ctype, strict),
+            CType::Double => write_numeric!(ptr, f64, value, ctype, strict),
+            
+            // `_Bool` is stored as a single 0 or 1 byte
+            CType::Bool => ptr.write_unaligned(u8::from(truthy(&value)?)),
             
-            // Boolean type
-            CType::Bool => {
-                let val = match value {
-                    LuaValue::Boolean(b) => b,
-                    LuaValue::Integer(i) => i != 0,
-                    _ => return Err(LuaError::RuntimeError("Expected boolean or integer".to_string())),
+            // Bit-fields keep the neighbouring bits of their storage
+            CType::Bitfield(inner, shift, width) => {
+                let bits = match (inner.resolved(), integral(value)) {
+                    (CType::Bool, value) => u64::from(truthy(&value)?),
+                    (_, LuaValue::Number(n)) if strict => return Err(not_representable(n, ctype)),
+                    (_, LuaValue::Number(n)) => clamp_bits(n, inner, *width),
+                    (_, LuaValue::Boolean(b)) => u64::from(b),
+                    (_, value) => match wide_integer(&value) {
+                        Some(i) if strict && !fits_bits(i, inner, *width) => {
+                            return Err(not_representable(i, ctype));
+                        }
+                        Some(i) => i as u64,
+                        None => return Err(expected("number", &value)),
+                    },
                 };
-                *(ptr as *mut bool) = val;
+                write_bits(ptr, *shift, *width, bits);
             }
+
+            // Enum type, stored as its underlying integer type
+            CType::Enum(_, _, repr) => write_converted(ptr, repr, value, strict)?,
             
             // POSIX types (Unix only)
             #[cfg(unix)]
-            CType::InoT => write_numeric!(ptr, libc::ino_t, value),
+            CType::InoT => write_numeric!(ptr, libc::ino_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::DevT => write_numeric!(ptr, libc::dev_t, value),
+            CType::DevT => write_numeric!(ptr, libc::dev_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::GidT => write_numeric!(ptr, libc::gid_t, value),
+            CType::GidT => write_numeric!(ptr, libc::gid_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::ModeT => write_numeric!(ptr, libc::mode_t, value),
+            CType::ModeT => write_numeric!(ptr, libc::mode_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::NlinkT => write_numeric!(ptr, libc::nlink_t, value),
+            CType::NlinkT => write_numeric!(ptr, libc::nlink_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::UidT => write_numeric!(ptr, libc::uid_t, value),
+            CType::UidT => write_numeric!(ptr, libc::uid_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::OffT => write_numeric!(ptr, libc::off_t, value),
+            CType::OffT => write_numeric!(ptr, libc::off_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::PidT => write_numeric!(ptr, libc::pid_t, value),
+            CType::PidT => write_numeric!(ptr, libc::pid_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::UsecondsT => write_numeric!(ptr, libc::useconds_t, value),
+            CType::UsecondsT => write_numeric!(ptr, libc::useconds_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::SusecondsT => write_numeric!(ptr, libc::suseconds_t, value),
+            CType::SusecondsT => write_numeric!(ptr, libc::suseconds_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::BlksizeT => write_numeric!(ptr, libc::blksize_t, value),
+            CType::BlksizeT => write_numeric!(ptr, libc::blksize_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::BlkcntT => write_numeric!(ptr, libc::blkcnt_t, value),
+            CType::BlkcntT => write_numeric!(ptr, libc::blkcnt_t, value, ctype, strict),
             #[cfg(unix)]
-            CType::TimeT => write_numeric!(ptr, libc::time_t, value),
+            CType::TimeT => write_numeric!(ptr, libc::time_t, value, ctype, strict),
             
             // Pointer type
             CType::Ptr(inner_type) => {
-                match value {
-                    LuaValue::Integer(i) => *(ptr as *mut usize) = i as usize,
+                // Lua strings are immutable, so only read-only pointers may view their data
+                let string_target = inner_type.is_const()
+                    && matches!(inner_type.resolved(), CType::Char | CType::UChar | CType::Void);
+                match integral(value) {
+                    LuaValue::Integer(i) => {
+                        config::check_address(i as usize)?;
+                        (ptr as *mut usize).write_unaligned(i as usize)
+                    }
                     LuaValue::UserData(ud) => {
                         let cdata = ud.borrow::<CData>()?;
-                        *(ptr as *mut *mut u8) = cdata.as_ptr();
+                        // A pointer to a struct made with `ffi.extend` also points to its base
+                        let upcast = match cdata.ctype.resolved() {
+                            CType::Ptr(source) | CType::Array(source, _) | CType::VLA(source) => {
+                                (inner_type.is_const() || !source.is_const()) && derives_from(source, inner_type)
+                            }
+                            _ => false,
+                        };
+                        if !upcast && !inner_type.accepts_pointer_from(&cdata.ctype) {
+                            return Err(LuaError::RuntimeError(format!(
+                                "Cannot convert '{}' to '{}'",
+                                cdata.ctype, ctype
+                            )));
+                        }
+                        (ptr as *mut *mut u8).write_unaligned(cdata.as_ptr());
                     }
-                    LuaValue::String(s) if matches!(**inner_type, CType::Char | CType::UChar) => {
-                        // String literal assignment to char* pointer
+                    LuaValue::String(s) if string_target => {
+                        // String literal assignment to const char* pointer
                         // Note: This creates a pointer to the string's data, which may be temporary
                         // In a real implementation, you'd need to manage string lifetime
                         let bytes = s.as_bytes();
-                        *(ptr as *mut *const u8) = bytes.as_ptr();
+                        (ptr as *mut *const u8).write_unaligned(bytes.as_ptr());
                     }
+                    LuaValue::String(_) => return Err(LuaError::RuntimeError(
+                        "Cannot convert a Lua string to a mutable pointer (use const char*)".to_string()
+                    )),
                     LuaValue::Nil => {
                         // NULL pointer assignment
-                        *(ptr as *mut usize) = 0;
+                        (ptr as *mut usize).write_unaligned(0);
                     }
-                    _ => return Err(LuaError::RuntimeError(
-                        "Expected pointer value (integer, cdata, string, or nil)".to_string()
-                    )),
+                    value => return Err(expected("pointer (cdata, integer, string or nil)", &value)),
                 }
             }
             
@@ -215,85 +1137,12 @@ fn write_value_to_ptr(ptr: *mut u8, ctype: &CType, value: LuaValue) -> LuaResult
                 ));
             }
             
-            // Array type - initialize from table
-            CType::Array(elem_type, count) => {
-                match value {
-                    LuaValue::Table(table) => {
-                        let elem_size = elem_type.size();
-                        for i in 0..*count {
-                            // Lua tables are 1-indexed
-                            if let Ok(elem_value) = table.get::<LuaValue>(i + 1) {
-                                let elem_ptr = ptr.add(i * elem_size);
-                                write_value_to_ptr(elem_ptr, elem_type, elem_value)?;
-                            }
-                        }
-                    }
-                    LuaValue::String(s) => {
-                        // String initialization for char arrays
-                        if matches!(**elem_type, CType::Char | CType::UChar) {
-                            let bytes = s.as_bytes();
-                            let copy_len = (*count).min(bytes.len());
-                            std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, copy_len);
-                            // Null-terminate if there's space
-                            if copy_len < *count {
-                                *ptr.add(copy_len) = 0;
-                            }
-                        } else {
-                            return Err(LuaError::RuntimeError(
-                                "String initialization only supported for char arrays".to_string()
-                            ));
-                        }
-                    }
-                    _ => {
-                        return Err(LuaError::RuntimeError(
-                            "Array initialization requires a table or string (for char arrays)".to_string()
-                        ));
-                    }
-                }
-            }
-            
-            // Struct type - initialize from table
-            CType::Struct(_, fields) => {
-                if let LuaValue::Table(table) = value {
-                    for field in fields {
-                        if let Ok(field_value) = table.get::<LuaValue>(field.name.as_str()) {
-                            let field_ptr = ptr.add(field.offset);
-                            write_value_to_ptr(field_ptr, &field.ctype, field_value)?;
-                        }
-                    }
-                } else {
-                    return Err(LuaError::RuntimeError(
-                        "Struct initialization requires a table".to_string()
-                    ));
-                }
-            }
-            
-            // Union type - initialize from table (typically first field or named field)
-            CType::Union(_, fields) => {
-                if let LuaValue::Table(table) = value {
-                    // Try to find a matching field name in the table
-                    for field in fields {
-                        if let Ok(field_value) = table.get::<LuaValue>(field.name.as_str()) {
-                            let field_ptr = ptr.add(field.offset);
-                            write_value_to_ptr(field_ptr, &field.ctype, field_value)?;
-                            // For unions, we only initialize one field
-                            break;
-                        }
-                    }
-                } else {
-                    return Err(LuaError::RuntimeError(
-                        "Union initialization requires a table".to_string()
-                    ));
-                }
-            }
-            
-            // Typedef - unwrap and write to the underlying type
-            CType::Typedef(_, inner_type) => {
-                write_value_to_ptr(ptr, inner_type, value)?;
-            }
-            
+            // Aggregates and wrapper types are written by `write_value`
+            CType::Array(_, _) | CType::Struct(_, _) | CType::Union(_, _) | CType::Typedef(_, _)
+            | CType::Const(_) | CType::Packed(_, _) | CType::Swapped(_) => write_value_to_ptr(ptr, ctype, value)?,
+
             // Void type - cannot write
-            CType::Void => {
+            CType::Void | CType::VarArgs => {
                 return Err(LuaError::RuntimeError(
                     "Cannot assign value to void type".to_string()
                 ));
@@ -302,14 +1151,15 @@ fn write_value_to_ptr(ptr: *mut u8, ctype: &CType, value: LuaValue) -> LuaResult
             // Function type - assign function pointer
             CType::Function(_, _) => {
                 match value {
-                    LuaValue::Integer(i) => *(ptr as *mut usize) = i as usize,
+                    LuaValue::Integer(i) => {
+                        config::check_address(i as usize)?;
+                        (ptr as *mut usize).write_unaligned(i as usize)
+                    }
                     LuaValue::UserData(ud) => {
                         let cdata = ud.borrow::<CData>()?;
-                        *(ptr as *mut *mut u8) = cdata.as_ptr();
+                        (ptr as *mut *mut u8).write_unaligned(cdata.as_ptr());
                     }
-                    _ => return Err(LuaError::RuntimeError(
-                        "Function pointer requires integer or cdata".to_string()
-                    )),
+                    value => return Err(expected("function pointer (cdata or integer)", &value)),
                 }
             }
         }
@@ -317,101 +1167,638 @@ fn write_value_to_ptr(ptr: *mut u8, ctype: &CType, value: LuaValue) -> LuaResult
     Ok(())
 }
 
+// Initialize struct or union fields from a table mixing positional entries, which fill the
+// fields in declaration order, and entries named after fields. A union sets a single member:
+// the first named one in declaration order, or else the first member from `{value}`
+fn write_fields(ptr: *mut u8, ctype: &CType, fields: &[CField], table: LuaTable, path: &mut String) -> LuaResult<()> {
+    let is_union = matches!(ctype, CType::Union(_, _));
+    let mut positional = Vec::new();
+    let mut named = Vec::new();
+    for pair in table.pairs::<LuaValue, LuaValue>() {
+        let (key, value) = pair?;
+        match integral(key) {
+            LuaValue::Integer(i) if i >= 1 => positional.push((i as usize - 1, value)),
+            LuaValue::String(name) => {
+                let name = name.to_str()?;
+                let Some(index) = fields.iter().position(|field| field.name == *name) else {
+                    return Err(LuaError::RuntimeError(at_path(
+                        path,
+                        &format!("Unknown field '{}' in initializer for {}", &*name, ctype),
+                    )));
+                };
+                named.push((index, value));
+            }
+            key => {
+                return Err(LuaError::RuntimeError(at_path(
+                    path,
+                    &format!("Invalid key in initializer for {}: {}", ctype, key.type_name()),
+                )));
+            }
+        }
+    }
+
+    let limit = if is_union { fields.len().min(1) } else { fields.len() };
+    if let Some((index, _)) = positional.iter().find(|(index, _)| *index >= limit) {
+        return Err(LuaError::RuntimeError(at_path(
+            path,
+            &format!("Too many initializers for {}: {} given, {} fields", ctype, index + 1, limit),
+        )));
+    }
+
+    // Named entries are applied last so they override positional ones
+    positional.sort_unstable_by_key(|(index, _)| *index);
+    named.sort_unstable_by_key(|(index, _)| *index);
+    if is_union && !named.is_empty() {
+        positional.clear();
+        named.truncate(1);
+    }
+    for (index, value) in positional.into_iter().chain(named) {
+        let field = &fields[index];
+        write_member(unsafe { ptr.add(field.offset) }, &field.ctype, value, path, &field.name)?;
+    }
+    Ok(())
+}
+
 // Helper function to initialize CData with a value
 fn initialize_cdata(cdata: &mut CData, value: LuaValue) -> LuaResult<()> {
     if cdata.ptr.is_null() || cdata.size == 0 {
         return Ok(());
     }
+    write_value_to_ptr(cdata.ptr, &cdata.ctype, value)
+}
+
+pub fn cast_cdata(lua: &Lua, type_name: &str, value: LuaValue) -> LuaResult<LuaAnyUserData> {
+    let ctype = lookup_type(type_name)?;
+
+    // Casting a Lua function to a function pointer type creates a callback
+    if let LuaValue::Function(func) = value {
+        let code = callback::create(lua, &ctype, func)?;
+        return lua.create_userdata(CData::from_ptr(ctype, code as *mut u8, Ownership::Borrowed));
+    }
+
+    if is_scalar(&ctype) {
+        return cast_scalar(lua, ctype, value);
+    }
+
+    // A cast keeps the provenance of its source: addresses from C stay foreign, views of
+    // memory owned elsewhere stay borrowed
+    // The bounds of a buffer carry over, so accesses through the cast stay checked
+    let (ptr, ownership, bounds) = match integral(value) {
+        LuaValue::Integer(i) => {
+            config::check_address(i as usize)?;
+            (i as *mut u8, Ownership::Foreign, None)
+        }
+        LuaValue::UserData(ud) => match ud.borrow::<CFunction>() {
+            Ok(func) => (func.ptr as *mut u8, Ownership::Foreign, None),
+            Err(_) => {
+                let source = ud.borrow::<CData>()?;
+                source.check_live()?;
+                let bounds = match source.ctype.resolved() {
+                    CType::Array(_, _) | CType::VLA(_) | CType::Ptr(_) => source.bounds(),
+                    _ => None,
+                };
+                (source.as_ptr(), source.derived_ownership(), bounds)
+            }
+        },
+        _ => return Err(LuaError::RuntimeError("Cannot cast this value".to_string())),
+    };
+
+    if config::strict_alignment() {
+        check_cast_alignment(&ctype, ptr)?;
+    }
+    let cdata = CData::from_ptr(ctype, ptr, ownership);
+    lua.create_userdata(match bounds {
+        Some(bounds) => cdata.with_bounds(bounds),
+        None => cdata,
+    })
+}
+
+// A pointer must suit what it points to, a view of memory the type it sees the memory as.
+// NULL and opaque targets (`void`, functions, incomplete structs) pass
+fn check_cast_alignment(ctype: &CType, ptr: *mut u8) -> LuaResult<()> {
+    // Resolving would strip packing, which lowers the alignment
+    let target = match ctype.resolved() {
+        CType::Ptr(target) => target,
+        _ => ctype,
+    };
+    let align = match target.resolved() {
+        CType::Void | CType::Function(_, _) => 1,
+        _ => target.alignment().max(1),
+    };
+    if ptr.is_null() || (ptr as usize).is_multiple_of(align) {
+        return Ok(());
+    }
+    let message = format!(
+        "Cannot cast address 0x{:x} to '{}': '{}' needs {}-byte alignment",
+        ptr as usize, ctype, target, align
+    );
+    Err(FfiError::new(ErrorKind::Runtime, message).with_type(ctype).into())
+}
+
+// Arithmetic types and `bool`, which casts convert by value
+fn is_scalar(ctype: &CType) -> bool {
+    !matches!(
+        ctype.resolved(),
+        CType::Void
+            | CType::Ptr(_)
+            | CType::Array(_, _)
+            | CType::VLA(_)
+            | CType::Struct(_, _)
+            | CType::Union(_, _)
+            | CType::Function(_, _)
+            | CType::VarArgs
+    )
+}
+
+// Convert a number, boolean, scalar cdata or address to a new value of `ctype` the way a C cast
+// does: integers wrap or extend, floating point truncates towards zero, whatever strict
+// conversions say about implicit ones
+fn cast_scalar(lua: &Lua, ctype: CType, value: LuaValue) -> LuaResult<LuaAnyUserData> {
+    let value = match value {
+        LuaValue::Integer(_) | LuaValue::Number(_) | LuaValue::Boolean(_) => value,
+        LuaValue::UserData(ud) => {
+            let source = ud.borrow::<CData>()?;
+            source.check_live()?;
+            match source.ctype.resolved() {
+                // Pointers and arrays convert their address
+                CType::Ptr(_) | CType::Function(_, _) | CType::Array(_, _) | CType::VLA(_) => {
+                    LuaValue::Integer(source.as_ptr() as usize as i64)
+                }
+                CType::Struct(_, _) | CType::Union(_, _) => {
+                    return Err(LuaError::RuntimeError(format!("Cannot cast '{}' to '{}'", source.ctype, ctype)));
+                }
+                _ if source.is_null() => return Err(LuaError::RuntimeError("NULL pointer".to_string())),
+                CType::Float | CType::Double => LuaValue::Number(cdata_to_number(ud.clone())?),
+                CType::Bool => LuaValue::Boolean(truthy(&LuaValue::UserData(ud.clone()))?),
+                _ => match cdata_integer(&source) {
+                    Some(i) => integer_value(lua, &source.ctype, i)?,
+                    None => return Err(LuaError::RuntimeError(format!("Cannot cast '{}' to '{}'", source.ctype, ctype))),
+                },
+            }
+        }
+        _ => return Err(LuaError::RuntimeError(format!("Cannot cast {} to '{}'", describe(&value), ctype))),
+    };
+    // Like C, truncate towards zero and then wrap rather than saturate at the type's range
+    let value = match value {
+        LuaValue::Boolean(b) if !matches!(ctype.resolved(), CType::Bool) => LuaValue::Integer(b.into()),
+        LuaValue::Number(n) if !matches!(ctype.resolved(), CType::Float | CType::Double | CType::Bool) && n.is_finite() => {
+            integer_value(lua, &CType::Int64, n.trunc() as i128)?
+        }
+        value => value,
+    };
+    let cdata = CData::new(ctype.clone(), ctype.size());
+    // Casts convert like C whatever `ffi.strictconv` says
+    write_converted(cdata.as_ptr(), &ctype, value, false)?;
+    create_owned(lua, cdata)
+}
+
+/// View the memory of a cdata as another type, e.g. a received packet as its header struct.
+/// Unlike `ffi.cast`, which converts values and pointers, this always reinterprets the bytes
+/// the cdata holds (or points to), and fails if the new type does not fit in them. A
+/// variable-length array (`T[?]`) takes as many elements as fit
+pub fn reinterpret(lua: &Lua, type_name: &str, cdata: LuaAnyUserData) -> LuaResult<LuaAnyUserData> {
+    let ctype = lookup_type(type_name)?;
+    let source = cdata.borrow::<CData>()?;
+    source.check_live()?;
+    if source.is_null() {
+        return Err(LuaError::RuntimeError("NULL pointer".to_string()));
+    }
+    // Memory a pointer refers to has no known extent
+    let extent = match source.ctype.resolved() {
+        CType::Ptr(_) => None,
+        CType::Function(_, _) => {
+            return Err(LuaError::RuntimeError(format!("Cannot reinterpret '{}'", source.ctype)));
+        }
+        _ => Some(source.size),
+    };
+    let ctype = match (&ctype, extent) {
+        (CType::VLA(elem), Some(extent)) if elem.size() > 0 => CType::Array(elem.clone(), extent / elem.size()),
+        (CType::VLA(_), _) => {
+            return Err(LuaError::RuntimeError(format!(
+                "Cannot size '{}' from '{}', whose extent is unknown",
+                ctype, source.ctype
+            )));
+        }
+        (ctype, _) if matches!(ctype.resolved(), CType::Void | CType::Function(_, _)) => {
+            return Err(LuaError::RuntimeError(format!("Cannot reinterpret memory as '{}'", ctype)));
+        }
+        (ctype, Some(extent)) if ctype.size() > extent => {
+            return Err(LuaError::RuntimeError(format!(
+                "Cannot reinterpret {}-byte '{}' as {}-byte '{}'",
+                extent,
+                source.ctype,
+                ctype.size(),
+                ctype
+            )));
+        }
+        (ctype, _) => ctype.clone(),
+    };
+    if extent.is_some() {
+        source.check_bounds(0, ctype.size())?;
+    }
+
+    // A pointer stored in the memory is read like a pointer member
+    let view = match ctype.resolved() {
+        CType::Ptr(_) => {
+            let address = unsafe { (source.as_ptr() as *const *mut u8).read_unaligned() };
+            CData::from_ptr(ctype, address, Ownership::Foreign)
+        }
+        _ => CData::from_ptr(ctype, source.as_ptr(), source.derived_ownership()),
+    };
+    lua.create_userdata(view)
+}
+
+/// A view of the same memory that refuses writes: its members, elements and the memory a
+/// pointer refers to are const, so assignments, `ffi.copy` and `ffi.fill` through it fail
+pub fn const_view(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<LuaAnyUserData> {
+    let source = cdata.borrow::<CData>()?;
+    source.check_live()?;
+    let view = CData::from_ptr(source.ctype.read_only(), source.as_ptr(), source.derived_ownership());
+    lua.create_userdata(view)
+}
+
+/// The value a pointer refers to, like `ptr[0]` but refusing NULL: scalars convert to Lua
+/// values and aggregates become views of the memory
+pub fn deref(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<LuaValue> {
+    let cd = cdata.borrow::<CData>()?;
+    cd.check_live()?;
+    match cd.ctype.resolved() {
+        CType::Ptr(target) if matches!(target.resolved(), CType::Void | CType::Function(_, _)) => {
+            Err(LuaError::RuntimeError(format!("Cannot dereference '{}'", cd.ctype)))
+        }
+        CType::Ptr(_) => {
+            cd.check_not_null()?;
+            read_member(lua, cd.as_ptr(), cd.ctype.member(0))
+        }
+        _ => Err(LuaError::RuntimeError(format!("Cannot dereference '{}', which is not a pointer", cd.ctype))),
+    }
+}
+
+pub fn set_metatype(lua: &Lua, type_name: &str, metatable: LuaTable) -> LuaResult<LuaValue> {
+    // Key by the type rather than its spelling, so every constructor and cast of it agrees
+    let ctype = lookup_type(type_name)?;
+    let key = metatype_key(&ctype);
+    let metatypes = metatypes(lua)?;
+    if metatypes.contains_key(key.as_str())? {
+        return Err(LuaError::RuntimeError(format!(
+            "Metatype of '{}' is already set and cannot be changed",
+            key
+        )));
+    }
+
+    // Move the contents behind the caller's table, which becomes a read-only view of them
+    let contents = lua.create_table()?;
+    for pair in metatable.pairs::<LuaValue, LuaValue>() {
+        let (name, value) = pair?;
+        contents.raw_set(name, value)?;
+    }
+    contents.set_metatable(metatable.metatable())?;
+    metatable.clear()?;
+    let guard = lua.create_table()?;
+    guard.raw_set("__index", &contents)?;
+    guard.raw_set(
+        "__newindex",
+        lua.create_function(|_, ()| -> LuaResult<()> {
+            Err(LuaError::RuntimeError("Cannot modify a metatable attached with ffi.metatype".to_string()))
+        })?,
+    )?;
+    let next: LuaFunction = lua.globals().raw_get("next")?;
+    let listed = contents.clone();
+    guard.raw_set(
+        "__pairs",
+        lua.create_function(move |_, _: LuaValue| Ok((next.clone(), listed.clone(), LuaValue::Nil)))?,
+    )?;
+    guard.raw_set("__metatable", false)?;
+    metatable.set_metatable(Some(guard))?;
+    metatypes.raw_set(key, contents)?;
+
+    // Return the metatable
+    Ok(LuaValue::Table(metatable))
+}
+
+// The type a metatable attaches to: a named struct, union or enum whatever typedef reaches it,
+// otherwise the last typedef in a chain of aliases, so `typedef int handle; typedef handle fd;`
+// shares one metatype while plain `int` stays untouched
+fn metatype_key(ctype: &CType) -> String {
+    if let tagged @ (CType::Struct(name, _) | CType::Union(name, _) | CType::Enum(name, _, _)) = ctype.resolved()
+        && !name.is_empty()
+    {
+        return tagged.to_string();
+    }
+    let mut ctype = ctype.unqualified();
+    while let CType::Typedef(_, inner) = ctype
+        && let alias @ CType::Typedef(_, _) = inner.unqualified()
+    {
+        ctype = alias;
+    }
+    ctype.to_string()
+}
+
+// Contents of the metatables attached with ffi.metatype, keyed by type
+fn metatypes(lua: &Lua) -> LuaResult<LuaTable> {
+    if let LuaValue::Table(metatypes) = lua.named_registry_value(METATYPES)? {
+        return Ok(metatypes);
+    }
+    let metatypes = lua.create_table()?;
+    lua.set_named_registry_value(METATYPES, &metatypes)?;
+    Ok(metatypes)
+}
+
+const METATYPES: &str = "ffi_metatypes";
+
+/// Look up `name` in the metatable attached to a cdata's type
+pub(crate) fn metamethod(lua: &Lua, ctype: &CType, name: &str) -> LuaResult<Option<LuaValue>> {
+    let LuaValue::Table(metatypes) = lua.named_registry_value(METATYPES)? else {
+        return Ok(None);
+    };
+    let Some(metatable) = metatypes.raw_get::<Option<LuaTable>>(metatype_key(ctype))? else {
+        return Ok(None);
+    };
+    Ok(match metatable.raw_get(name)? {
+        LuaValue::Nil => None,
+        handler => Some(handler),
+    })
+}
+
+pub fn get_address(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<LuaAnyUserData> {
+    let cd = cdata.borrow::<CData>()?;
+    let ptr_type = CType::Ptr(Box::new((*cd.ctype).clone()));
+    let addr_cdata = CData::from_ptr(ptr_type, cd.as_ptr(), Ownership::Borrowed);
+    lua.create_userdata(addr_cdata)
+}
+
+// One step of a field path such as `points[2].x`
+enum PathStep<'a> {
+    Field(&'a str),
+    Index(usize),
+}
+
+fn parse_field_path(path: &str) -> LuaResult<Vec<PathStep<'_>>> {
+    let invalid = || LuaError::RuntimeError(format!("Invalid field path: {}", path));
+    let mut steps = Vec::new();
+    let mut rest = path.trim();
+    while !rest.is_empty() {
+        if let Some(index) = rest.strip_prefix('[') {
+            let (digits, tail) = index.split_once(']').ok_or_else(invalid)?;
+            steps.push(PathStep::Index(digits.trim().parse().map_err(|_| invalid())?));
+            rest = tail;
+        } else {
+            // Fields after the first are separated by '.'
+            let field = match rest.strip_prefix('.') {
+                Some(field) => field,
+                None if steps.is_empty() => rest,
+                None => return Err(invalid()),
+            };
+            let end = field.find(['.', '[']).unwrap_or(field.len());
+            let name = &field[..end];
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return Err(invalid());
+            }
+            steps.push(PathStep::Field(name));
+            rest = &field[end..];
+        }
+    }
+    if steps.is_empty() {
+        return Err(invalid());
+    }
+    Ok(steps)
+}
+
+/// Typed pointer to a field or element inside a cdata, e.g. `points[2].x`. Pointers along
+/// the path are followed, so the path works like `->` and `[]` in C
+pub fn get_field_address(lua: &Lua, cdata: LuaAnyUserData, path: &str) -> LuaResult<LuaAnyUserData> {
+    let cd = cdata.borrow::<CData>()?;
+    let mut ctype = (*cd.ctype).clone();
+    let mut ptr = cd.as_ptr();
+    // Pointer cdata hold their target address; pointers inside objects must be loaded
+    let mut loaded = true;
+
+    for step in parse_field_path(path)? {
+        if let CType::Ptr(target) = ctype.resolved() {
+            let target = (**target).clone();
+            if !loaded {
+                ptr = unsafe { (ptr as *const *mut u8).read_unaligned() };
+            }
+            if ptr.is_null() {
+                return Err(LuaError::RuntimeError(format!(
+                    "Cannot follow a NULL pointer in field path: {}",
+                    path
+                )));
+            }
+            ctype = target;
+            if let PathStep::Index(i) = step {
+                ptr = unsafe { ptr.add(i * ctype.size()) };
+                loaded = false;
+                continue;
+            }
+        }
+        loaded = false;
 
-    match &cdata.ctype {
-        CType::Struct(_, fields) | CType::Union(_, fields) => {
-            // Initialize struct/union fields from a table
-            if let LuaValue::Table(table) = value {
-                for field in fields {
-                    if let Ok(field_value) = table.get::<LuaValue>(field.name.as_str()) {
-                        let field_ptr = unsafe { cdata.ptr.add(field.offset) };
-                        write_value_to_ptr(field_ptr, &field.ctype, field_value)?;
-                    }
+        // Members of a const object are const as well
+        let constant = ctype.is_const();
+        let member = match (&step, ctype.resolved()) {
+            (PathStep::Field(name), CType::Struct(_, fields) | CType::Union(_, fields)) => {
+                let field = fields.iter().find(|field| field.name == *name).ok_or_else(|| {
+                    LuaError::RuntimeError(format!("Unknown field: {}", name))
+                })?;
+                if let CType::Bitfield(_, _, _) = field.ctype {
+                    return Err(LuaError::RuntimeError(format!("Cannot take the address of bit-field: {}", name)));
                 }
-            } else {
-                return Err(LuaError::RuntimeError(
-                    "Struct/union initialization requires a table".to_string()
-                ));
+                ptr = unsafe { ptr.add(field.offset) };
+                field.ctype.clone()
             }
-        }
-        CType::Array(elem_type, count) => {
-            // Initialize array elements from a table
-            if let LuaValue::Table(table) = value {
-                let elem_size = elem_type.size();
-                for i in 0..*count {
-                    // Lua tables are 1-indexed
-                    if let Ok(elem_value) = table.get::<LuaValue>(i + 1) {
-                        let elem_ptr = unsafe { cdata.ptr.add(i * elem_size) };
-                        write_value_to_ptr(elem_ptr, elem_type, elem_value)?;
-                    }
+            (PathStep::Index(i), CType::Array(elem_type, count)) => {
+                if i >= count {
+                    return Err(LuaError::RuntimeError(format!(
+                        "Index {} out of bounds for '{}'",
+                        i, ctype
+                    )));
                 }
-            } else {
-                return Err(LuaError::RuntimeError(
-                    "Array initialization requires a table".to_string()
-                ));
+                ptr = unsafe { ptr.add(i * elem_type.size()) };
+                (**elem_type).clone()
             }
-        }
-        _ => {
-            // Initialize scalar types directly
-            write_value_to_ptr(cdata.ptr, &cdata.ctype, value)?;
-        }
+            (PathStep::Field(name), _) => {
+                return Err(LuaError::RuntimeError(format!(
+                    "Cannot access field '{}' of '{}'",
+                    name, ctype
+                )));
+            }
+            (PathStep::Index(_), _) => {
+                return Err(LuaError::RuntimeError(format!("Cannot index '{}'", ctype)));
+            }
+        };
+        ctype = if constant && !member.is_const() { CType::Const(Box::new(member)) } else { member };
     }
-    Ok(())
+
+    let addr_cdata = CData::from_ptr(CType::Ptr(Box::new(ctype)), ptr, Ownership::Borrowed);
+    lua.create_userdata(addr_cdata)
 }
 
-pub fn cast_cdata(lua: &Lua, type_name: &str, value: LuaValue) -> LuaResult<LuaAnyUserData> {
-    let ctype = lookup_type(type_name)?;
+pub fn set_gc(
+    lua: &Lua,
+    cdata: LuaAnyUserData,
+    finalizer: Option<LuaValue>,
+) -> LuaResult<LuaAnyUserData> {
+    if let Some(fin) = &finalizer
+        && !matches!(fin, LuaValue::Function(_) | LuaValue::UserData(_))
+    {
+        return Err(LuaError::RuntimeError("Finalizer must be a function or nil".to_string()));
+    }
 
-    let ptr = match value {
-        LuaValue::Integer(i) => i as *mut u8,
-        LuaValue::UserData(ud) => {
-            let cdata = ud.borrow::<CData>()?;
-            cdata.as_ptr()
+    // Only foreign memory can take a finalizer, and only through one cdata at a time
+    cdata.borrow_mut::<CData>()?.set_finalized(finalizer.is_some())?;
+
+    // The finalizer lives in the cdata's user value, so it dies with the cdata rather than
+    // lingering in the registry; replacing or removing it disarms the previous one
+    disarm_finalizer(&cdata)?;
+    let state = match finalizer {
+        Some(fin) => {
+            let arm: LuaFunction = finalizers(lua)?.get("arm")?;
+            Some(arm.call::<LuaTable>((&cdata, fin))?)
         }
-        _ => return Err(LuaError::RuntimeError("Cannot cast this value".to_string())),
+        None => None,
     };
+    cdata.set_named_user_value(FINALIZER, state)?;
+    Ok(cdata)
+}
 
-    let cdata = CData::from_ptr(ctype, ptr, false);
-    lua.create_userdata(cdata)
+const FINALIZER: &str = "ffi_gc";
+
+// `arm(cdata, finalizer)` returns a state table that calls `finalizer(cdata)` once the cdata is
+// collected, unless `run(state)` has called it already. Tables only have `__gc` from Lua 5.2,
+// so 5.1 and LuaJIT hang it on a proxy instead; Luau never collects with finalizers
+fn finalizers(lua: &Lua) -> LuaResult<LuaTable> {
+    const KEY: &str = "ffi_finalizers";
+    if let LuaValue::Table(finalizers) = lua.named_registry_value(KEY)? {
+        return Ok(finalizers);
+    }
+    let finalizers: LuaTable = lua
+        .load(
+            r#"
+            local function run(state)
+                local finalizer = state[2]
+                if finalizer ~= nil then
+                    state[2] = nil
+                    finalizer(state[1])
+                end
+            end
+            local function arm(cdata, finalizer)
+                local state = {cdata, finalizer}
+                if newproxy then
+                    local proxy = newproxy(true)
+                    getmetatable(proxy).__gc = function() run(state) end
+                    state[3] = proxy
+                else
+                    setmetatable(state, {__gc = run})
+                end
+                return state
+            end
+            return {arm = arm, run = run}
+            "#,
+        )
+        .set_name("=ffi.gc")
+        .eval()?;
+    lua.set_named_registry_value(KEY, &finalizers)?;
+    Ok(finalizers)
 }
 
-pub fn set_metatype(lua: &Lua, type_name: &str, metatable: LuaTable) -> LuaResult<LuaValue> {
-    // Store the metatable in the Lua registry with a key based on type name
-    let registry_key = format!("ffi_metatype_{}", type_name);
-    lua.set_named_registry_value(&registry_key, metatable.clone())?;
-    
-    // Return the metatable
-    Ok(LuaValue::Table(metatable))
+// Stop a previously attached finalizer from running
+fn disarm_finalizer(cdata: &LuaAnyUserData) -> LuaResult<()> {
+    if let Some(state) = cdata.named_user_value::<Option<LuaTable>>(FINALIZER)? {
+        state.raw_set(2, LuaValue::Nil)?;
+    }
+    Ok(())
 }
 
-pub fn get_address(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<LuaAnyUserData> {
-    let cd = cdata.borrow::<CData>()?;
-    let ptr_type = CType::Ptr(Box::new(cd.ctype.clone()));
-    let addr_cdata = CData::from_ptr(ptr_type, cd.as_ptr(), false);
-    lua.create_userdata(addr_cdata)
+/// Release a cdata's memory now rather than at garbage collection: Lua-owned memory is
+/// deallocated and foreign memory goes to its `ffi.gc` finalizer. The cdata then reads as NULL
+/// and any access through it errors
+pub fn free_cdata(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<()> {
+    let (ownership, callback) = {
+        let cd = cdata.borrow::<CData>()?;
+        let callback = matches!(cd.ctype.resolved(), CType::Ptr(inner) if matches!(inner.resolved(), CType::Function(_, _)));
+        (cd.ownership, callback)
+    };
+    let finalized = match ownership {
+        // Callbacks release their trampoline instead
+        Ownership::Borrowed if callback => {
+            let free: LuaFunction = LuaFunction::from_lua(callback::method(lua, "free")?, lua)?;
+            return free.call(cdata);
+        }
+        Ownership::Lua => Ok(()),
+        Ownership::Finalized => match cdata.named_user_value::<Option<LuaTable>>(FINALIZER)? {
+            Some(state) => {
+                let run: LuaFunction = finalizers(lua)?.get("run")?;
+                run.call(state)
+            }
+            None => Ok(()),
+        },
+        Ownership::Foreign => {
+            return Err(LuaError::RuntimeError(
+                "Cannot free memory owned by C without a finalizer (attach one with ffi.gc)".to_string(),
+            ));
+        }
+        Ownership::Borrowed => {
+            return Err(LuaError::RuntimeError(
+                "Cannot free borrowed memory, which belongs to another cdata".to_string(),
+            ));
+        }
+        Ownership::Freed => return Err(LuaError::RuntimeError("cdata has already been freed".to_string())),
+    };
+    // The cdata is poisoned even if the finalizer failed, so it never runs twice
+    let mut cd = cdata.borrow_mut::<CData>()?;
+    #[cfg(feature = "canaries")]
+    cd.check_canaries()?;
+    if ownership == Ownership::Lua {
+        report_release(lua, cd.size);
+    }
+    cd.release();
+    finalized
 }
 
-pub fn set_gc(
-    lua: &Lua,
-    cdata: LuaAnyUserData,
-    finalizer: Option<LuaFunction>,
-) -> LuaResult<LuaAnyUserData> {
-    // Store the finalizer in a registry table associated with the userdata
-    // This is a workaround since mlua doesn't allow direct metatable modification
-    if let Some(fin) = finalizer {
-        // Create a unique key for this userdata in the registry
-        let registry_key = format!("ffi_gc_{:p}", cdata.to_pointer());
-        lua.set_named_registry_value(&registry_key, fin)?;
-        
-        // Note: In a complete implementation, we would need to modify the CData
-        // struct to store a flag indicating it has a finalizer, and call it in Drop
+/// Run `body` with an allocator whose cdata are freed, newest first, when `body` returns or
+/// raises. The allocator takes the arguments of `ffi.new`, or adopts a cdata that is already
+/// Lua-owned or carries an `ffi.gc` finalizer
+pub fn scope(lua: &Lua, body: LuaFunction) -> LuaResult<LuaMultiValue> {
+    let members: Rc<RefCell<Option<Vec<LuaAnyUserData>>>> = Rc::new(RefCell::new(Some(Vec::new())));
+    let registry = members.clone();
+    let alloc = lua.create_function(move |lua, (value, init, vla_init): (LuaValue, Option<LuaValue>, Option<LuaValue>)| {
+        let cdata = match value {
+            LuaValue::String(type_name) => new_cdata(lua, &type_name.to_str()?, init, vla_init)?,
+            LuaValue::UserData(ud) => {
+                if !matches!(ud.borrow::<CData>()?.ownership, Ownership::Lua | Ownership::Finalized) {
+                    return Err(LuaError::RuntimeError(
+                        "Only cdata from ffi.new or with an ffi.gc finalizer can be freed by a scope".to_string(),
+                    ));
+                }
+                ud
+            }
+            _ => return Err(LuaError::RuntimeError("Expected a type name or cdata".to_string())),
+        };
+        match registry.borrow_mut().as_mut() {
+            Some(members) => members.push(cdata.clone()),
+            None => return Err(LuaError::RuntimeError("The scope has already ended".to_string())),
+        }
+        Ok(cdata)
+    })?;
+
+    let result = body.call::<LuaMultiValue>(alloc);
+
+    // Clean up even if the body raised; its error takes precedence over cleanup errors
+    let mut cleanup = Ok(());
+    for cdata in members.borrow_mut().take().unwrap_or_default().into_iter().rev() {
+        let freed = match cdata.borrow::<CData>() {
+            Ok(cd) if cd.ownership == Ownership::Freed => continue,
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+        let freed = freed.and_then(|_| free_cdata(lua, cdata));
+        if cleanup.is_ok() {
+            cleanup = freed;
+        }
     }
-    Ok(cdata)
+    let values = result?;
+    cleanup.map(|_| values)
 }
 
 pub fn sizeof_type(type_name: &str) -> LuaResult<usize> {
@@ -419,13 +1806,23 @@ pub fn sizeof_type(type_name: &str) -> LuaResult<usize> {
     Ok(ctype.size())
 }
 
+/// Size of a cdata: the memory of known extent at its address, such as its own allocation or
+/// the buffer a pointer was cast from, else the size of its type
+pub fn sizeof_cdata(cdata: &CData) -> LuaResult<usize> {
+    cdata.check_live()?;
+    Ok(cdata.extent().unwrap_or(cdata.ctype.size()))
+}
+
 pub fn offsetof_field(type_name: &str, field: &str) -> LuaResult<usize> {
     let ctype = lookup_type(type_name)?;
 
-    match ctype {
+    match ctype.resolved() {
         CType::Struct(_, fields) | CType::Union(_, fields) => {
             for f in fields {
                 if f.name == field {
+                    if let CType::Bitfield(_, _, _) = f.ctype {
+                        return Err(LuaError::RuntimeError(format!("Cannot take the offset of bit-field: {}", field)));
+                    }
                     return Ok(f.offset);
                 }
             }
@@ -438,8 +1835,239 @@ pub fn offsetof_field(type_name: &str, field: &str) -> LuaResult<usize> {
     }
 }
 
+/// Members of a struct or union in declaration order, as `{name, offset, type}` tables; bit-fields
+/// also have `bitpos` (from `offset`) and `bitsize`, with `type` naming the declared type
+pub fn struct_fields(lua: &Lua, type_name: &str) -> LuaResult<LuaTable> {
+    let ctype = lookup_type(type_name)?;
+    let (CType::Struct(_, fields) | CType::Union(_, fields)) = ctype.resolved() else {
+        return Err(LuaError::RuntimeError(format!("Not a struct or union: {}", type_name)));
+    };
+
+    let list = lua.create_table_with_capacity(fields.len(), 0)?;
+    for field in fields {
+        let entry = lua.create_table_with_capacity(0, 5)?;
+        entry.set("name", field.name.as_str())?;
+        entry.set("offset", field.offset)?;
+        let declared = match &field.ctype {
+            CType::Bitfield(inner, bitpos, bitsize) => {
+                entry.set("bitpos", *bitpos)?;
+                entry.set("bitsize", *bitsize)?;
+                inner
+            }
+            declared => declared,
+        };
+        entry.set("type", canonical_type_name(declared).unwrap_or_else(|| declared.to_string()))?;
+        list.push(entry)?;
+    }
+    Ok(list)
+}
+
+/// Check the layout of a type against `expected`: `size` and `align` of the type, any other key
+/// the offset of the member of that name (members named `size` or `align` go in a `fields`
+/// table). All mismatches are reported together
+pub fn assert_layout(lua: &Lua, type_name: &str, expected: LuaTable) -> LuaResult<()> {
+    let ctype = lookup_type(type_name)?;
+    let mut mismatches = Vec::new();
+    for (key, actual) in [("size", ctype.size()), ("align", ctype.alignment())] {
+        match expected.get::<Option<usize>>(key)? {
+            Some(expected) if expected != actual => {
+                mismatches.push(format!("{} is {}, expected {}", key, actual, expected));
+            }
+            _ => {}
+        }
+    }
+
+    let mut offsets = HashMap::new();
+    for pair in expected.pairs::<String, LuaValue>() {
+        let (key, value) = pair?;
+        match key.as_str() {
+            "size" | "align" => {}
+            "fields" => {
+                for pair in LuaTable::from_lua(value, lua)?.pairs::<String, usize>() {
+                    let (name, offset) = pair?;
+                    offsets.insert(name, offset);
+                }
+            }
+            _ => {
+                offsets.insert(key, usize::from_lua(value, lua)?);
+            }
+        }
+    }
+    // Members in declaration order, then any the type lacks
+    if let CType::Struct(_, fields) | CType::Union(_, fields) = ctype.resolved() {
+        for field in fields {
+            let Some(expected) = offsets.remove(&field.name) else {
+                continue;
+            };
+            if let CType::Bitfield(_, _, _) = field.ctype {
+                mismatches.push(format!("{} is a bit-field and has no offset", field.name));
+            } else if field.offset != expected {
+                mismatches.push(format!("{} is at offset {}, expected {}", field.name, field.offset, expected));
+            }
+        }
+    }
+    let mut missing: Vec<String> = offsets.into_keys().collect();
+    missing.sort();
+    mismatches.extend(missing.into_iter().map(|name| format!("no member {}", name)));
+
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    let message = format!("Layout of {} does not match: {}", type_name, mismatches.join("; "));
+    Err(FfiError::new(ErrorKind::Runtime, message).with_type(&ctype).into())
+}
+
+pub fn enum_name(type_name: &str, value: i64) -> LuaResult<Option<String>> {
+    let ctype = lookup_type(type_name)?;
+
+    match &ctype {
+        CType::Enum(_, _, _) | CType::Typedef(_, _) => Ok(ctype.enum_name(value).map(str::to_string)),
+        _ => Err(LuaError::RuntimeError(format!("Not an enum type: {}", type_name))),
+    }
+}
+
+/// Truth value of `value` stored into a `bool`: numbers and scalar cdata follow C (non-zero is
+/// true), pointers are true unless NULL, nil and false are false and anything else is true
+pub(crate) fn truthy(value: &LuaValue) -> LuaResult<bool> {
+    match value {
+        LuaValue::Nil => Ok(false),
+        LuaValue::Boolean(b) => Ok(*b),
+        LuaValue::Integer(i) => Ok(*i != 0),
+        LuaValue::Number(n) => Ok(*n != 0.0),
+        LuaValue::UserData(ud) => match ud.borrow::<CData>() {
+            Ok(cdata) => match cdata.ctype.resolved() {
+                CType::Ptr(_) | CType::Array(_, _) | CType::VLA(_) | CType::Struct(_, _) | CType::Union(_, _) => {
+                    Ok(!cdata.is_null())
+                }
+                _ if cdata.is_null() => Ok(false),
+                CType::Float => Ok(unsafe { (cdata.ptr as *const f32).read_unaligned() } != 0.0),
+                CType::Double => Ok(unsafe { (cdata.ptr as *const f64).read_unaligned() } != 0.0),
+                // Integers and bools are non-zero when any of their bytes is
+                scalar => {
+                    let len = scalar.size().min(cdata.size);
+                    Ok(unsafe { std::slice::from_raw_parts(cdata.ptr, len) }.iter().any(|byte| *byte != 0))
+                }
+            },
+            Err(_) => Ok(!is_null(value)?),
+        },
+        _ => Ok(true),
+    }
+}
+
+/// A NULL pointer of the pointer type `type_name`, which unlike `ffi.nullptr` passes
+/// `ffi.istype` for that type
+pub fn null_pointer(lua: &Lua, type_name: &str) -> LuaResult<LuaAnyUserData> {
+    let ctype = lookup_type(type_name)?;
+    if !matches!(ctype.resolved(), CType::Ptr(_)) {
+        let message = format!("Not a pointer type: {}", ctype);
+        return Err(FfiError::new(ErrorKind::Runtime, message).with_type(&ctype).into());
+    }
+    lua.create_userdata(CData::from_ptr(ctype, std::ptr::null_mut(), Ownership::Foreign))
+}
+
+/// The address a value refers to as a `uintptr_t` cdata: what a pointer holds, where other
+/// cdata live, or the entry point of a function. `nil` is address 0
+pub fn address_of(lua: &Lua, value: &LuaValue) -> LuaResult<LuaAnyUserData> {
+    let address = match value {
+        LuaValue::Nil => 0,
+        LuaValue::LightUserData(ud) => ud.0 as usize,
+        LuaValue::UserData(ud) => match ud.borrow::<CFunction>() {
+            Ok(func) => func.ptr as usize,
+            Err(_) => {
+                let cdata = ud.borrow::<CData>()?;
+                cdata.check_live()?;
+                cdata.as_ptr() as usize
+            }
+        },
+        _ => {
+            return Err(LuaError::RuntimeError(format!(
+                "Expected cdata or nil, got {}",
+                value.type_name()
+            )));
+        }
+    };
+    let cdata = CData::new(CType::SizeT, size_of::<usize>());
+    unsafe { (cdata.ptr as *mut usize).write_unaligned(address) };
+    lua.create_userdata(cdata)
+}
+
+/// A pointer of the same type whose address has the tag `bits` set, for libraries that keep
+/// flags in the low bits of aligned pointers. A pointer to an aligned type only takes tags
+/// that fit its alignment bits, and only while those are clear
+pub fn tag_pointer(lua: &Lua, pointer: LuaAnyUserData, bits: i64) -> LuaResult<LuaAnyUserData> {
+    let cd = tagged_pointer(&pointer)?;
+    let Ok(bits) = usize::try_from(bits) else {
+        return Err(LuaError::RuntimeError(format!("Invalid pointer tag: {}", bits)));
+    };
+    let address = cd.as_ptr() as usize;
+    if let Some(spare) = alignment_bits(&cd.ctype) {
+        if bits & !spare != 0 {
+            let message = format!("Tag 0x{:x} does not fit the alignment bits of '{}' (mask 0x{:x})", bits, cd.ctype, spare);
+            return Err(FfiError::new(ErrorKind::Runtime, message).with_type(&cd.ctype).into());
+        }
+        if address & spare != 0 {
+            let message = format!("Pointer 0x{:x} of type '{}' is already tagged", address, cd.ctype);
+            return Err(FfiError::new(ErrorKind::Runtime, message).with_type(&cd.ctype).into());
+        }
+    }
+    lua.create_userdata(CData::from_ptr(cd.ctype.clone(), (address | bits) as *mut u8, cd.derived_ownership()))
+}
+
+/// Split a tagged pointer into the pointer with the bits of `mask` cleared and those bits. The
+/// mask defaults to the bits the alignment of the pointed-to type leaves free
+pub fn untag_pointer(lua: &Lua, pointer: LuaAnyUserData, mask: Option<i64>) -> LuaResult<(LuaAnyUserData, i64)> {
+    let cd = tagged_pointer(&pointer)?;
+    let mask = match mask {
+        Some(mask) => usize::try_from(mask).map_err(|_| LuaError::RuntimeError(format!("Invalid tag mask: {}", mask)))?,
+        None => match alignment_bits(&cd.ctype) {
+            Some(spare) => spare,
+            None => {
+                let message = format!("'{}' has no alignment bits to spare; give ffi.untag a mask", cd.ctype);
+                return Err(FfiError::new(ErrorKind::Runtime, message).with_type(&cd.ctype).into());
+            }
+        },
+    };
+    let address = cd.as_ptr() as usize;
+    let untagged = CData::from_ptr(cd.ctype.clone(), (address & !mask) as *mut u8, cd.derived_ownership());
+    Ok((lua.create_userdata(untagged)?, (address & mask) as i64))
+}
+
+// The low address bits the alignment of a pointer's target keeps clear, if it has any
+fn alignment_bits(ctype: &CType) -> Option<usize> {
+    match ctype.resolved() {
+        CType::Ptr(target) if target.alignment() > 1 => Some(target.alignment() - 1),
+        _ => None,
+    }
+}
+
+fn tagged_pointer(pointer: &LuaAnyUserData) -> LuaResult<LuaUserDataRef<CData>> {
+    let cd = pointer.borrow::<CData>()?;
+    cd.check_live()?;
+    if !matches!(cd.ctype.resolved(), CType::Ptr(_)) {
+        return Err(LuaError::RuntimeError(format!("Not a pointer: {}", cd.ctype)));
+    }
+    Ok(cd)
+}
+
+/// Check whether a value is a NULL pointer; `nil` counts as NULL
+pub fn is_null(value: &LuaValue) -> LuaResult<bool> {
+    match value {
+        LuaValue::Nil => Ok(true),
+        LuaValue::LightUserData(ud) => Ok(ud.0.is_null()),
+        LuaValue::UserData(ud) => match ud.borrow::<CFunction>() {
+            Ok(func) => Ok(func.ptr.is_null()),
+            Err(_) => Ok(ud.borrow::<CData>()?.is_null()),
+        },
+        _ => Err(LuaError::RuntimeError(format!(
+            "Expected cdata or nil, got {}",
+            value.type_name()
+        ))),
+    }
+}
+
 pub fn cdata_to_number(cdata: LuaAnyUserData) -> LuaResult<f64> {
     let cd = cdata.borrow::<CData>()?;
+    cd.check_live()?;
 
     if cd.is_null() {
         return Ok(0.0);
@@ -453,15 +2081,28 @@ pub fn cdata_to_number(cdata: LuaAnyUserData) -> LuaResult<f64> {
             cd.size, type_size
         )));
     }
+    if !matches!(cd.ctype.resolved(), CType::Ptr(_)) {
+        cd.check_bounds(0, type_size)?;
+    }
+
+    // 64-bit integers round to the nearest double unless strict conversions are on
+    if let Some(i) = cdata_integer(&cd) {
+        let n = i as f64;
+        if config::strict_conversions() && n as i128 != i {
+            return Err(LuaError::RuntimeError(format!(
+                "{} is not representable as a Lua number (use ffi.tostring_int)",
+                i
+            )));
+        }
+        return Ok(n);
+    }
 
     unsafe {
-        match cd.ctype {
-            CType::Int => Ok(*(cd.ptr as *const i32) as f64),
-            CType::UInt => Ok(*(cd.ptr as *const u32) as f64),
-            CType::Long => Ok(*(cd.ptr as *const isize) as f64),
-            CType::Float => Ok(*(cd.ptr as *const f32) as f64),
-            CType::Double => Ok(*(cd.ptr as *const f64)),
-            CType::Ptr(_) => Ok(cd.ptr as usize as f64),
+        match cd.ctype.resolved() {
+            CType::Bool => Ok(if *cd.ptr != 0 { 1.0 } else { 0.0 }),
+            CType::Float => Ok((cd.ptr as *const f32).read_unaligned() as f64),
+            CType::Double => Ok((cd.ptr as *const f64).read_unaligned()),
+            CType::Ptr(_) | CType::Function(_, _) => Ok(cd.ptr as usize as f64),
             _ => Err(LuaError::RuntimeError(
                 "Cannot convert to number".to_string(),
             )),
@@ -469,57 +2110,270 @@ pub fn cdata_to_number(cdata: LuaAnyUserData) -> LuaResult<f64> {
     }
 }
 
-pub fn cdata_to_string(cdata: LuaAnyUserData) -> LuaResult<String> {
+/// Exact decimal representation of an integer or integer cdata, e.g. a `uint64_t` above 2^53
+pub fn integer_to_string(value: &LuaValue) -> LuaResult<String> {
+    match wide_integer(&integral(value.clone())) {
+        Some(i) => Ok(i.to_string()),
+        None => Err(expected("integer or integer cdata", value)),
+    }
+}
+
+/// Hexadecimal digits of an integer, integer cdata or address. Cdata default to two digits per
+/// byte of their type; like LuaJIT's `bit.tohex`, `width` keeps that many low digits and a
+/// negative width selects upper case
+pub fn to_hex(value: &LuaValue, width: Option<i64>) -> LuaResult<String> {
+    let (bits, natural) = match integral(value.clone()) {
+        LuaValue::Integer(i) => (i as u64, None),
+        LuaValue::UserData(ud) => {
+            if let Ok(func) = ud.borrow::<CFunction>() {
+                (func.ptr as usize as u64, Some(size_of::<usize>()))
+            } else {
+                let cd = ud.borrow::<CData>()?;
+                match cdata_integer(&cd) {
+                    Some(i) => (i as u64, Some(cd.ctype.size())),
+                    None if matches!(cd.ctype.resolved(), CType::Float | CType::Double | CType::Bool) => {
+                        return Err(expected("integer, pointer or cdata", value));
+                    }
+                    // Pointers give their value, aggregates their address
+                    None => (cd.as_ptr() as usize as u64, Some(size_of::<usize>())),
+                }
+            }
+        }
+        _ => return Err(expected("integer, pointer or cdata", value)),
+    };
+    let digits = match width {
+        Some(width) => (width.unsigned_abs() as usize).clamp(1, 16),
+        None => natural.map_or(0, |bytes| bytes * 2),
+    };
+    // Keep the low digits, so negative values show their two's complement at that width
+    let bits = if digits > 0 && digits < 16 { bits & ((1u64 << (4 * digits)) - 1) } else { bits };
+    Ok(match width {
+        Some(width) if width < 0 => format!("{:0digits$X}", bits),
+        _ => format!("{:0digits$x}", bits),
+    })
+}
+
+/// The characters a `char` pointer or array points to: up to the NUL terminator (or the end
+/// of an array without one), or exactly `len` bytes. The bytes go straight into the Lua string
+/// as they are, without passing through UTF-8 validation
+pub fn cdata_to_string(lua: &Lua, cdata: LuaAnyUserData, len: Option<usize>) -> LuaResult<LuaString> {
     let cd = cdata.borrow::<CData>()?;
+    cd.check_live()?;
 
     if cd.is_null() {
         return Err(LuaError::RuntimeError("NULL pointer".to_string()));
     }
 
-    match &cd.ctype {
-        CType::Ptr(inner) | CType::Array(inner, _) | CType::VLA(inner) => match **inner {
-            CType::Char | CType::UChar => unsafe {
-                let c_str = CStr::from_ptr(cd.ptr as *const i8);
-                Ok(c_str.to_string_lossy().to_string())
+    let limit = match cd.ctype.resolved() {
+        CType::Ptr(inner) | CType::Array(inner, _) | CType::VLA(inner) => match inner.resolved() {
+            CType::Char | CType::UChar | CType::Int8 | CType::UInt8 => match cd.ctype.resolved() {
+                CType::Ptr(_) => None,
+                _ => Some(cd.size),
             },
-            _ => Err(LuaError::RuntimeError("Not a string pointer".to_string())),
+            _ => return Err(LuaError::RuntimeError("Not a string pointer".to_string())),
         },
-        _ => Err(LuaError::RuntimeError("Not a string".to_string())),
+        _ => return Err(LuaError::RuntimeError("Not a string".to_string())),
+    };
+    let len = match (len, limit) {
+        (Some(len), _) => len,
+        (None, Some(limit)) => {
+            let bytes = unsafe { std::slice::from_raw_parts(cd.ptr, limit) };
+            bytes.iter().position(|&b| b == 0).unwrap_or(limit)
+        }
+        (None, None) => unsafe { CStr::from_ptr(cd.ptr as *const c_char).to_bytes().len() },
+    };
+    cd.check_bounds(0, len)?;
+    lua.create_string(unsafe { std::slice::from_raw_parts(cd.ptr, len) })
+}
+
+/// Copy `len` bytes of the memory a cdata points to or occupies into a Lua string, by default
+/// all of the cdata's own memory. This is the single copy Lua needs to own a string: its
+/// strings are immutable, hashed and allocated by Lua itself, so none can be made to refer to
+/// existing memory
+pub fn intern(lua: &Lua, cdata: LuaAnyUserData, len: Option<usize>) -> LuaResult<LuaString> {
+    let cd = cdata.borrow::<CData>()?;
+    cd.check_live()?;
+    let len = match (len, cd.ctype.resolved()) {
+        (Some(len), _) => len,
+        (None, CType::Ptr(_)) => {
+            return Err(LuaError::RuntimeError(format!(
+                "ffi.intern needs a length for '{}', which does not know how much memory it points to",
+                cd.ctype
+            )));
+        }
+        (None, _) => cd.size,
+    };
+    if len == 0 {
+        return lua.create_string("");
+    }
+    cd.check_not_null()?;
+    cd.check_bounds(0, len)?;
+    lua.create_string(unsafe { std::slice::from_raw_parts(cd.as_ptr(), len) })
+}
+
+/// Copy a UTF-8 Lua string into a new NUL-terminated `wchar_t` array, as UTF-16 where
+/// `wchar_t` has two bytes (Windows) and UTF-32 otherwise; invalid UTF-8 becomes U+FFFD
+pub fn to_wide(lua: &Lua, text: &[u8]) -> LuaResult<LuaAnyUserData> {
+    let units = wide_units(text);
+    let ctype = CType::Array(Box::new(CType::wchar()), units.len() + 1);
+    let size = ctype.size();
+    let cdata = CData::new(ctype, size);
+    unsafe { write_wide_units(cdata.ptr, &units) };
+    create_owned(lua, cdata)
+}
+
+// The `wchar_t` units of UTF-8 text, without a terminator
+fn wide_units(text: &[u8]) -> Vec<u32> {
+    let text = String::from_utf8_lossy(text);
+    if CType::wchar().size() == 2 {
+        text.encode_utf16().map(u32::from).collect()
+    } else {
+        text.chars().map(u32::from).collect()
+    }
+}
+
+unsafe fn write_wide_units(ptr: *mut u8, units: &[u32]) {
+    for (i, &unit) in units.iter().enumerate() {
+        unsafe {
+            match CType::wchar().size() {
+                2 => (ptr as *mut u16).add(i).write_unaligned(unit as u16),
+                _ => (ptr as *mut u32).add(i).write_unaligned(unit),
+            }
+        }
+    }
+}
+
+/// Read a `wchar_t` string up to its NUL terminator, or exactly `len` units, back into UTF-8;
+/// unpaired surrogates and invalid code points become U+FFFD
+pub fn from_wide(lua: &Lua, cdata: LuaAnyUserData, len: Option<usize>) -> LuaResult<LuaString> {
+    let cd = cdata.borrow::<CData>()?;
+    cd.check_live()?;
+    match cd.ctype.resolved() {
+        CType::Ptr(inner) | CType::Array(inner, _) | CType::VLA(inner) if inner.is_wchar() => {}
+        _ => return Err(LuaError::RuntimeError(format!("Not a wide string: '{}'", cd.ctype))),
+    }
+    if cd.is_null() {
+        return Err(LuaError::RuntimeError("NULL pointer".to_string()));
+    }
+
+    let unit_size = CType::wchar().size();
+    let read = |i: usize| unsafe {
+        match unit_size {
+            2 => (cd.ptr as *const u16).add(i).read_unaligned() as u32,
+            _ => (cd.ptr as *const u32).add(i).read_unaligned(),
+        }
+    };
+    // Arrays without a terminator end at their last element
+    let limit = match cd.ctype.resolved() {
+        CType::Array(_, count) => *count,
+        _ => usize::MAX,
+    };
+    let count = match len {
+        Some(len) => len,
+        None => (0..limit).find(|&i| read(i) == 0).unwrap_or(limit),
+    };
+    let size = count.checked_mul(unit_size).ok_or_else(|| -> LuaError {
+        let message = format!("Length {} is too large for '{}'", count, cd.ctype);
+        FfiError::new(ErrorKind::OutOfBounds, message).with_type(&cd.ctype).into()
+    })?;
+    cd.check_bounds(0, size)?;
+    let units = (0..count).map(read);
+    let text: String = if unit_size == 2 {
+        char::decode_utf16(units.map(|unit| unit as u16))
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    } else {
+        units.map(|unit| char::from_u32(unit).unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+    };
+    lua.create_string(text)
+}
+
+// Reject writes into memory only reachable through const-qualified types
+fn check_writable(cd: &CData) -> LuaResult<()> {
+    let read_only = match cd.ctype.resolved() {
+        CType::Ptr(inner) => inner.is_const(),
+        CType::Array(inner, _) | CType::VLA(inner) => inner.is_const() || cd.ctype.is_const(),
+        _ => cd.ctype.is_const(),
+    };
+    if read_only {
+        let error = FfiError::new(ErrorKind::ReadOnly, "Cannot write to read-only (const) memory");
+        return Err(error.with_type(&cd.ctype).into());
     }
+    Ok(())
 }
 
-pub fn copy_memory(dst: LuaAnyUserData, src: LuaValue, len: Option<usize>) -> LuaResult<usize> {
+pub fn copy_memory(
+    dst: LuaAnyUserData,
+    dst_offset: usize,
+    src: LuaValue,
+    src_offset: usize,
+    len: Option<usize>,
+) -> LuaResult<usize> {
     let dst_cd = dst.borrow::<CData>()?;
+    dst_cd.check_live()?;
+    check_writable(&dst_cd)?;
+    let dst_extent = copy_extent(&dst_cd);
 
     match src {
         LuaValue::String(s) => {
             let bytes = s.as_bytes();
-            let copy_len = len.unwrap_or(bytes.len());
+            let available = bytes.len().checked_sub(src_offset).ok_or_else(|| {
+                LuaError::RuntimeError(format!(
+                    "Source offset {} is past the end of a {}-byte string",
+                    src_offset,
+                    bytes.len()
+                ))
+            })?;
+            let copy_len = len.unwrap_or(available);
+            if copy_len > available {
+                return Err(LuaError::RuntimeError(format!(
+                    "Cannot copy {} bytes from a {}-byte string at offset {}",
+                    copy_len,
+                    bytes.len(),
+                    src_offset
+                )));
+            }
 
             // Validate destination buffer size
-            if copy_len > dst_cd.size {
+            if let Some(size) = dst_extent
+                && dst_offset.saturating_add(copy_len) > size
+            {
                 return Err(LuaError::RuntimeError(format!(
                     "Buffer overflow: trying to copy {} bytes to buffer of size {}",
-                    copy_len, dst_cd.size
+                    copy_len,
+                    size.saturating_sub(dst_offset)
                 )));
             }
 
             unsafe {
-                std::ptr::copy_nonoverlapping(bytes.as_ptr(), dst_cd.ptr, copy_len);
+                let dst_ptr = dst_cd.ptr.add(dst_offset);
+                std::ptr::copy_nonoverlapping(bytes[src_offset..].as_ptr(), dst_ptr, copy_len);
                 // Only null-terminate if we have space and it wasn't explicitly specified
-                if len.is_none() && copy_len < dst_cd.size {
-                    *dst_cd.ptr.add(copy_len) = 0;
+                if len.is_none() && dst_extent.is_none_or(|size| dst_offset + copy_len < size) {
+                    *dst_ptr.add(copy_len) = 0;
                 }
             }
             Ok(copy_len)
         }
         LuaValue::UserData(src_ud) => {
             let src_cd = src_ud.borrow::<CData>()?;
+            src_cd.check_live()?;
             let copy_len = len.ok_or_else(|| {
                 LuaError::RuntimeError("Length required for cdata copy".to_string())
             })?;
+            for (cd, offset, extent) in [(&dst_cd, dst_offset, dst_extent), (&src_cd, src_offset, copy_extent(&src_cd))] {
+                if let Some(size) = extent
+                    && offset.saturating_add(copy_len) > size
+                {
+                    return Err(LuaError::RuntimeError(format!(
+                        "Cannot copy {} bytes at offset {} of the {}-byte '{}'",
+                        copy_len, offset, size, cd.ctype
+                    )));
+                }
+            }
+            // Offsets into the same buffer may overlap
             unsafe {
-                std::ptr::copy_nonoverlapping(src_cd.ptr, dst_cd.ptr, copy_len);
+                std::ptr::copy(src_cd.ptr.add(src_offset), dst_cd.ptr.add(dst_offset), copy_len);
             }
             Ok(copy_len)
         }
@@ -529,8 +2383,103 @@ pub fn copy_memory(dst: LuaAnyUserData, src: LuaValue, len: Option<usize>) -> Lu
     }
 }
 
+// Bytes a copy may touch: a cdata's own memory, or no known limit behind a pointer
+fn copy_extent(cd: &CData) -> Option<usize> {
+    match cd.ctype.resolved() {
+        CType::Ptr(_) => None,
+        _ => Some(cd.size),
+    }
+}
+
+/// Compare the values of two cdata of the same type member by member: padding bytes are
+/// ignored, floating point members compare as numbers and pointers by address. A union is
+/// compared byte for byte, as which member is live is unknown. Cdata of different types are
+/// never equal
+pub fn cdata_equal(a: LuaAnyUserData, b: LuaAnyUserData) -> LuaResult<bool> {
+    let a = a.borrow::<CData>()?;
+    let b = b.borrow::<CData>()?;
+    if !a.ctype.same_type(&b.ctype) {
+        return Ok(false);
+    }
+    let (a_ptr, b_ptr) = (value_ptr(&a)?, value_ptr(&b)?);
+    Ok(values_equal(a_ptr, b_ptr, &a.ctype))
+}
+
+/// Hash of a cdata's value consistent with `cdata_equal`: equal values hash alike within a
+/// process, whatever their padding holds
+pub fn cdata_hash(cdata: LuaAnyUserData) -> LuaResult<i64> {
+    let cdata = cdata.borrow::<CData>()?;
+    let mut hasher = DefaultHasher::new();
+    hash_value(value_ptr(&cdata)?, &cdata.ctype, &mut hasher);
+    Ok(hasher.finish() as i64)
+}
+
+// Where a cdata's value is: the address a pointer holds is itself the value
+fn value_ptr(cdata: &CData) -> LuaResult<*const u8> {
+    cdata.check_live()?;
+    match cdata.ctype.resolved() {
+        CType::Ptr(_) | CType::Function(_, _) => Ok(&cdata.ptr as *const *mut u8 as *const u8),
+        _ if cdata.is_null() => Err(LuaError::RuntimeError("NULL pointer".to_string())),
+        ctype => {
+            cdata.check_bounds(0, ctype.size())?;
+            Ok(cdata.ptr)
+        }
+    }
+}
+
+fn values_equal(a: *const u8, b: *const u8, ctype: &CType) -> bool {
+    match ctype {
+        CType::Typedef(_, inner) | CType::Const(inner) | CType::Packed(_, inner) => values_equal(a, b, inner),
+        CType::Struct(_, fields) => fields
+            .iter()
+            .all(|field| values_equal(a.wrapping_add(field.offset), b.wrapping_add(field.offset), &field.ctype)),
+        CType::Array(elem, count) => (0..*count).all(|i| {
+            let offset = i * elem.size();
+            values_equal(a.wrapping_add(offset), b.wrapping_add(offset), elem)
+        }),
+        CType::Float => unsafe { (a as *const f32).read_unaligned() == (b as *const f32).read_unaligned() },
+        CType::Double => unsafe { (a as *const f64).read_unaligned() == (b as *const f64).read_unaligned() },
+        CType::Bool => unsafe { (a.read() != 0) == (b.read() != 0) },
+        CType::Bitfield(_, shift, width) => read_bits(a, *shift, *width) == read_bits(b, *shift, *width),
+        ctype => unsafe {
+            std::slice::from_raw_parts(a, ctype.size()) == std::slice::from_raw_parts(b, ctype.size())
+        },
+    }
+}
+
+fn hash_value(ptr: *const u8, ctype: &CType, hasher: &mut DefaultHasher) {
+    match ctype {
+        CType::Typedef(_, inner) | CType::Const(inner) | CType::Packed(_, inner) => hash_value(ptr, inner, hasher),
+        CType::Struct(_, fields) => {
+            for field in fields {
+                hash_value(ptr.wrapping_add(field.offset), &field.ctype, hasher);
+            }
+        }
+        CType::Array(elem, count) => {
+            for i in 0..*count {
+                hash_value(ptr.wrapping_add(i * elem.size()), elem, hasher);
+            }
+        }
+        // `-0.0 == 0.0`, so both hash as zero
+        CType::Float => {
+            let value = unsafe { (ptr as *const f32).read_unaligned() };
+            (if value == 0.0 { 0 } else { value.to_bits() }).hash(hasher);
+        }
+        CType::Double => {
+            let value = unsafe { (ptr as *const f64).read_unaligned() };
+            (if value == 0.0 { 0 } else { value.to_bits() }).hash(hasher);
+        }
+        CType::Bool => (unsafe { ptr.read() } != 0).hash(hasher),
+        CType::Bitfield(_, shift, width) => read_bits(ptr, *shift, *width).hash(hasher),
+        ctype => unsafe { std::slice::from_raw_parts(ptr, ctype.size()) }.hash(hasher),
+    }
+}
+
 pub fn fill_memory(cdata: LuaAnyUserData, len: usize, value: u8) -> LuaResult<()> {
     let cd = cdata.borrow::<CData>()?;
+    cd.check_live()?;
+    check_writable(&cd)?;
+    cd.check_bounds(0, len)?;
     unsafe {
         std::ptr::write_bytes(cd.ptr, value, len);
     }
@@ -539,60 +2488,112 @@ pub fn fill_memory(cdata: LuaAnyUserData, len: usize, value: u8) -> LuaResult<()
 
 #[inline]
 fn lookup_basic_type(type_name: &str) -> Option<CType> {
-    BASIC_TYPES.get(type_name).cloned()
+    BASIC_TYPES.get(type_name).cloned().or_else(|| lookup_posix_type(type_name))
 }
 
-pub fn lookup_type(type_name: &str) -> LuaResult<CType> {
-    // Strip type qualifiers (const, volatile, restrict, etc.)
-    let stripped_name = type_name
-        .trim()
-        .trim_start_matches("const")
-        .trim()
-        .trim_start_matches("volatile")
-        .trim()
-        .trim_start_matches("restrict")
-        .trim();
-    
-    // Check basic types first (fastest path)
-    if let Some(ctype) = lookup_basic_type(stripped_name) {
-        return Ok(ctype);
-    }
+#[cfg(unix)]
+fn lookup_posix_type(type_name: &str) -> Option<CType> {
+    Some(match type_name {
+        "ino_t" => CType::InoT,
+        "dev_t" => CType::DevT,
+        "gid_t" => CType::GidT,
+        "mode_t" => CType::ModeT,
+        "nlink_t" => CType::NlinkT,
+        "uid_t" => CType::UidT,
+        "off_t" => CType::OffT,
+        "pid_t" => CType::PidT,
+        "useconds_t" => CType::UsecondsT,
+        "suseconds_t" => CType::SusecondsT,
+        "blksize_t" => CType::BlksizeT,
+        "blkcnt_t" => CType::BlkcntT,
+        "time_t" => CType::TimeT,
+        _ => return None,
+    })
+}
+
+#[cfg(not(unix))]
+fn lookup_posix_type(_type_name: &str) -> Option<CType> {
+    None
+}
 
-    // Check for pointer type
-    if stripped_name.ends_with('*') {
-        let base_type = stripped_name.trim_end_matches('*').trim();
-        let inner = lookup_type(base_type)?;
-        return Ok(CType::Ptr(Box::new(inner)));
+/// Look up a single type name (basic type, typedef or `struct X`-style tag) without parsing
+#[inline]
+pub fn lookup_named_type(name: &str) -> Option<CType> {
+    if name == "wchar_t" {
+        return Some(CType::wchar());
     }
+    if let Some(ctype) = CType::byte_order(name) {
+        return Some(ctype);
+    }
+    lookup_basic_type(name).or_else(|| lookup_registered_type(name))
+}
 
-    // Check for array type
-    if let Some(open_bracket) = stripped_name.find('[') {
-        let base_type = stripped_name[..open_bracket].trim();
-        let inner = lookup_type(base_type)?;
+// Qualifiers that have no effect on a type's layout or conversions (`const` does, since
+// it makes the cdata read-only, so it is left for the parser)
+const TYPE_QUALIFIERS: &[&str] = &["volatile", "restrict"];
 
-        let close_bracket = stripped_name.find(']').ok_or_else(|| {
-            LuaError::RuntimeError(format!("Invalid array type (missing ']'): {}", type_name))
-        })?;
+/// Split a type string into identifier/number words and punctuation, drop qualifier
+/// words wherever they appear and rejoin with a single space between adjacent words only
+/// (`volatile unsigned   int` -> `unsigned int`)
+fn strip_qualifiers(type_name: &str) -> String {
+    let mut normalized = String::with_capacity(type_name.len());
+    let mut last_was_word = false;
+    let mut rest = type_name;
 
-        let size_str = stripped_name[open_bracket + 1..close_bracket].trim();
-        
-        // Check for VLA syntax [?]
-        if size_str == "?" {
-            return Ok(CType::VLA(Box::new(inner)));
-        }
-        
-        let size = if size_str.is_empty() {
-            0 // Flexible array
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+            if TYPE_QUALIFIERS.contains(&word) {
+                continue;
+            }
+            if last_was_word {
+                normalized.push(' ');
+            }
+            normalized.push_str(word);
+            last_was_word = true;
         } else {
-            size_str.parse::<usize>().map_err(|_| {
-                LuaError::RuntimeError(format!("Invalid array size: '{}'", size_str))
-            })?
-        };
+            normalized.push(c);
+            rest = &rest[c.len_utf8()..];
+            last_was_word = false;
+        }
+    }
+    normalized
+}
 
-        return Ok(CType::Array(Box::new(inner), size));
+pub fn lookup_type(type_name: &str) -> LuaResult<CType> {
+    // Check basic types first (fastest path), spelled with any spacing or layout-neutral qualifiers
+    if let Some(ctype) = lookup_basic_type(&strip_qualifiers(type_name)) {
+        return Ok(ctype);
     }
 
-    // Look up in the type registry for structs/typedefs
-    lookup_registered_type(stripped_name)
-        .ok_or_else(|| LuaError::RuntimeError(format!("Unknown type: {}", type_name)))
+    // Everything else goes through the C declarator grammar shared with cdef
+    parser::parse_type_string(type_name)
+        .map_err(|message| FfiError::new(ErrorKind::UnknownType, message).with_type(type_name.trim()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Both byte orders are checked on every host, so little-endian CI covers big-endian layouts
+    #[test]
+    fn test_bits_in_both_byte_orders() {
+        // struct { unsigned a : 3; unsigned b : 5; unsigned c : 12; } with a = 5, b = 17, c = 0xabc
+        for (big_endian, storage) in [(false, [0x8d, 0xbc, 0x0a]), (true, [0xb1, 0xab, 0xc0])] {
+            let mut bytes = [0u8; 3];
+            write_bits_ordered(bytes.as_mut_ptr(), 0, 3, 5, big_endian);
+            write_bits_ordered(bytes.as_mut_ptr(), 3, 5, 17, big_endian);
+            write_bits_ordered(bytes.as_mut_ptr(), 8, 12, 0xabc, big_endian);
+            assert_eq!(bytes, storage, "big_endian = {}", big_endian);
+            assert_eq!(read_bits_ordered(bytes.as_ptr(), 0, 3, big_endian), 5);
+            assert_eq!(read_bits_ordered(bytes.as_ptr(), 3, 5, big_endian), 17);
+            assert_eq!(read_bits_ordered(bytes.as_ptr(), 8, 12, big_endian), 0xabc);
+        }
+    }
 }
@@ -0,0 +1,190 @@
+// Detours for C functions: the first instructions of a function make way for a jump to its
+// replacement and move to a trampoline, through which the original stays callable
+
+use std::collections::BTreeMap;
+use std::io;
+use std::ptr;
+use std::sync::Mutex;
+
+use iced_x86::{
+    BlockEncoder, BlockEncoderOptions, Code, Decoder, DecoderOptions, FlowControl, Instruction, InstructionBlock,
+};
+use mlua::prelude::*;
+
+use crate::callback;
+use crate::cdata::{CData, CFunction, Ownership};
+use crate::ctype::CType;
+
+#[cfg(not(all(unix, any(target_arch = "x86", target_arch = "x86_64"))))]
+compile_error!("the `hook` feature supports x86 and x86-64 on Unix only");
+
+const BITNESS: u32 = if cfg!(target_pointer_width = "64") { 64 } else { 32 };
+
+// `jmp [rip]` followed by the absolute address on x86-64, `jmp rel32` on x86
+const JUMP_LEN: usize = if BITNESS == 64 { 14 } else { 5 };
+
+// Room for the instructions a jump overwrites, the last of which may be 15 bytes long
+const MAX_STOLEN: usize = JUMP_LEN + 15;
+
+// Hooked functions by address, with the bytes the jump overwrote
+static HOOKS: Mutex<BTreeMap<usize, Vec<u8>>> = Mutex::new(BTreeMap::new());
+
+/// Redirect calls of the function `target` points to to `replacement`, a Lua function or a
+/// function pointer, returning a pointer of the same type that calls the original.
+///
+/// The caller vouches that nothing runs the function's first instructions while they are
+/// patched, and that no code in the function jumps back into them
+pub fn hook(lua: &Lua, target: LuaAnyUserData, replacement: LuaValue) -> LuaResult<LuaAnyUserData> {
+    let (address, ctype) = function_of(&target)?;
+    let mut hooks = HOOKS.lock().unwrap();
+    if hooks.contains_key(&address) {
+        return Err(LuaError::RuntimeError(format!("Function at 0x{:x} is already hooked", address)));
+    }
+    let detour = match replacement {
+        LuaValue::Function(func) => callback::create(lua, &ctype, func)? as usize,
+        LuaValue::UserData(ud) => function_of(&ud)?.0,
+        other => {
+            return Err(LuaError::RuntimeError(format!("Cannot hook a function with a {}", other.type_name())));
+        }
+    };
+    let (trampoline, overwritten) = unsafe { patch(address, detour)? };
+    hooks.insert(address, overwritten);
+    lua.create_userdata(CData::from_ptr(ctype, trampoline as *mut u8, Ownership::Foreign))
+}
+
+/// Undo `hook`, returning false if the function was not hooked. The trampoline stays in place
+/// for any thread still running it, so pointers to the original remain callable
+pub fn unhook(target: LuaAnyUserData) -> LuaResult<bool> {
+    let (address, _) = function_of(&target)?;
+    let Some(overwritten) = HOOKS.lock().unwrap().remove(&address) else {
+        return Ok(false);
+    };
+    unsafe { write_code(address, &overwritten)? };
+    Ok(true)
+}
+
+// Address and function pointer type of a function pointer cdata or a declared library function
+fn function_of(ud: &LuaAnyUserData) -> LuaResult<(usize, CType)> {
+    if let Ok(func) = ud.borrow::<CFunction>() {
+        return match &func.ctype {
+            Some(ctype) => Ok((func.ptr as usize, CType::Ptr(Box::new(ctype.clone())))),
+            None => Err(LuaError::RuntimeError(format!("Missing declaration for symbol '{}'", func.name))),
+        };
+    }
+    let cdata = ud.borrow::<CData>()?;
+    cdata.check_not_null()?;
+    match cdata.ctype.resolved() {
+        CType::Ptr(inner) if matches!(inner.resolved(), CType::Function(_, _)) => {
+            Ok((cdata.as_ptr() as usize, (*cdata.ctype).clone()))
+        }
+        function @ CType::Function(_, _) => Ok((cdata.as_ptr() as usize, CType::Ptr(Box::new(function.clone())))),
+        _ => Err(LuaError::RuntimeError(format!("Not a function pointer: {}", cdata.ctype))),
+    }
+}
+
+// Move the instructions at `target` that a jump needs room for to a trampoline, then jump
+// from `target` to `detour`. Returns the trampoline and the bytes the jump replaced
+unsafe fn patch(target: usize, detour: usize) -> LuaResult<(usize, Vec<u8>)> {
+    let code = unsafe { std::slice::from_raw_parts(target as *const u8, MAX_STOLEN) };
+    let mut decoder = Decoder::with_ip(BITNESS, code, target as u64, DecoderOptions::NONE);
+    let mut moved = Vec::new();
+    let mut len = 0;
+    while len < JUMP_LEN {
+        let instruction = decoder.decode();
+        if instruction.is_invalid() {
+            return Err(LuaError::RuntimeError(format!("Cannot decode the instructions at 0x{:x}", target)));
+        }
+        len += instruction.len();
+        let ends = matches!(
+            instruction.flow_control(),
+            FlowControl::Return | FlowControl::UnconditionalBranch | FlowControl::IndirectBranch
+        );
+        if ends && len < JUMP_LEN {
+            return Err(LuaError::RuntimeError(format!("Function at 0x{:x} is too short to hook", target)));
+        }
+        moved.push(instruction);
+    }
+
+    // The moved instructions continue with the rest of the function; the encoder rewrites
+    // branches that no longer reach, including this one
+    let jump = if BITNESS == 64 { Code::Jmp_rel32_64 } else { Code::Jmp_rel32_32 };
+    moved.push(Instruction::with_branch(jump, (target + len) as u64).map_err(LuaError::external)?);
+    let trampoline = allocate_near(target)?;
+    let encoded = BlockEncoder::encode(BITNESS, InstructionBlock::new(&moved, trampoline as u64), BlockEncoderOptions::NONE)
+        .map_err(|e| LuaError::RuntimeError(format!("Cannot relocate the instructions at 0x{:x}: {}", target, e)))?;
+    unsafe {
+        ptr::copy_nonoverlapping(encoded.code_buffer.as_ptr(), trampoline as *mut u8, encoded.code_buffer.len());
+        if libc::mprotect(trampoline as *mut libc::c_void, page_size(), libc::PROT_READ | libc::PROT_EXEC) != 0 {
+            return Err(LuaError::external(io::Error::last_os_error()));
+        }
+    }
+
+    let mut jump = if BITNESS == 64 {
+        let mut jump = vec![0xFF, 0x25, 0, 0, 0, 0];
+        jump.extend_from_slice(&(detour as u64).to_le_bytes());
+        jump
+    } else {
+        let mut jump = vec![0xE9];
+        jump.extend_from_slice(&(detour.wrapping_sub(target + JUMP_LEN) as u32).to_le_bytes());
+        jump
+    };
+    // Pad what is left of the last overwritten instruction with int3
+    jump.resize(len, 0xCC);
+    let overwritten = code[..len].to_vec();
+    unsafe { write_code(target, &jump)? };
+    Ok((trampoline, overwritten))
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+// A writable page for a trampoline, within reach of the rip-relative operands of code at
+// `near` if one can be found. Trampolines are never freed
+fn allocate_near(near: usize) -> LuaResult<usize> {
+    const STEP: usize = 1 << 20;
+    const REACH: usize = 1 << 30;
+    let page = page_size();
+    let map = |hint: usize| {
+        let ptr = unsafe {
+            libc::mmap(
+                hint as *mut libc::c_void,
+                page,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        (ptr != libc::MAP_FAILED).then_some(ptr as usize)
+    };
+
+    if BITNESS == 64 {
+        let hints = (1..REACH / STEP).flat_map(|i| [near.checked_sub(i * STEP), near.checked_add(i * STEP)]);
+        for hint in hints.flatten() {
+            match map(hint & !(page - 1)) {
+                Some(ptr) if ptr.abs_diff(near) < REACH => return Ok(ptr),
+                Some(ptr) => unsafe {
+                    libc::munmap(ptr as *mut libc::c_void, page);
+                },
+                None => {}
+            }
+        }
+    }
+    map(0).ok_or_else(|| LuaError::external(io::Error::last_os_error()))
+}
+
+// Overwrite code, making its pages writable for the duration
+unsafe fn write_code(address: usize, bytes: &[u8]) -> LuaResult<()> {
+    let start = address & !(page_size() - 1);
+    let len = address + bytes.len() - start;
+    let protect = |flags| unsafe { libc::mprotect(start as *mut libc::c_void, len, flags) };
+    if protect(libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC) != 0 {
+        return Err(LuaError::external(io::Error::last_os_error()));
+    }
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, bytes.len()) };
+    if protect(libc::PROT_READ | libc::PROT_EXEC) != 0 {
+        return Err(LuaError::external(io::Error::last_os_error()));
+    }
+    Ok(())
+}
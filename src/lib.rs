@@ -1,81 +1,225 @@
+#[cfg(feature = "abi-check")]
+pub mod abi_check;
+mod arith;
+mod call;
+mod callback;
+#[cfg(feature = "canaries")]
+mod canary;
 mod cdata;
+#[cfg(feature = "cdefgen")]
+pub mod cdefgen;
+pub mod config;
 pub mod ctype;
 mod dylib;
+pub mod error;
 mod ffi_ops;
+#[cfg(feature = "hook")]
+mod hook;
+mod parallel;
 mod parser;
+#[cfg(feature = "serde")]
+pub mod serde_cdata;
+mod symbols;
+mod syserr;
 
 use mlua::prelude::*;
 
+use crate::config::FfiConfig;
+use crate::ctype::CType;
+use crate::error::{ErrorKind, FfiError};
+
+#[cfg(feature = "wasm")]
+pub use dylib::host::register_library;
+
 const LUA_FFI_VERSION: &str = "0.1.1-rust";
 
 /// Create the FFI module with all exported functions
 pub fn lua_module(lua: &Lua) -> LuaResult<LuaTable> {
+    lua_module_with_config(lua, FfiConfig::default())
+}
+
+/// Create the FFI module for a Lua state configured by `config`, which applies to every FFI
+/// function and cdata of that state
+pub fn lua_module_with_config(lua: &Lua, config: FfiConfig) -> LuaResult<LuaTable> {
+    // Default C library
+    let c_lib = match config.library() {
+        _ if !config.exports("C") => None,
+        Some(name) => Some(cdata::CLib::load(name).map_err(|e| {
+            FfiError::new(ErrorKind::LibraryNotFound, format!("Failed to load library '{}': {}", name, e))
+        })?),
+        None => Some(cdata::CLib::load_default().map_err(LuaError::RuntimeError)?),
+    };
+    let omitted = config.omitted().to_vec();
+    config::install(lua, config);
+
     let exports = lua.create_table()?;
 
     exports.set("VERSION", LUA_FFI_VERSION)?;
 
     // Core FFI functions
-    exports.set("cdef", lua.create_function(ffi_cdef)?)?;
-    exports.set("load", lua.create_function(ffi_load)?)?;
-    exports.set("new", lua.create_function(ffi_new)?)?;
-    exports.set("cast", lua.create_function(ffi_cast)?)?;
-    exports.set("metatype", lua.create_function(ffi_metatype)?)?;
-    exports.set("typeof", lua.create_function(ffi_typeof)?)?;
+    exports.set("cdef", function(lua, ffi_cdef)?)?;
+    exports.set("extend", function(lua, ffi_extend)?)?;
+    exports.set("load", function(lua, ffi_load)?)?;
+    exports.set("new", function(lua, ffi_new)?)?;
+    exports.set("cast", function(lua, ffi_cast)?)?;
+    exports.set("reinterpret", function(lua, ffi_reinterpret)?)?;
+    exports.set("const_view", function(lua, ffi_const_view)?)?;
+    exports.set("metatype", function(lua, ffi_metatype)?)?;
+    exports.set("typeof", function(lua, ffi_typeof)?)?;
+    exports.set("out", function(lua, ffi_out)?)?;
     
     // Memory operations
-    exports.set("addressof", lua.create_function(ffi_addressof)?)?;
-    exports.set("gc", lua.create_function(ffi_gc)?)?;
-    exports.set("sizeof", lua.create_function(ffi_sizeof)?)?;
-    exports.set("offsetof", lua.create_function(ffi_offsetof)?)?;
+    exports.set("addressof", function(lua, ffi_addressof)?)?;
+    exports.set("deref", function(lua, ffi_deref)?)?;
+    exports.set("symaddr", function(lua, ffi_symaddr)?)?;
+    exports.set("whereis", function(lua, ffi_whereis)?)?;
+    #[cfg(feature = "hook")]
+    exports.set("hook", function(lua, ffi_hook)?)?;
+    #[cfg(feature = "hook")]
+    exports.set("unhook", function(lua, ffi_unhook)?)?;
+    exports.set("gc", function(lua, ffi_gc)?)?;
+    exports.set("free", function(lua, ffi_free)?)?;
+    exports.set("scope", function(lua, ffi_scope)?)?;
+    exports.set("sizeof", function(lua, ffi_sizeof)?)?;
+    exports.set("offsetof", function(lua, ffi_offsetof)?)?;
+    exports.set("fields", function(lua, ffi_fields)?)?;
+    exports.set("assert_layout", function(lua, ffi_assert_layout)?)?;
     
     // Type checking and conversion
-    exports.set("istype", lua.create_function(ffi_istype)?)?;
-    exports.set("tonumber", lua.create_function(ffi_tonumber)?)?;
-    exports.set("string", lua.create_function(ffi_string)?)?;
+    exports.set("istype", function(lua, ffi_istype)?)?;
+    exports.set("tonumber", function(lua, ffi_tonumber)?)?;
+    exports.set("string", function(lua, ffi_string)?)?;
+    exports.set("intern", function(lua, ffi_intern)?)?;
+    exports.set("wide", function(lua, ffi_wide)?)?;
+    exports.set("wstring", function(lua, ffi_wstring)?)?;
+    exports.set("tostring_int", function(lua, ffi_tostring_int)?)?;
+    exports.set("tohex", function(lua, ffi_tohex)?)?;
+    exports.set("enumname", function(lua, ffi_enumname)?)?;
+    exports.set("isnull", function(lua, ffi_isnull)?)?;
+    exports.set("null", function(lua, ffi_null)?)?;
+    exports.set("address", function(lua, ffi_address)?)?;
+    exports.set("tagptr", function(lua, ffi_tagptr)?)?;
+    exports.set("untag", function(lua, ffi_untag)?)?;
+    exports.set("strictconv", function(lua, ffi_strictconv)?)?;
+    exports.set("strictalign", function(lua, ffi_strictalign)?)?;
     
     // Buffer operations
-    exports.set("copy", lua.create_function(ffi_copy)?)?;
-    exports.set("fill", lua.create_function(ffi_fill)?)?;
+    exports.set("copy", function(lua, ffi_copy)?)?;
+    exports.set("fill", function(lua, ffi_fill)?)?;
+    exports.set("equal", function(lua, ffi_equal)?)?;
+    exports.set("hash", function(lua, ffi_hash)?)?;
+    exports.set("dump", function(lua, ffi_dump)?)?;
+    exports.set("undump", function(lua, ffi_undump)?)?;
     
     // System operations
-    exports.set("errno", lua.create_function(ffi_errno)?)?;
+    exports.set("errno", function(lua, ffi_errno)?)?;
+    exports.set("strerror", function(lua, ffi_strerror)?)?;
+    exports.set("winerror", function(lua, ffi_winerror)?)?;
+    exports.set("lasterror", function(lua, ffi_lasterror)?)?;
+    exports.set("clearerr", function(lua, ffi_clearerr)?)?;
+    exports.set("pump_callbacks", function(lua, ffi_pump_callbacks)?)?;
+    exports.set("call_with_timeout", function(lua, ffi_call_with_timeout)?)?;
+    exports.set("parallel_map", function(lua, ffi_parallel_map)?)?;
+    #[cfg(feature = "canaries")]
+    exports.set("checkcanaries", function(lua, ffi_checkcanaries)?)?;
 
     // Constants
     let nullptr = cdata::CData::new_null_ptr();
     exports.set("nullptr", lua.create_userdata(nullptr)?)?;
+    exports.set("E", syserr::errno_names(lua)?)?;
 
-    // Default C library
-    let c_lib = cdata::CLib::load_default().map_err(LuaError::RuntimeError)?;
-    exports.set("C", lua.create_userdata(c_lib)?)?;
+    if let Some(c_lib) = c_lib {
+        exports.set("C", lua.create_userdata(c_lib)?)?;
+    }
+
+    for name in omitted {
+        if name != "C" && exports.raw_get::<LuaValue>(name.as_str())?.is_nil() {
+            return Err(LuaError::RuntimeError(format!("Cannot omit unknown ffi function: {}", name)));
+        }
+        exports.raw_set(name, LuaValue::Nil)?;
+    }
+
+    // `err:kind()` and friends on the errors pcall catches
+    error::install_methods(lua)?;
 
     Ok(exports)
 }
 
+// Module function that runs with the configuration of its Lua state in effect
+fn function<A, R>(lua: &Lua, f: fn(&Lua, A) -> LuaResult<R>) -> LuaResult<LuaFunction>
+where
+    A: FromLuaMulti + 'static,
+    R: IntoLuaMulti + 'static,
+{
+    lua.create_function(move |lua, args| {
+        let _active = config::enter(lua);
+        f(lua, args)
+    })
+}
+
+/// Declare C types and functions for every Lua state, as `ffi.cdef` does; e.g. cdefs generated
+/// by a `build.rs` and embedded with `include_str!`
+pub fn cdef(code: &str) -> Result<(), String> {
+    parser::parse_cdef(code, Default::default())
+}
+
+/// Register the module with an existing Lua state so that `require("luaffi")` returns it.
+/// Luau cannot load C modules, so embedders call this instead and `require("@luaffi")`
+pub fn register(lua: &Lua) -> LuaResult<LuaTable> {
+    let exports = lua_module(lua)?;
+    let name = if cfg!(feature = "luau") { "@luaffi" } else { "luaffi" };
+    lua.register_module(name, &exports)?;
+    Ok(exports)
+}
+
+#[cfg(not(feature = "luau"))]
 fn init(state: *mut mlua::lua_State) -> libc::c_int {
     unsafe { mlua::Lua::entrypoint1(state, lua_module) }
 }
 
+#[cfg(not(feature = "luau"))]
 #[unsafe(no_mangle)]
 pub extern "C-unwind" fn luaopen_luaffi(state: *mut mlua::lua_State) -> libc::c_int {
     init(state)
 }
 
+/// Same entry point under LuaJIT's module name, so `require "ffi"` loads this library
+#[cfg(all(feature = "ffi-alias", not(feature = "luau")))]
+#[unsafe(no_mangle)]
+pub extern "C-unwind" fn luaopen_ffi(state: *mut mlua::lua_State) -> libc::c_int {
+    init(state)
+}
+
 /// Parse C definitions and register types
-fn ffi_cdef(_lua: &Lua, code: String) -> LuaResult<()> {
-    parser::parse_cdef(&code)
-        .map_err(|e| LuaError::RuntimeError(format!("Failed to parse C definitions: {}", e)))
+fn ffi_cdef(_lua: &Lua, (code, options): (String, Option<LuaTable>)) -> LuaResult<()> {
+    let options = options.map(ffi_ops::cdef_options).transpose()?.unwrap_or_default();
+    parser::parse_cdef(&code, options)
+        .map_err(|e| FfiError::new(ErrorKind::ParseError, format!("Failed to parse C definitions: {}", e)).into())
+}
+
+fn ffi_extend(lua: &Lua, (base, code, options): (String, String, Option<LuaTable>)) -> LuaResult<()> {
+    ffi_ops::extend(lua, &base, &code, options)
 }
 
 /// Load a dynamic library by name
 fn ffi_load(_lua: &Lua, name: String) -> LuaResult<LuaAnyUserData> {
-    let lib = cdata::CLib::load(&name)
-        .map_err(|e| LuaError::RuntimeError(format!("Failed to load library '{}': {}", name, e)))?;
+    config::check_load(&name)?;
+    let lib = cdata::CLib::load(&name).map_err(|e| {
+        FfiError::new(ErrorKind::LibraryNotFound, format!("Failed to load library '{}': {}", name, e))
+    })?;
     _lua.create_userdata(lib)
 }
 
 #[inline]
-fn ffi_new(lua: &Lua, (type_name, init): (String, Option<LuaValue>)) -> LuaResult<LuaAnyUserData> {
-    ffi_ops::new_cdata(lua, &type_name, init)
+fn ffi_new(
+    lua: &Lua,
+    (type_name, init, vla_init): (String, Option<LuaValue>, Option<LuaValue>),
+) -> LuaResult<LuaAnyUserData> {
+    ffi_ops::new_cdata(lua, &type_name, init, vla_init)
+}
+
+fn ffi_out(lua: &Lua, (type_name, init): (String, Option<LuaValue>)) -> LuaResult<LuaAnyUserData> {
+    ffi_ops::new_out(lua, &type_name, init)
 }
 
 #[inline]
@@ -83,42 +227,152 @@ fn ffi_cast(lua: &Lua, (type_name, value): (String, LuaValue)) -> LuaResult<LuaA
     ffi_ops::cast_cdata(lua, &type_name, value)
 }
 
+fn ffi_reinterpret(lua: &Lua, (type_name, cdata): (String, LuaAnyUserData)) -> LuaResult<LuaAnyUserData> {
+    ffi_ops::reinterpret(lua, &type_name, cdata)
+}
+
+fn ffi_const_view(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<LuaAnyUserData> {
+    ffi_ops::const_view(lua, cdata)
+}
+
 fn ffi_metatype(lua: &Lua, (type_name, metatable): (String, LuaTable)) -> LuaResult<LuaValue> {
     ffi_ops::set_metatype(lua, &type_name, metatable)
 }
 
-fn ffi_typeof(_lua: &Lua, type_name: String) -> LuaResult<String> {
-    Ok(type_name)
+/// Types are identified by their declaration, spelled the same way for equal types so the
+/// result can key dispatch tables: `ffi.typeof("unsigned") == ffi.typeof(ffi.new("unsigned int"))`
+fn ffi_typeof(_lua: &Lua, (value, args): (LuaValue, LuaMultiValue)) -> LuaResult<String> {
+    match value {
+        LuaValue::String(template) if !args.is_empty() => ffi_ops::substitute_type(&template.to_str()?, args),
+        LuaValue::String(type_name) => {
+            let type_name = type_name.to_str()?;
+            let ctype = ffi_ops::lookup_type(&type_name)?;
+            Ok(ffi_ops::canonical_type_name(&ctype).unwrap_or_else(|| type_name.to_string()))
+        }
+        LuaValue::UserData(ud) => {
+            let cdata = ud.borrow::<cdata::CData>()?;
+            ffi_ops::canonical_type_name(&cdata.ctype).ok_or_else(|| {
+                LuaError::RuntimeError(format!("Type {} has no name it can be looked up by", cdata.ctype))
+            })
+        }
+        other => Err(LuaError::RuntimeError(format!(
+            "Expected a type name or cdata, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn ffi_addressof(lua: &Lua, (cdata, path): (LuaAnyUserData, Option<LuaValue>)) -> LuaResult<LuaAnyUserData> {
+    match path.map(ffi_ops::integral) {
+        None | Some(LuaValue::Nil) => ffi_ops::get_address(lua, cdata),
+        // An integer addresses an array element
+        Some(LuaValue::Integer(i)) if i >= 0 => ffi_ops::get_field_address(lua, cdata, &format!("[{}]", i)),
+        Some(LuaValue::String(s)) => ffi_ops::get_field_address(lua, cdata, &s.to_str()?),
+        Some(other) => Err(LuaError::RuntimeError(format!(
+            "Expected a field path or array index, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn ffi_deref(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<LuaValue> {
+    ffi_ops::deref(lua, cdata)
 }
 
-fn ffi_addressof(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<LuaAnyUserData> {
-    ffi_ops::get_address(lua, cdata)
+/// Address of a library symbol, e.g. to hand a C function to a registration API
+fn ffi_symaddr(lua: &Lua, (lib, name): (LuaUserDataRef<cdata::CLib>, String)) -> LuaResult<LuaAnyUserData> {
+    lua.create_userdata(lib.address(&name)?)
+}
+
+/// Which loaded module provides a symbol, given by name or address: its path and the name of
+/// the symbol there, or nil. A name resolves as an undeclared `ffi.C.name` would
+fn ffi_whereis(_lua: &Lua, value: LuaValue) -> LuaResult<(Option<String>, Option<String>)> {
+    let address = match value {
+        LuaValue::String(name) => {
+            let c_lib = cdata::CLib::load_default().map_err(LuaError::RuntimeError)?;
+            match c_lib.get_symbol(&name.to_str()?) {
+                Some(address) => address,
+                None => return Ok((None, None)),
+            }
+        }
+        LuaValue::UserData(ud) => match ud.borrow::<cdata::CFunction>() {
+            Ok(func) => func.ptr,
+            Err(_) => ud.borrow::<cdata::CData>()?.as_ptr() as *mut libc::c_void,
+        },
+        other => {
+            return Err(LuaError::RuntimeError(format!(
+                "Expected a symbol name or pointer, got {}",
+                other.type_name()
+            )));
+        }
+    };
+    Ok(match dylib::module_of(address) {
+        Some((path, symbol)) => (Some(path), symbol),
+        None => (None, None),
+    })
+}
+
+#[cfg(feature = "hook")]
+fn ffi_hook(lua: &Lua, (target, replacement): (LuaAnyUserData, LuaValue)) -> LuaResult<LuaAnyUserData> {
+    hook::hook(lua, target, replacement)
+}
+
+#[cfg(feature = "hook")]
+fn ffi_unhook(_lua: &Lua, target: LuaAnyUserData) -> LuaResult<bool> {
+    hook::unhook(target)
 }
 
 fn ffi_gc(
     lua: &Lua,
-    (cdata, finalizer): (LuaAnyUserData, LuaFunction),
+    (cdata, finalizer): (LuaAnyUserData, Option<LuaValue>),
 ) -> LuaResult<LuaAnyUserData> {
-    ffi_ops::set_gc(lua, cdata, Some(finalizer))
+    ffi_ops::set_gc(lua, cdata, finalizer)
+}
+
+fn ffi_free(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<()> {
+    ffi_ops::free_cdata(lua, cdata)
+}
+
+fn ffi_scope(lua: &Lua, body: LuaFunction) -> LuaResult<LuaMultiValue> {
+    ffi_ops::scope(lua, body)
 }
 
 #[inline]
-fn ffi_sizeof(_lua: &Lua, type_name: String) -> LuaResult<usize> {
-    ffi_ops::sizeof_type(&type_name)
+fn ffi_sizeof(lua: &Lua, value: LuaValue) -> LuaResult<usize> {
+    match value {
+        LuaValue::UserData(ud) => ffi_ops::sizeof_cdata(&*ud.borrow::<cdata::CData>()?),
+        value => ffi_ops::sizeof_type(&String::from_lua(value, lua)?),
+    }
 }
 
 fn ffi_offsetof(_lua: &Lua, (type_name, field): (String, String)) -> LuaResult<usize> {
     ffi_ops::offsetof_field(&type_name, &field)
 }
 
+fn ffi_fields(lua: &Lua, type_name: String) -> LuaResult<LuaTable> {
+    ffi_ops::struct_fields(lua, &type_name)
+}
+
+fn ffi_assert_layout(lua: &Lua, (type_name, expected): (String, LuaTable)) -> LuaResult<()> {
+    ffi_ops::assert_layout(lua, &type_name, expected)
+}
+
 fn ffi_istype(_lua: &Lua, (type_name, value): (String, LuaValue)) -> LuaResult<bool> {
     // Check if value is a CData with the specified type
     match value {
         LuaValue::UserData(ud) => {
             if let Ok(cdata) = ud.borrow::<cdata::CData>() {
-                // Try to parse the expected type
+                // Try to parse the expected type; qualifiers only matter below the top level
                 match ffi_ops::lookup_type(&type_name) {
-                    Ok(expected_type) => Ok(cdata.ctype == expected_type),
+                    Ok(expected_type) => Ok(match (expected_type.resolved(), cdata.ctype.resolved()) {
+                        // `void*` matches any object pointer it converts to or from implicitly
+                        (CType::Ptr(target), CType::Ptr(source))
+                            if matches!(target.resolved(), CType::Void) || matches!(source.resolved(), CType::Void) =>
+                        {
+                            target.accepts_pointer_from(&cdata.ctype)
+                        }
+                        _ => cdata.ctype.unqualified() == expected_type.unqualified(),
+                    }),
                     Err(_) => Ok(false),
                 }
             } else {
@@ -133,50 +387,151 @@ fn ffi_tonumber(_lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<f64> {
     ffi_ops::cdata_to_number(cdata)
 }
 
-fn ffi_string(_lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<String> {
-    ffi_ops::cdata_to_string(cdata)
+fn ffi_tohex(_lua: &Lua, (value, width): (LuaValue, Option<i64>)) -> LuaResult<String> {
+    ffi_ops::to_hex(&value, width)
 }
 
+fn ffi_tostring_int(_lua: &Lua, value: LuaValue) -> LuaResult<String> {
+    ffi_ops::integer_to_string(&value)
+}
+
+fn ffi_string(lua: &Lua, (cdata, len): (LuaAnyUserData, Option<usize>)) -> LuaResult<LuaString> {
+    ffi_ops::cdata_to_string(lua, cdata, len)
+}
+
+fn ffi_intern(lua: &Lua, (cdata, len): (LuaAnyUserData, Option<usize>)) -> LuaResult<LuaString> {
+    ffi_ops::intern(lua, cdata, len)
+}
+
+fn ffi_wide(lua: &Lua, text: LuaString) -> LuaResult<LuaAnyUserData> {
+    ffi_ops::to_wide(lua, &text.as_bytes())
+}
+
+fn ffi_wstring(lua: &Lua, (cdata, len): (LuaAnyUserData, Option<usize>)) -> LuaResult<LuaString> {
+    ffi_ops::from_wide(lua, cdata, len)
+}
+
+fn ffi_enumname(_lua: &Lua, (type_name, value): (String, i64)) -> LuaResult<Option<String>> {
+    ffi_ops::enum_name(&type_name, value)
+}
+
+fn ffi_isnull(_lua: &Lua, value: LuaValue) -> LuaResult<bool> {
+    ffi_ops::is_null(&value)
+}
+
+fn ffi_null(lua: &Lua, type_name: String) -> LuaResult<LuaAnyUserData> {
+    ffi_ops::null_pointer(lua, &type_name)
+}
+
+fn ffi_address(lua: &Lua, value: LuaValue) -> LuaResult<LuaAnyUserData> {
+    ffi_ops::address_of(lua, &value)
+}
+
+fn ffi_tagptr(lua: &Lua, (pointer, bits): (LuaAnyUserData, i64)) -> LuaResult<LuaAnyUserData> {
+    ffi_ops::tag_pointer(lua, pointer, bits)
+}
+
+fn ffi_untag(lua: &Lua, (pointer, mask): (LuaAnyUserData, Option<i64>)) -> LuaResult<(LuaAnyUserData, i64)> {
+    ffi_ops::untag_pointer(lua, pointer, mask)
+}
+
+/// Query or toggle strict numeric conversions; returns the setting in effect before the call
+fn ffi_strictconv(lua: &Lua, enabled: Option<bool>) -> LuaResult<bool> {
+    Ok(match enabled {
+        Some(enabled) => config::set_strict_conversions(lua, enabled),
+        None => config::strict_conversions(),
+    })
+}
+
+/// Query or toggle alignment checks of casts; returns the setting in effect before the call
+fn ffi_strictalign(lua: &Lua, enabled: Option<bool>) -> LuaResult<bool> {
+    Ok(match enabled {
+        Some(enabled) => config::set_strict_alignment(lua, enabled),
+        None => config::strict_alignment(),
+    })
+}
+
+/// `ffi.copy(dst, src [, len])`, or with byte offsets `ffi.copy(dst, doff, src, soff [, len])`
 fn ffi_copy(
-    _lua: &Lua,
-    (dst, src, len): (LuaAnyUserData, LuaValue, Option<usize>),
+    lua: &Lua,
+    (dst, second, third, fourth, fifth): (LuaAnyUserData, LuaValue, LuaValue, Option<usize>, Option<usize>),
 ) -> LuaResult<usize> {
-    ffi_ops::copy_memory(dst, src, len)
+    if second.is_number() || second.is_integer() {
+        let dst_offset = usize::from_lua(second, lua)?;
+        return ffi_ops::copy_memory(dst, dst_offset, third, fourth.unwrap_or(0), fifth);
+    }
+    ffi_ops::copy_memory(dst, 0, second, 0, Option::<usize>::from_lua(third, lua)?)
 }
 
 fn ffi_fill(_lua: &Lua, (cdata, len, value): (LuaAnyUserData, usize, Option<u8>)) -> LuaResult<()> {
     ffi_ops::fill_memory(cdata, len, value.unwrap_or(0))
 }
 
-fn ffi_errno(_lua: &Lua, _new_errno: Option<i32>) -> LuaResult<i32> {
-    #[cfg(unix)]
-    {
-        unsafe {
-            #[cfg(target_os = "linux")]
-            {
-                let errno_ptr = libc::__errno_location();
-                let old_errno = *errno_ptr;
-                if let Some(new) = _new_errno {
-                    *errno_ptr = new;
-                }
-                Ok(old_errno)
-            }
-            #[cfg(not(target_os = "linux"))]
-            {
-                // For BSD/macOS: errno is accessed differently
-                let old_errno = *libc::__error();
-                if let Some(new) = _new_errno {
-                    *libc::__error() = new;
-                }
-                Ok(old_errno)
-            }
-        }
-    }
-    #[cfg(not(unix))]
-    {
-        // Windows and other platforms
-        Err(LuaError::RuntimeError(
-            "errno not supported on this platform".to_string(),
-        ))
+fn ffi_equal(_lua: &Lua, (a, b): (LuaAnyUserData, LuaAnyUserData)) -> LuaResult<bool> {
+    ffi_ops::cdata_equal(a, b)
+}
+
+fn ffi_hash(_lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<i64> {
+    ffi_ops::cdata_hash(cdata)
+}
+
+fn ffi_dump(lua: &Lua, cdata: LuaAnyUserData) -> LuaResult<LuaString> {
+    ffi_ops::dump(lua, cdata)
+}
+
+fn ffi_undump(lua: &Lua, (type_name, bytes): (String, LuaString)) -> LuaResult<LuaAnyUserData> {
+    ffi_ops::undump(lua, &type_name, &bytes.as_bytes())
+}
+
+/// Verify the canaries of every buffer cdata own, returning how many were checked
+#[cfg(feature = "canaries")]
+fn ffi_checkcanaries(_lua: &Lua, _: ()) -> LuaResult<usize> {
+    canary::check_all().map_err(LuaError::RuntimeError)
+}
+
+fn ffi_pump_callbacks(lua: &Lua, _: ()) -> LuaResult<usize> {
+    callback::pump(lua)
+}
+
+/// `ffi.call_with_timeout(fn, ms, ...)`: call a C function, giving up after `ms` milliseconds
+fn ffi_call_with_timeout(
+    lua: &Lua,
+    (func, ms, args): (LuaAnyUserData, f64, LuaMultiValue),
+) -> LuaResult<LuaValue> {
+    if !(ms >= 0.0 && ms.is_finite()) {
+        return Err(LuaError::RuntimeError(format!("Invalid timeout: {} ms", ms)));
     }
+    call::call_with_timeout(lua, func, std::time::Duration::from_secs_f64(ms / 1000.0), args)
+}
+
+fn ffi_parallel_map(
+    lua: &Lua,
+    (func, src, options): (LuaAnyUserData, LuaAnyUserData, Option<LuaTable>),
+) -> LuaResult<LuaAnyUserData> {
+    parallel::parallel_map(lua, func, src, options)
+}
+
+/// The last FFI error raised on this thread, or nil
+fn ffi_lasterror(_lua: &Lua, _: ()) -> LuaResult<LuaValue> {
+    Ok(match error::last_error() {
+        Some(error) => LuaValue::Error(Box::new(LuaError::external(error))),
+        None => LuaValue::Nil,
+    })
+}
+
+fn ffi_clearerr(_lua: &Lua, _: ()) -> LuaResult<()> {
+    error::clear_error();
+    Ok(())
+}
+
+fn ffi_errno(_lua: &Lua, new_errno: Option<i32>) -> LuaResult<i32> {
+    syserr::errno(new_errno)
+}
+
+fn ffi_strerror(_lua: &Lua, code: Option<i32>) -> LuaResult<String> {
+    syserr::strerror(code)
+}
+
+fn ffi_winerror(_lua: &Lua, code: Option<u32>) -> LuaResult<String> {
+    syserr::winerror(code)
 }
@@ -0,0 +1,207 @@
+// Element-wise application of a C function over cdata arrays, split across worker threads
+
+use std::ffi::c_void;
+use std::mem::size_of;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+
+use libffi::low::{self, CodePtr, ffi_cif, ffi_type};
+use libffi::middle::Type;
+use mlua::prelude::*;
+
+use crate::call::{self, Callee};
+use crate::callback;
+use crate::cdata::{create_owned, CData};
+use crate::ctype::CType;
+
+// What every worker shares: the function, its CIF and the two arrays
+struct Job {
+    cif: *mut ffi_cif,
+    fn_ptr: *mut c_void,
+    src: *mut u8,
+    dst: *mut u8,
+    in_stride: usize,
+    out_stride: usize,
+    ret_offset: usize,
+}
+
+// Workers only read the CIF and write disjoint parts of the output
+unsafe impl Sync for Job {}
+
+impl Job {
+    unsafe fn run(&self, range: std::ops::Range<usize>) {
+        let mut result = vec![0u64; self.out_stride.max(size_of::<u64>()).div_ceil(size_of::<u64>())];
+        for i in range {
+            unsafe {
+                let mut arg = self.src.add(i * self.in_stride) as *mut c_void;
+                libffi::raw::ffi_call(
+                    self.cif,
+                    Some(*CodePtr::from_ptr(self.fn_ptr).as_fun()),
+                    result.as_mut_ptr() as *mut c_void,
+                    &mut arg,
+                );
+                let value = (result.as_ptr() as *const u8).add(self.ret_offset);
+                std::ptr::copy_nonoverlapping(value, self.dst.add(i * self.out_stride), self.out_stride);
+            }
+        }
+    }
+}
+
+/// Apply `func`, a C function of one parameter, to each of the first `n` elements of `src`,
+/// storing the results in `out` or a new array. The elements are split into one contiguous
+/// chunk per thread; the function must be safe to call concurrently
+pub fn parallel_map(
+    lua: &Lua,
+    func: LuaAnyUserData,
+    src: LuaAnyUserData,
+    options: Option<LuaTable>,
+) -> LuaResult<LuaAnyUserData> {
+    let callee = Callee::of(&func)?;
+    let CType::Function(ret, params) = callee.ctype.resolved() else {
+        return Err(LuaError::RuntimeError("Not a function type".to_string()));
+    };
+    let [param] = params.as_slice() else {
+        return Err(LuaError::RuntimeError(format!(
+            "ffi.parallel_map needs a function of one parameter, '{}' takes {}",
+            callee.name,
+            params.len()
+        )));
+    };
+    if matches!(param, CType::VarArgs) || matches!(ret.resolved(), CType::Void) {
+        return Err(LuaError::RuntimeError(format!(
+            "ffi.parallel_map needs a function returning a value for one fixed parameter: {}",
+            callee.ctype
+        )));
+    }
+
+    let options = match options {
+        Some(options) => options,
+        None => lua.create_table()?,
+    };
+    // Both arrays stay borrowed until the workers are done, so callbacks cannot free them
+    let source = src.borrow::<CData>()?;
+    source.check_live()?;
+    let count = element_count(&source, param)?;
+    let n = match (options.get::<Option<usize>>("n")?, count) {
+        (Some(n), Some(count)) if n > count => {
+            return Err(LuaError::RuntimeError(format!(
+                "ffi.parallel_map cannot map {} elements of '{}', which has {}",
+                n, source.ctype, count
+            )));
+        }
+        (Some(n), _) | (None, Some(n)) => n,
+        (None, None) => {
+            return Err(LuaError::RuntimeError(format!(
+                "ffi.parallel_map needs the option 'n' for '{}', which has no length",
+                source.ctype
+            )));
+        }
+    };
+    let in_stride = param.size();
+    let out_stride = ret.size();
+    let too_many = || LuaError::RuntimeError(format!("ffi.parallel_map cannot map {} elements", n));
+    source.check_bounds(0, n.checked_mul(in_stride).ok_or_else(too_many)?)?;
+    let out_len = n.checked_mul(out_stride).ok_or_else(too_many)?;
+
+    let out = match options.get::<Option<LuaAnyUserData>>("out")? {
+        Some(out) => {
+            let target = out.borrow::<CData>()?;
+            target.check_live()?;
+            if let Some(count) = element_count(&target, ret)?
+                && n > count
+            {
+                return Err(LuaError::RuntimeError(format!(
+                    "ffi.parallel_map cannot store {} results in '{}', which has room for {}",
+                    n, target.ctype, count
+                )));
+            }
+            target.check_bounds(0, out_len)?;
+            drop(target);
+            out
+        }
+        None => {
+            let array = CType::Array(ret.clone(), n);
+            create_owned(lua, CData::new(array, out_len))?
+        }
+    };
+    let target = out.borrow::<CData>()?;
+    let dst = target.as_ptr();
+    if n == 0 {
+        drop(target);
+        return Ok(out);
+    }
+
+    let ret_ffi_type = call::ffi_type_of(ret)?;
+    let param_ffi_type = call::ffi_type_of(param)?;
+    let mut arg_ffi_ptrs: Vec<*mut ffi_type> = vec![Type::as_raw_ptr(&param_ffi_type)];
+    let mut cif: ffi_cif = Default::default();
+    unsafe {
+        low::prep_cif(&mut cif, call::abi_of(callee.convention), 1, ret_ffi_type.as_raw_ptr(), arg_ffi_ptrs.as_mut_ptr())
+    }
+    .map_err(|e| LuaError::RuntimeError(format!("Failed to prepare function call: {:?}", e)))?;
+
+    let threads = match options.get::<Option<usize>>("threads")? {
+        Some(0) => return Err(LuaError::RuntimeError("ffi.parallel_map needs at least one thread".to_string())),
+        Some(threads) => threads,
+        None => thread::available_parallelism().map_or(1, |threads| threads.get()),
+    }
+    .min(n);
+    let job = Job {
+        cif: &mut cif,
+        fn_ptr: callee.ptr,
+        src: source.as_ptr(),
+        dst,
+        in_stride,
+        out_stride,
+        ret_offset: call::return_offset(ret),
+    };
+
+    let chunk = n.div_ceil(threads);
+    let mut callback_error = None;
+    thread::scope(|scope| {
+        let (done, finished) = mpsc::channel();
+        for start in (0..n).step_by(chunk) {
+            let (job, done) = (&job, done.clone());
+            scope.spawn(move || {
+                unsafe { job.run(start..(start + chunk).min(n)) };
+                let _ = done.send(());
+            });
+        }
+        drop(done);
+        // Callbacks from the workers queue up for this thread; run them until all are done
+        loop {
+            if let Err(e) = callback::pump(lua) {
+                callback_error.get_or_insert(e);
+            }
+            match finished.recv_timeout(call::PUMP_INTERVAL) {
+                Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+    callback::pump(lua)?;
+    if let Some(e) = callback_error {
+        return Err(e);
+    }
+    source.check_live()?;
+    target.check_live()?;
+    drop(target);
+    Ok(out)
+}
+
+// Elements of `ctype` in an array cdata, or none for a pointer to them. Anything else, or
+// elements of another type, is an error
+fn element_count(cdata: &CData, ctype: &CType) -> LuaResult<Option<usize>> {
+    let (elem, count) = match cdata.ctype.resolved() {
+        CType::Array(elem, _) | CType::VLA(elem) => (elem, Some(cdata.size / elem.size().max(1))),
+        CType::Ptr(elem) => (elem, None),
+        _ => return Err(LuaError::RuntimeError(format!("Not an array or pointer: {}", cdata.ctype))),
+    };
+    if !elem.unqualified().same_type(ctype.unqualified()) {
+        return Err(LuaError::RuntimeError(format!(
+            "'{}' does not hold elements of type '{}'",
+            cdata.ctype, ctype
+        )));
+    }
+    Ok(count)
+}
@@ -1,18 +1,120 @@
+use std::cell::{Cell, RefCell};
+
 use nom::IResult;
 use nom::Parser;
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take_while, take_while1};
-use nom::character::complete::{char, digit1, multispace0, multispace1};
+use nom::character::complete::{char, multispace0, multispace1, space0, space1};
 use nom::combinator::{map, opt};
-use nom::multi::{many0, separated_list0};
-use nom::sequence::delimited;
+use nom::multi::{many0, separated_list0, separated_list1};
+use nom::sequence::{delimited, preceded};
 
-use crate::ctype::{CField, CType};
+use crate::ctype::{CallConv, CField, CType};
 use crate::ffi_ops;
+use crate::symbols;
+
+/// Defaults applied to one `ffi.cdef` block
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CdefOptions {
+    /// Maximum member alignment of structs and unions, like `#pragma pack(N)`
+    pub pack: Option<usize>,
+    /// Calling convention of functions declared without one
+    pub convention: CallConv,
+    /// Reject unknown type names and declarations that cannot be parsed instead of
+    /// skipping them
+    pub strict: bool,
+    /// Underlying type of enums declared without one
+    pub enums: EnumRepr,
+    /// How bit-fields are packed into storage units
+    pub bitfields: BitfieldLayout,
+    /// Treat every function of the block as optional, as if declared `__attribute__((weak))`
+    pub optional: Option<MissingSymbol>,
+    /// How calls of the functions of the block deal with signals
+    pub signals: Option<SignalHandling>,
+}
+
+/// What an optional function does when called while its library lacks the symbol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingSymbol {
+    /// Raise a catchable `SYMBOL_NOT_FOUND` error
+    #[default]
+    Raise,
+    /// Return the zero value of the return type (nil for `void`)
+    Zero,
+}
+
+/// How a function is protected from signals arriving while it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalHandling {
+    /// Call again while the call fails with `EINTR`: a result of -1 or NULL with errno set
+    /// to `EINTR` afterwards. Functions returning `void` are called once
+    Retry,
+    /// Block every signal that can be blocked for the duration of the call, except those
+    /// reporting faults such as `SIGSEGV`
+    Mask,
+}
+
+/// How the underlying integer type of an enum is chosen when the declaration does not name one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnumRepr {
+    /// `int`, or a 64-bit type when the values do not fit (GCC and Clang)
+    #[default]
+    Int,
+    /// The smallest integer type holding every value, like `-fshort-enums`
+    Short,
+    /// A fixed integer type of the given size in bytes and signedness
+    Fixed(usize, bool),
+}
+
+/// Bit-field layout rules; the default follows the target's C compiler
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitfieldLayout {
+    /// GCC and Clang: bit-fields share storage with any neighbouring bits, only moving to
+    /// the next unit when they would straddle one of their own type's alignment
+    #[cfg_attr(not(target_env = "msvc"), default)]
+    Gcc,
+    /// MSVC: consecutive bit-fields share a unit only while they have the same type size
+    /// and fit, and each unit occupies the full size of its type
+    #[cfg_attr(target_env = "msvc", default)]
+    Msvc,
+}
+
+thread_local! {
+    // While parsing cdef blocks, unknown type names are tolerated as placeholder typedefs
+    static LENIENT_NAMES: Cell<bool> = const { Cell::new(false) };
+    // Calling convention named in the function declaration being parsed
+    static CALLING_CONVENTION: Cell<CallConv> = const { Cell::new(CallConv::C) };
+    // Whether the function declaration being parsed carries `__attribute__((weak))`
+    static WEAK_DECLARATION: Cell<bool> = const { Cell::new(false) };
+    // Options of the cdef block being parsed
+    static CDEF_OPTIONS: Cell<CdefOptions> = Cell::new(CdefOptions::default());
+    // Types standing in for the `PARAMETER_PREFIX`-named placeholders of a type string
+    static TYPE_PARAMETERS: RefCell<Vec<CType>> = const { RefCell::new(Vec::new()) };
+    // Struct embedded at the start of the struct whose body begins at the given address, and
+    // the struct that came of it
+    static EXTENSION: RefCell<Option<Extension>> = const { RefCell::new(None) };
+    // Enumerators of the declaration being parsed, registered once the whole declaration parses
+    static PENDING_CONSTANTS: RefCell<Vec<(String, i64)>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Extension {
+    body: *const u8,
+    base: CType,
+    member: Option<String>,
+    result: Option<Result<CType, String>>,
+}
 
-/// Parse C definitions and register types in the global registry
-pub fn parse_cdef(code: &str) -> Result<(), String> {
+/// Prefix of the placeholder names that `parse_type_string_with` resolves to its parameters
+pub const PARAMETER_PREFIX: &str = "__ffi_param_";
+
+/// Parse C definitions and register types in the global registry; `options` sets the
+/// block-wide defaults for packing, calling convention and strictness
+pub fn parse_cdef(code: &str, options: CdefOptions) -> Result<(), String> {
+    let lenient = LENIENT_NAMES.replace(!options.strict);
+    let previous = CDEF_OPTIONS.replace(options);
     let result: IResult<&str, Vec<()>> = many0(parse_declaration).parse(code);
+    LENIENT_NAMES.set(lenient);
+    CDEF_OPTIONS.set(previous);
 
     match result {
         Ok((remaining, _)) => {
@@ -30,9 +132,72 @@ pub fn parse_cdef(code: &str) -> Result<(), String> {
     }
 }
 
+/// Parse C definitions like `parse_cdef`, embedding `base` at offset 0 of the first struct
+/// defined. The base becomes the member `member`, or with none its fields become the struct's
+/// own. Returns the extended struct
+pub fn parse_extension(code: &str, base: CType, member: Option<String>, options: CdefOptions) -> Result<CType, String> {
+    let Some(body) = code.find('{') else {
+        return Err("No struct definition to extend".to_string());
+    };
+    let extension = Extension { body: code[body..].as_ptr(), base, member, result: None };
+    let previous = EXTENSION.replace(Some(extension));
+    let parsed = parse_cdef(code, options);
+    let extension = EXTENSION.replace(previous).expect("extension in progress");
+    match (extension.result, parsed) {
+        (Some(Err(e)), _) | (_, Err(e)) => Err(e),
+        (Some(Ok(ctype)), Ok(())) => Ok(ctype),
+        (None, Ok(())) => Err("No struct definition to extend".to_string()),
+    }
+}
+
+/// Parse a complete runtime type string such as `unsigned long long*`, `int (*)[10]`
+/// or `const char*[?]`
+pub fn parse_type_string(type_str: &str) -> Result<CType, String> {
+    match declaring_constants(type_str, parse_type_name, |result| matches!(result, Ok(("", _)))) {
+        Ok(("", ctype)) => Ok(ctype),
+        Ok(_) => Err(format!("Invalid type: {}", type_str.trim())),
+        Err(nom::Err::Failure(e)) if e.code == nom::error::ErrorKind::Tag => {
+            // The error points at the offending name, possibly preceded by its tag keyword
+            let mut words = e.input.split(|c: char| !(c.is_alphanumeric() || c == '_'));
+            let name = match words.next().unwrap_or_default() {
+                kind @ ("struct" | "union" | "enum") => {
+                    format!("{} {}", kind, words.find(|w| !w.is_empty()).unwrap_or_default())
+                }
+                name => name.to_string(),
+            };
+            let known = ffi_ops::type_names();
+            let names = known.iter().map(String::as_str).chain(BASIC_TYPE_KEYWORDS.iter().copied());
+            Err(format!("Unknown type: {}{}", name, symbols::did_you_mean(&symbols::suggestions(&name, names))))
+        }
+        Err(_) => Err(format!("Invalid type: {}", type_str.trim())),
+    }
+}
+
+/// Parse a type string in which the names `__ffi_param_0`, `__ffi_param_1`, ... stand for
+/// `parameters`, so types of any shape can be spliced in where a type name may appear
+pub fn parse_type_string_with(type_str: &str, parameters: Vec<CType>) -> Result<CType, String> {
+    let previous = TYPE_PARAMETERS.replace(parameters);
+    let result = parse_type_string(type_str);
+    TYPE_PARAMETERS.set(previous);
+    result
+}
+
+/// A type specifier followed by an abstract declarator, e.g. `const char *[4]`
+fn parse_type_name(input: &str) -> IResult<&str, CType> {
+    let (input, base) = parse_type_specifier(input)?;
+    let (input, declarator) = parse_declarator(input)?;
+    let (input, _) = multispace0(input)?;
+    match declarator.apply(base) {
+        (None, ctype) => Ok((input, ctype)),
+        (Some(_), _) => Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify))),
+    }
+}
+
 /// Parse a single declaration (struct, typedef, or function)
 fn parse_declaration(input: &str) -> IResult<&str, ()> {
     let (input, _) = multispace0(input)?;
+    // GCC marks declarations using extensions with `__extension__`
+    let input = skip_words(input, &["__extension__"]);
     
     // Early return if no input left
     if input.is_empty() {
@@ -41,115 +206,948 @@ fn parse_declaration(input: &str) -> IResult<&str, ()> {
     
     // Try parsing different declaration types
     alt((
-        map(parse_struct, |_| ()),
-        map(parse_typedef, |_| ()),
-        map(parse_function, |_| ()),
+        parse_define,
+        |input| declaring_constants(input, parse_typedef, Result::is_ok),
+        |input| declaring_constants(input, parse_tagged_declaration, Result::is_ok),
+        |input| declaring_constants(input, parse_function_declaration, Result::is_ok),
+        |input| declaring_constants(input, skip_declaration, Result::is_ok),
     )).parse(input)
 }
 
-fn parse_struct(input: &str) -> IResult<&str, CType> {
+/// Run `parser`, registering the enumerators it parses only when `complete` accepts its
+/// result, so a declaration that fails part-way leaves no constants behind
+fn declaring_constants<'a, T>(
+    input: &'a str,
+    parser: impl FnOnce(&'a str) -> IResult<&'a str, T>,
+    complete: impl FnOnce(&IResult<&'a str, T>) -> bool,
+) -> IResult<&'a str, T> {
+    let mark = PENDING_CONSTANTS.with_borrow(Vec::len);
+    let result = parser(input);
+    let parsed = PENDING_CONSTANTS.with_borrow_mut(|pending| pending.split_off(mark));
+    if complete(&result) {
+        for (name, value) in parsed {
+            ffi_ops::register_constant(name, value);
+        }
+    }
+    result
+}
+
+/// `struct X { ... };`, `union U { ... };` or `enum E { ... };` on its own
+fn parse_tagged_declaration(input: &str) -> IResult<&str, ()> {
+    let (input, _) = parse_type_specifier(input)?;
     let (input, _) = multispace0(input)?;
-    let (input, _) = tag("struct")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, name) = identifier(input)?;
+    let (input, _) = char(';')(input)?;
     let (input, _) = multispace0(input)?;
-    let (input, mut fields) = delimited(char('{'), parse_struct_fields, char('}')).parse(input)?;
+    Ok((input, ()))
+}
+
+/// `int printf(const char *fmt, ...);`; declared functions are registered so the library
+/// symbol of the same name can be called
+fn parse_function_declaration(input: &str) -> IResult<&str, ()> {
+    CALLING_CONVENTION.set(CDEF_OPTIONS.get().convention);
+    WEAK_DECLARATION.set(false);
+    let (input, base) = parse_type_specifier(input)?;
+    let (input, declarators) = separated_list1(char(','), parse_declarator).parse(input)?;
     let (input, _) = multispace0(input)?;
     let (input, _) = char(';')(input)?;
     let (input, _) = multispace0(input)?;
 
-    // Calculate field offsets with proper alignment
-    calculate_field_offsets(&mut fields);
+    let convention = CALLING_CONVENTION.get();
+    let options = CDEF_OPTIONS.get();
+    let optional = match options.optional {
+        None if WEAK_DECLARATION.get() => Some(MissingSymbol::Raise),
+        optional => optional,
+    };
+    for declarator in declarators {
+        if let (Some(name), ctype @ CType::Function(_, _)) = declarator.apply(base.clone()) {
+            ffi_ops::register_symbol(name.to_string(), ctype, convention, optional, options.signals);
+        }
+    }
+    Ok((input, ()))
+}
+
+// Qualifiers that do not change the layout of a type, including GCC's alternate spellings
+const TYPE_QUALIFIERS: &[&str] = &[
+    "const", "volatile", "restrict", "_Atomic", "__const", "__const__", "__volatile", "__volatile__",
+    "__restrict", "__restrict__",
+];
 
-    let name_string = name.to_string();
-    let ctype = CType::Struct(name_string.clone(), fields);
-    
-    // Register the type in global registry
-    ffi_ops::register_type(name_string, ctype.clone());
+// Storage classes, function specifiers and other decoration irrelevant to the declared type
+const STORAGE_CLASSES: &[&str] = &["extern", "static", "inline", "__inline", "__inline__", "__extension__"];
+
+// Keywords that combine into a single basic type (`unsigned long long int`)
+const BASIC_TYPE_KEYWORDS: &[&str] = &[
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "bool", "_Bool",
+];
+
+/// Parse the type specifier part of a declaration: qualifiers, basic type keywords,
+/// `struct`/`union`/`enum` types (optionally with a body) or a typedef name
+fn parse_type_specifier(input: &str) -> IResult<&str, CType> {
+    let mut input = input;
+    let mut keywords = Vec::new();
+    let mut named = None;
+    let mut constant = false;
+
+    loop {
+        input = skip_decorations(input);
+        let (rest, _) = multispace0(input)?;
+        let Ok((after, word)) = identifier(rest) else {
+            break;
+        };
+
+        if word == "_Atomic" && named.is_none() && keywords.is_empty() && after.trim_start().starts_with('(') {
+            // `_Atomic(T)` names a type with the same layout as `T`
+            let (after, ctype) = delimited((multispace0, char('(')), parse_type_name, char(')')).parse(after)?;
+            named = Some(ctype);
+            input = after;
+        } else if TYPE_QUALIFIERS.contains(&word) || STORAGE_CLASSES.contains(&word) {
+            constant |= matches!(word, "const" | "__const" | "__const__");
+            input = after;
+        } else if named.is_none() && BASIC_TYPE_KEYWORDS.contains(&word) {
+            keywords.push(word);
+            input = after;
+        } else if named.is_some() || !keywords.is_empty() {
+            // Anything else after a complete type is the declarator name
+            break;
+        } else if matches!(word, "struct" | "union" | "enum") {
+            let (after, ctype) = parse_tagged_type(word, after).map_err(|e| match e {
+                nom::Err::Failure(e) if e.code == nom::error::ErrorKind::Tag => {
+                    nom::Err::Failure(nom::error::Error::new(rest, e.code))
+                }
+                e => e,
+            })?;
+            named = Some(ctype);
+            input = after;
+        } else if let Some(ctype) = lookup_type_name(word) {
+            named = Some(ctype);
+            input = after;
+        } else {
+            return Err(nom::Err::Failure(nom::error::Error::new(rest, nom::error::ErrorKind::Tag)));
+        }
+    }
+
+    let ctype = match named {
+        Some(ctype) => Some(ctype),
+        None => basic_type_from_keywords(&keywords),
+    };
+    match ctype {
+        Some(ctype) if constant => Ok((input, CType::Const(Box::new(ctype)))),
+        Some(ctype) => Ok((input, ctype)),
+        None => Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Tag))),
+    }
+}
+
+// Resolve a single-word type name (`int32_t`, a typedef or a registered struct)
+fn lookup_type_name(name: &str) -> Option<CType> {
+    if let Some(index) = name.strip_prefix(PARAMETER_PREFIX).and_then(|index| index.parse::<usize>().ok())
+        && let Some(ctype) = TYPE_PARAMETERS.with_borrow(|parameters| parameters.get(index).cloned())
+    {
+        return Some(ctype);
+    }
+    match ffi_ops::lookup_named_type(name) {
+        Some(ctype) => Some(ctype),
+        // Inside cdef, fall back to typedef for unknown types
+        None if LENIENT_NAMES.get() => Some(CType::Typedef(name.to_string(), Box::new(CType::Int))),
+        None => None,
+    }
+}
+
+// Combine basic type keywords into a type, e.g. `unsigned short int` -> UShort
+fn basic_type_from_keywords(keywords: &[&str]) -> Option<CType> {
+    let unsigned = keywords.contains(&"unsigned");
+    let short = keywords.contains(&"short");
+    let longs = keywords.iter().filter(|k| **k == "long").count();
+    let base = keywords
+        .iter()
+        .find(|k| !matches!(**k, "signed" | "unsigned" | "short" | "long" | "int"));
+
+    let ctype = match (base.copied(), short, longs) {
+        (Some("char"), false, 0) if unsigned => CType::UChar,
+        (Some("char"), false, 0) => CType::Char,
+        (Some("float"), false, 0) => CType::Float,
+        (Some("double"), false, 0) => CType::Double,
+        (Some("void"), false, 0) => CType::Void,
+        (Some("bool" | "_Bool"), false, 0) => CType::Bool,
+        (None, true, 0) if unsigned => CType::UShort,
+        (None, true, 0) => CType::Short,
+        (None, false, 0) if unsigned => CType::UInt,
+        (None, false, 0) if !keywords.is_empty() => CType::Int,
+        (None, false, 1) if unsigned => CType::ULong,
+        (None, false, 1) => CType::Long,
+        (None, false, 2) if unsigned => CType::ULongLong,
+        (None, false, 2) => CType::LongLong,
+        _ => return None,
+    };
+    Some(ctype)
+}
+
+/// `struct`/`union`/`enum` followed by a tag and/or a body; bodies with a tag are registered
+fn parse_tagged_type<'a>(kind: &str, input: &'a str) -> IResult<&'a str, CType> {
+    let (rest, _) = multispace0(skip_decorations(input))?;
+    let mut packed = packed_attribute(input, rest);
+    let (input, name) = opt(identifier).parse(rest)?;
+    let (rest, _) = multispace0(skip_decorations(input))?;
+    packed |= packed_attribute(input, rest);
+    let input = rest;
+    let name_string = name.unwrap_or_default().to_string();
+
+    // C23 and C++ enums may name their underlying type: `enum Flags : uint8_t { ... }`
+    let (input, underlying) = if kind == "enum" {
+        let (input, underlying) = opt(preceded((char(':'), multispace0), parse_type_specifier)).parse(input)?;
+        let (input, _) = multispace0(input)?;
+        if underlying.as_ref().is_some_and(|ctype| ctype.resolved().integer_kind().is_none()) {
+            return Err(nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+        }
+        (input, underlying)
+    } else {
+        (input, None)
+    };
+
+    if !input.starts_with('{') {
+        let Some(name) = name else {
+            return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Tag)));
+        };
+        let tagged_name = format!("{} {}", kind, name);
+        if let Some(ctype) = ffi_ops::lookup_named_type(&tagged_name) {
+            return Ok((input, ctype));
+        }
+        if !LENIENT_NAMES.get() {
+            return Err(nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Tag)));
+        }
+
+        // Inside cdef, mentioning an unknown tag declares an incomplete (forward) type
+        let ctype = match kind {
+            "struct" => CType::Struct(name_string, Vec::new()),
+            "union" => CType::Union(name_string, Vec::new()),
+            _ => CType::Enum(name_string, Vec::new(), Box::new(underlying.unwrap_or(CType::Int))),
+        };
+        ffi_ops::register_type(tagged_name, ctype.clone());
+        return Ok((input, ctype));
+    }
+
+    let (input, ctype) = if kind == "enum" {
+        let (input, values) = delimited(char('{'), parse_enumerators, char('}')).parse(input)?;
+        // `__attribute__((packed))` may also follow the closing brace
+        packed |= packed_attribute(input, skip_decorations(input));
+        let repr = match underlying {
+            Some(underlying) => underlying,
+            None if packed => enum_type(&values, EnumRepr::Short),
+            None => enum_type(&values, CDEF_OPTIONS.get().enums),
+        };
+        (input, CType::Enum(name_string.clone(), values, Box::new(repr)))
+    } else {
+        let body = input.as_ptr();
+        let (input, mut fields) = delimited(char('{'), parse_struct_fields, char('}')).parse(input)?;
+        // The struct `ffi.extend` extends starts with its base
+        let extension = EXTENSION.with_borrow(|extension| {
+            let extension = extension.as_ref().filter(|extension| kind == "struct" && extension.body == body)?;
+            Some((extension.base.clone(), extension.member.clone()))
+        });
+        if let Some((base, member)) = &extension {
+            fields.insert(0, CField { name: member.clone().unwrap_or_default(), ctype: base.clone(), offset: 0 });
+        }
+        let pack = CDEF_OPTIONS.get().pack;
+        let ctype = if kind == "struct" {
+            // Calculate field offsets with proper alignment
+            calculate_field_offsets(&mut fields, pack, CDEF_OPTIONS.get().bitfields);
+            if let Some((base, None)) = &extension {
+                fields = match flatten_base(base, fields) {
+                    Ok(fields) => fields,
+                    Err(e) => {
+                        set_extension_result(Err(e));
+                        return Err(nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+                    }
+                };
+            }
+            CType::Struct(name_string.clone(), without_unnamed(fields))
+        } else {
+            CType::Union(name_string.clone(), without_unnamed(fields))
+        };
+        let ctype = match pack {
+            Some(pack) if pack < ctype.alignment() => CType::Packed(pack, Box::new(ctype)),
+            _ => ctype,
+        };
+        if extension.is_some() {
+            set_extension_result(Ok(ctype.clone()));
+        }
+        (input, ctype)
+    };
+
+    // Anonymous types are only usable where they are declared. Named ones are reachable
+    // both by tag (`struct Point`) and, for convenience, by the bare name
+    if !name_string.is_empty() {
+        ffi_ops::register_type(format!("{} {}", kind, name_string), ctype.clone());
+        ffi_ops::register_type(name_string, ctype.clone());
+    }
 
     Ok((input, ctype))
 }
 
-/// Calculate field offsets with proper alignment
+/// Calculate field offsets with proper alignment, capped at `pack` bytes if given
 #[inline]
-fn calculate_field_offsets(fields: &mut [CField]) {
-    let mut offset = 0;
+fn calculate_field_offsets(fields: &mut [CField], pack: Option<usize>, layout: BitfieldLayout) {
+    // Bits used so far, and the MSVC storage unit still open as (offset, size, bits used)
+    let mut bitpos = 0usize;
+    let mut unit: Option<(usize, usize, usize)> = None;
     for field in fields.iter_mut() {
-        let align = field.ctype.alignment();
-        // Align offset to field alignment
-        offset = (offset + align - 1) & !(align - 1);
-        field.offset = offset;
-        offset += field.ctype.size();
+        let align = field.ctype.alignment().min(pack.unwrap_or(usize::MAX));
+        let CType::Bitfield(inner, shift, width) = &mut field.ctype else {
+            // Align offset to field alignment
+            let offset = bitpos.div_ceil(8).next_multiple_of(align);
+            field.offset = offset;
+            bitpos = (offset + field.ctype.size()) * 8;
+            unit = None;
+            continue;
+        };
+
+        let width = *width;
+        if width == 0 {
+            // An unnamed zero-width bit-field ends the current unit
+            bitpos = bitpos.next_multiple_of(align * 8);
+            field.offset = bitpos / 8;
+            unit = None;
+            continue;
+        }
+        match layout {
+            BitfieldLayout::Gcc => {
+                let unit_bits = align * 8;
+                let packed = align < inner.alignment();
+                if !packed && bitpos / unit_bits != (bitpos + width - 1) / unit_bits {
+                    bitpos = bitpos.next_multiple_of(unit_bits);
+                }
+                // Packed bit-fields follow each other bit by bit
+                field.offset = if packed { bitpos / 8 } else { bitpos / unit_bits * align };
+                *shift = bitpos - field.offset * 8;
+                bitpos += width;
+            }
+            BitfieldLayout::Msvc => match &mut unit {
+                Some((offset, size, used)) if *size == inner.size() && *used + width <= *size * 8 => {
+                    field.offset = *offset;
+                    *shift = *used;
+                    *used += width;
+                }
+                _ => {
+                    let offset = bitpos.div_ceil(8).next_multiple_of(align);
+                    field.offset = offset;
+                    *shift = 0;
+                    unit = Some((offset, inner.size(), width));
+                    bitpos = (offset + inner.size()) * 8;
+                }
+            },
+        }
+    }
+}
+
+// The fields of the struct `base` in place of the unnamed member laid out for it at the start
+// of `fields`, which keep their offsets
+fn flatten_base(base: &CType, fields: Vec<CField>) -> Result<Vec<CField>, String> {
+    let CType::Struct(_, inherited) = base.resolved() else {
+        return Err(format!("Cannot extend '{}', which is not a struct", base));
+    };
+    let mut flattened = inherited.clone();
+    for field in fields.into_iter().skip(1) {
+        if inherited.iter().any(|inherited| inherited.name == field.name) {
+            return Err(format!("Field '{}' is already a member of '{}'", field.name, base));
+        }
+        flattened.push(field);
     }
+    Ok(flattened)
+}
+
+fn set_extension_result(result: Result<CType, String>) {
+    EXTENSION.with_borrow_mut(|extension| {
+        if let Some(extension) = extension {
+            extension.result = Some(result);
+        }
+    });
+}
+
+// Unnamed bit-fields only take part in the layout
+fn without_unnamed(fields: Vec<CField>) -> Vec<CField> {
+    fields.into_iter().filter(|field| !field.name.is_empty()).collect()
 }
 
 fn parse_struct_fields(input: &str) -> IResult<&str, Vec<CField>> {
     let (input, _) = multispace0(input)?;
-    let (input, fields) = separated_list0(char(';'), parse_field).parse(input)?;
-    let (input, _) = opt(char(';')).parse(input)?;
+    let (input, fields) = many0(parse_field_declaration).parse(input)?;
     let (input, _) = multispace0(input)?;
-    Ok((input, fields))
+    Ok((input, fields.into_iter().flatten().collect()))
 }
 
-fn parse_field(input: &str) -> IResult<&str, CField> {
+/// One field declaration line, possibly declaring several fields (`int x, *y;`)
+fn parse_field_declaration(input: &str) -> IResult<&str, Vec<CField>> {
     let (input, _) = multispace0(input)?;
-    let (input, type_name) = parse_type(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, name) = identifier(input)?;
-    let (input, array_size) = opt(parse_array_size).parse(input)?;
+    let (input, base) = parse_type_specifier(input)?;
+    let (input, declarators) = separated_list1(char(','), (parse_declarator, opt(parse_bit_width))).parse(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, _) = char(';')(input)?;
+    let (input, _) = multispace0(input)?;
+
+    let mut fields = Vec::with_capacity(declarators.len());
+    for (declarator, width) in declarators {
+        let (name, ctype) = declarator.apply(base.clone());
+        let invalid = || nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Verify));
+        let ctype = match width {
+            // Bit-fields must be integers no wider than their type, and only unnamed ones may be empty
+            Some(width) => {
+                let integral = match ctype.resolved() {
+                    CType::Bool | CType::Enum(_, _, _) => true,
+                    ctype => ctype.integer_kind().is_some(),
+                };
+                if !integral || width < 0 || width as usize > ctype.size() * 8 || (width == 0 && name.is_some()) {
+                    return Err(invalid());
+                }
+                CType::Bitfield(Box::new(ctype), 0, width as usize)
+            }
+            None if name.is_none() => {
+                return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+            }
+            None => ctype,
+        };
+        fields.push(CField {
+            name: name.unwrap_or_default().to_string(),
+            ctype,
+            offset: 0, // Will be calculated later
+        });
+    }
+    Ok((input, fields))
+}
+
+// `: width` after a field declarator
+fn parse_bit_width(input: &str) -> IResult<&str, i64> {
+    let (input, _) = (multispace0, char(':'), multispace0).parse(input)?;
+    let (input, width) = parse_const_expr(input)?;
     let (input, _) = multispace0(input)?;
+    Ok((input, width))
+}
+
+/// A parsed (possibly abstract) declarator such as `*name[4]` or `(*cb)(int)`
+struct Declarator<'a> {
+    name: Option<&'a str>,
+    pointers: usize,
+    inner: Option<Box<Declarator<'a>>>,
+    suffixes: Vec<DeclaratorSuffix>,
+}
+
+enum DeclaratorSuffix {
+    Array(usize),
+    Vla,
+    Function(Vec<CType>),
+}
+
+impl<'a> Declarator<'a> {
+    /// Build the declared type from the base type, following C's inside-out reading rules
+    fn apply(self, base: CType) -> (Option<&'a str>, CType) {
+        let mut ctype = base;
+        for _ in 0..self.pointers {
+            ctype = CType::Ptr(Box::new(ctype));
+        }
+        for suffix in self.suffixes.into_iter().rev() {
+            ctype = match suffix {
+                DeclaratorSuffix::Array(count) => CType::Array(Box::new(ctype), count),
+                DeclaratorSuffix::Vla => CType::VLA(Box::new(ctype)),
+                DeclaratorSuffix::Function(params) => CType::Function(Box::new(ctype), params),
+            };
+        }
+        match self.inner {
+            Some(inner) => inner.apply(ctype),
+            None => (self.name, ctype),
+        }
+    }
+}
 
-    let ctype = if let Some(size) = array_size {
-        CType::Array(Box::new(type_name), size)
+fn parse_declarator(input: &str) -> IResult<&str, Declarator<'_>> {
+    let mut input = input;
+    let mut pointers = 0;
+    loop {
+        let (rest, _) = multispace0(skip_decorations(input))?;
+        if let Some(rest) = rest.strip_prefix('*') {
+            pointers += 1;
+            input = skip_qualifiers(rest);
+        } else {
+            input = rest;
+            break;
+        }
+    }
+
+    // A parenthesised declarator starts with `*` (possibly after a calling convention);
+    // otherwise `(` opens a parameter list
+    let (mut input, name, inner) = if input.starts_with('(')
+        && skip_decorations(&input[1..]).trim_start().starts_with('*')
+    {
+        let (rest, inner) = delimited(char('('), parse_declarator, (multispace0, char(')'))).parse(input)?;
+        (rest, None, Some(Box::new(inner)))
     } else {
-        type_name
+        let (rest, name) = opt(identifier).parse(input)?;
+        (rest, name, None)
     };
 
-    Ok((
-        input,
-        CField {
-            name: name.to_string(),
-            ctype,
-            offset: 0, // Will be calculated later
-        },
+    let mut suffixes = Vec::new();
+    loop {
+        let (rest, _) = multispace0(input)?;
+        if rest.starts_with('[') {
+            let (rest, suffix) = parse_array_suffix(rest)?;
+            suffixes.push(suffix);
+            input = rest;
+        } else if rest.starts_with('(') {
+            let (rest, params) = delimited(char('('), parse_parameters, char(')')).parse(rest)?;
+            suffixes.push(DeclaratorSuffix::Function(params));
+            input = rest;
+        } else {
+            break;
+        }
+    }
+
+    // Trailing attributes, e.g. `void fail(void) __attribute__((noreturn))`
+    let input = skip_decorations(input);
+    Ok((input, Declarator { name, pointers, inner, suffixes }))
+}
+
+// Skip `const`/`volatile` following a `*`
+fn skip_qualifiers(input: &str) -> &str {
+    skip_words(input, TYPE_QUALIFIERS)
+}
+
+// Calling conventions and Windows API macros; the others use the platform default ABI
+const CALLING_CONVENTIONS: &[(&str, CallConv)] = &[
+    ("__cdecl", CallConv::C),
+    ("_cdecl", CallConv::C),
+    ("__thiscall", CallConv::C),
+    ("__vectorcall", CallConv::C),
+    ("WINAPIV", CallConv::C),
+    ("__stdcall", CallConv::Stdcall),
+    ("_stdcall", CallConv::Stdcall),
+    ("WINAPI", CallConv::Stdcall),
+    ("APIENTRY", CallConv::Stdcall),
+    ("CALLBACK", CallConv::Stdcall),
+    ("NTAPI", CallConv::Stdcall),
+    ("STDMETHODCALLTYPE", CallConv::Stdcall),
+    ("__fastcall", CallConv::Fastcall),
+    ("_fastcall", CallConv::Fastcall),
+];
+
+// SAL annotations look like `_In_`, `_Out_opt_` or `_Inout_updates_(count)`
+fn is_sal_annotation(word: &str) -> bool {
+    word.len() > 2
+        && word.starts_with('_')
+        && word.ends_with('_')
+        && word[1..].starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+/// Skip declaration decoration that does not affect the type: SAL annotations,
+/// `__declspec(...)`, `__attribute__((...))` and calling conventions (which are remembered,
+/// as is a weak attribute)
+fn skip_decorations(input: &str) -> &str {
+    let mut input = input;
+    loop {
+        let rest = input.trim_start();
+        let Ok((after, word)) = identifier(rest) else {
+            return input;
+        };
+        let takes_args = matches!(word, "__declspec" | "__attribute__" | "__attribute") || is_sal_annotation(word);
+        if !takes_args {
+            let Some((_, convention)) = CALLING_CONVENTIONS.iter().find(|(name, _)| *name == word) else {
+                return input;
+            };
+            CALLING_CONVENTION.set(*convention);
+        }
+
+        let after = after.trim_start();
+        input = match after.strip_prefix('(') {
+            Some(args) if takes_args => match skip_balanced_parens(args) {
+                Some(after) => {
+                    if word.starts_with("__attribute") && is_weak_attribute(&args[..args.len() - after.len()]) {
+                        WEAK_DECLARATION.set(true);
+                    }
+                    after
+                }
+                None => return input,
+            },
+            _ => after,
+        };
+    }
+}
+
+// Whether the arguments of an `__attribute__` name `weak` (or `__weak__`)
+fn is_weak_attribute(args: &str) -> bool {
+    args.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .any(|word| matches!(word, "weak" | "__weak__"))
+}
+
+// Whether the decorations skipped between `input` and `rest` include a packed attribute
+fn packed_attribute(input: &str, rest: &str) -> bool {
+    input[..input.len() - rest.len()].contains("packed")
+}
+
+// Underlying integer type of an enum with the given enumerators
+fn enum_type(values: &[(String, i64)], repr: EnumRepr) -> CType {
+    let min = values.iter().map(|(_, value)| *value).min().unwrap_or(0);
+    let max = values.iter().map(|(_, value)| *value).max().unwrap_or(0);
+    match repr {
+        EnumRepr::Fixed(size, signed) => CType::integer(size, signed).unwrap_or(CType::Int),
+        EnumRepr::Int if min >= i32::MIN as i64 && max <= i32::MAX as i64 => CType::Int,
+        EnumRepr::Int if min >= 0 && max <= u32::MAX as i64 => CType::UInt,
+        EnumRepr::Int if min >= 0 => CType::ULongLong,
+        EnumRepr::Int => CType::LongLong,
+        EnumRepr::Short => {
+            // Unsigned when no value is negative, as GCC does
+            let signed = min < 0;
+            let size = [1, 2, 4]
+                .into_iter()
+                .find(|size| {
+                    let bits = size * 8;
+                    if signed {
+                        min >= -(1i64 << (bits - 1)) && max < 1i64 << (bits - 1)
+                    } else {
+                        max < 1i64 << bits
+                    }
+                })
+                .unwrap_or(8);
+            CType::integer(size, signed).unwrap_or(CType::Int)
+        }
+    }
+}
+
+// Skip to just past the `)` closing an already opened parenthesis
+fn skip_balanced_parens(input: &str) -> Option<&str> {
+    let mut depth = 1;
+    for (i, c) in input.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&input[i + 1..]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Skip any of the given keywords, returning the input after the last one
+fn skip_words<'a>(input: &'a str, words: &[&str]) -> &'a str {
+    let mut input = input;
+    loop {
+        let rest = input.trim_start();
+        match identifier(rest) {
+            Ok((after, word)) if words.contains(&word) => input = after,
+            _ => return input,
+        }
+    }
+}
+
+/// Function parameter list; `(void)` means no parameters and a trailing `...` makes the
+/// function variadic
+fn parse_parameters(input: &str) -> IResult<&str, Vec<CType>> {
+    // Conventions of function pointer parameters do not apply to the declared function
+    let convention = CALLING_CONVENTION.get();
+    let parsed = separated_list0(char(','), alt((parse_varargs, parse_parameter))).parse(input);
+    CALLING_CONVENTION.set(convention);
+    let (input, params) = parsed?;
+    let (input, _) = multispace0(input)?;
+
+    if matches!(params.as_slice(), [CType::Void]) {
+        return Ok((input, Vec::new()));
+    }
+    if params.iter().rev().skip(1).any(|param| *param == CType::VarArgs) {
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+    }
+    Ok((input, params))
+}
+
+fn parse_varargs(input: &str) -> IResult<&str, CType> {
+    let (input, _) = multispace0(input)?;
+    let (input, _) = tag("...")(input)?;
+    let (input, _) = multispace0(input)?;
+    Ok((input, CType::VarArgs))
+}
+
+fn parse_parameter(input: &str) -> IResult<&str, CType> {
+    let (input, _) = multispace0(input)?;
+    let (input, base) = parse_type_specifier(input)?;
+    let (input, declarator) = parse_declarator(input)?;
+    let (input, _) = multispace0(input)?;
+
+    // Array and function parameters decay to pointers
+    let ctype = match declarator.apply(base).1 {
+        CType::Array(inner, _) => CType::Ptr(inner),
+        function @ CType::Function(_, _) => CType::Ptr(Box::new(function)),
+        ctype => ctype,
+    };
+    Ok((input, ctype))
+}
+
+fn parse_enumerators(input: &str) -> IResult<&str, Vec<(String, i64)>> {
+    let mut values = Vec::new();
+    let mut next = 0i64;
+    let (mut input, _) = multispace0(input)?;
+
+    // Enumerators are visible as they are parsed so later values can refer to earlier ones
+    while let Ok((rest, name)) = identifier(input) {
+        let (rest, _) = multispace0(rest)?;
+        let (rest, explicit) = opt(|input| {
+            let (input, _) = char('=')(input)?;
+            parse_const_expr(input)
+        })
+        .parse(rest)?;
+        let value = explicit.unwrap_or(next);
+        next = value.wrapping_add(1);
+        PENDING_CONSTANTS.with_borrow_mut(|pending| pending.push((name.to_string(), value)));
+        values.push((name.to_string(), value));
+
+        let (rest, _) = multispace0(rest)?;
+        let (rest, comma) = opt(char(',')).parse(rest)?;
+        let (rest, _) = multispace0(rest)?;
+        input = rest;
+        if comma.is_none() {
+            break;
+        }
+    }
+
+    Ok((input, values))
+}
+
+/// Evaluate an integer constant expression (enumerator values, array sizes)
+fn parse_const_expr(input: &str) -> IResult<&str, i64> {
+    parse_binary_expr(input, 0)
+}
+
+// Binary operators grouped by precedence, loosest binding first
+const BINARY_OPERATORS: &[&[&str]] = &[
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+fn parse_binary_expr(input: &str, level: usize) -> IResult<&str, i64> {
+    if level == BINARY_OPERATORS.len() {
+        return parse_unary_expr(input);
+    }
+
+    let (mut input, mut value) = parse_binary_expr(input, level + 1)?;
+    loop {
+        let (rest, _) = multispace0(input)?;
+        // `||` and `&&` are logical operators, not two bitwise ones
+        let op = BINARY_OPERATORS[level].iter().find(|op| {
+            rest.starts_with(**op) && !(op.len() == 1 && rest[1..].starts_with(**op))
+        });
+        let Some(op) = op else {
+            return Ok((input, value));
+        };
+
+        let (rest, rhs) = parse_binary_expr(&rest[op.len()..], level + 1)?;
+        value = match *op {
+            "|" => value | rhs,
+            "^" => value ^ rhs,
+            "&" => value & rhs,
+            "<<" => value.wrapping_shl(rhs as u32),
+            ">>" => value.wrapping_shr(rhs as u32),
+            "+" => value.wrapping_add(rhs),
+            "-" => value.wrapping_sub(rhs),
+            "*" => value.wrapping_mul(rhs),
+            _ if rhs == 0 => {
+                return Err(nom::Err::Failure(nom::error::Error::new(
+                    input,
+                    nom::error::ErrorKind::Verify,
+                )));
+            }
+            "/" => value.wrapping_div(rhs),
+            _ => value.wrapping_rem(rhs),
+        };
+        input = rest;
+    }
+}
+
+fn parse_unary_expr(input: &str) -> IResult<&str, i64> {
+    let (input, _) = multispace0(input)?;
+    alt((
+        map((char('-'), parse_unary_expr), |(_, v)| v.wrapping_neg()),
+        map((char('+'), parse_unary_expr), |(_, v)| v),
+        map((char('~'), parse_unary_expr), |(_, v)| !v),
+        map((char('!'), parse_unary_expr), |(_, v)| (v == 0) as i64),
+        delimited(char('('), parse_const_expr, (multispace0, char(')'))),
+        parse_sizeof,
+        parse_integer,
+        parse_constant_name,
     ))
+    .parse(input)
+}
+
+fn parse_sizeof(input: &str) -> IResult<&str, i64> {
+    let (input, _) = tag("sizeof")(input)?;
+    let (input, _) = multispace0(input)?;
+    // A whole type name, so parentheses inside it (`sizeof(int (*)(void))`) stay balanced
+    let (rest, ctype) = delimited(char('('), parse_type_name, char(')')).parse(input)?;
+    Ok((rest, ctype.size() as i64))
 }
 
-// Parse type with optimized matching - use ffi_ops lookup to avoid duplication
-fn parse_type(input: &str) -> IResult<&str, CType> {
-    let (input, type_str) = identifier(input)?;
+fn parse_constant_name(input: &str) -> IResult<&str, i64> {
+    let (rest, name) = identifier(input)?;
+    let pending = PENDING_CONSTANTS.with_borrow(|pending| {
+        pending.iter().rev().find(|(pending, _)| pending == name).map(|&(_, value)| value)
+    });
+    match pending.or_else(|| ffi_ops::lookup_constant(name)) {
+        Some(value) => Ok((rest, value)),
+        None => Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        ))),
+    }
+}
 
-    // Try to look up as basic type first (fast path)
-    let ctype = if let Ok(basic_type) = ffi_ops::lookup_type(type_str) {
-        basic_type
+fn parse_integer(input: &str) -> IResult<&str, i64> {
+    alt((parse_char_literal, parse_number_literal)).parse(input)
+}
+
+/// Integer literal in decimal, hex (`0x1F`), octal (`0755`) or binary (`0b1010`) form,
+/// with optional `u`/`l` suffixes
+fn parse_number_literal(input: &str) -> IResult<&str, i64> {
+    if !input.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Digit)));
+    }
+    let (rest, literal) = take_while1(|c: char| c.is_ascii_alphanumeric())(input)?;
+
+    let body = literal.trim_end_matches(['u', 'U', 'l', 'L']);
+    let (radix, digits) = if let Some(hex) = body.strip_prefix("0x").or(body.strip_prefix("0X")) {
+        (16, hex)
+    } else if let Some(bin) = body.strip_prefix("0b").or(body.strip_prefix("0B")) {
+        (2, bin)
+    } else if body.len() > 1 && body.starts_with('0') {
+        (8, &body[1..])
     } else {
-        // Fall back to typedef for unknown types
-        CType::Typedef(type_str.to_string(), Box::new(CType::Int))
+        (10, body)
     };
 
-    Ok((input, ctype))
+    // Parse as unsigned so values like 0xFFFFFFFFFFFFFFFF keep their bit pattern
+    let value = u64::from_str_radix(digits, radix).map_err(|_| {
+        nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Digit))
+    })?;
+    Ok((rest, value as i64))
+}
+
+/// Character literal such as `'A'`, `'\n'`, `'\x41'` or `'\0'`
+fn parse_char_literal(input: &str) -> IResult<&str, i64> {
+    let (rest, _) = char('\'')(input)?;
+    let mut chars = rest.chars();
+    let failure = || nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Char));
+
+    let (value, consumed) = match chars.next().ok_or_else(failure)? {
+        '\\' => {
+            let escape = chars.next().ok_or_else(failure)?;
+            match escape {
+                'n' => (b'\n' as i64, 2),
+                't' => (b'\t' as i64, 2),
+                'r' => (b'\r' as i64, 2),
+                'a' => (0x07, 2),
+                'b' => (0x08, 2),
+                'f' => (0x0c, 2),
+                'v' => (0x0b, 2),
+                'x' => {
+                    let hex: String = rest[2..].chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+                    let value = i64::from_str_radix(&hex, 16).map_err(|_| failure())?;
+                    (value, 2 + hex.len())
+                }
+                '0'..='7' => {
+                    let oct: String = rest[1..].chars().take(3).take_while(|c| c.is_digit(8)).collect();
+                    let value = i64::from_str_radix(&oct, 8).map_err(|_| failure())?;
+                    (value, 1 + oct.len())
+                }
+                other => (other as i64, 1 + other.len_utf8()),
+            }
+        }
+        '\'' => return Err(failure()),
+        c => (c as i64, c.len_utf8()),
+    };
+
+    match rest[consumed..].strip_prefix('\'') {
+        Some(rest) => Ok((rest, value)),
+        None => Err(failure()),
+    }
 }
 
-fn parse_array_size(input: &str) -> IResult<&str, usize> {
+/// `[N]`, `[]` (flexible array, zero length) or `[?]` (variable length, sized by ffi.new)
+fn parse_array_suffix(input: &str) -> IResult<&str, DeclaratorSuffix> {
     let (input, _) = char('[')(input)?;
-    let (input, digits) = digit1(input)?;
+    let (input, _) = multispace0(input)?;
+    if let Some(rest) = input.strip_prefix(']') {
+        return Ok((rest, DeclaratorSuffix::Array(0)));
+    }
+    if let Some(rest) = input.strip_prefix('?') {
+        let (rest, _) = multispace0(rest)?;
+        let (rest, _) = char(']')(rest)?;
+        return Ok((rest, DeclaratorSuffix::Vla));
+    }
+
+    let (input, size) = parse_const_expr(input)?;
+    let (input, _) = multispace0(input)?;
     let (input, _) = char(']')(input)?;
-    let size = digits.parse().expect("Failed to parse array size");
-    Ok((input, size))
+    if size < 0 {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+    Ok((input, DeclaratorSuffix::Array(size as usize)))
+}
+
+/// `#define NAME <constant expression>`; other macros are skipped
+fn parse_define(input: &str) -> IResult<&str, ()> {
+    let (input, _) = char('#')(input)?;
+    let (input, _) = space0(input)?;
+    let (input, _) = tag("define")(input)?;
+    let (input, _) = space1(input)?;
+    let (input, name) = identifier(input)?;
+    let (input, body) = take_while(|c| c != '\n')(input)?;
+    let (input, _) = multispace0(input)?;
+
+    if let Ok((rest, value)) = parse_const_expr(body)
+        && rest.trim().is_empty()
+    {
+        ffi_ops::register_constant(name.to_string(), value);
+    }
+
+    Ok((input, ()))
 }
 
 fn parse_typedef(input: &str) -> IResult<&str, ()> {
     let (input, _) = multispace0(input)?;
     let (input, _) = tag("typedef")(input)?;
     let (input, _) = multispace1(input)?;
-    // Skip typedef for now
+    alt((parse_typedef_declarators, skip_typedef)).parse(input)
+}
+
+/// `typedef <type> <declarator>, ...;`, registering each declared name
+fn parse_typedef_declarators(input: &str) -> IResult<&str, ()> {
+    let (input, base) = parse_type_specifier(input)?;
+    let (input, declarators) = separated_list1(char(','), parse_declarator).parse(input)?;
+    let (input, _) = multispace0(input)?;
+    let (input, _) = char(';')(input)?;
+
+    for declarator in declarators {
+        if let (Some(name), ctype) = declarator.apply(base.clone()) {
+            ffi_ops::register_type(name.to_string(), CType::Typedef(name.to_string(), Box::new(ctype)));
+        }
+    }
+    Ok((input, ()))
+}
+
+// Typedefs the declarator grammar does not understand are skipped
+fn skip_typedef(input: &str) -> IResult<&str, ()> {
     let (input, _) = take_while(|c| c != ';')(input)?;
     let (input, _) = char(';')(input)?;
     Ok((input, ()))
 }
 
-fn parse_function(input: &str) -> IResult<&str, ()> {
-    // Skip function declarations for now
+// Declarations the grammar does not understand are skipped up to the end of the statement
+fn skip_declaration(input: &str) -> IResult<&str, ()> {
+    if CDEF_OPTIONS.get().strict {
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+    }
     // Must consume at least one character
     let (input, _) = take_while1(|c: char| c != ';' && c != '\n')(input)?;
     let (input, _) = opt(char(';')).parse(input)?;
@@ -168,7 +1166,7 @@ mod tests {
     #[test]
     fn test_parse_simple_struct() {
         let code = "struct Point { int x; int y; };";
-        let result = parse_cdef(code);
+        let result = parse_cdef(code, CdefOptions::default());
         if let Err(e) = &result {
             eprintln!("Parse error: {}", e);
         }
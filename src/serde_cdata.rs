@@ -0,0 +1,269 @@
+// Serde support for struct and array cdata, driven by their C types
+
+use std::fmt;
+
+use mlua::prelude::*;
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::cdata::{self, CData};
+use crate::ctype::CType;
+use crate::ffi_ops;
+
+/// Serializes the value of a cdata: structs and unions become maps of their members (a union
+/// only its first), arrays sequences, `char` arrays strings, enums their enumerator names and
+/// scalars numbers or booleans. Pointers have no portable value and fail to serialize
+///
+/// ```ignore
+/// let json = serde_json::to_string(&luaffi::serde_cdata::Serializable::new(&ud)?)?;
+/// ```
+pub struct Serializable {
+    cdata: LuaUserDataRef<CData>,
+}
+
+impl Serializable {
+    pub fn new(cdata: &LuaAnyUserData) -> LuaResult<Self> {
+        let cdata = cdata.borrow::<CData>()?;
+        cdata.check_live()?;
+        if cdata.is_null() {
+            return Err(LuaError::RuntimeError("NULL pointer".to_string()));
+        }
+        cdata.check_bounds(0, cdata.ctype.size())?;
+        Ok(Self { cdata })
+    }
+}
+
+impl Serialize for Serializable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Value { ptr: self.cdata.ptr, ctype: &self.cdata.ctype }.serialize(serializer)
+    }
+}
+
+// The value of type `ctype` stored at `ptr`
+struct Value<'a> {
+    ptr: *const u8,
+    ctype: &'a CType,
+}
+
+impl Serialize for Value<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ptr = self.ptr;
+        match self.ctype {
+            CType::Typedef(_, inner) | CType::Const(inner) | CType::Packed(_, inner) => {
+                Value { ptr, ctype: inner }.serialize(serializer)
+            }
+            CType::Struct(_, fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for field in fields {
+                    map.serialize_entry(&field.name, &Value { ptr: ptr.wrapping_add(field.offset), ctype: &field.ctype })?;
+                }
+                map.end()
+            }
+            // Which member is live is unknown, so a union is taken as its first, as C initializes it
+            CType::Union(_, fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len().min(1)))?;
+                if let Some(field) = fields.first() {
+                    map.serialize_entry(&field.name, &Value { ptr: ptr.wrapping_add(field.offset), ctype: &field.ctype })?;
+                }
+                map.end()
+            }
+            CType::Array(elem, count) if matches!(elem.resolved(), CType::Char) => {
+                let bytes = unsafe { std::slice::from_raw_parts(ptr, *count) };
+                let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                serializer.serialize_str(&String::from_utf8_lossy(&bytes[..len]))
+            }
+            CType::Array(elem, count) => {
+                let mut seq = serializer.serialize_seq(Some(*count))?;
+                for i in 0..*count {
+                    seq.serialize_element(&Value { ptr: ptr.wrapping_add(i * elem.size()), ctype: elem })?;
+                }
+                seq.end()
+            }
+            CType::Bool => serializer.serialize_bool(unsafe { ptr.read() } != 0),
+            CType::Float => serializer.serialize_f32(unsafe { (ptr as *const f32).read_unaligned() }),
+            CType::Double => serializer.serialize_f64(unsafe { (ptr as *const f64).read_unaligned() }),
+            CType::Bitfield(inner, shift, width) => {
+                let bits = ffi_ops::read_bits(ptr, *shift, *width);
+                let value = if ffi_ops::is_signed(inner) && *width < 64 {
+                    let unused = 64 - *width as u32;
+                    (((bits << unused) as i64) >> unused) as i128
+                } else {
+                    bits as i128
+                };
+                match inner.resolved() {
+                    CType::Bool => serializer.serialize_bool(bits != 0),
+                    _ => serialize_integer(serializer, inner, value),
+                }
+            }
+            ctype => match integer_at(ptr, ctype) {
+                Some(value) => serialize_integer(serializer, ctype, value),
+                None => Err(ser::Error::custom(format!("Cannot serialize a value of type '{}'", ctype))),
+            },
+        }
+    }
+}
+
+// Enumerators serialize by name when the value has one
+fn serialize_integer<S: Serializer>(serializer: S, ctype: &CType, value: i128) -> Result<S::Ok, S::Error> {
+    if let Some(name) = i64::try_from(value).ok().and_then(|value| ctype.enum_name(value)) {
+        return serializer.serialize_str(name);
+    }
+    match i64::try_from(value) {
+        Ok(value) => serializer.serialize_i64(value),
+        Err(_) => serializer.serialize_u64(value as u64),
+    }
+}
+
+// Value of an integer, enum or byte-order integer stored at `ptr`
+fn integer_at(ptr: *const u8, ctype: &CType) -> Option<i128> {
+    match ctype {
+        CType::Typedef(_, inner) | CType::Const(inner) | CType::Enum(_, _, inner) => integer_at(ptr, inner),
+        CType::Swapped(inner) => {
+            let mut bytes = [0u8; 8];
+            let size = inner.size().min(bytes.len());
+            unsafe { std::ptr::copy_nonoverlapping(ptr, bytes.as_mut_ptr(), size) };
+            bytes[..size].reverse();
+            integer_at(bytes.as_ptr(), inner)
+        }
+        ctype => {
+            let (size, signed) = ctype.integer_layout()?;
+            Some(unsafe { ffi_ops::read_integer(ptr, size, signed) })
+        }
+    }
+}
+
+/// Deserializes a new cdata of `type_name` from the shape `Serializable` produces. Members and
+/// elements left out are zero, and enum members accept enumerator names as well as numbers
+///
+/// ```ignore
+/// let point = Seed::new(&lua, "struct point")?.deserialize(&mut serde_json::Deserializer::from_str(json))?;
+/// ```
+pub struct Seed<'a> {
+    lua: &'a Lua,
+    type_name: String,
+    ctype: CType,
+}
+
+impl<'a> Seed<'a> {
+    pub fn new(lua: &'a Lua, type_name: &str) -> LuaResult<Self> {
+        let ctype = ffi_ops::lookup_type(type_name)?;
+        Ok(Self { lua, type_name: type_name.to_string(), ctype })
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Seed<'_> {
+    type Value = LuaAnyUserData;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let value = Initializer { lua: self.lua, ctype: &self.ctype }.deserialize(deserializer)?;
+        ffi_ops::new_cdata(self.lua, &self.type_name, Some(value), None).map_err(de::Error::custom)
+    }
+}
+
+// Builds the Lua initializer (as `ffi.new` takes it) for a value of type `ctype`
+#[derive(Clone, Copy)]
+struct Initializer<'a> {
+    lua: &'a Lua,
+    ctype: &'a CType,
+}
+
+impl<'de> DeserializeSeed<'de> for Initializer<'_> {
+    type Value = LuaValue;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<LuaValue, D::Error> {
+        match self.ctype.resolved() {
+            CType::Struct(_, _) | CType::Union(_, _) => deserializer.deserialize_map(self),
+            CType::Array(elem, _) | CType::VLA(elem) if !matches!(elem.resolved(), CType::Char) => {
+                deserializer.deserialize_seq(self)
+            }
+            CType::Ptr(_) | CType::Function(_, _) | CType::Void => {
+                Err(de::Error::custom(format!("Cannot deserialize a value of type '{}'", self.ctype)))
+            }
+            _ => deserializer.deserialize_any(self),
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for Initializer<'_> {
+    type Value = LuaValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a value of type '{}'", self.ctype)
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<LuaValue, E> {
+        Ok(LuaValue::Boolean(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<LuaValue, E> {
+        cdata::integer_value(self.lua, &CType::Int64, value as i128).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<LuaValue, E> {
+        cdata::integer_value(self.lua, &CType::UInt64, value as i128).map_err(E::custom)
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<LuaValue, E> {
+        Ok(LuaValue::Number(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<LuaValue, E> {
+        let ctype = match self.ctype.resolved() {
+            CType::Bitfield(inner, _, _) => inner.resolved(),
+            ctype => ctype,
+        };
+        if let CType::Enum(_, values, _) = ctype {
+            return match values.iter().find(|(name, _)| name == value) {
+                Some((_, value)) => Ok(LuaValue::Integer(*value)),
+                None => Err(E::custom(format!("'{}' is not an enumerator of '{}'", value, self.ctype))),
+            };
+        }
+        self.visit_bytes(value.as_bytes())
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<LuaValue, E> {
+        self.lua.create_string(value).map(LuaValue::String).map_err(E::custom)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<LuaValue, E> {
+        Ok(LuaValue::Nil)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<LuaValue, E> {
+        Ok(LuaValue::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<LuaValue, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<LuaValue, A::Error> {
+        let elem = match self.ctype.resolved() {
+            CType::Array(elem, _) | CType::VLA(elem) => elem,
+            _ => return Err(de::Error::invalid_type(de::Unexpected::Seq, &self)),
+        };
+        let table = self.lua.create_table().map_err(de::Error::custom)?;
+        let mut index = 1;
+        while let Some(value) = seq.next_element_seed(Initializer { lua: self.lua, ctype: elem })? {
+            table.raw_set(index, value).map_err(de::Error::custom)?;
+            index += 1;
+        }
+        Ok(LuaValue::Table(table))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<LuaValue, A::Error> {
+        let fields = match self.ctype.resolved() {
+            CType::Struct(_, fields) | CType::Union(_, fields) => fields,
+            _ => return Err(de::Error::invalid_type(de::Unexpected::Map, &self)),
+        };
+        let table = self.lua.create_table().map_err(de::Error::custom)?;
+        while let Some(name) = map.next_key::<String>()? {
+            let Some(field) = fields.iter().find(|field| field.name == name) else {
+                return Err(de::Error::custom(format!("'{}' has no member named '{}'", self.ctype, name)));
+            };
+            let value = map.next_value_seed(Initializer { lua: self.lua, ctype: &field.ctype })?;
+            table.raw_set(name, value).map_err(de::Error::custom)?;
+        }
+        Ok(LuaValue::Table(table))
+    }
+}
@@ -0,0 +1,114 @@
+// Helpers for matching and describing symbol and type names
+
+/// Check whether an Itanium-mangled C++ symbol contains `name` as one of its identifiers
+fn mangled_contains(symbol: &str, name: &str) -> bool {
+    if !symbol.starts_with("_Z") || name.is_empty() {
+        return false;
+    }
+    // Identifiers are encoded as <length><name>, e.g. `_ZN2ns3addEii` for `ns::add(int, int)`
+    let encoded = format!("{}{}", name.len(), name);
+    // A digit before the match would make it part of a longer length prefix
+    symbol
+        .match_indices(&encoded)
+        .any(|(i, _)| !symbol[..i].ends_with(|c: char| c.is_ascii_digit()))
+}
+
+/// Human-readable form of a symbol name: demangled when the `demangle` feature is enabled
+pub fn display_name(symbol: &str) -> String {
+    #[cfg(feature = "demangle")]
+    if let Some(demangled) = demangle(symbol) {
+        return demangled;
+    }
+    symbol.to_string()
+}
+
+/// Mangled C++ symbols that may be what a failed lookup of `name` was looking for
+pub fn cpp_candidates<'a>(exports: &'a [String], name: &str) -> Vec<&'a str> {
+    let mut candidates: Vec<&str> = exports
+        .iter()
+        .map(String::as_str)
+        .filter(|symbol| mangled_contains(symbol, name))
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+    candidates
+}
+
+/// Find the mangled symbol whose demangled name is `name`, either the full signature
+/// (`ns::add(int, int)`) or, if unambiguous, the qualified name alone (`ns::add`)
+#[cfg(feature = "demangle")]
+pub fn find_demangled<'a>(exports: &'a [String], name: &str) -> Option<&'a str> {
+    let name = name.trim();
+    let mut by_name = None;
+    let mut ambiguous = false;
+
+    for symbol in exports.iter().filter(|symbol| symbol.starts_with("_Z")) {
+        let Some(demangled) = demangle(symbol) else {
+            continue;
+        };
+        if demangled == name {
+            return Some(symbol);
+        }
+        if demangled.split('(').next() == Some(name) {
+            ambiguous |= by_name.is_some_and(|found| found != symbol.as_str());
+            by_name = Some(symbol.as_str());
+        }
+    }
+
+    if ambiguous { None } else { by_name }
+}
+
+/// Up to three of `names` closest to a misspelled `name`, best match first
+pub fn suggestions<'a>(name: &str, names: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let len = name.chars().count();
+    let limit = (len / 3).max(1);
+    let mut scored: Vec<(usize, &str)> = names
+        .into_iter()
+        .filter(|candidate| *candidate != name && candidate.chars().count().abs_diff(len) <= limit)
+        .filter_map(|candidate| {
+            let distance = edit_distance(name, candidate);
+            (distance <= limit).then_some((distance, candidate))
+        })
+        .collect();
+    scored.sort_unstable();
+    scored.dedup();
+    scored.into_iter().take(3).map(|(_, candidate)| candidate).collect()
+}
+
+/// Message suffix for suggestions, e.g. ` (did you mean: printf?)`
+pub fn did_you_mean(suggestions: &[&str]) -> String {
+    if suggestions.is_empty() {
+        return String::new();
+    }
+    format!(" (did you mean: {}?)", suggestions.join(", "))
+}
+
+// Levenshtein distance that also counts swapping two adjacent characters as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(feature = "demangle")]
+fn demangle(symbol: &str) -> Option<String> {
+    cpp_demangle::Symbol::new(symbol.as_bytes()).ok()?.demangle().ok()
+}
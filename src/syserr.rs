@@ -0,0 +1,183 @@
+// Error codes of the operating system: errno, its names and messages, and on Windows the
+// messages of `GetLastError` codes
+
+use mlua::prelude::*;
+
+// Names of the errno values POSIX defines, with this platform's numbers
+#[cfg(unix)]
+const ERRNO_NAMES: &[(&str, libc::c_int)] = &[
+    ("EPERM", libc::EPERM),
+    ("ENOENT", libc::ENOENT),
+    ("ESRCH", libc::ESRCH),
+    ("EINTR", libc::EINTR),
+    ("EIO", libc::EIO),
+    ("ENXIO", libc::ENXIO),
+    ("E2BIG", libc::E2BIG),
+    ("ENOEXEC", libc::ENOEXEC),
+    ("EBADF", libc::EBADF),
+    ("ECHILD", libc::ECHILD),
+    ("EAGAIN", libc::EAGAIN),
+    ("EWOULDBLOCK", libc::EWOULDBLOCK),
+    ("ENOMEM", libc::ENOMEM),
+    ("EACCES", libc::EACCES),
+    ("EFAULT", libc::EFAULT),
+    ("EBUSY", libc::EBUSY),
+    ("EEXIST", libc::EEXIST),
+    ("EXDEV", libc::EXDEV),
+    ("ENODEV", libc::ENODEV),
+    ("ENOTDIR", libc::ENOTDIR),
+    ("EISDIR", libc::EISDIR),
+    ("EINVAL", libc::EINVAL),
+    ("ENFILE", libc::ENFILE),
+    ("EMFILE", libc::EMFILE),
+    ("ENOTTY", libc::ENOTTY),
+    ("ETXTBSY", libc::ETXTBSY),
+    ("EFBIG", libc::EFBIG),
+    ("ENOSPC", libc::ENOSPC),
+    ("ESPIPE", libc::ESPIPE),
+    ("EROFS", libc::EROFS),
+    ("EMLINK", libc::EMLINK),
+    ("EPIPE", libc::EPIPE),
+    ("EDOM", libc::EDOM),
+    ("ERANGE", libc::ERANGE),
+    ("EDEADLK", libc::EDEADLK),
+    ("ENAMETOOLONG", libc::ENAMETOOLONG),
+    ("ENOLCK", libc::ENOLCK),
+    ("ENOSYS", libc::ENOSYS),
+    ("ENOTEMPTY", libc::ENOTEMPTY),
+    ("ELOOP", libc::ELOOP),
+    ("ENOMSG", libc::ENOMSG),
+    ("EIDRM", libc::EIDRM),
+    ("ENOLINK", libc::ENOLINK),
+    ("EPROTO", libc::EPROTO),
+    ("EBADMSG", libc::EBADMSG),
+    ("EOVERFLOW", libc::EOVERFLOW),
+    ("EILSEQ", libc::EILSEQ),
+    ("ENOTSOCK", libc::ENOTSOCK),
+    ("EDESTADDRREQ", libc::EDESTADDRREQ),
+    ("EMSGSIZE", libc::EMSGSIZE),
+    ("EPROTOTYPE", libc::EPROTOTYPE),
+    ("ENOPROTOOPT", libc::ENOPROTOOPT),
+    ("EPROTONOSUPPORT", libc::EPROTONOSUPPORT),
+    ("ENOTSUP", libc::ENOTSUP),
+    ("EOPNOTSUPP", libc::EOPNOTSUPP),
+    ("EAFNOSUPPORT", libc::EAFNOSUPPORT),
+    ("EADDRINUSE", libc::EADDRINUSE),
+    ("EADDRNOTAVAIL", libc::EADDRNOTAVAIL),
+    ("ENETDOWN", libc::ENETDOWN),
+    ("ENETUNREACH", libc::ENETUNREACH),
+    ("ENETRESET", libc::ENETRESET),
+    ("ECONNABORTED", libc::ECONNABORTED),
+    ("ECONNRESET", libc::ECONNRESET),
+    ("ENOBUFS", libc::ENOBUFS),
+    ("EISCONN", libc::EISCONN),
+    ("ENOTCONN", libc::ENOTCONN),
+    ("ETIMEDOUT", libc::ETIMEDOUT),
+    ("ECONNREFUSED", libc::ECONNREFUSED),
+    ("EHOSTUNREACH", libc::EHOSTUNREACH),
+    ("EALREADY", libc::EALREADY),
+    ("EINPROGRESS", libc::EINPROGRESS),
+    ("ESTALE", libc::ESTALE),
+    ("EDQUOT", libc::EDQUOT),
+    ("ECANCELED", libc::ECANCELED),
+    ("EOWNERDEAD", libc::EOWNERDEAD),
+    ("ENOTRECOVERABLE", libc::ENOTRECOVERABLE),
+];
+
+#[cfg(target_os = "linux")]
+fn errno_location() -> *mut libc::c_int {
+    unsafe { libc::__errno_location() }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn errno_location() -> *mut libc::c_int {
+    // BSD and macOS name it differently
+    unsafe { libc::__error() }
+}
+
+/// The errno of this thread, replaced by `new` if given
+pub fn errno(new: Option<i32>) -> LuaResult<i32> {
+    #[cfg(unix)]
+    {
+        let location = errno_location();
+        let old = unsafe { *location };
+        if let Some(new) = new {
+            unsafe { *location = new };
+        }
+        Ok(old)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = new;
+        Err(LuaError::RuntimeError("errno not supported on this platform".to_string()))
+    }
+}
+
+/// The errno names this platform knows, mapped to their values
+pub fn errno_names(lua: &Lua) -> LuaResult<LuaTable> {
+    #[cfg(unix)]
+    {
+        lua.create_table_from(ERRNO_NAMES.iter().copied())
+    }
+    #[cfg(not(unix))]
+    {
+        lua.create_table()
+    }
+}
+
+/// The C library's description of an errno value, by default the current one
+pub fn strerror(code: Option<i32>) -> LuaResult<String> {
+    let code = match code {
+        Some(code) => code,
+        None => errno(None)?,
+    };
+    #[cfg(unix)]
+    {
+        // The XSI strerror_r, which fills the buffer rather than maybe returning a static string
+        let mut buf = [0 as libc::c_char; 256];
+        if unsafe { libc::strerror_r(code, buf.as_mut_ptr(), buf.len()) } != 0 {
+            return Ok(format!("Unknown error {}", code));
+        }
+        let message = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+        Ok(message.to_string_lossy().into_owned())
+    }
+    #[cfg(not(unix))]
+    {
+        Err(LuaError::RuntimeError(format!("Cannot describe errno {} on this platform", code)))
+    }
+}
+
+/// The system's message for a Win32 error code, by default the thread's `GetLastError()`
+pub fn winerror(code: Option<u32>) -> LuaResult<String> {
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::GetLastError;
+        use windows_sys::Win32::System::Diagnostics::Debug::{
+            FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS, FormatMessageW,
+        };
+
+        let code = code.unwrap_or_else(|| unsafe { GetLastError() });
+        let mut buf = [0u16; 512];
+        let len = unsafe {
+            FormatMessageW(
+                FORMAT_MESSAGE_FROM_SYSTEM | FORMAT_MESSAGE_IGNORE_INSERTS,
+                std::ptr::null(),
+                code,
+                0,
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                std::ptr::null(),
+            )
+        };
+        if len == 0 {
+            return Ok(format!("Unknown error {}", code));
+        }
+        // Messages end in a line break
+        Ok(String::from_utf16_lossy(&buf[..len as usize]).trim_end().to_string())
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = code;
+        Err(LuaError::RuntimeError("winerror is only supported on Windows".to_string()))
+    }
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "cdefgen")]
+
+use luaffi::cdefgen::{self, Builder};
+use mlua::Lua;
+
+const HEADER: &str = r#"
+#include <stddef.h>
+#include <stdio.h>
+
+#define CDEFGEN_VERSION 3
+#define CDEFGEN_MASK (1 << 4)
+#define CDEFGEN_NAME "gen"
+#define OTHER_LIMIT 10
+
+typedef struct cdefgen_point {
+    int x, y;
+} cdefgen_point;
+
+typedef int (*cdefgen_callback)(cdefgen_point *point, void *data);
+
+enum cdefgen_mode { CDEFGEN_FAST = 1, CDEFGEN_SLOW = 2 };
+
+__attribute__((visibility("default"))) int cdefgen_sum(const cdefgen_point *points, size_t count);
+void cdefgen_each(cdefgen_callback callback, void *data);
+int cdefgen_print(FILE *stream, cdefgen_point point);
+
+static inline int cdefgen_twice(int x) {
+    return x * 2;
+}
+
+int other_function(void);
+"#;
+
+#[test]
+fn test_generate_from_header() {
+    let dir = std::env::temp_dir().join(format!("cdefgen-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let header = dir.join("cdefgen_sample.h");
+    std::fs::write(&header, HEADER).unwrap();
+
+    let bindings = Builder::new().header(&header).allow("cdefgen_").allow("CDEFGEN_").generate().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let cdef = &bindings.cdef;
+    assert!(cdef.contains("cdefgen_sum"), "{}", cdef);
+    assert!(cdef.contains("typedef int (*cdefgen_callback)"), "{}", cdef);
+    assert!(cdef.contains("#define CDEFGEN_VERSION 3"), "{}", cdef);
+    // Included headers, other prefixes and inline definitions are left out
+    assert!(!cdef.contains("printf"), "{}", cdef);
+    assert!(!cdef.contains("other_function") && !cdef.contains("OTHER_LIMIT"), "{}", cdef);
+    assert!(!cdef.contains("cdefgen_twice"), "{}", cdef);
+    // `FILE` comes from an included header, so this declaration cannot be loaded on its own
+    assert!(bindings.skipped.iter().any(|skipped| skipped.contains("cdefgen_print")), "{:?}", bindings.skipped);
+
+    luaffi::cdef(cdef).unwrap();
+    let lua = Lua::new();
+    lua.globals().set("ffi", luaffi::lua_module(&lua).unwrap()).unwrap();
+    lua.load(
+        r#"
+        local p = ffi.new("cdefgen_point", { 1, 2 })
+        assert(p.y == 2)
+        assert(ffi.sizeof("cdefgen_callback") == ffi.sizeof("void*"))
+        assert(ffi.enumname("enum cdefgen_mode", 2) == "CDEFGEN_SLOW")
+        assert(ffi.sizeof("char[CDEFGEN_MASK]") == 16)
+    "#,
+    )
+    .exec()
+    .unwrap();
+}
+
+#[test]
+fn test_scan_preprocessed_source() {
+    let source = r#"
+# 1 "/usr/include/stdlib.h" 1 3
+extern void *malloc(size_t size);
+# 5 "include/scan_sample.h"
+struct scan_sample { int a; char b[4]; };
+int scan_sample_get(struct scan_sample *s,
+                    int index);
+#line 9 "C:\\sdk\\include\\scan_sample.h"
+typedef unsigned short scan_sample_id;
+"#;
+    let bindings = cdefgen::scan(source, &["scan_sample.h".into()], &[]);
+    assert_eq!(
+        bindings.cdef,
+        "struct scan_sample { int a; char b[4]; };\n\
+         int scan_sample_get(struct scan_sample *s, int index);\n\
+         typedef unsigned short scan_sample_id;\n"
+    );
+    assert!(bindings.skipped.is_empty(), "{:?}", bindings.skipped);
+}
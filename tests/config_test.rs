@@ -0,0 +1,220 @@
+use std::alloc::Layout;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use luaffi::config::{AllocatorHooks, FfiConfig, Registry, SandboxPolicy};
+use mlua::prelude::*;
+
+fn create_lua_with_config(config: FfiConfig) -> Lua {
+    let lua = Lua::new();
+    lua.globals().set("ffi", luaffi::lua_module_with_config(&lua, config).unwrap()).unwrap();
+    lua
+}
+
+#[test]
+fn test_safe_mode() {
+    let lua = create_lua_with_config(FfiConfig::default().safe(true));
+    lua.load(
+        r#"
+        ffi.cdef[[ typedef struct { int *p; } safe_holder; ]]
+        local ok, err = pcall(ffi.cast, "int*", 0x1000)
+        assert(not ok and err:kind() == "NOT_PERMITTED", tostring(err))
+
+        local holder = ffi.new("safe_holder")
+        ok, err = pcall(function() holder.p = 0x1000 end)
+        assert(not ok and err:kind() == "NOT_PERMITTED", tostring(err))
+
+        ok, err = pcall(ffi.undump, "int*", ffi.dump(ffi.new("size_t", 0x1000)))
+        assert(not ok and err:kind() == "NOT_PERMITTED", tostring(err))
+
+        -- NULL and pointers to memory the cdata already know about are fine
+        assert(ffi.isnull(ffi.cast("int*", 0)))
+        local n = ffi.new("int[1]", {7})
+        holder.p = n
+        assert(holder.p[0] == 7 and ffi.cast("int*", n)[0] == 7)
+    "#,
+    )
+    .exec()
+    .unwrap();
+
+    // Other states are unaffected
+    let lua = create_lua_with_config(FfiConfig::default());
+    lua.load(r#"assert(ffi.cast("int*", 0x1000) ~= nil)"#).exec().unwrap();
+}
+
+#[test]
+fn test_sandbox_policy() {
+    let lua = create_lua_with_config(FfiConfig::default().sandbox(SandboxPolicy::NoLoad));
+    lua.load(
+        r#"
+        local ok, err = pcall(ffi.load, "m")
+        assert(not ok and err:kind() == "NOT_PERMITTED", tostring(err))
+        assert(ffi.C ~= nil)
+    "#,
+    )
+    .exec()
+    .unwrap();
+
+    let policy = SandboxPolicy::Allow(vec!["luaffi_allowed_but_missing".to_string()]);
+    let lua = create_lua_with_config(FfiConfig::default().sandbox(policy));
+    lua.load(
+        r#"
+        local ok, err = pcall(ffi.load, "m")
+        assert(not ok and err:kind() == "NOT_PERMITTED", tostring(err))
+        ok, err = pcall(ffi.load, "luaffi_allowed_but_missing")
+        assert(not ok and err:kind() == "LIBRARY_NOT_FOUND", tostring(err))
+    "#,
+    )
+    .exec()
+    .unwrap();
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_default_library() {
+    let lua = create_lua_with_config(FfiConfig::default().default_library("libm.so.6"));
+    lua.load(
+        r#"
+        ffi.cdef[[ double cos(double); ]]
+        assert(ffi.C.cos(0) == 1)
+    "#,
+    )
+    .exec()
+    .unwrap();
+
+    let lua = Lua::new();
+    let result = luaffi::lua_module_with_config(&lua, FfiConfig::default().default_library("luaffi_no_such_lib"));
+    assert!(result.is_err());
+}
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static FREED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe fn counting_alloc(layout: Layout) -> *mut u8 {
+    ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+    // Hand out dirty memory: cdata must still start zeroed
+    let ptr = unsafe { std::alloc::alloc(layout) };
+    if !ptr.is_null() {
+        unsafe { std::ptr::write_bytes(ptr, 0xAB, layout.size()) };
+    }
+    ptr
+}
+
+unsafe fn counting_dealloc(ptr: *mut u8, layout: Layout) {
+    FREED.fetch_add(layout.size(), Ordering::SeqCst);
+    unsafe { std::alloc::dealloc(ptr, layout) }
+}
+
+#[test]
+fn test_allocator_hooks() {
+    let hooks = AllocatorHooks { alloc: counting_alloc, dealloc: counting_dealloc };
+    let lua = create_lua_with_config(FfiConfig::default().allocator(hooks));
+    lua.load(
+        r#"
+        local buf = ffi.new("uint8_t[1000]")
+        assert(buf[0] == 0 and buf[999] == 0)
+        local partial = ffi.new("int[4]", {1, 2})
+        assert(partial[1] == 2 and partial[2] == 0 and partial[3] == 0)
+        buf[999] = 5
+        assert(buf[999] == 5)
+        buf = nil
+        collectgarbage()
+        collectgarbage()
+    "#,
+    )
+    .exec()
+    .unwrap();
+    assert!(ALLOCATED.load(Ordering::SeqCst) >= 1000);
+    assert!(FREED.load(Ordering::SeqCst) >= 1000);
+}
+
+#[test]
+fn test_private_registries() {
+    luaffi::cdef("typedef int registry_global_t;").unwrap();
+
+    let registry = Registry::new();
+    let first = create_lua_with_config(FfiConfig::default().registry(registry.clone()));
+    let second = create_lua_with_config(FfiConfig::default().registry(registry));
+    let other = create_lua_with_config(FfiConfig::default().registry(Registry::new()));
+
+    first.load(r#"ffi.cdef[[ typedef struct { int a, b; } registry_private_t; ]]"#).exec().unwrap();
+    second
+        .load(r#"assert(ffi.sizeof("registry_private_t") == 8 and ffi.sizeof("registry_global_t") == 4)"#)
+        .exec()
+        .unwrap();
+    other
+        .load(
+            r#"
+            assert(not pcall(ffi.sizeof, "registry_private_t"))
+            assert(ffi.sizeof("registry_global_t") == 4)
+        "#,
+        )
+        .exec()
+        .unwrap();
+
+    // Nor do private declarations reach the global registry
+    let global = create_lua_with_config(FfiConfig::default());
+    global.load(r#"assert(not pcall(ffi.typeof, "registry_private_t"))"#).exec().unwrap();
+}
+
+#[test]
+fn test_selective_export() {
+    let lua = create_lua_with_config(FfiConfig::default().without(&["load", "C", "cast", "free"]));
+    lua.load(
+        r#"
+        assert(ffi.load == nil and ffi.C == nil and ffi.cast == nil and ffi.free == nil)
+
+        -- The rest still work together
+        ffi.cdef[[ typedef struct { int x; } export_point; ]]
+        local p = ffi.new("export_point", {3})
+        assert(p.x == 3 and ffi.sizeof("export_point") == 4 and ffi.istype("export_point", p))
+
+        -- Nor is `free` reachable as a method
+        assert(not pcall(function() return p.free end))
+    "#,
+    )
+    .exec()
+    .unwrap();
+
+    let lua = Lua::new();
+    let result = luaffi::lua_module_with_config(&lua, FfiConfig::default().without(&["lod"]));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_nil_for_missing_symbols() {
+    let lua = create_lua_with_config(FfiConfig::default().nil_for_missing_symbols(true));
+    lua.load(
+        r#"
+        assert(ffi.C.luaffi_surely_missing_symbol == nil)
+        assert(ffi.C.abs ~= nil and ffi.C:has("abs"))
+    "#,
+    )
+    .exec()
+    .unwrap();
+}
+
+#[test]
+fn test_strict_conversions_per_state() {
+    let strict = create_lua_with_config(FfiConfig::default().strict_conversions(true));
+    let lenient = create_lua_with_config(FfiConfig::default());
+    strict
+        .load(r#"assert(ffi.strictconv() and not pcall(ffi.new, "uint8_t", 300))"#)
+        .exec()
+        .unwrap();
+
+    // Toggling one state leaves the others on the same thread alone
+    lenient.load(r#"assert(not ffi.strictconv(true) and not pcall(ffi.new, "uint8_t", 300))"#).exec().unwrap();
+    strict.load(r#"ffi.strictconv(false); assert(ffi.tonumber(ffi.new("uint8_t", 300)) == 44)"#).exec().unwrap();
+    lenient.load(r#"assert(ffi.strictconv() and not pcall(ffi.new, "uint8_t", 300))"#).exec().unwrap();
+}
+
+#[test]
+fn test_strict_alignment_per_state() {
+    let strict = create_lua_with_config(FfiConfig::default().strict_alignment(true));
+    let lenient = create_lua_with_config(FfiConfig::default());
+    strict.load(r#"assert(ffi.strictalign() and not pcall(ffi.cast, "int*", 0x1001))"#).exec().unwrap();
+    lenient.load(r#"assert(not ffi.strictalign() and ffi.cast("int*", 0x1001) ~= nil)"#).exec().unwrap();
+    lenient.load(r#"ffi.strictalign(true)"#).exec().unwrap();
+    strict.load(r#"ffi.strictalign(false); assert(ffi.cast("int*", 0x1001) ~= nil)"#).exec().unwrap();
+    lenient.load(r#"assert(not pcall(ffi.cast, "int*", 0x1001))"#).exec().unwrap();
+}
@@ -1,4 +1,4 @@
-use luaffi::ctype::{CType, CField};
+use luaffi::ctype::{CType, CField, TypeHandle};
 
 #[test]
 fn test_basic_type_sizes() {
@@ -6,11 +6,58 @@ fn test_basic_type_sizes() {
     assert_eq!(CType::Char.size(), std::mem::size_of::<u8>());
     assert_eq!(CType::Short.size(), std::mem::size_of::<i16>());
     assert_eq!(CType::Int.size(), std::mem::size_of::<i32>());
-    assert_eq!(CType::Long.size(), std::mem::size_of::<isize>());
+    assert_eq!(CType::Long.size(), std::mem::size_of::<std::ffi::c_long>());
     assert_eq!(CType::Float.size(), std::mem::size_of::<f32>());
     assert_eq!(CType::Double.size(), std::mem::size_of::<f64>());
 }
 
+#[test]
+fn test_target_alignments() {
+    use std::ffi::{c_long, c_longlong};
+    use std::mem::align_of;
+
+    assert_eq!(CType::Short.alignment(), align_of::<i16>());
+    assert_eq!(CType::Int.alignment(), align_of::<i32>());
+    assert_eq!(CType::Long.alignment(), align_of::<c_long>());
+    assert_eq!(CType::LongLong.alignment(), align_of::<c_longlong>());
+    assert_eq!(CType::UInt64.alignment(), align_of::<u64>());
+    assert_eq!(CType::Float.alignment(), align_of::<f32>());
+    assert_eq!(CType::Double.alignment(), align_of::<f64>());
+    assert_eq!(CType::SizeT.alignment(), align_of::<usize>());
+    #[cfg(unix)]
+    {
+        assert_eq!(CType::ModeT.size(), std::mem::size_of::<libc::mode_t>());
+        assert_eq!(CType::OffT.alignment(), align_of::<libc::off_t>());
+        assert_eq!(CType::PidT.posix_integer(), Some((4, 4, true)));
+    }
+
+    // Struct layouts follow the same rules as `#[repr(C)]`
+    #[repr(C)]
+    struct Mixed {
+        c: u8,
+        d: f64,
+        l: c_long,
+        i: i64,
+    }
+    let fields = [("c", CType::Char), ("d", CType::Double), ("l", CType::Long), ("i", CType::Int64)];
+    let mut offset = 0usize;
+    let fields: Vec<CField> = fields
+        .into_iter()
+        .map(|(name, ctype)| {
+            offset = offset.next_multiple_of(ctype.alignment());
+            let field = CField { name: name.to_string(), offset, ctype };
+            offset += field.ctype.size();
+            field
+        })
+        .collect();
+    assert_eq!(fields[1].offset, std::mem::offset_of!(Mixed, d));
+    assert_eq!(fields[2].offset, std::mem::offset_of!(Mixed, l));
+    assert_eq!(fields[3].offset, std::mem::offset_of!(Mixed, i));
+    let mixed = CType::Struct("mixed".to_string(), fields);
+    assert_eq!(mixed.size(), std::mem::size_of::<Mixed>());
+    assert_eq!(mixed.alignment(), align_of::<Mixed>());
+}
+
 #[test]
 fn test_fixed_width_type_sizes() {
     assert_eq!(CType::Int8.size(), 1);
@@ -300,3 +347,87 @@ fn test_complex_struct() {
     assert!(struct_type.size() > 0);
     assert!(struct_type.alignment() > 0);
 }
+
+#[test]
+fn test_enum_layout_and_names() {
+    let color = CType::Enum(
+        "Color".to_string(),
+        vec![("RED".to_string(), 0), ("GREEN".to_string(), 1)],
+        Box::new(CType::Int),
+    );
+    assert_eq!(color.size(), 4);
+    assert_eq!(color.alignment(), 4);
+    assert_eq!(color.enum_name(1), Some("GREEN"));
+    assert_eq!(color.enum_name(2), None);
+
+    let alias = CType::Typedef("color_t".to_string(), Box::new(color));
+    assert_eq!(alias.enum_name(0), Some("RED"));
+
+    let flags = CType::Enum("Flags".to_string(), vec![("ON".to_string(), 1)], Box::new(CType::UInt8));
+    assert_eq!(flags.size(), 1);
+    assert_eq!(flags.alignment(), 1);
+}
+
+#[test]
+fn test_pointer_compatibility() {
+    let int_ptr = CType::Ptr(Box::new(CType::Int));
+    let void = CType::Void;
+    let const_char = CType::Const(Box::new(CType::Char));
+
+    assert!(CType::Int.same_type(&CType::Int32));
+    assert!(!CType::Int.same_type(&CType::UInt));
+    assert!(void.accepts_pointer_from(&int_ptr));
+    assert!(CType::Int.accepts_pointer_from(&CType::Ptr(Box::new(CType::Void))));
+    assert!(CType::Int.accepts_pointer_from(&CType::Array(Box::new(CType::Int32), 4)));
+    assert!(!CType::Float.accepts_pointer_from(&int_ptr));
+    // Dropping const is never implicit, adding it is
+    assert!(!void.accepts_pointer_from(&CType::Ptr(Box::new(const_char.clone()))));
+    assert!(const_char.accepts_pointer_from(&CType::Ptr(Box::new(CType::Char))));
+}
+
+#[test]
+fn test_type_display() {
+    let const_char_ptr = CType::Ptr(Box::new(CType::Const(Box::new(CType::Char))));
+    assert_eq!(const_char_ptr.to_string(), "const char *");
+    assert_eq!(CType::Array(Box::new(CType::UInt), 4).to_string(), "unsigned int [4]");
+
+    let callback = CType::Ptr(Box::new(CType::Function(Box::new(CType::Int), vec![const_char_ptr])));
+    assert_eq!(callback.to_string(), "int (*)(const char *)");
+    assert_eq!(CType::Struct("Point".to_string(), vec![]).to_string(), "struct Point");
+}
+
+#[test]
+fn test_bitfield_span() {
+    // Five bits starting at bit 6 of the field's storage span two bytes
+    let bits = CType::Bitfield(Box::new(CType::UInt), 6, 5);
+    assert_eq!(bits.size(), 2);
+    assert_eq!(bits.alignment(), 4);
+    assert_eq!(bits.to_string(), "unsigned int : 5");
+}
+
+#[test]
+fn test_type_handle_members() {
+    let inner = CType::Struct(
+        "Inner".to_string(),
+        vec![CField { name: "v".to_string(), ctype: CType::Int, offset: 0 }],
+    );
+    let outer = CType::Array(Box::new(inner.clone()), 4);
+    let handle = TypeHandle::new(outer);
+
+    // Member handles are built once and shared by clones of the handle
+    let element = handle.member(0).unwrap();
+    assert_eq!(**element, inner);
+    assert!(std::ptr::eq(element, handle.clone().member(0).unwrap()));
+    assert_eq!(**element.member(0).unwrap(), CType::Int);
+    assert!(element.member(1).is_none());
+    assert!(TypeHandle::new(CType::Int).member(0).is_none());
+    assert_eq!(handle.to_string(), "struct Inner [4]");
+}
+
+#[cfg(feature = "abi-check")]
+#[test]
+fn test_abi_self_check() {
+    if let Err(differences) = luaffi::abi_check::verify() {
+        panic!("Type layouts differ from the C compiler's:\n{}", differences.join("\n"));
+    }
+}
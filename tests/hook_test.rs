@@ -0,0 +1,61 @@
+#![cfg(feature = "hook")]
+
+use mlua::prelude::*;
+
+fn create_lua_with_ffi() -> Lua {
+    let lua = Lua::new();
+    lua.globals().set("ffi", luaffi::lua_module(&lua).unwrap()).unwrap();
+    lua
+}
+
+// Straight-line code, long enough for the jump a hook writes over its start
+#[inline(never)]
+extern "C" fn hook_target(x: i32) -> i32 {
+    std::hint::black_box(x).wrapping_mul(3).wrapping_add(std::hint::black_box(7))
+}
+
+#[inline(never)]
+extern "C" fn hook_replacement(x: i32) -> i32 {
+    std::hint::black_box(x).wrapping_sub(std::hint::black_box(1000))
+}
+
+fn call_target(x: i32) -> i32 {
+    let target: extern "C" fn(i32) -> i32 = std::hint::black_box(hook_target);
+    target(x)
+}
+
+#[test]
+fn test_hook_with_lua_function() {
+    let lua = create_lua_with_ffi();
+    lua.globals().set("target_address", hook_target as extern "C" fn(i32) -> i32 as usize).unwrap();
+    lua.load(
+        r#"
+        target = ffi.cast("int (*)(int)", target_address)
+        assert(target(2) == 13)
+        original = ffi.hook(target, function(x) return original(x) + 100 end)
+        assert(target(2) == 113 and original(2) == 13)
+        assert(not pcall(ffi.hook, target, function(x) return x end))
+    "#,
+    )
+    .exec()
+    .unwrap();
+
+    // Calls from C go to the replacement too
+    assert_eq!(call_target(2), 113);
+
+    lua.load(r#"assert(ffi.unhook(target) and not ffi.unhook(target) and target(2) == 13)"#).exec().unwrap();
+    assert_eq!(call_target(2), 13);
+
+    // A function pointer replaces the function as well
+    lua.globals().set("replacement_address", hook_replacement as extern "C" fn(i32) -> i32 as usize).unwrap();
+    lua.load(
+        r#"
+        local original = ffi.hook(target, ffi.cast("int (*)(int)", replacement_address))
+        assert(target(2) == -998 and original(2) == 13)
+        assert(ffi.unhook(target))
+    "#,
+    )
+    .exec()
+    .unwrap();
+    assert_eq!(call_target(2), 13);
+}
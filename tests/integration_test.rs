@@ -1,4 +1,3 @@
-use luaffi;
 use mlua::prelude::*;
 
 // Helper function to create a Lua VM with the FFI module loaded
@@ -172,12 +171,15 @@ fn test_ffi_fill() {
             r#"
         local buffer = ffi.new("char[10]")
         ffi.fill(buffer, 10, 0)
+        local ok, err = pcall(ffi.fill, ffi.new("char[4]"), 100000, 65)
+        assert(not ok and err:kind() == "OUT_OF_BOUNDS", tostring(err))
+        assert(not pcall(ffi.fill, ffi.cast("char*", buffer) + 8, 3))
         return true
     "#,
         )
         .eval::<bool>();
 
-    assert!(result.is_ok());
+    assert!(result.is_ok(), "fill failed: {:?}", result.err());
 }
 
 #[test]
@@ -202,6 +204,218 @@ fn test_ffi_errno() {
     }
 }
 
+#[test]
+#[cfg(unix)]
+fn test_errno_names() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        assert(ffi.E.ENOENT == 2 and ffi.E.EINTR == 4 and type(ffi.E.EAGAIN) == "number")
+        assert(ffi.E.EWOULDBLOCK ~= nil and ffi.E.NOT_AN_ERRNO == nil)
+        assert(ffi.strerror(ffi.E.ENOENT) == "No such file or directory", ffi.strerror(ffi.E.ENOENT))
+
+        -- The current errno by default, as set by C
+        ffi.cdef[[ int close(int fd); ]]
+        assert(ffi.C.close(-1) == -1)
+        assert(ffi.errno() == ffi.E.EBADF)
+        assert(ffi.strerror() == ffi.strerror(ffi.E.EBADF))
+        ffi.errno(ffi.E.EACCES)
+        assert(ffi.strerror() == "Permission denied")
+        assert(type(ffi.strerror(123456)) == "string")
+    "#).exec();
+    assert!(result.is_ok(), "errno names failed: {:?}", result.err());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_signal_handling() {
+    extern "C" fn ignore(_: libc::c_int) {}
+
+    // A handler without SA_RESTART makes a signal interrupt a blocking call with EINTR
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = ignore as extern "C" fn(libc::c_int) as usize;
+        libc::sigaction(libc::SIGUSR2, &action, std::ptr::null_mut());
+    }
+    // Interrupt this thread while the call sleeps, whatever else the process runs
+    let interrupt = || {
+        let thread = unsafe { libc::pthread_self() } as usize;
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            unsafe { libc::pthread_kill(thread as libc::pthread_t, libc::SIGUSR2) };
+        })
+    };
+
+    let lua = create_lua_with_ffi();
+    lua.load(r#"ffi.cdef("int poll(void *fds, unsigned long n, int timeout);", {signals = "retry"})"#)
+        .exec()
+        .unwrap();
+    let plain = lua.load(r#"ffi.cast("int (*)(void *, unsigned long, int)", ffi.C.poll)"#).eval::<LuaValue>().unwrap();
+    lua.globals().set("plain_poll", plain).unwrap();
+
+    let sleeper = interrupt();
+    let result: (i64, i32) = lua.load("return plain_poll(nil, 0, 500), ffi.errno()").eval().unwrap();
+    sleeper.join().unwrap();
+    assert_eq!(result, (-1, libc::EINTR));
+
+    let sleeper = interrupt();
+    let result: i64 = lua.load("return ffi.C.poll(nil, 0, 200)").eval().unwrap();
+    sleeper.join().unwrap();
+    assert_eq!(result, 0);
+
+    lua.load(r#"ffi.cdef("int poll(void *fds, unsigned long n, int timeout);", {signals = "mask"})"#)
+        .exec()
+        .unwrap();
+    let sleeper = interrupt();
+    let result: i64 = lua.load("return ffi.C.poll(nil, 0, 200)").eval().unwrap();
+    sleeper.join().unwrap();
+    assert_eq!(result, 0);
+
+    // Faults stay deliverable while the other signals are masked
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef("int pthread_sigmask(int how, const void *set, void *old);", {signals = "mask"})
+        local old = ffi.new("uint8_t[128]")
+        assert(ffi.C.pthread_sigmask(0, nil, old) == 0)
+        -- Bit n - 1 stands for signal n: SIGUSR1 (10) is blocked, SIGSEGV (11) is not
+        assert(old[1] % 4 >= 2 and old[1] % 8 < 4, tostring(old[1]))
+    "#).exec();
+    assert!(result.is_ok(), "masked call failed: {:?}", result.err());
+
+    // A function returning nothing is called once, whatever errno it leaves
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef("void qsort(void *base, size_t n, size_t size, int (*cmp)(const void *, const void *));",
+            {signals = "retry"})
+        local calls = 0
+        local cmp = ffi.cast("int (*)(const void *, const void *)", function()
+            calls = calls + 1
+            ffi.errno(ffi.E.EINTR)
+            return 0
+        end)
+        ffi.C.qsort(ffi.new("int[2]"), 2, ffi.sizeof("int"), cmp)
+        assert(calls == 1, calls)
+        cmp:free()
+    "#).exec();
+    assert!(result.is_ok(), "retried void call failed: {:?}", result.err());
+
+    let result = lua.load(r#"ffi.cdef("int getpid(void);", {signals = "ignore"})"#).exec();
+    assert!(result.is_err());
+}
+
+#[test]
+#[cfg(windows)]
+fn test_winerror() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local message = ffi.winerror(2)
+        assert(#message > 0 and not message:find("[\r\n]$"), message)
+        assert(ffi.winerror(5) ~= message)
+
+        ffi.cdef[[ void SetLastError(uint32_t code); ]]
+        local kernel32 = ffi.load("kernel32")
+        kernel32.SetLastError(5)
+        assert(ffi.winerror() == ffi.winerror(5))
+    "#).exec();
+    assert!(result.is_ok(), "winerror failed: {:?}", result.err());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_call_with_timeout() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            int abs(int);
+            int usleep(unsigned int usec);
+            void qsort(void *base, size_t n, size_t size, int (*cmp)(const void *, const void *));
+        ]]
+        assert(ffi.call_with_timeout(ffi.C.abs, 1000, -5) == 5)
+
+        -- Callbacks made on the worker run here while the call waits
+        local values = ffi.new("int[4]", {3, 1, 4, 2})
+        local cmp = ffi.cast("int (*)(const void *, const void *)", function(a, b)
+            return ffi.cast("const int*", a)[0] - ffi.cast("const int*", b)[0]
+        end)
+        ffi.call_with_timeout(ffi.C.qsort, 5000, values, 4, ffi.sizeof("int"), cmp)
+        assert(values[0] == 1 and values[1] == 2 and values[2] == 3 and values[3] == 4)
+
+        -- Function pointers work too
+        local abs = ffi.cast("int (*)(int)", ffi.C.abs)
+        assert(ffi.call_with_timeout(abs, 1000, -7) == 7)
+
+        -- errno set on the worker is seen here
+        ffi.cdef[[ int close(int fd); ]]
+        ffi.errno(0)
+        assert(ffi.call_with_timeout(ffi.C.close, 1000, -1) == -1 and ffi.errno() == ffi.E.EBADF)
+
+        local ok, err = pcall(ffi.call_with_timeout, ffi.C.usleep, 20, 500000)
+        assert(not ok and tostring(err):find("did not return within 20 ms"), tostring(err))
+        assert(not pcall(ffi.call_with_timeout, ffi.C.abs, -1, 1))
+    "#).exec();
+    assert!(result.is_ok(), "timed call failed: {:?}", result.err());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_call_with_timeout_signals() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        -- Functions declared with signal handling keep it on the worker
+        ffi.cdef("int pthread_sigmask(int how, const void *set, void *old);", {signals = "mask"})
+        local old = ffi.new("uint8_t[128]")
+        assert(ffi.call_with_timeout(ffi.C.pthread_sigmask, 1000, 0, nil, old) == 0)
+        -- SIGUSR1, signal 10, was blocked during the call
+        assert(old[1] % 4 >= 2, tostring(old[1]))
+    "#).exec();
+    assert!(result.is_ok(), "timed call with signals failed: {:?}", result.err());
+}
+
+#[test]
+fn test_parallel_map() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ int abs(int); ]]
+        local n = 10000
+        local src = ffi.new("int[?]", n)
+        for i = 0, n - 1 do src[i] = -i end
+
+        local out = ffi.parallel_map(ffi.C.abs, src)
+        assert(ffi.istype("int[10000]", out))
+        for i = 0, n - 1 do assert(out[i] == i) end
+
+        -- Into an existing array, from a pointer, on a given number of threads
+        local dst = ffi.new("int32_t[100]")
+        assert(ffi.parallel_map(ffi.C.abs, ffi.cast("int*", src) + 10, {n = 90, out = dst, threads = 3}) == dst)
+        assert(dst[0] == 10 and dst[89] == 99 and dst[90] == 0)
+
+        -- Lua callbacks run on this thread while the workers wait for them
+        local half = ffi.cast("double (*)(double)", function(x) return x / 2 end)
+        local halves = ffi.parallel_map(half, ffi.new("double[5]", {2, 4, 6, 8, 10}), {threads = 2})
+        assert(halves[0] == 1 and halves[4] == 5)
+
+        assert(not pcall(ffi.parallel_map, ffi.C.abs, ffi.cast("int*", src)))
+        assert(not pcall(ffi.parallel_map, ffi.C.abs, ffi.new("double[4]")))
+        assert(not pcall(ffi.parallel_map, ffi.C.abs, src, {n = n + 1}))
+        assert(not pcall(ffi.parallel_map, ffi.C.abs, src, {out = ffi.new("int[10]")}))
+
+        -- Counts are checked against arrays viewed inside other memory too, and must not overflow
+        ffi.cdef[[ typedef struct { int head; int items[4]; } pmap_holder; ]]
+        local holder = ffi.new("pmap_holder", {0, {-1, -2, -3, -4}})
+        assert(not pcall(ffi.parallel_map, ffi.C.abs, holder.items, {n = 5}))
+        assert(not pcall(ffi.parallel_map, ffi.C.abs, src, {n = 5, out = holder.items}))
+        assert(ffi.parallel_map(ffi.C.abs, holder.items, {n = 4, out = holder.items})[3] == 4)
+        assert(not pcall(ffi.parallel_map, ffi.C.abs, ffi.cast("int*", 8), {n = 2^62}))
+
+        -- The output stays alive while callbacks run
+        local target = ffi.new("double[2]")
+        local free_target = ffi.cast("double (*)(double)", function(x) ffi.free(target) return x end)
+        assert(not pcall(ffi.parallel_map, free_target, ffi.new("double[2]", {1, 2}), {out = target, threads = 1}))
+        target[1] = 3
+        assert(ffi.sizeof(target) == 16 and target[1] == 3)
+        half:free()
+        free_target:free()
+    "#).exec();
+    assert!(result.is_ok(), "parallel map failed: {:?}", result.err());
+}
+
 #[test]
 fn test_complex_struct() {
     let lua = create_lua_with_ffi();
@@ -280,7 +494,7 @@ fn test_fixed_width_types() {
 
     for type_name in types {
         let result = lua
-            .load(&format!("return ffi.typeof('{}')", type_name))
+            .load(format!("return ffi.typeof('{}')", type_name))
             .eval::<String>();
         assert!(result.is_ok(), "Failed for type: {}", type_name);
     }
@@ -758,6 +972,272 @@ fn test_constants_exist() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn test_array_length_from_initializer() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local arr = ffi.new("int[]", {1, 2, 3, 4})
+        assert(ffi.typeof(arr) == ffi.typeof("int[4]") and #arr == 4 and arr[3] == 4)
+
+        local s = ffi.new("char[]", "hello")
+        assert(ffi.typeof(s) == ffi.typeof("char[6]") and ffi.string(s) == "hello" and s[5] == 0)
+
+        ffi.cdef[[ typedef struct { int x, y; } inferred_point; ]]
+        local points = ffi.new("inferred_point[]", {{1, 2}, {3, 4}})
+        assert(ffi.typeof(points) == ffi.typeof("inferred_point[2]") and points[1].y == 4)
+
+        -- Without an initializer the array stays empty
+        assert(#ffi.new("int[]") == 0)
+
+        -- Array typedefs have a length too
+        ffi.cdef[[ typedef int len_arr4[4]; ]]
+        assert(#ffi.new("len_arr4") == 4 and #ffi.new("const len_arr4") == 4)
+    "#).exec();
+    assert!(result.is_ok(), "array length inference failed: {:?}", result.err());
+}
+
+#[test]
+fn test_vla_initializer() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local s = "hello"
+        local buf = ffi.new("char[?]", #s + 1, s)
+        assert(#buf == 6 and ffi.string(buf) == "hello" and buf[5] == 0)
+
+        local nums = ffi.new("int[?]", 4, {7, 8})
+        assert(nums[0] == 7 and nums[1] == 8 and nums[2] == 0 and nums[3] == 0)
+
+        -- Fixed-size arrays copy the string and terminate it when there is room
+        local fixed = ffi.new("char[8]", s)
+        assert(ffi.string(fixed) == "hello" and fixed[5] == 0 and fixed[7] == 0)
+        local exact = ffi.new("char[5]", s)
+        assert(exact[4] == string.byte("o"))
+
+        local ok, err = pcall(ffi.new, "int[4]", {1}, {2})
+        assert(not ok and tostring(err):find("variable-length", 1, true), tostring(err))
+
+        -- Scope allocators take the same arguments
+        ffi.scope(function(alloc)
+            assert(ffi.string(alloc("char[?]", 3, "ab")) == "ab")
+        end)
+    "#).exec();
+    assert!(result.is_ok(), "VLA initializers failed: {:?}", result.err());
+}
+
+#[test]
+fn test_pointer_from_address() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local p = ffi.new("void*", 0x10000)
+        assert(ffi.tonumber(p) == 0x10000 and p == ffi.cast("void*", 0x10000))
+        assert(ffi.isnull(ffi.new("int*", 0)))
+
+        local arr = ffi.new("int[2]", {5, 6})
+        local q = ffi.new("int*", ffi.tonumber(ffi.cast("void*", arr)) + 4)
+        assert(q[0] == 6)
+
+        -- Numbers that cannot be the address of the target are rejected, unlike with ffi.cast
+        for _, address in ipairs({5, -8, 0x10001}) do
+            local ok, err = pcall(ffi.new, "int*", address)
+            assert(not ok and tostring(err):find("ffi.cast", 1, true), tostring(err))
+        end
+        assert(ffi.tonumber(ffi.cast("int*", 5)) == 5)
+        assert(ffi.new("char*", 0x10001) ~= nil)
+    "#).exec();
+    assert!(result.is_ok(), "pointer construction failed: {:?}", result.err());
+}
+
+#[test]
+fn test_typed_null() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef struct null_node null_node; typedef int (*null_cb)(int); ]]
+        local p = ffi.null("int*")
+        assert(ffi.isnull(p) and ffi.istype("int*", p) and ffi.typeof(p) == ffi.typeof("int*"))
+        assert(p == ffi.nullptr and not ffi.istype("double*", p))
+
+        local node = ffi.null("null_node*")
+        assert(ffi.istype("null_node*", node) and ffi.isnull(node))
+        assert(ffi.istype("null_cb", ffi.null("null_cb")))
+
+        assert(not pcall(ffi.null, "int"))
+    "#).exec();
+    assert!(result.is_ok(), "typed NULL failed: {:?}", result.err());
+}
+
+#[test]
+fn test_address() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef struct { int a; int b; } address_pair; ]]
+        local a = ffi.address(ffi.cast("int*", 0x1000))
+        assert(ffi.istype("uintptr_t", a) and ffi.tonumber(a) == 0x1000)
+        assert(ffi.sizeof("uintptr_t") == ffi.sizeof("void*"))
+
+        -- A pointer gives what it holds, other cdata where they live
+        local pair = ffi.new("address_pair")
+        local p = ffi.addressof(pair)
+        assert(ffi.address(pair) == ffi.address(p))
+        assert(ffi.address(ffi.addressof(pair, "b")) - ffi.address(pair) == 4)
+        assert(ffi.tonumber(ffi.address(nil)) == 0 and ffi.tonumber(ffi.address(ffi.nullptr)) == 0)
+
+        -- No bits are lost on 64-bit
+        if ffi.sizeof("void*") == 8 then
+            local high = ffi.address(ffi.cast("void*", -1))
+            assert(ffi.tostring_int(high) == "18446744073709551615", ffi.tostring_int(high))
+        end
+
+        assert(not pcall(ffi.address, 42))
+    "#).exec();
+    assert!(result.is_ok(), "address failed: {:?}", result.err());
+}
+
+#[test]
+fn test_pointer_tagging() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local values = ffi.new("int64_t[2]", {11, 22})
+        local p = ffi.cast("int64_t*", values)
+
+        local tagged = ffi.tagptr(p, 5)
+        assert(ffi.istype("int64_t*", tagged))
+        assert(ffi.address(tagged) - ffi.address(p) == 5)
+
+        -- The default mask covers the alignment bits of the target
+        local plain, tag = ffi.untag(tagged)
+        assert(plain == p and tag == 5 and plain[0] == 11)
+        plain, tag = ffi.untag(ffi.tagptr(p + 1, 2), 3)
+        assert(plain == p + 1 and tag == 2 and plain[0] == 22)
+
+        -- High bits work the same, without going through Lua numbers
+        if ffi.sizeof("void*") == 8 then
+            local high = ffi.tagptr(ffi.cast("void*", p), 0x7f00000000000000)
+            local untagged, bits = ffi.untag(high, 0x7f00000000000000)
+            assert(untagged == ffi.cast("void*", p) and bits == 0x7f00000000000000)
+        end
+
+        assert(not pcall(ffi.untag, ffi.cast("char*", p)))
+        assert(not pcall(ffi.tagptr, p, -1))
+
+        -- Tags must fit the alignment bits and not land on an existing tag
+        local ok, err = pcall(ffi.tagptr, ffi.cast("int*", p), 4)
+        assert(not ok and tostring(err):find("does not fit", 1, true), tostring(err))
+        ok, err = pcall(ffi.tagptr, tagged, 2)
+        assert(not ok and tostring(err):find("already tagged", 1, true), tostring(err))
+        ok, err = pcall(ffi.untag, tagged, -1)
+        assert(not ok and tostring(err):find("Invalid tag mask", 1, true), tostring(err))
+        assert(not pcall(ffi.tagptr, ffi.new("int"), 1))
+    "#).exec();
+    assert!(result.is_ok(), "pointer tagging failed: {:?}", result.err());
+}
+
+#[test]
+fn test_cast_keeps_extent() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local buf = ffi.new("char[16]")
+        local ints = ffi.cast("int*", buf)
+        assert(ffi.sizeof(ints) == 16 and ffi.sizeof(buf) == 16)
+        ints[3] = 42
+        assert(ints[3] == 42)
+
+        local ok, err = pcall(function() return ints[4] end)
+        assert(not ok and err:kind() == "OUT_OF_BOUNDS", tostring(err))
+        ok, err = pcall(function() ints[-1] = 1 end)
+        assert(not ok and err:kind() == "OUT_OF_BOUNDS", tostring(err))
+
+        -- Casting again keeps the length of the original buffer
+        local shorts = ffi.cast("short*", ffi.cast("void*", ints))
+        assert(ffi.sizeof(shorts) == 16 and shorts[7] == 0)
+        assert(not pcall(function() return shorts[8] end))
+
+        -- Moving the pointer keeps the buffer's bounds in view, in both directions
+        local second = ints + 1
+        assert(ffi.sizeof(second) == 12 and second[2] == 42 and second[-1] == 0)
+        assert(not pcall(function() return second[3] end))
+        assert(not pcall(function() return second[-2] end))
+        assert(not pcall(function() return (ints + 1)[100] end))
+        assert(ffi.sizeof(ints + 5) == 0 and (ints + 5 - 2)[0] == 42)
+
+        -- Only buffers pass their bounds on
+        local scalar = ffi.new("int64_t")
+        assert(ffi.sizeof(ffi.cast("int*", scalar)) == ffi.sizeof("int*"))
+
+        -- Pointers made from bare addresses are not checked
+        local raw = ffi.cast("int*", ffi.tonumber(ffi.address(buf)))
+        assert(ffi.sizeof(raw) == ffi.sizeof("int*") and raw[3] == 42)
+    "#).exec();
+    assert!(result.is_ok(), "cast extent failed: {:?}", result.err());
+}
+
+#[test]
+fn test_extend_struct() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ struct ext_base { int kind; double weight; }; ]]
+        ffi.extend("struct ext_base", "struct ext_derived { char tag; };")
+        assert(ffi.offsetof("struct ext_derived", "kind") == 0)
+        assert(ffi.offsetof("struct ext_derived", "weight") == ffi.offsetof("struct ext_base", "weight"))
+        assert(ffi.offsetof("struct ext_derived", "tag") == ffi.sizeof("struct ext_base"))
+
+        local items = ffi.new("struct ext_derived[1]", {{kind = 3, weight = 1.5, tag = 65}})
+        assert(items[0].kind == 3 and items[0].weight == 1.5 and items[0].tag == 65)
+
+        -- Pointers to the derived struct pass where the base is expected, through any depth
+        local kind_of = ffi.cast("int (*)(struct ext_base *)", function(base) return base[0].kind end)
+        assert(kind_of(items) == 3)
+        ffi.extend("struct ext_derived", "typedef struct ext_leaf { int extra; } ext_leaf;")
+        assert(kind_of(ffi.new("ext_leaf[1]", {{kind = 7, extra = 1}})) == 7)
+        assert(not pcall(kind_of, ffi.new("int[1]")))
+        assert(not pcall(kind_of, ffi.cast("const struct ext_derived *", items)))
+
+        -- Without flattening the base is a member
+        ffi.extend("struct ext_base", "struct ext_boxed { int count; };", {field = "super"})
+        local boxed = ffi.new("struct ext_boxed", {super = {kind = 2}, count = 4})
+        assert(boxed.super.kind == 2 and boxed.count == 4)
+        assert(ffi.offsetof("struct ext_boxed", "super") == 0)
+
+        local ok, err = pcall(ffi.extend, "struct ext_base", "struct ext_clash { int kind; };")
+        assert(not ok and err:kind() == "PARSE_ERROR" and err:message():find("already a member", 1, true), tostring(err))
+        assert(not pcall(ffi.extend, "int", "struct ext_bad { int x; };"))
+        assert(not pcall(ffi.extend, "struct ext_base", "int no_struct_here;"))
+        kind_of:free()
+    "#).exec();
+    assert!(result.is_ok(), "struct extension failed: {:?}", result.err());
+}
+
+#[test]
+fn test_strict_alignment() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef struct { int a; double b; } align_pair; ]]
+        ffi.cdef("typedef struct { char c; int i; } align_packed;", {pack = 1})
+        local buf = ffi.new("uint64_t[4]")
+        local bytes = ffi.cast("char*", buf)
+
+        -- Off by default
+        assert(ffi.strictalign() == false)
+        assert(ffi.cast("int*", bytes + 1) ~= nil)
+
+        assert(ffi.strictalign(true) == false)
+        local ok, err = pcall(ffi.cast, "int*", bytes + 1)
+        assert(not ok and tostring(err):find("4%-byte alignment"), tostring(err))
+        assert(not pcall(ffi.cast, "align_pair*", bytes + 4))
+        assert(not pcall(ffi.cast, "double*", ffi.tonumber(ffi.address(buf)) + 2))
+
+        -- Aligned addresses, NULL and targets without alignment pass
+        assert(ffi.cast("int*", bytes + 4) ~= nil)
+        assert(ffi.cast("uint64_t*", bytes + 8) ~= nil)
+        assert(ffi.isnull(ffi.cast("int*", 0)))
+        assert(ffi.cast("void*", bytes + 1) ~= nil and ffi.cast("char*", bytes + 3) ~= nil)
+        assert(ffi.cast("align_packed*", bytes + 1) ~= nil)
+
+        assert(ffi.strictalign(false) == true)
+        assert(ffi.cast("int*", bytes + 1) ~= nil)
+    "#).exec();
+    assert!(result.is_ok(), "strict alignment failed: {:?}", result.err());
+}
+
 #[test]
 fn test_vla_syntax() {
     let lua = create_lua_with_ffi();
@@ -799,7 +1279,7 @@ fn test_vla_different_types() {
 
     for type_name in types {
         let result = lua
-            .load(&format!("return ffi.typeof('{}')", type_name))
+            .load(format!("return ffi.typeof('{}')", type_name))
             .eval::<String>();
         assert!(result.is_ok(), "Failed for VLA type: {}", type_name);
     }
@@ -835,7 +1315,7 @@ fn test_vla_with_various_qualifiers() {
 
     for type_name in types {
         let result = lua
-            .load(&format!("return ffi.typeof('{}')", type_name))
+            .load(format!("return ffi.typeof('{}')", type_name))
             .eval::<String>();
         assert!(
             result.is_ok(),
@@ -894,7 +1374,7 @@ fn test_various_pointer_array_vla() {
 
     for type_name in types {
         let result = lua
-            .load(&format!("return ffi.typeof('{}')", type_name))
+            .load(format!("return ffi.typeof('{}')", type_name))
             .eval::<String>();
         assert!(
             result.is_ok(),
@@ -931,3 +1411,2737 @@ fn test_vla_with_float_size() {
         result.err()
     );
 }
+
+#[test]
+fn test_enumname() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            enum Status { STATUS_OK, STATUS_RETRY = 5, STATUS_FAILED, STATUS_FATAL = -1 };
+        ]]
+        assert(ffi.enumname("Status", 0) == "STATUS_OK")
+        assert(ffi.enumname("Status", 5) == "STATUS_RETRY")
+        assert(ffi.enumname("Status", 6) == "STATUS_FAILED")
+        assert(ffi.enumname("Status", -1) == "STATUS_FATAL")
+        assert(ffi.enumname("Status", 42) == nil)
+        assert(ffi.sizeof("Status") == 4)
+        assert(not pcall(ffi.enumname, "int", 0))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "enumname failed: {:?}", result.err());
+}
+
+#[test]
+fn test_cdef_constant_expressions() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            enum ExprFlags {
+                EXPR_A = 1 << 4 | 3,
+                EXPR_B = EXPR_A + 1,
+                EXPR_C = (EXPR_B - 3) * 2 % 7,
+                EXPR_D = ~0 & 255 - 0 ^ 1,
+                EXPR_E = sizeof(int) * 2,
+                EXPR_F = -(4 / 2),
+                EXPR_G = sizeof(int (*)(void)) * 3,
+                EXPR_H = sizeof(char[(2 + 3) * (1 + 1)]) + sizeof( unsigned  short )
+            };
+            struct ExprBuffer { char data[16*1024]; int tail[EXPR_E]; };
+        ]]
+        assert(ffi.enumname("ExprFlags", 3 * ffi.sizeof("void*")) == "EXPR_G")
+        assert(ffi.enumname("ExprFlags", 12) == "EXPR_H")
+        assert(ffi.enumname("ExprFlags", 19) == "EXPR_A")
+        assert(ffi.enumname("ExprFlags", 20) == "EXPR_B")
+        assert(ffi.enumname("ExprFlags", 6) == "EXPR_C")
+        assert(ffi.enumname("ExprFlags", 254) == "EXPR_D")
+        assert(ffi.enumname("ExprFlags", 8) == "EXPR_E")
+        assert(ffi.enumname("ExprFlags", -2) == "EXPR_F")
+        assert(ffi.sizeof("ExprBuffer") == 16 * 1024 + 8 * 4)
+        assert(ffi.sizeof("int[2 * 8]") == 64)
+        assert(not pcall(ffi.cdef, "enum ExprBad { EXPR_BAD = 1 / 0 };"))
+        -- Enumerators before the failing one are not left registered either
+        assert(not pcall(ffi.cdef, "enum ExprHalf { EXPR_HALF_A = 3, EXPR_HALF_B = 1 / 0 };"))
+        assert(not pcall(ffi.sizeof, "char[EXPR_HALF_A]"))
+        -- Outside strict mode the unterminated enum is skipped, without its enumerators
+        ffi.cdef("enum ExprOpen { EXPR_OPEN_A = 4, EXPR_OPEN_B = EXPR_OPEN_A + 1")
+        assert(not pcall(ffi.sizeof, "char[EXPR_OPEN_B]"))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "constant expressions failed: {:?}", result.err());
+}
+
+#[test]
+fn test_cdef_literal_forms() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            #define LIT_BUF_SIZE 0x10
+            #define LIT_MODE 0755
+            #define LIT_MAX(a, b) ((a) > (b) ? (a) : (b))
+            enum LitKinds {
+                LIT_CHAR = 'A',
+                LIT_NEWLINE = '\n',
+                LIT_HEX_ESCAPE = '\x7f',
+                LIT_NUL = '\0',
+                LIT_HEX = 0x1F,
+                LIT_OCT = LIT_MODE,
+                LIT_BIN = 0b1010,
+                LIT_SUFFIX = 12UL
+            };
+            struct LitBuffer { char data[LIT_BUF_SIZE]; char tag[0b100]; };
+        ]]
+        assert(ffi.enumname("LitKinds", 65) == "LIT_CHAR")
+        assert(ffi.enumname("LitKinds", 10) == "LIT_NEWLINE")
+        assert(ffi.enumname("LitKinds", 127) == "LIT_HEX_ESCAPE")
+        assert(ffi.enumname("LitKinds", 0) == "LIT_NUL")
+        assert(ffi.enumname("LitKinds", 31) == "LIT_HEX")
+        assert(ffi.enumname("LitKinds", 493) == "LIT_OCT")
+        assert(ffi.enumname("LitKinds", 12) == "LIT_SUFFIX")
+        assert(ffi.sizeof("LitBuffer") == 20)
+        assert(ffi.sizeof("char[0x20]") == 32)
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "literal forms failed: {:?}", result.err());
+}
+
+#[test]
+fn test_typedef_function_pointer() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            typedef void (*callback_t)(int, void*);
+            typedef int (*compare_t)(const void *a, const void *b);
+            typedef unsigned long long handle_t, *handle_ptr_t;
+            struct Handler {
+                char tag;
+                callback_t on_event;
+                compare_t cmp;
+                handle_t id;
+            };
+            void register_handler(callback_t cb, void *ctx);
+        ]]
+        local ptr_size = ffi.sizeof("void*")
+        assert(ffi.sizeof("callback_t") == ptr_size)
+        assert(ffi.sizeof("handle_t") == 8)
+        assert(ffi.sizeof("handle_ptr_t") == ptr_size)
+        assert(ffi.offsetof("Handler", "on_event") == ptr_size)
+        assert(ffi.offsetof("Handler", "cmp") == 2 * ptr_size)
+        assert(ffi.sizeof("Handler") == 3 * ptr_size + 8)
+
+        local cb = ffi.cast("callback_t", 0x1000)
+        assert(ffi.istype("callback_t", cb))
+        local h = ffi.new("Handler")
+        h.on_event = cb
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "function pointer typedef failed: {:?}", result.err());
+}
+
+#[test]
+fn test_tagged_type_names() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            struct TagPoint { int x; int y; };
+            union TagValue { int i; double d; };
+            enum TagColor { TAG_RED, TAG_GREEN };
+            struct TagShadow { int a; int b; };
+            typedef char TagShadow;
+            struct TagLine { struct TagPoint from; struct TagPoint to; enum TagColor color; };
+        ]]
+        local p = ffi.new("struct TagPoint", {x = 1, y = 2})
+        assert(p.x == 1 and p.y == 2)
+        assert(ffi.sizeof("union TagValue") == 8)
+        assert(ffi.sizeof("enum TagColor") == 4)
+        assert(ffi.enumname("enum TagColor", 1) == "TAG_GREEN")
+        assert(ffi.typeof("struct  TagPoint") ~= nil)
+        assert(ffi.sizeof("struct TagPoint*") == ffi.sizeof("void*"))
+        assert(ffi.sizeof("struct TagPoint[3]") == 24)
+        assert(ffi.offsetof("struct TagLine", "color") == 16)
+
+        -- C keeps tags and typedef names apart
+        assert(ffi.sizeof("TagShadow") == 1)
+        assert(ffi.sizeof("struct TagShadow") == 8)
+
+        local pp = ffi.cast("struct TagPoint*", ffi.addressof(p))
+        assert(ffi.istype("struct TagPoint*", pp))
+        local ok, err = pcall(ffi.sizeof, "union TagPoint")
+        assert(not ok and tostring(err):find("Unknown type: union TagPoint", 1, true))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "tagged type names failed: {:?}", result.err());
+}
+
+#[test]
+fn test_qualifier_tokens_in_type_strings() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            typedef short constexpr_t;
+            typedef double volatile_flag;
+        ]]
+        local ptr_size = ffi.sizeof("void*")
+        assert(ffi.sizeof("constexpr_t") == 2)
+        assert(ffi.sizeof("const volatile_flag") == 8)
+        assert(ffi.sizeof("volatile_flag const") == 8)
+        assert(ffi.sizeof("char const *") == ptr_size)
+        assert(ffi.sizeof("const char * const") == ptr_size)
+        assert(ffi.sizeof("unsigned   int") == 4)
+        assert(ffi.sizeof("volatile  unsigned long long") == 8)
+        assert(ffi.istype("int", ffi.new("volatile int")))
+        assert(ffi.sizeof("int * restrict") == ptr_size)
+        assert(ffi.sizeof("char const *[4]") == 4 * ptr_size)
+
+        local p = ffi.cast("int * const", 0)
+        assert(ffi.istype("int*", p))
+        local pp = ffi.cast("int **", 0)
+        assert(ffi.istype("int**", pp) and not ffi.istype("int*", pp))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "qualifier handling failed: {:?}", result.err());
+}
+
+#[test]
+fn test_declarator_type_strings() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        local ptr_size = ffi.sizeof("void*")
+        assert(ffi.sizeof("unsigned long long") == 8)
+        assert(ffi.sizeof("unsigned long long*") == ptr_size)
+        assert(ffi.sizeof("signed char") == 1)
+        assert(ffi.sizeof("long int") == ffi.sizeof("long"))
+        assert(ffi.sizeof("int (*)[10]") == ptr_size)
+        assert(ffi.sizeof("int *[10]") == 10 * ptr_size)
+        assert(ffi.sizeof("char *(*)(int)") == ptr_size)
+        assert(ffi.sizeof("char *(*[3])(int)") == 3 * ptr_size)
+
+        local p = ffi.cast("int (*)[10]", 0)
+        assert(ffi.istype("int(*)[10]", p))
+        assert(not ffi.istype("int*", p))
+
+        local buf = ffi.new("unsigned long long[?]", 4)
+        assert(#buf == 4)
+
+        local ok, err = pcall(ffi.sizeof, "unit32_t")
+        assert(not ok and tostring(err):find("Unknown type: unit32_t", 1, true))
+        assert(not pcall(ffi.sizeof, "int x"))
+        assert(not pcall(ffi.sizeof, "int (*"))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "declarator type strings failed: {:?}", result.err());
+}
+
+#[test]
+fn test_multi_dimensional_arrays() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        assert(ffi.sizeof("int[3][4]") == 48)
+        assert(ffi.sizeof("char[2][3][5]") == 30)
+
+        local grid = ffi.new("int[3][4]", {{1, 2, 3, 4}, {5, 6, 7, 8}})
+        assert(#grid == 3 and #grid[0] == 4)
+        assert(grid[0][0] == 1 and grid[1][2] == 7)
+        -- rows missing from the initializer stay zeroed
+        assert(grid[2][3] == 0)
+
+        grid[2][3] = 42
+        assert(grid[2][3] == 42)
+
+        local rows = ffi.new("double[?][2]", 5)
+        assert(#rows == 5 and #rows[4] == 2)
+        rows[4][1] = 1.5
+        assert(rows[4][1] == 1.5)
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "multi-dimensional arrays failed: {:?}", result.err());
+}
+
+#[test]
+fn test_function_pointer_types() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            int abs(int x);
+            extern int snprintf(char *buf, size_t size, const char *fmt, ...);
+        ]]
+        assert(ffi.typeof("void(*)(int)") == "void (*)(int)")
+        assert(ffi.sizeof("int(*)(const char*, ...)") == ffi.sizeof("void*"))
+        assert(not pcall(ffi.typeof, "int(*)(..., int)"))
+
+        local abs_fn = ffi.cast("int(*)(int)", ffi.C.abs)
+        assert(abs_fn(-42) == 42)
+        assert(ffi.C.abs(-7) == 7)
+        assert(not pcall(abs_fn))
+        assert(not pcall(ffi.C.labs, -1))
+
+        local fmt = ffi.cast("int(*)(char*, size_t, const char*, ...)", ffi.C.snprintf)
+        local buf = ffi.new("char[64]")
+        local n = fmt(buf, 64, "%s=%d/%.1f", "x", 42, 2.5)
+        assert(ffi.string(buf) == "x=42/2.5")
+        assert(n == 8)
+        assert(ffi.C.snprintf(buf, 64, "%d", 7) == 1)
+
+        local null_fn = ffi.cast("void(*)(int)", 0)
+        assert(not pcall(null_fn, 1))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "function pointer types failed: {:?}", result.err());
+}
+
+#[test]
+fn test_const_pointer_targets() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            struct ConstLabel {
+                const char *text;
+                char *buffer;
+                const int id;
+            };
+        ]]
+        local buf = ffi.new("char[8]")
+        ffi.copy(buf, "abc")
+        local ro = ffi.cast("const char*", buf)
+        local rw = ffi.cast("char*", buf)
+
+        assert(ffi.string(ro) == "abc")
+        assert(ffi.string(rw) == "abc")
+        assert(ffi.istype("const char*", ro))
+        assert(not ffi.istype("char*", ro))
+        assert(not ffi.istype("const char*", rw))
+        assert(ffi.istype("int", ffi.new("const int", 3)))
+
+        rw[0] = 65
+        assert(ro[0] == 65)
+        assert(not pcall(function() ro[0] = 66 end))
+        assert(not pcall(ffi.copy, ro, "xyz"))
+        assert(not pcall(ffi.fill, ro, 2))
+
+        local label = ffi.new("struct ConstLabel", { id = 7 })
+        label.text = "read-only view"
+        assert(ffi.string(label.text) == "read-only view")
+        assert(not pcall(function() label.buffer = "mutable" end))
+        label.buffer = buf
+        assert(label.id == 7)
+        assert(not pcall(function() label.id = 8 end))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "const pointer targets failed: {:?}", result.err());
+}
+
+#[test]
+fn test_void_pointer_compatibility() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            typedef struct VoidHandle { void *data; int *values; } VoidHandle;
+            void *memset(void *s, int c, size_t n);
+        ]]
+        local values = ffi.new("int[4]", {1, 2, 3, 4})
+        local p = ffi.cast("int*", values)
+        local opaque = ffi.cast("void*", values)
+
+        -- Object pointers and void* convert both ways without casts
+        local h = ffi.new("VoidHandle")
+        h.data = p
+        h.values = opaque
+        assert(h.values[3] == 4)
+        h.values = values
+        ffi.C.memset(p, 0, ffi.sizeof("int") * 2)
+        assert(values[0] == 0 and values[2] == 3)
+
+        assert(ffi.istype("void*", p))
+        assert(ffi.istype("int*", opaque))
+        assert(ffi.istype("const void*", p))
+        assert(not ffi.istype("void*", ffi.cast("const int*", p)))
+
+        -- Other pointer mismatches still need an explicit cast
+        local ok, err = pcall(function() h.values = ffi.cast("float*", p) end)
+        assert(not ok and tostring(err):find("Cannot convert 'float %*' to 'int %*'"), tostring(err))
+        assert(not pcall(function() h.data = ffi.cast("const int*", p) end))
+        h.values = ffi.cast("int32_t*", p)
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "void pointer compatibility failed: {:?}", result.err());
+}
+
+#[test]
+fn test_array_to_pointer_decay() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[ struct DecayHolder { int items[3]; int *cursor; }; ]]
+        local arr = ffi.new("int[3]", {10, 20, 30})
+
+        local p = ffi.new("int*", arr)
+        assert(p[0] == 10 and p[2] == 30)
+        p[1] = 21
+        assert(arr[1] == 21)
+        assert(ffi.cast("int*", arr)[2] == 30)
+
+        local h = ffi.new("struct DecayHolder", { items = {1, 2, 3} })
+        h.cursor = h.items
+        assert(h.cursor[2] == 3)
+        h.cursor = arr
+        assert(h.cursor[1] == 21)
+        assert(not pcall(function() h.cursor = ffi.new("double[2]") end))
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "array to pointer decay failed: {:?}", result.err());
+}
+
+#[test]
+fn test_null_checks() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[ char *getenv(const char *name); ]]
+        local missing = ffi.C.getenv("LUAFFI_SURELY_UNSET_VARIABLE")
+        assert(ffi.isnull(missing))
+        assert(missing == ffi.nullptr)
+        assert(ffi.isnull(nil))
+        assert(ffi.isnull(ffi.new("int*")))
+
+        local value = ffi.new("int[1]")
+        local p = ffi.cast("int*", value)
+        assert(not ffi.isnull(p))
+        assert(p ~= ffi.nullptr)
+        assert(p == ffi.cast("int*", value))
+        assert(p ~= ffi.C)
+        assert(not pcall(ffi.isnull, 0))
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "null checks failed: {:?}", result.err());
+}
+
+#[test]
+fn test_field_addresses() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            typedef struct { int x, y; } FieldPoint;
+            typedef struct { int count; FieldPoint pts[3]; FieldPoint *next; } FieldShape;
+            int sscanf(const char *str, const char *format, ...);
+        ]]
+        local shape = ffi.new("FieldShape")
+        assert(ffi.C.sscanf("3 42", "%d %d", ffi.addressof(shape, "count"), ffi.addressof(shape, "pts[1].y")) == 2)
+        assert(shape.count == 3)
+        assert(shape.pts[1].y == 42)
+
+        local px = ffi.addressof(shape, "pts[2].x")
+        assert(ffi.istype("int*", px))
+        px[0] = 7
+        assert(shape.pts[2].x == 7)
+        assert(ffi.addressof(shape.pts, 2) == ffi.addressof(shape, "pts[2]"))
+
+        -- Pointers along the path are followed
+        local other = ffi.new("FieldPoint[2]")
+        shape.next = other
+        ffi.addressof(shape, "next[1].y")[0] = 5
+        assert(other[1].y == 5)
+        assert(ffi.addressof(ffi.cast("FieldShape*", shape), "count")[0] == 3)
+
+        assert(not pcall(ffi.addressof, shape, "pts[3]"))
+        assert(not pcall(ffi.addressof, shape, "missing"))
+        assert(not pcall(ffi.addressof, shape, "count.x"))
+        assert(not pcall(ffi.addressof, shape, "pts[1"))
+        shape.next = nil
+        assert(not pcall(ffi.addressof, shape, "next.x"))
+
+        local frozen = ffi.cast("const FieldShape*", shape)
+        assert(ffi.istype("const int*", ffi.addressof(frozen, "count")))
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "field addresses failed: {:?}", result.err());
+}
+
+#[test]
+fn test_integral_number_indices() {
+    let lua = create_lua_with_ffi();
+
+    // Lua 5.1/5.2 and LuaJIT pass every number as a float
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        local arr = ffi.new("int[4]", {1, 2, 3, 4})
+        assert(arr[2.0] == 3)
+        arr[3.0] = 40
+        assert(arr[3] == 40)
+        assert(ffi.addressof(arr, 1.0)[0] == 2)
+        assert(ffi.isnull(ffi.cast("void*", 0.0)))
+        assert(not pcall(function() return arr[1.5] end))
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "integral number indices failed: {:?}", result.err());
+}
+
+#[test]
+fn test_register_module() {
+    let lua = Lua::new();
+    let exports = luaffi::register(&lua).expect("Failed to register FFI module");
+
+    let name = if cfg!(feature = "luau") { "@luaffi" } else { "luaffi" };
+    let required: LuaTable = lua
+        .load(format!("return require('{}')", name))
+        .eval()
+        .expect("Failed to require registered module");
+    assert_eq!(required, exports);
+    assert!(required.contains_key("cdef").unwrap());
+}
+
+#[test]
+fn test_callbacks() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            typedef int (*cb_compare)(const void *, const void *);
+            void qsort(void *base, size_t nmemb, size_t size, cb_compare compar);
+        ]]
+        -- Lua functions convert to function pointers when passed as arguments
+        local values = ffi.new("int[5]", {5, 3, 9, 1, 7})
+        ffi.C.qsort(values, 5, ffi.sizeof("int"), function(a, b)
+            return ffi.cast("const int*", a)[0] - ffi.cast("const int*", b)[0]
+        end)
+        assert(values[0] == 1 and values[2] == 5 and values[4] == 9)
+
+        local op = ffi.cast("int (*)(int, int)", function(a, b) return a + b end)
+        assert(op(2, 3) == 5)
+        op:set(function(a, b) return a * b end)
+        assert(op(2, 3) == 6)
+        op:free()
+        assert(not pcall(op, 2, 3))
+        assert(not pcall(op.free, op))
+
+        -- Errors raised by a callback surface from the C call that invoked it
+        local failing = ffi.cast("int (*)(int)", function() error("callback failed") end)
+        local ok, err = pcall(failing, 1)
+        assert(not ok and tostring(err):find("callback failed"), tostring(err))
+        failing:free()
+        assert(not pcall(ffi.cast, "int (*)(int, ...)", function() end))
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "callbacks failed: {:?}", result.err());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_foreign_thread_callbacks() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            typedef unsigned long pthread_t;
+            int pthread_create(pthread_t *thread, const void *attr, void *(*start)(void *), void *arg);
+            int pthread_join(pthread_t thread, void **retval);
+        ]]
+        local seen
+        local start = ffi.cast("void *(*)(void *)", function(arg)
+            seen = arg
+            return arg
+        end)
+
+        local thread = ffi.new("pthread_t[1]")
+        assert(ffi.C.pthread_create(thread, nil, start, ffi.cast("void*", 42)) == 0)
+
+        -- The thread waits for its result until the call is delivered here
+        local ran, deadline = 0, os.clock() + 10
+        while ran == 0 and os.clock() < deadline do
+            ran = ffi.pump_callbacks()
+        end
+        assert(ran == 1)
+        assert(seen == ffi.cast("void*", 42))
+
+        local retval = ffi.new("void*[1]")
+        assert(ffi.C.pthread_join(thread[0], retval) == 0)
+        assert(retval[0] == ffi.cast("void*", 42))
+        start:free()
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "foreign thread callbacks failed: {:?}", result.err());
+}
+
+#[test]
+fn test_cdef_options() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef([[
+            struct WireHeader { uint8_t kind; uint32_t length; uint16_t flags; };
+            struct WireFrame { struct WireHeader header; uint8_t payload[3]; };
+        ]], { pack = 1 })
+        assert(ffi.sizeof("struct WireHeader") == 7)
+        assert(ffi.offsetof("struct WireHeader", "length") == 1)
+        assert(ffi.offsetof("struct WireHeader", "flags") == 5)
+        assert(ffi.sizeof("struct WireFrame") == 10)
+
+        local frame = ffi.new("struct WireFrame", { header = { kind = 2, length = 70000, flags = 9 } })
+        assert(frame.header.length == 70000 and frame.header.flags == 9)
+        local bytes = ffi.cast("uint8_t*", frame)
+        assert(bytes[0] == 2 and bytes[1] == 0x70 and bytes[2] == 0x11 and bytes[3] == 0x01)
+
+        ffi.cdef("struct HalfPacked { char tag; double value; };", { pack = 2 })
+        assert(ffi.offsetof("struct HalfPacked", "value") == 2)
+        assert(ffi.sizeof("struct HalfPacked") == 10)
+
+        -- The default convention only changes the ABI on 32-bit Windows
+        ffi.cdef("int abs(int x);", { abi = "stdcall" })
+        assert(ffi.C.abs(-4) == 4)
+
+        -- Strict blocks reject what lenient parsing would skip or guess
+        ffi.cdef("typedef opts_mystery_t opts_lenient_t;")
+        assert(not pcall(ffi.cdef, "typedef opts_mystery_t opts_strict_t;", { strict = true }))
+        assert(not pcall(ffi.cdef, "this is not C", { strict = true }))
+
+        assert(not pcall(ffi.cdef, "struct Bad1 { int x; };", { pack = 3 }))
+        assert(not pcall(ffi.cdef, "struct Bad2 { int x; };", { abi = "pascal" }))
+        assert(not pcall(ffi.cdef, "struct Bad3 { int x; };", { packing = 1 }))
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "cdef options failed: {:?}", result.err());
+}
+
+#[test]
+fn test_out_parameters() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            long strtol(const char *nptr, char **endptr, int base);
+            double frexp(double x, int *exp);
+            typedef struct OutPair { int a; int b; } OutPair;
+        ]]
+
+        -- Arrays from ffi.out decay to T* and read back with :get()
+        local exp = ffi.out("int")
+        assert(#exp == 1 and exp:get() == 0)
+        local mantissa = ffi.C.frexp(8.0, exp)
+        assert(mantissa == 0.5, "mantissa: " .. tostring(mantissa))
+        assert(exp:get() == 4, "exp: " .. tostring(exp:get()))
+
+        local endptr = ffi.out("char*")
+        assert(ffi.isnull(endptr:get()))
+        assert(ffi.C.strtol("123abc", endptr, 10) == 123)
+        assert(ffi.string(endptr:get()) == "abc")
+
+        -- Optional initial value, including aggregates
+        local seeded = ffi.out("int", 7)
+        assert(seeded:get() == 7 and seeded[0] == 7)
+        local pair = ffi.out("OutPair", {a = 1, b = 2})
+        assert(pair:get().b == 2)
+
+        local ok, err = pcall(ffi.out, "void")
+        assert(not ok and tostring(err):find("out%-parameter"), tostring(err))
+
+        -- Only ffi.out buffers have :get(), and it checks the memory like indexing does
+        assert(not pcall(function() return ffi.new("int[1]").get end))
+        assert(not pcall(function() return ffi.cast("int[1]", 0):get() end))
+        local freed = ffi.out("int", 3)
+        local get = freed.get
+        ffi.free(freed)
+        local ok, err = pcall(get, freed)
+        assert(not ok and err:kind() == "USE_AFTER_FREE", tostring(err))
+    "#).exec();
+    assert!(result.is_ok(), "Out-parameter test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_bool_semantics() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct BoolFlags { bool a; bool b; int count; } BoolFlags;
+            int snprintf(char *str, size_t size, const char *format, ...);
+        ]]
+        assert(ffi.sizeof("bool") == 1 and ffi.sizeof("bool[4]") == 4)
+        assert(ffi.offsetof("BoolFlags", "b") == 1 and ffi.sizeof("BoolFlags") == 8)
+
+        -- Writes accept truthy values and store 0 or 1
+        local flags = ffi.new("bool[6]")
+        flags[0] = true
+        flags[1] = 2
+        flags[2] = "yes"
+        flags[3] = 0
+        flags[4] = nil
+        flags[5] = ffi.new("int[1]", {3})[0]
+        local bytes = ffi.cast("uint8_t*", flags)
+        local expected = {1, 1, 1, 0, 0, 1}
+        for i = 0, 5 do
+            assert(bytes[i] == expected[i + 1], "byte " .. i .. ": " .. tostring(bytes[i]))
+            assert(flags[i] == (expected[i + 1] == 1))
+        end
+
+        -- Any non-zero byte written by C reads as true
+        bytes[3] = 0x80
+        assert(flags[3] == true)
+
+        -- bool parameters and results cross calls as one-byte _Bool
+        local negate = ffi.cast("bool (*)(bool)", function(b) return not b end)
+        assert(negate(false) == true and negate(true) == false)
+        negate:free()
+
+        -- Booleans promote to int through varargs
+        local buf = ffi.new("char[8]")
+        ffi.C.snprintf(buf, 8, "%d%d", true, flags[4])
+        assert(ffi.string(buf) == "10", ffi.string(buf))
+    "#).exec();
+    assert!(result.is_ok(), "Bool semantics test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_enum_underlying_types() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            enum ReprDefault { REPR_A, REPR_B };
+            enum ReprWide { REPR_WIDE = 0x100000000 };
+            enum ReprFixed : uint8_t { REPR_F0, REPR_F1 = 200 };
+            enum __attribute__((packed)) ReprPacked { REPR_P0, REPR_P1 = 300 };
+            enum ReprPackedAfter { REPR_N = -1, REPR_M = 100 } __attribute__((packed));
+            typedef struct ReprHolder { enum ReprFixed kind; uint8_t tag; enum ReprPacked small; } ReprHolder;
+        ]]
+        assert(ffi.sizeof("enum ReprDefault") == 4)
+        assert(ffi.sizeof("enum ReprWide") == 8)
+        assert(ffi.sizeof("enum ReprFixed") == 1)
+        assert(ffi.sizeof("enum ReprPacked") == 2)
+        assert(ffi.sizeof("enum ReprPackedAfter") == 1)
+
+        -- Layout and values follow the underlying type
+        assert(ffi.offsetof("ReprHolder", "tag") == 1 and ffi.offsetof("ReprHolder", "small") == 2)
+        assert(ffi.sizeof("ReprHolder") == 4)
+        local holder = ffi.new("ReprHolder", {kind = 200, tag = 7, small = 300})
+        assert(holder.kind == 200 and holder.tag == 7 and holder.small == 300)
+        assert(ffi.cast("uint8_t*", holder)[0] == 200)
+
+        local negative = ffi.new("enum ReprPackedAfter[1]", {-1})
+        assert(negative[0] == -1)
+
+        -- A cdef option picks the underlying type of enums that do not name one
+        ffi.cdef("enum ReprShort { REPR_S = 1 };", {enum = "short"})
+        ffi.cdef("enum ReprMsvc { REPR_MSVC = 1 };", {enum = "int16_t"})
+        assert(ffi.sizeof("enum ReprShort") == 1 and ffi.sizeof("enum ReprMsvc") == 2)
+
+        local ok, err = pcall(ffi.cdef, "enum ReprBad { REPR_BAD };", {enum = "float"})
+        assert(not ok and tostring(err):find("integer type"), tostring(err))
+        ok = pcall(ffi.cdef, "enum ReprBadBase : double { REPR_BAD_BASE };")
+        assert(not ok)
+    "#).exec();
+    assert!(result.is_ok(), "Enum underlying type test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_bitfield_layouts() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local decls = [[
+            typedef struct BfFlags { unsigned a:3; unsigned b:5; unsigned c:30; } BfFlags;
+            typedef struct BfMixed { char c; int x:4; char d; } BfMixed;
+            typedef struct BfSizes { short a:4; int b:4; } BfSizes;
+            typedef struct BfBreak { int a:3; int :0; int b:3; } BfBreak;
+            typedef struct BfSigned { int s:4; unsigned u:4; bool flag:1; } BfSigned;
+        ]]
+        ffi.cdef(decls, {bitfields = "gcc"})
+        assert(ffi.sizeof("BfFlags") == 8 and ffi.sizeof("BfMixed") == 4)
+        assert(ffi.offsetof("BfMixed", "d") == 2)
+        assert(ffi.sizeof("BfSizes") == 4 and ffi.sizeof("BfBreak") == 8)
+
+        -- Writes keep the neighbouring bits and reads sign-extend signed fields
+        local flags = ffi.new("BfFlags", {a = 5, b = 17, c = 1})
+        local bytes = ffi.cast("uint8_t*", flags)
+        assert(bytes[0] == 5 + 17 * 8 and bytes[4] == 1)
+        flags.b = 0
+        assert(flags.a == 5 and flags.b == 0 and flags.c == 1 and bytes[0] == 5)
+        local signed = ffi.new("BfSigned")
+        signed.s = -3
+        signed.u = 17
+        signed.flag = true
+        assert(signed.s == -3 and signed.u == 1 and signed.flag == true)
+
+        -- Bit-fields have no address or offset of their own
+        assert(not pcall(ffi.offsetof, "BfFlags", "b"))
+        assert(not pcall(ffi.addressof, flags, "b"))
+
+        -- Packed bit-fields follow each other bit by bit
+        ffi.cdef("typedef struct BfPacked { int a:4; int b:30; } BfPacked;", {pack = 1, bitfields = "gcc"})
+        local packed = ffi.new("BfPacked", {b = 1})
+        assert(ffi.sizeof("BfPacked") == 5 and ffi.cast("uint8_t*", packed)[0] == 16)
+
+        -- MSVC starts a new unit when the type size changes and units fill their whole type
+        ffi.cdef(decls:gsub("Bf", "Ms"), {bitfields = "msvc"})
+        assert(ffi.sizeof("MsFlags") == 8 and ffi.sizeof("MsMixed") == 12)
+        assert(ffi.offsetof("MsMixed", "d") == 8)
+        assert(ffi.sizeof("MsSizes") == 8 and ffi.sizeof("MsBreak") == 8)
+        local sizes = ffi.new("MsSizes", {a = -1, b = 7})
+        assert(sizes.a == -1 and sizes.b == 7 and ffi.cast("uint8_t*", sizes)[4] == 7)
+
+        -- Widths must fit the type, and only unnamed bit-fields may be empty
+        assert(not pcall(ffi.cdef, "struct BfTooWide { char c:9; };"))
+        assert(not pcall(ffi.cdef, "struct BfNamedEmpty { int z:0; };"))
+        assert(not pcall(ffi.cdef, "struct BfFloat { float f:3; };"))
+        assert(not pcall(ffi.cdef, "struct BfBadLayout { int a:1; };", {bitfields = "borland"}))
+    "#).exec();
+    assert!(result.is_ok(), "Bit-field layout test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_mixed_initializers() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct InitMixed { int a; int b; int flags; double scale; } InitMixed;
+            typedef union InitUnion { int i; float f; } InitUnion;
+        ]]
+        -- Positional entries fill fields in order, named entries go to their field
+        local s = ffi.new("InitMixed", {1, 2, flags = 0x10})
+        assert(s.a == 1 and s.b == 2 and s.flags == 0x10 and s.scale == 0)
+        local t = ffi.new("InitMixed", {7, scale = 0.5})
+        assert(t.a == 7 and t.b == 0 and t.scale == 0.5)
+
+        -- Named entries win over positional ones for the same field
+        assert(ffi.new("InitMixed", {1, a = 9}).a == 9)
+
+        -- Unions take their first member positionally or one named member
+        assert(ffi.new("InitUnion", {42}).i == 42)
+        assert(ffi.new("InitUnion", {f = 1.5}).f == 1.5)
+
+        local ok, err = pcall(ffi.new, "InitMixed", {1, flgas = 2})
+        assert(not ok and tostring(err):find("Unknown field 'flgas'"), tostring(err))
+        ok, err = pcall(ffi.new, "InitMixed", {1, 2, 3, 4, 5})
+        assert(not ok and tostring(err):find("Too many initializers"), tostring(err))
+        ok, err = pcall(ffi.new, "InitUnion", {1, 2})
+        assert(not ok and tostring(err):find("Too many initializers"), tostring(err))
+    "#).exec();
+    assert!(result.is_ok(), "Mixed initializer test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_nested_initializers() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct NestInner { int x; int y; } NestInner;
+            typedef union NestValue { int i; double d; } NestValue;
+            typedef struct NestItem { char name[8]; NestValue value; } NestItem;
+            typedef struct NestOuter {
+                NestInner inner;
+                int values[3];
+                NestItem items[2];
+                uint8_t tag[4];
+            } NestOuter;
+        ]]
+        local outer = ffi.new("NestOuter", {
+            inner = {x = 1, y = 2},
+            values = {1, 2, 3},
+            items = {{"first", {i = 7}}, {name = "second", value = {d = 2.5}}},
+            tag = "abc",
+        })
+        assert(outer.inner.x == 1 and outer.inner.y == 2)
+        assert(outer.values[0] == 1 and outer.values[2] == 3)
+        assert(ffi.string(outer.items[0].name) == "first" and outer.items[0].value.i == 7)
+        assert(ffi.string(outer.items[1].name) == "second" and outer.items[1].value.d == 2.5)
+        assert(outer.tag[0] == string.byte("a") and outer.tag[3] == 0)
+
+        -- Nested aggregates may also be initialized from cdata of the same type
+        local inner = ffi.new("NestInner", {5, 6})
+        local copy = ffi.new("NestOuter", {inner = inner, items = {outer.items[1]}})
+        assert(copy.inner.y == 6 and copy.items[0].value.d == 2.5)
+
+        -- Errors name the member that failed
+        local ok, err = pcall(ffi.new, "NestOuter", {items = {{}, {value = {bogus = 1}}}})
+        assert(not ok and tostring(err):find("field 'items%[1%]%.value'"), tostring(err))
+        ok, err = pcall(ffi.new, "NestOuter", {inner = {x = "one"}})
+        assert(not ok and tostring(err):find("field 'inner%.x'"), tostring(err))
+        ok, err = pcall(ffi.new, "NestInner[2]", {{1, 2}, {y = {}}})
+        assert(not ok and tostring(err):find("element '%[1%]%.y'"), tostring(err))
+        ok, err = pcall(ffi.new, "NestOuter", {inner = ffi.new("NestItem")})
+        assert(not ok and tostring(err):find("Cannot convert"), tostring(err))
+
+        -- Errors raised by an initializer's __index are not swallowed
+        local lazy = setmetatable({1}, {__index = function(_, i) error("no value " .. i) end})
+        ok, err = pcall(ffi.new, "int[3]", lazy)
+        assert(not ok and tostring(err):find("element '%[1%]'.*no value 2"), tostring(err))
+        ok, err = pcall(ffi.new, "NestOuter", {values = lazy})
+        assert(not ok and tostring(err):find("field 'values%[1%]'"), tostring(err))
+    "#).exec();
+    assert!(result.is_ok(), "Nested initializer test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_initializer_error_paths() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct PathItem { const char *name; int count; } PathItem;
+            typedef struct PathList { PathItem items[3]; int sizes[2]; } PathList;
+        ]]
+        local function fails(pattern, f, ...)
+            local ok, err = pcall(f, ...)
+            assert(not ok, "expected an error matching " .. pattern)
+            assert(tostring(err):find(pattern, 1, true), tostring(err))
+        end
+
+        fails("field 'items[2].name': expected pointer (cdata, integer, string or nil), got table",
+            ffi.new, "PathList", {items = {[3] = {name = {}}}})
+        fails("field 'items[0].count': expected number, got string",
+            ffi.new, "PathList", {items = {{count = "many"}}})
+        fails("field 'sizes': expected table, got boolean", ffi.new, "PathList", {sizes = true})
+        fails("element '[1]': expected table, got number", ffi.new, "PathItem[2]", {{}, 5})
+
+        -- Assignments name the field or element too
+        local list = ffi.new("PathList")
+        fails("field 'count': expected number, got cdata<PathList>", function()
+            list.items[0].count = list
+        end)
+        fails("element '[1]': expected number, got string", function() list.sizes[1] = "x" end)
+    "#).exec();
+    assert!(result.is_ok(), "Initializer error path test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_strict_conversions() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ struct StrictFlags { unsigned int mode : 3; int level : 4; }; ]]
+        local function fails(f)
+            local ok, err = pcall(f)
+            assert(not ok, "expected an error")
+            return tostring(err)
+        end
+
+        -- Off by default: values are truncated as C would
+        assert(ffi.strictconv() == false)
+        local b = ffi.new("uint8_t[1]")
+        b[0] = 300
+        assert(b[0] == 44)
+
+        assert(ffi.strictconv(true) == false)
+        local err = fails(function() b[0] = 300 end)
+        assert(err:find("300 is not representable as uint8_t"), err)
+        fails(function() b[0] = -1 end)
+        fails(function() ffi.new("unsigned int", -5) end)
+        err = fails(function() ffi.new("int", 1.5) end)
+        assert(err:find("1.5 is not representable as int"), err)
+        fails(function() ffi.new("int16_t", 40000) end)
+        fails(function() ffi.new("float", 1e300) end)
+
+        -- Representable values still convert
+        b[0] = 255
+        assert(b[0] == 255)
+        assert(ffi.new("int[1]", {2.0})[0] == 2)
+        local d = ffi.new("double[1]", {0.1})
+        assert(d[0] == 0.1)
+        local big = ffi.new("int64_t[1]", {-9007199254740992})
+        assert(big[0] == -9007199254740992)
+
+        -- Bit-fields are checked against their width
+        local f = ffi.new("struct StrictFlags", {mode = 7, level = -8})
+        assert(f.mode == 7 and f.level == -8)
+        fails(function() f.mode = 8 end)
+        fails(function() f.level = 8 end)
+
+        -- Struct initializers report the member path
+        err = fails(function() ffi.new("struct StrictFlags", {mode = -1}) end)
+        assert(err:find("field 'mode'"), err)
+
+        assert(ffi.strictconv(false) == true)
+        b[0] = 300
+        assert(b[0] == 44)
+    "#).exec();
+    assert!(result.is_ok(), "Strict conversions failed: {:?}", result.err());
+}
+
+#[test]
+fn test_nan_and_infinity_conversions() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            int abs(int x);
+            struct NanFlags { unsigned int mode : 3; int level : 4; };
+        ]]
+        local nan, inf = 0/0, math.huge
+
+        -- NaN converts to zero, infinities clamp to the type's range
+        local i = ffi.new("int32_t[3]", {nan, inf, -inf})
+        assert(i[0] == 0 and i[1] == 2147483647 and i[2] == -2147483648)
+        local u = ffi.new("uint8_t[2]", {inf, -inf})
+        assert(u[0] == 255 and u[1] == 0)
+        local f = ffi.new("struct NanFlags", {mode = inf, level = -inf})
+        assert(f.mode == 7 and f.level == -8)
+        f.mode = nan
+        assert(f.mode == 0)
+
+        -- Floating point targets keep the value
+        local d = ffi.new("double[2]", {inf, nan})
+        assert(d[0] == inf and d[1] ~= d[1])
+
+        -- Arguments follow the same rules
+        assert(ffi.C.abs(nan) == 0)
+        assert(ffi.C.abs(inf) == 2147483647)
+
+        -- Strict mode rejects them
+        ffi.strictconv(true)
+        local ok, err = pcall(function() i[0] = nan end)
+        assert(not ok and tostring(err):find("NaN is not representable as int32_t"), tostring(err))
+        ok, err = pcall(ffi.C.abs, inf)
+        assert(not ok and tostring(err):find("inf is not representable as int"), tostring(err))
+        assert(not pcall(function() f.level = -inf end))
+        d[0] = -inf
+        assert(d[0] == -inf)
+        ffi.strictconv(false)
+    "#).exec();
+    assert!(result.is_ok(), "NaN and infinity conversions failed: {:?}", result.err());
+}
+
+// Needs Lua 5.3 integers
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+#[test]
+fn test_64bit_integer_round_trip() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            unsigned long long strtoull(const char *s, char **end, int base);
+            struct WideFlags { uint64_t all : 64; };
+        ]]
+
+        -- Values Lua integers hold come back as integers, bit for bit
+        local i = ffi.new("int64_t[2]", {math.maxinteger, math.mininteger})
+        assert(math.type(i[0]) == "integer" and i[0] == math.maxinteger)
+        assert(i[1] == math.mininteger)
+        local u = ffi.new("uint64_t[1]", {math.maxinteger})
+        assert(math.type(u[0]) == "integer" and u[0] == math.maxinteger)
+
+        -- Larger unsigned values stay boxed instead of wrapping negative
+        u[0] = -1
+        local max = u[0]
+        assert(type(max) == "userdata" and ffi.istype("uint64_t", max))
+        local copy = ffi.new("uint64_t[1]")
+        copy[0] = max
+        i[0] = copy[0]
+        assert(i[0] == -1)
+
+        local parsed = ffi.C.strtoull("18446744073709551615", nil, 10)
+        assert(ffi.istype("uint64_t", parsed))
+        i[0] = parsed
+        assert(i[0] == -1)
+
+        local f = ffi.new("struct WideFlags")
+        f.all = max
+        assert(ffi.istype("uint64_t", f.all))
+        i[0] = f.all
+        assert(i[0] == -1)
+
+        -- Strict mode checks the boxed value's range
+        ffi.strictconv(true)
+        local ok, err = pcall(function() i[0] = max end)
+        assert(not ok and tostring(err):find("18446744073709551615 is not representable as int64_t"), tostring(err))
+        copy[0] = max
+        ffi.strictconv(false)
+    "#).exec();
+    assert!(result.is_ok(), "64-bit integer round trip failed: {:?}", result.err());
+}
+
+// Needs Lua 5.3 integers
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+#[test]
+fn test_exact_integer_strings() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local u = ffi.new("uint64_t[2]")
+        u[0] = -1
+        u[1] = 9007199254740993
+        assert(ffi.tostring_int(u[0]) == "18446744073709551615")
+        assert(ffi.tostring_int(ffi.new("int64_t", math.mininteger)) == "-9223372036854775808")
+        assert(ffi.tostring_int(ffi.new("uint8_t", 200)) == "200")
+        assert(ffi.tostring_int(42) == "42")
+        assert(ffi.tostring_int(-7.0) == "-7")
+        assert(not pcall(ffi.tostring_int, 1.5))
+        assert(not pcall(ffi.tostring_int, ffi.new("double", 1)))
+
+        -- tonumber rounds to the nearest double, or refuses in strict mode
+        assert(ffi.tonumber(u[0]) == 2^64)
+        assert(ffi.tonumber(ffi.new("uint16_t", 65535)) == 65535)
+        local big = ffi.new("uint64_t", 9007199254740993)
+        assert(ffi.tonumber(big) == 9007199254740992)
+        ffi.strictconv(true)
+        local ok, err = pcall(ffi.tonumber, big)
+        assert(not ok and tostring(err):find("9007199254740993 is not representable"), tostring(err))
+        assert(ffi.tonumber(ffi.new("int64_t", 1024)) == 1024)
+        ffi.strictconv(false)
+    "#).exec();
+    assert!(result.is_ok(), "Exact integer strings failed: {:?}", result.err());
+}
+
+#[test]
+fn test_tohex() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ struct HexPoint { int x, y; }; ]]
+        assert(ffi.tohex(255) == "ff")
+        assert(ffi.tohex(255, 4) == "00ff")
+        assert(ffi.tohex(0xabcd, -2) == "CD")
+        assert(ffi.tohex(-1) == "ffffffffffffffff")
+        assert(ffi.tohex(-1, 8) == "ffffffff")
+
+        -- Cdata are padded to their own size
+        assert(ffi.tohex(ffi.new("uint8_t", 10)) == "0a")
+        assert(ffi.tohex(ffi.new("int32_t", -2)) == "fffffffe")
+        local u = ffi.new("uint64_t[1]")
+        u[0] = -1
+        assert(ffi.tohex(u[0]) == "ffffffffffffffff")
+
+        local digits = ffi.sizeof("void*") * 2
+        local p = ffi.cast("void*", 0x1234)
+        assert(ffi.tohex(p) == string.rep("0", digits - 4) .. "1234")
+        assert(#ffi.tohex(ffi.C.printf) == digits)
+        local point = ffi.new("struct HexPoint")
+        assert(ffi.tohex(point) == ffi.tohex(ffi.addressof(point)))
+
+        assert(not pcall(ffi.tohex, 1.5))
+        assert(not pcall(ffi.tohex, ffi.new("double", 1)))
+        assert(not pcall(ffi.tohex, "ff"))
+    "#).exec();
+    assert!(result.is_ok(), "tohex failed: {:?}", result.err());
+}
+
+// Needs Lua 5.3 integers
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+#[test]
+fn test_mixed_integer_arithmetic() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ unsigned long long strtoull(const char *s, char **end, int base); ]]
+        local len = ffi.new("int64_t", 100)
+        assert(len < 4096 and 4096 > len and len <= 100 and not (len < 100))
+        assert(len + 1 == 101 and 1 + len == 101)
+        assert(len * 3 - 50 == 250)
+        assert(len / 7 == 14 and len % 7 == 2 and -len == -100)
+        assert(len ^ 2 == 10000)
+        assert(len / 8.0 == 12.5 and len + 0.5 == 100.5)
+        assert(not pcall(function() return len / 0 end))
+
+        -- Anything involving a uint64_t is done in uint64_t, like C
+        local max = ffi.C.strtoull("18446744073709551615", nil, 10)
+        assert(max > 0 and not (max < -1) and max == ffi.C.strtoull("0xffffffffffffffff", nil, 16))
+        local wrapped = max + 1
+        assert(wrapped == 0 and math.type(wrapped) == "integer")
+        local half = max / 2
+        assert(half == math.maxinteger)
+        assert(ffi.tostring_int(max - 1) == "18446744073709551614")
+        assert(ffi.tostring_int(-ffi.new("uint64_t", 1)) == "18446744073709551615")
+
+        -- Signed 64-bit results wrap around
+        assert(ffi.new("int64_t", math.maxinteger) + 1 == math.mininteger)
+
+        -- Boxed values with the same value compare equal
+        assert(ffi.new("uint64_t", 7) == ffi.new("uint64_t", 7))
+        assert(ffi.new("int64_t", 7) ~= ffi.new("int64_t", 8))
+        assert(ffi.cast("void*", 4096) > 4095)
+    "#).exec();
+    assert!(result.is_ok(), "Mixed integer arithmetic failed: {:?}", result.err());
+}
+
+#[test]
+fn test_pointer_arithmetic() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        -- Pointers move by whole elements and compare by address
+        local arr = ffi.new("int32_t[4]", {10, 20, 30, 40})
+        local p = ffi.cast("int32_t*", arr)
+        local q = p + 3
+        assert(q[0] == 40 and (q - 1)[0] == 30 and (1 + p)[0] == 20)
+        assert(q - p == 3 and p < q and q >= p and p == arr)
+        assert(ffi.cast("char*", q) - ffi.cast("char*", p) == 12)
+        assert(not pcall(function() return p + q end))
+        assert(not pcall(function() return p + 0.5 end))
+        assert(not pcall(function() return ffi.cast("void*", p) + 1 end))
+        assert(not pcall(function() return p - ffi.cast("char*", p) end))
+    "#).exec();
+    assert!(result.is_ok(), "Pointer arithmetic failed: {:?}", result.err());
+}
+
+#[test]
+fn test_tonumber_all_scalars() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef enum { TONUM_A = 3, TONUM_B = -2 } tonum_e; ]]
+        local types = {
+            "char", "unsigned char", "short", "unsigned short", "int", "unsigned int", "long",
+            "unsigned long", "long long", "unsigned long long", "int8_t", "uint8_t", "int16_t",
+            "uint16_t", "int32_t", "uint32_t", "int64_t", "uint64_t", "size_t", "ssize_t",
+            "float", "double", "const int",
+        }
+        for _, t in ipairs(types) do
+            assert(ffi.tonumber(ffi.new(t, 7)) == 7, t)
+        end
+        assert(ffi.tonumber(ffi.new("bool", true)) == 1 and ffi.tonumber(ffi.new("bool", false)) == 0)
+        assert(ffi.tonumber(ffi.new("tonum_e", -2)) == -2)
+        assert(ffi.tonumber(ffi.cast("void*", 4096)) == 4096)
+    "#).exec();
+    assert!(result.is_ok(), "tonumber failed: {:?}", result.err());
+
+    #[cfg(unix)]
+    {
+        let result: Result<(), _> = lua.load(r#"
+            for _, t in ipairs{ "ino_t", "dev_t", "gid_t", "mode_t", "nlink_t", "uid_t", "off_t", "pid_t",
+                                "useconds_t", "suseconds_t", "blksize_t", "blkcnt_t", "time_t" } do
+                assert(ffi.tonumber(ffi.new(t, 7)) == 7, t)
+            end
+        "#).exec();
+        assert!(result.is_ok(), "tonumber of POSIX types failed: {:?}", result.err());
+    }
+}
+
+// Needs Lua 5.3 integers
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+#[test]
+fn test_scalar_cdata_arithmetic() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef enum { SCALAR_RED = 2 } scalar_color; ]]
+        local n = ffi.new("int", 20)
+        assert(n * 2 + 1 == 41 and math.type(n * 2) == "integer")
+        assert(n % 3 == 2 and -n == -20 and n < 21 and n == ffi.new("short", 20))
+        -- Narrower integers divide and raise to powers like Lua numbers, not C integers
+        assert(ffi.new("int", 5) / 2 == 2.5 and n / 3 == 20 / 3 and n ^ 2 == 400.0)
+        assert(ffi.new("int", -7) % 2 == 1 and ffi.new("short", 7) % -2 == -1)
+        assert(not pcall(function() return n % ffi.new("int", 0) end))
+        -- 64-bit integers follow C
+        assert(ffi.new("int64_t", 5) / 2 == 2 and ffi.new("int64_t", -7) % 2 == -1)
+        assert(ffi.new("uint8_t", 200) + ffi.new("uint8_t", 100) == 300)
+        assert(ffi.new("unsigned int", 0) - 1 == -1)
+        assert(ffi.new("scalar_color", 2) * 3 == 6)
+        assert(ffi.new("bool", true) + 1 == 2)
+        assert(ffi.new("char", 65) + 0.5 == 65.5)
+    "#).exec();
+    assert!(result.is_ok(), "Scalar cdata arithmetic failed: {:?}", result.err());
+
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    {
+        let result: Result<(), _> = lua.load(r#"
+            local i = ffi.new("int", 5)
+            assert(i // 2 == 2 and math.type(i // 2) == "integer" and ffi.new("int", -7) // 2 == -4)
+            assert(ffi.new("int64_t", -7) // 2 == -4 and ffi.new("uint64_t", 7) // 2 == 3)
+            assert(ffi.new("double", 7.5) // 2 == 3.0 and i // 0.5 == 10.0)
+            assert(not pcall(function() return i // 0 end))
+        "#).exec();
+        assert!(result.is_ok(), "Floor division of cdata failed: {:?}", result.err());
+    }
+
+    #[cfg(unix)]
+    {
+        let result: Result<(), _> = lua.load(r#"
+            assert(ffi.new("time_t", -5) + 1 == -4)
+        "#).exec();
+        assert!(result.is_ok(), "POSIX cdata arithmetic failed: {:?}", result.err());
+    }
+}
+
+#[test]
+fn test_float_cdata_arithmetic() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local d = ffi.new("double", 1.5)
+        local f = ffi.cast("float", 2.5)
+        assert(d + 1 == 2.5 and 2 * d == 3 and d / 2 == 0.75 and d - 0.5 == 1)
+        assert(-d == -1.5 and d ^ 2 == 2.25 and d % 1 == 0.5)
+        assert(f + d == 4 and d == ffi.new("double", 1.5))
+
+        -- Mixed with 64-bit integers the computation is done in floating point
+        assert(ffi.new("int64_t", 3) * d == 4.5)
+        assert(ffi.new("float", 0 / 0) ~= ffi.new("float", 0 / 0))
+    "#).exec();
+    assert!(result.is_ok(), "Float cdata arithmetic failed: {:?}", result.err());
+
+    // Comparing cdata with numbers needs Lua 5.2, `math.type` Lua 5.3
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    {
+        let result: Result<(), _> = lua.load(r#"
+            local d = ffi.new("double", 1.5)
+            assert(math.type(d + 1) == "float")
+            assert(d < 2 and 1 < d and ffi.cast("float", 2.5) >= d)
+        "#).exec();
+        assert!(result.is_ok(), "Float cdata comparison failed: {:?}", result.err());
+    }
+}
+
+#[test]
+fn test_boxed_integer_table_keys() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ unsigned long long strtoull(const char *s, char **end, int base); ]]
+        local handles = {}
+        handles[ffi.C.strtoull("18446744073709551615", nil, 10)] = "max"
+        handles[ffi.C.strtoull("42", nil, 10)] = "small"
+        collectgarbage()
+
+        -- Equal boxed values are the same object
+        local max = ffi.C.strtoull("0xffffffffffffffff", nil, 16)
+        assert(handles[max] == "max")
+        local u = ffi.new("uint64_t[2]")
+        u[0] = -1
+        assert(rawequal(u[0], max) and handles[u[0]] == "max")
+        assert(handles[max + 0] == "max")
+        assert(handles[max - 1] == nil)
+
+        -- Values Lua integers hold are plain integer keys
+        assert(handles[42] == "small")
+    "#).exec();
+    assert!(result.is_ok(), "Boxed integer table keys failed: {:?}", result.err());
+}
+
+#[test]
+fn test_typeof_identity() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            struct TypeofPoint { int x, y; };
+            typedef struct { int id; } TypeofAnon;
+        ]]
+        assert(ffi.typeof("int") == ffi.typeof("signed int"))
+        assert(ffi.typeof("unsigned") == ffi.typeof("unsigned int"))
+        assert(ffi.typeof("char const*") == ffi.typeof("const char *"))
+        assert(ffi.typeof("struct TypeofPoint*") == ffi.typeof("struct TypeofPoint *"))
+        assert(ffi.typeof("int") ~= ffi.typeof("unsigned int"))
+
+        -- Cdata report the same identity, so types can key dispatch tables
+        local handlers = {
+            [ffi.typeof("struct TypeofPoint")] = "point",
+            [ffi.typeof("TypeofAnon")] = "anon",
+            [ffi.typeof("uint32_t")] = "u32",
+        }
+        assert(handlers[ffi.typeof(ffi.new("struct TypeofPoint"))] == "point")
+        assert(handlers[ffi.typeof(ffi.new("TypeofAnon"))] == "anon")
+        assert(handlers[ffi.typeof(ffi.new("uint32_t", 1))] == "u32")
+        assert(not pcall(ffi.typeof, 42))
+    "#).exec();
+    assert!(result.is_ok(), "typeof identity failed: {:?}", result.err());
+}
+
+#[test]
+fn test_member_views() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct { float x, y; } ViewVec;
+            struct ViewBody { ViewVec pos; ViewVec vel[2]; struct ViewBody *next; int id; };
+        ]]
+        local bodies = ffi.new("struct ViewBody[8]")
+        for i = 0, 7 do
+            local body = bodies[i]
+            body.id = i
+            body.pos.x = i * 2
+            body.vel[1].y = i + 0.5
+            body.next = i < 7 and bodies + (i + 1) or nil
+        end
+
+        -- Views write through to the parent and keep the member's declared type
+        local sum = 0
+        for i = 0, 7 do
+            sum = sum + bodies[i].pos.x + bodies[i].vel[1].y
+        end
+        assert(sum == 56 + 32)
+        assert(ffi.typeof(bodies[3].pos) == "ViewVec")
+        assert(bodies[0].next == bodies + 1 and bodies[6].next == bodies + 7)
+        assert(bodies[7].next == nil or ffi.isnull(bodies[7].next))
+    "#).exec();
+    assert!(result.is_ok(), "Member views failed: {:?}", result.err());
+}
+
+#[test]
+fn test_owned_storage_alignment_and_zeroing() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local function address(cd) return tonumber(ffi.tohex(cd), 16) end
+
+        -- Small and large objects are both aligned for their type and zero-filled
+        for _, n in ipairs{1, 3, 8, 200} do
+            local doubles = ffi.new("double[?]", n)
+            assert(address(doubles) % 8 == 0)
+            for i = 0, n - 1 do assert(doubles[i] == 0) end
+            local bytes = ffi.new("uint8_t[?]", n * 64)
+            for i = 0, n * 64 - 1 do assert(bytes[i] == 0) end
+        end
+    "#).exec();
+    assert!(result.is_ok(), "Owned storage failed: {:?}", result.err());
+}
+
+#[test]
+fn test_array_bounds() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ struct BoundsHolder { int before; int values[3]; int after; }; ]]
+        local arr = ffi.new("int[4]", {1, 2, 3, 4})
+        local ok, err = pcall(function() return arr[4] end)
+        assert(not ok and tostring(err):find("Index 4 out of bounds for 'int %[4%]'"), tostring(err))
+        assert(not pcall(function() return arr[-1] end))
+        assert(not pcall(function() arr[4] = 0 end))
+
+        local vla = ffi.new("int[?]", 3)
+        assert(not pcall(function() return vla[3] end))
+        vla[2] = 7
+        assert(vla[2] == 7)
+
+        -- Arrays inside structs are checked too, so neighbouring fields stay untouched
+        local holder = ffi.new("struct BoundsHolder", {after = 9})
+        assert(not pcall(function() holder.values[3] = 1 end))
+        assert(holder.after == 9)
+
+        -- Pointers have no known extent and may index backwards
+        local p = ffi.cast("int*", arr) + 2
+        assert(p[-1] == 2 and p[1] == 4)
+    "#).exec();
+    assert!(result.is_ok(), "Array bounds failed: {:?}", result.err());
+}
+
+#[test]
+fn test_pointer_ownership() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            void *malloc(size_t size);
+            void free(void *ptr);
+            typedef struct { int a; int b; } OwnershipPair;
+        ]]
+        local function release(p) ffi.C.free(p) end
+
+        -- Memory from ffi.new is freed with its cdata, and views of it are borrowed
+        local pair = ffi.new("OwnershipPair")
+        local ok, err = pcall(ffi.gc, pair, release)
+        assert(not ok and tostring(err):find("owned by Lua"), tostring(err))
+        for _, view in ipairs({ffi.cast("int*", pair), ffi.addressof(pair), ffi.addressof(pair, "b")}) do
+            ok, err = pcall(ffi.gc, view, release)
+            assert(not ok and tostring(err):find("borrowed"), tostring(err))
+        end
+
+        -- Memory from C takes one finalizer, whichever cdata it goes through
+        local raw = ffi.C.malloc(16)
+        local p = ffi.gc(ffi.cast("int*", raw), release)
+        ok, err = pcall(ffi.gc, raw, release)
+        assert(not ok and tostring(err):find("already has a finalizer"), tostring(err))
+        ok, err = pcall(ffi.gc, ffi.cast("char*", p), release)
+        assert(not ok and tostring(err):find("borrowed"), tostring(err))
+
+        -- Removing the finalizer gives the memory back
+        ffi.gc(p, nil)
+        ffi.gc(raw, nil)
+        ffi.C.free(raw)
+    "#).exec();
+    assert!(result.is_ok(), "Pointer ownership test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_explicit_free() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            void *malloc(size_t size);
+            void free(void *ptr);
+            typedef struct { int x; int y; } FreePoint;
+            typedef struct { int free; } FreeField;
+        ]]
+
+        -- Lua-owned memory is released at once and the cdata refuses further use
+        local p = ffi.new("FreePoint", {1, 2})
+        p:free()
+        assert(ffi.isnull(p))
+        local ok, err = pcall(function() return p.x end)
+        assert(not ok and tostring(err):find("freed"), tostring(err))
+        ok, err = pcall(function() p.y = 3 end)
+        assert(not ok and tostring(err):find("freed"), tostring(err))
+        ok, err = pcall(ffi.free, p)
+        assert(not ok and tostring(err):find("already been freed"), tostring(err))
+
+        local buf = ffi.new("char[16]", "hello")
+        ffi.free(buf)
+        assert(not pcall(ffi.string, buf))
+        assert(not pcall(ffi.fill, buf, 4))
+        local n = ffi.new("int", 5)
+        n:free()
+        ok, err = pcall(ffi.tonumber, n)
+        assert(not ok and tostring(err):find("freed"), tostring(err))
+
+        -- Fields shadow the method
+        assert(ffi.new("FreeField", {7}).free == 7)
+
+        -- Views cannot be freed, nor C memory without a finalizer
+        local owner = ffi.new("int[4]")
+        ok, err = pcall(ffi.free, ffi.cast("int*", owner))
+        assert(not ok and tostring(err):find("borrowed"), tostring(err))
+        local raw = ffi.C.malloc(8)
+        ok, err = pcall(ffi.free, raw)
+        assert(not ok and tostring(err):find("without a finalizer"), tostring(err))
+
+        -- Finalized memory runs its finalizer exactly once
+        local calls = 0
+        local q = ffi.gc(raw, function(ptr) calls = calls + 1; ffi.C.free(ptr) end)
+        q:free()
+        assert(calls == 1 and ffi.isnull(q))
+        assert(not pcall(ffi.free, q))
+        assert(calls == 1)
+    "#).exec();
+    assert!(result.is_ok(), "Explicit free test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_scope() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            void *malloc(size_t size);
+            void free(void *ptr);
+            typedef struct { int x; int y; } ScopePoint;
+        ]]
+
+        -- Results pass through and everything allocated is freed on the way out
+        local kept, released = nil, 0
+        local sum, extra = ffi.scope(function(alloc)
+            local p = alloc("ScopePoint", {3, 4})
+            local buf = alloc("int[?]", 8)
+            buf[7] = 5
+            kept = p
+            alloc(ffi.gc(ffi.C.malloc(4), function(ptr) released = released + 1; ffi.C.free(ptr) end))
+            return p.x + p.y + buf[7], "extra"
+        end)
+        assert(sum == 12 and extra == "extra")
+        assert(released == 1 and ffi.isnull(kept))
+        assert(not pcall(function() return kept.x end))
+
+        -- Errors propagate after cleanup, and cdata freed early are skipped
+        local leaked
+        local ok, err = pcall(ffi.scope, function(alloc)
+            leaked = alloc("ScopePoint")
+            local early = alloc("int[4]")
+            early:free()
+            alloc(leaked)
+            error("boom")
+        end)
+        assert(not ok and tostring(err):find("boom"), tostring(err))
+        assert(ffi.isnull(leaked))
+
+        -- Views cannot join, and the allocator dies with its scope
+        local escaped
+        ok, err = pcall(ffi.scope, function(alloc)
+            escaped = alloc
+            alloc(ffi.cast("int*", ffi.new("int[2]")))
+        end)
+        assert(not ok and tostring(err):find("can be freed by a scope"), tostring(err))
+        ok, err = pcall(escaped, "int")
+        assert(not ok and tostring(err):find("already ended"), tostring(err))
+    "#).exec();
+    assert!(result.is_ok(), "Scope test failed: {:?}", result.err());
+}
+
+// Luau never runs finalizers on collection
+#[cfg(not(feature = "luau"))]
+#[test]
+fn test_finalizers_run_on_collection() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            void *malloc(size_t size);
+            void free(void *ptr);
+        ]]
+
+        local collected = 0
+        local function count(ptr) collected = collected + 1; ffi.C.free(ptr) end
+        for _ = 1, 10 do
+            ffi.gc(ffi.C.malloc(16), count)
+        end
+        collectgarbage()
+        collectgarbage()
+        assert(collected == 10, collected)
+
+        -- Replaced and removed finalizers never run
+        local replaced = 0
+        local p = ffi.gc(ffi.C.malloc(16), function() replaced = replaced + 1 end)
+        ffi.gc(p, count)
+        local q = ffi.gc(ffi.C.malloc(16), function() replaced = replaced + 1 end)
+        ffi.gc(q, nil)
+        ffi.C.free(q)
+        p, q = nil, nil
+        collectgarbage()
+        collectgarbage()
+        assert(replaced == 0 and collected == 11, replaced .. " " .. collected)
+
+        -- C functions work as finalizers
+        ffi.gc(ffi.C.malloc(16), ffi.C.free)
+        collectgarbage()
+        collectgarbage()
+        assert(not pcall(ffi.gc, ffi.new("int"), print))
+        assert(not pcall(ffi.gc, ffi.C.malloc(1), 42))
+    "#).exec();
+    assert!(result.is_ok(), "Finalizer collection test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_allocations_drive_collection() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        collectgarbage()
+        -- The headers of 200 buffers hardly grow the Lua heap, but their 1 MiB payloads
+        -- must still make the collector run
+        local live = setmetatable({}, {__mode = "v"})
+        for i = 1, 200 do
+            live[i] = ffi.new("char[?]", 1024 * 1024)
+        end
+        local remaining = 0
+        for _ in pairs(live) do remaining = remaining + 1 end
+        assert(remaining < 200, remaining)
+    "#).exec();
+    assert!(result.is_ok(), "Allocation accounting test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_metatype_once_and_everywhere() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            struct MtPoint { double x; double y; };
+        ]]
+        local writes = {}
+        local mt = {
+            __index = {
+                length = function(p) return math.sqrt(p.x * p.x + p.y * p.y) end,
+                x = "shadowed by the field",
+            },
+            __newindex = function(p, key, value) writes[key] = value end,
+        }
+        assert(ffi.metatype("struct MtPoint", mt) == mt)
+
+        -- Constructors, casts and member views all see the methods
+        local p = ffi.new("struct MtPoint", {3, 4})
+        assert(p:length() == 5 and p.x == 3)
+        local view = ffi.cast("struct MtPoint*", p)[0]
+        assert(view:length() == 5)
+        local arr = ffi.new("struct MtPoint[2]", {{6, 8}})
+        assert(arr[0]:length() == 10)
+        assert(ffi.cast("const struct MtPoint*", p)[0]:length() == 5)
+
+        -- Unknown keys go to __newindex, fields are still assigned directly
+        p.color = "red"
+        p.y = 0
+        assert(writes.color == "red" and writes.y == nil and p:length() == 3)
+
+        -- The association is permanent and the metatable read-only
+        local ok, err = pcall(ffi.metatype, "struct MtPoint", {})
+        assert(not ok and tostring(err):find("already set"), tostring(err))
+        ok, err = pcall(function() mt.__tostring = tostring end)
+        assert(not ok and tostring(err):find("Cannot modify"), tostring(err))
+        assert(mt.__newindex and getmetatable(mt) == false)
+
+        -- Built-in methods remain reachable when __index lacks them
+        p:free()
+    "#).exec();
+    assert!(result.is_ok(), "Metatype test failed: {:?}", result.err());
+
+    // `pairs` sees through the read-only metatable where Lua has `__pairs`
+    #[cfg(any(feature = "lua52", feature = "lua53", feature = "lua54"))]
+    {
+        let result: Result<(), _> = lua.load(r#"
+            ffi.cdef[[ struct MtPairs { int x; }; ]]
+            local mt = ffi.metatype("struct MtPairs", { __index = {}, __len = function() return 0 end })
+            local count = 0
+            for _ in pairs(mt) do count = count + 1 end
+            assert(count == 2, count)
+        "#).exec();
+        assert!(result.is_ok(), "Metatype pairs failed: {:?}", result.err());
+    }
+}
+
+#[test]
+fn test_metatype_operators() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct { double x, y; } OpVec2;
+        ]]
+        local function vec(x, y) return ffi.new("OpVec2", {x, y}) end
+        ffi.metatype("OpVec2", {
+            __add = function(a, b) return vec(a.x + b.x, a.y + b.y) end,
+            __sub = function(a, b) return vec(a.x - b.x, a.y - b.y) end,
+            __mul = function(a, b)
+                if type(a) == "number" then return vec(a * b.x, a * b.y) end
+                return vec(a.x * b, a.y * b)
+            end,
+            __unm = function(a) return vec(-a.x, -a.y) end,
+            __eq = function(a, b) return a.x == b.x and a.y == b.y end,
+            __lt = function(a, b) return a.x * a.x + a.y * a.y < b.x * b.x + b.y * b.y end,
+            __le = function(a, b) return not (b < a) end,
+            __len = function(a) return math.sqrt(a.x * a.x + a.y * a.y) end,
+            __call = function(a, scale) return a.x * scale, a.y * scale end,
+        })
+
+        local a, b = vec(1, 2), vec(3, 4)
+        local sum = a + b
+        assert(sum.x == 4 and sum.y == 6)
+        assert((b - a).x == 2)
+        assert((a * 3).y == 6 and (2 * a).x == 2)
+        assert((-a).y == -2)
+        assert(a + b == vec(4, 6) and a ~= b)
+        assert(a < b and a <= b and not (b < a))
+        assert(#b == 5)
+        local x, y = a(10)
+        assert(x == 10 and y == 20)
+
+        -- Types without operators still fail, and built-in arithmetic keeps precedence
+        ffi.cdef[[ typedef struct { int n; } OpPlain; ]]
+        assert(not pcall(function() return ffi.new("OpPlain") + 1 end))
+        local p = ffi.new("int[3]")
+        assert((p + 2) - p == 2 and p + 1 > p)
+    "#).exec();
+    assert!(result.is_ok(), "Metatype operator test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_metatype_aliases() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct MtAliasPoint { int x; } MtAliasPoint;
+            typedef MtAliasPoint MtAliasOther;
+            typedef enum MtColor { MT_RED, MT_GREEN } MtColor;
+            typedef int mt_handle;
+            typedef mt_handle mt_fd;
+            struct MtHolder { struct MtAliasPoint p; MtColor c; };
+        ]]
+
+        -- Set through a typedef, the metatype reaches the struct under every alias
+        ffi.metatype("MtAliasOther", { __index = { twice = function(p) return p.x * 2 end } })
+        assert(ffi.new("struct MtAliasPoint", {3}):twice() == 6)
+        assert(ffi.new("MtAliasPoint", {4}):twice() == 8)
+        assert(ffi.new("struct MtHolder", {{5}}).p:twice() == 10)
+        assert(not pcall(ffi.metatype, "struct MtAliasPoint", {}))
+
+        -- Enums get methods too, whichever name made the cdata
+        ffi.metatype("enum MtColor", { __index = { name = function(c) return ffi.enumname("MtColor", ffi.tonumber(c)) end } })
+        assert(ffi.new("MtColor", 1):name() == "MT_GREEN")
+        assert(ffi.new("struct MtHolder", {c = 0}).c == 0)
+        assert(ffi.cast("enum MtColor", 1):name() == "MT_GREEN")
+
+        -- A typedef of a scalar keeps its metatype to itself and its aliases
+        ffi.metatype("mt_handle", { __index = { valid = function(h) return ffi.tonumber(h) >= 0 end } })
+        assert(ffi.new("mt_fd", 3):valid() and not ffi.new("mt_handle", -1):valid())
+        assert(not pcall(function() return ffi.new("int", 1):valid() end))
+    "#).exec();
+    assert!(result.is_ok(), "Metatype alias test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_typeof_substitution() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef struct { int id; } SubstItem; ]]
+
+        -- Types, sizes and names splice in, sequentially or by number
+        local pair = ffi.typeof("struct { int x; $ y; }", "double")
+        local p = ffi.new(pair, {1, 2.5})
+        assert(p.x == 1 and p.y == 2.5)
+        assert(ffi.sizeof(ffi.typeof("$[$]", "SubstItem", 4)) == 4 * ffi.sizeof("SubstItem"))
+        local named = ffi.new(ffi.typeof("struct { $1 $2; $1 $3; }", "short", "lo", "hi"), {1, 2})
+        assert(named.lo == 1 and named.hi == 2)
+
+        -- Cdata splice in their type, and any declarator shape works where a type name goes
+        local items = ffi.new("SubstItem[2]")
+        assert(ffi.typeof("$ *", items) == "SubstItem (*)[2]")
+        assert(ffi.typeof("$ *", "void (*)(int)") == "void (**)(int)")
+        assert(ffi.istype(ffi.typeof("$", "int32_t"), ffi.new("int32_t")))
+
+        local ok, err = pcall(ffi.typeof, "$[$]", "int")
+        assert(not ok and tostring(err):find("no argument #2"), tostring(err))
+        ok, err = pcall(ffi.typeof, "struct { int $; }", "not an identifier")
+        assert(not ok, tostring(err))
+    "#).exec();
+    assert!(result.is_ok(), "Typeof substitution test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_wide_strings() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            size_t wcslen(const wchar_t *s);
+            int wcscmp(const wchar_t *a, const wchar_t *b);
+            wchar_t *wcschr(const wchar_t *s, wchar_t c);
+        ]]
+
+        -- Round trip through a fresh NUL-terminated buffer, including non-BMP characters
+        local text = "héllo wörld \240\159\152\128"
+        local wide = ffi.wide(text)
+        assert(ffi.wstring(wide) == text)
+        local units = ffi.sizeof("wchar_t") == 2 and 14 or 13
+        assert(ffi.typeof(wide) == ffi.typeof("wchar_t[$]", units + 1))
+        assert(#wide == units + 1 and wide[units] == 0)
+        assert(ffi.C.wcslen(wide) == units)
+        assert(ffi.wstring(wide, 5) == "héllo")
+
+        -- Lua strings convert automatically for const wchar_t* parameters
+        assert(ffi.C.wcslen("wörld") == 5)
+        assert(ffi.C.wcscmp(wide, text) == 0)
+        local rest = ffi.C.wcschr(wide, string.byte("w"))
+        assert(ffi.wstring(rest) == "wörld \240\159\152\128")
+
+        assert(not pcall(ffi.wstring, ffi.new("char[4]")))
+        local ok, err = pcall(ffi.wstring, ffi.new("wchar_t[2]"), 2^62)
+        assert(not ok and err:kind() == "OUT_OF_BOUNDS", tostring(err))
+        assert(ffi.wstring(ffi.new("wchar_t[3]", {104, 105, 33})) == "hi!")
+    "#).exec();
+    assert!(result.is_ok(), "Wide string test failed: {:?}", result.err());
+}
+
+#[test]
+fn test_wide_array_initialization() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef struct { wchar_t name[8]; int id; } wide_record; ]]
+
+        -- Sized, variable-length and unsized arrays take UTF-8 strings like char arrays do
+        local fixed = ffi.new("wchar_t[16]", "héllo")
+        assert(ffi.wstring(fixed) == "héllo" and fixed[5] == 0)
+        local vla = ffi.new("wchar_t[?]", 10, "wörld")
+        assert(#vla == 10 and ffi.wstring(vla) == "wörld")
+        local exact = ffi.new("wchar_t[]", "ab\240\159\152\128")
+        local units = ffi.sizeof("wchar_t") == 2 and 4 or 3
+        assert(#exact == units + 1 and exact[units] == 0 and ffi.wstring(exact) == "ab\240\159\152\128")
+
+        -- Too long a string is cut at the array's end, without a terminator
+        local short = ffi.new("wchar_t[3]", "abcdef")
+        assert(ffi.wstring(short) == "abc")
+
+        -- Fields are assigned the same way
+        local record = ffi.new("wide_record", {name = "ünï", id = 7})
+        assert(ffi.wstring(record.name) == "ünï" and record.id == 7)
+        record.name = "x"
+        assert(ffi.wstring(record.name) == "x" and record.id == 7)
+    "#).exec();
+    assert!(result.is_ok(), "Wide array initialization failed: {:?}", result.err());
+}
+
+#[test]
+fn test_string_from_buffers() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef struct { uint8_t a; char b[3]; } intern_pair; ]]
+
+        -- Any bytes survive, including NULs and invalid UTF-8
+        local bytes = ffi.new("uint8_t[6]", {0x41, 0, 0xff, 0x42, 0x43, 0x44})
+        assert(ffi.intern(bytes) == "A\0\255BCD")
+        assert(ffi.intern(bytes, 3) == "A\0\255")
+        assert(ffi.intern(ffi.cast("void*", bytes + 3), 2) == "BC")
+        assert(ffi.intern(ffi.new("intern_pair", {0x41, "BCD"})) == "ABCD")
+        assert(ffi.intern(ffi.nullptr, 0) == "")
+        assert(not pcall(ffi.intern, ffi.cast("void*", bytes)))
+        assert(not pcall(ffi.intern, bytes, 7))
+        assert(not pcall(ffi.intern, ffi.nullptr, 1))
+
+        -- ffi.string stops at NUL, at the end of an unterminated array, or after len bytes
+        local text = ffi.new("char[8]", "ab\255cd")
+        assert(ffi.string(text) == "ab\255cd")
+        assert(ffi.string(text, 7) == "ab\255cd\0\0")
+        local full = ffi.new("char[3]", "xyz")
+        assert(ffi.string(full) == "xyz")
+        local size = 1024 * 1024
+        local big = ffi.new("char[?]", size)
+        ffi.fill(big, size, 0x61)
+        assert(#ffi.string(big, size) == size)
+        assert(not pcall(ffi.string, text, 9))
+    "#).exec();
+    assert!(result.is_ok(), "String from buffers failed: {:?}", result.err());
+}
+
+#[cfg(feature = "wasm")]
+#[test]
+fn test_registered_host_libraries() {
+    extern "C" fn host_add(a: i32, b: i32) -> i32 {
+        a + b
+    }
+    extern "C" fn host_twice(x: f64) -> f64 {
+        x * 2.0
+    }
+    luaffi::register_library("hostmath", &[("host_add", host_add as *const std::ffi::c_void)]);
+    luaffi::register_library("C", &[("host_twice", host_twice as *const std::ffi::c_void)]);
+
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            int host_add(int a, int b);
+            double host_twice(double x);
+        ]]
+        local hostmath = ffi.load("hostmath")
+        assert(hostmath.host_add(2, 3) == 5)
+        assert(ffi.C.host_twice(1.25) == 2.5)
+        -- Registered libraries do not fall back to the C library
+        assert(not pcall(function() return hostmath.host_twice end))
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "Registered host libraries failed: {:?}", result.err());
+}
+
+#[test]
+fn test_byte_order_types() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            typedef struct {
+                be_uint16 port;
+                le_uint32 length;
+                be_int32 offset;
+                be_uint64 serial;
+            } byte_order_header;
+        ]]
+
+        local h = ffi.new("byte_order_header", { 0x1234, 0x01020304, -2, 0x01020304050607 })
+        assert(h.port == 0x1234)
+        assert(h.length == 0x01020304)
+        assert(h.offset == -2)
+        assert(h.serial == 0x01020304050607)
+
+        -- The bytes are in the declared order whatever the host's
+        local bytes = ffi.cast("uint8_t*", h)
+        assert(bytes[0] == 0x12 and bytes[1] == 0x34)
+        local base = ffi.offsetof("byte_order_header", "length")
+        assert(bytes[base] == 0x04 and bytes[base + 3] == 0x01)
+        base = ffi.offsetof("byte_order_header", "offset")
+        assert(bytes[base] == 0xff and bytes[base + 3] == 0xfe)
+        base = ffi.offsetof("byte_order_header", "serial")
+        assert(bytes[base] == 0x00 and bytes[base + 1] == 0x01 and bytes[base + 7] == 0x07)
+
+        h.port = 80
+        assert(bytes[0] == 0 and bytes[1] == 80)
+
+        -- Scalars convert and compare by value
+        local n = ffi.new("be_uint32", 0xdeadbeef)
+        assert(ffi.tonumber(n) == 0xdeadbeef)
+        assert(ffi.sizeof("le_int64") == 8 and ffi.sizeof("be_int16") == 2)
+        assert(tostring(ffi.typeof("be_uint32")):find("be_uint32"))
+        -- Exactly one byte order is the host's own
+        local le, be = ffi.new("le_uint32[1]", { 1 }), ffi.new("be_uint32[1]", { 1 })
+        assert((ffi.cast("uint32_t*", le)[0] == 1) ~= (ffi.cast("uint32_t*", be)[0] == 1))
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "Byte-order types failed: {:?}", result.err());
+}
+
+#[test]
+fn test_dump_undump() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            typedef struct { int id; double weight; char tag[4]; } dump_record;
+        ]]
+
+        local r = ffi.new("dump_record", { 7, 2.5, "abc" })
+        local bytes = ffi.dump(r)
+        assert(#bytes == ffi.sizeof("dump_record"))
+
+        local copy = ffi.undump("dump_record", bytes)
+        assert(copy.id == 7 and copy.weight == 2.5 and ffi.string(copy.tag) == "abc")
+        -- The copy has memory of its own
+        copy.id = 8
+        assert(r.id == 7)
+
+        -- Arrays, scalars and pointers
+        local arr = ffi.new("int[3]", { 1, 2, 3 })
+        assert(#ffi.dump(arr) == 3 * ffi.sizeof("int"))
+        local n = ffi.undump("int[?]", ffi.dump(arr))
+        assert(#ffi.dump(n) == #ffi.dump(arr) and n[2] == 3)
+        assert(ffi.undump("uint16_t[1]", ffi.dump(ffi.new("uint16_t[1]", { 513 })))[0] == 513)
+        local p = ffi.cast("int*", arr)
+        assert(ffi.undump("int*", ffi.dump(p)) == p)
+
+        -- Sizes are checked
+        local ok, err = pcall(ffi.undump, "dump_record", bytes .. "x")
+        assert(not ok and tostring(err):find("Cannot undump"), tostring(err))
+        ok, err = pcall(ffi.undump, "int[?]", "abcde")
+        assert(not ok and tostring(err):find("Cannot undump"), tostring(err))
+        assert(#ffi.dump(ffi.nullptr) == ffi.sizeof("void*"))
+    "#,
+        )
+        .exec();
+    assert!(result.is_ok(), "dump/undump failed: {:?}", result.err());
+}
+
+#[test]
+fn test_scalar_casts_convert_values() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        -- Integers wrap or extend like C conversions
+        assert(ffi.tonumber(ffi.cast("uint8_t", ffi.new("int", 300))) == 44)
+        assert(ffi.tonumber(ffi.cast("int16_t", ffi.new("uint16_t", 65535))) == -1)
+        assert(ffi.tostring_int(ffi.cast("uint64_t", ffi.new("int8_t", -1))) == "18446744073709551615")
+        assert(ffi.tonumber(ffi.cast("int", 7)) == 7)
+
+        -- Floating point truncates towards zero before wrapping
+        assert(ffi.tonumber(ffi.cast("int", ffi.new("double", -3.9))) == -3)
+        assert(ffi.tonumber(ffi.cast("int8_t", -130.5)) == 126)
+        assert(ffi.tonumber(ffi.cast("double", ffi.new("int", -300))) == -300)
+        assert(ffi.tonumber(ffi.cast("int", ffi.cast("bool", ffi.new("int", 2)))) == 1)
+
+        -- The result is a new value, not a view of the source
+        local source = ffi.new("int", 5)
+        local copy = ffi.cast("long", source)
+        ffi.fill(source, ffi.sizeof("int"), 0)
+        assert(ffi.tonumber(copy) == 5)
+
+        -- Pointers convert to their address; explicit casts ignore strict conversions
+        local arr = ffi.new("int[2]")
+        assert(ffi.cast("size_t", arr) == ffi.cast("size_t", ffi.cast("void*", arr)))
+        ffi.strictconv(true)
+        local ok = pcall(ffi.cast, "uint8_t", 256)
+        ffi.strictconv(false)
+        assert(ok)
+
+        local ok, err = pcall(ffi.cast, "int", ffi.new("struct { int a; }"))
+        assert(not ok and tostring(err):find("Cannot cast"))
+    "#).exec();
+    assert!(result.is_ok(), "Scalar casts should convert values: {:?}", result.err());
+}
+
+#[test]
+fn test_reinterpret_memory() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct { uint8_t version; uint8_t kind; uint16_t length; } reinterpret_header;
+        ]]
+        local packet = ffi.new("uint8_t[8]", { 2, 7, 0, 0, 0xAA, 0xBB, 0xCC, 0xDD })
+
+        -- The view shares the packet's memory
+        local header = ffi.reinterpret("reinterpret_header", packet)
+        assert(header.version == 2 and header.kind == 7)
+        header.kind = 9
+        assert(packet[1] == 9)
+
+        -- Variable-length arrays take as many elements as fit
+        local words = ffi.reinterpret("uint16_t[?]", packet)
+        words[3] = 0
+        assert(packet[6] == 0 and packet[7] == 0)
+        assert(not pcall(function() return words[4] end))
+
+        -- The type must fit in the memory, unlike a pointer cast
+        local ok, err = pcall(ffi.reinterpret, "uint64_t[2]", packet)
+        assert(not ok and tostring(err):find("Cannot reinterpret 8%-byte"))
+        local ok, err = pcall(ffi.reinterpret, "reinterpret_header", ffi.new("uint8_t", 1))
+        assert(not ok)
+
+        -- Memory behind a pointer has no known extent and is not checked
+        local p = ffi.cast("uint8_t*", packet)
+        assert(ffi.reinterpret("reinterpret_header", p).version == 2)
+        assert(not pcall(ffi.reinterpret, "uint8_t[?]", p))
+        assert(not pcall(ffi.reinterpret, "int", ffi.nullptr))
+    "#).exec();
+    assert!(result.is_ok(), "Reinterpret failed: {:?}", result.err());
+}
+
+#[test]
+fn test_inline_anonymous_types() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local point = ffi.typeof("struct { int x; int y; }")
+        assert(ffi.sizeof(point) == 8)
+        local p = ffi.new(point, {3, 4})
+        assert(p.x == 3 and p.y == 4)
+
+        -- The type of anonymous cdata is spelled with its body and can be looked up again
+        local nested = ffi.new("struct { struct { int a; char b; } inner; union { int i; float f; } u; unsigned bits : 3; }")
+        local name = ffi.typeof(nested)
+        assert(name == "struct { struct { int a; char b; } inner; union { int i; float f; } u; unsigned int bits : 3; }", name)
+        assert(ffi.istype(name, nested) and ffi.sizeof(name) == 16)
+        assert(ffi.typeof(nested.inner) == "struct { int a; char b; }")
+        local items = ffi.new("struct { int x; }[2]", {{1}, {2}})
+        assert(ffi.sizeof(ffi.typeof(items)) == 8 and items[1].x == 2)
+    "#).exec();
+    assert!(result.is_ok(), "inline anonymous types failed: {:?}", result.err());
+}
+
+#[test]
+fn test_struct_fields() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct { char tag; double value; int *next; unsigned flags : 3; unsigned mode : 2; } fields_t;
+            typedef union { int i; float f; } fields_u;
+        ]]
+        local fields = ffi.fields("fields_t")
+        assert(#fields == 5)
+        assert(fields[1].name == "tag" and fields[1].offset == 0 and fields[1].type == "char")
+        assert(fields[2].name == "value" and fields[2].offset == ffi.offsetof("fields_t", "value"))
+        assert(fields[2].type == "double" and fields[3].type == "int *", fields[3].type)
+        assert(ffi.sizeof(fields[3].type) == ffi.sizeof("void*"))
+        assert(fields[4].bitpos == 0 and fields[4].bitsize == 3 and fields[4].type == "unsigned int", fields[4].type)
+        assert(fields[5].offset == fields[4].offset and fields[5].bitpos == 3 and fields[5].bitsize == 2)
+        assert(fields[1].bitsize == nil)
+
+        local members = ffi.fields("fields_u")
+        assert(#members == 2 and members[1].offset == 0 and members[2].offset == 0 and members[2].type == "float")
+
+        assert(not pcall(ffi.fields, "int"))
+    "#).exec();
+    assert!(result.is_ok(), "struct field metadata failed: {:?}", result.err());
+}
+
+#[test]
+fn test_assert_layout() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef struct { int x; double y; int size; unsigned bits : 4; } layout_t; ]]
+        ffi.assert_layout("layout_t", {size = 24, align = 8, x = 0, y = 8, fields = {size = 16}})
+        ffi.assert_layout("int", {size = 4, align = 4})
+
+        local ok, err = pcall(ffi.assert_layout, "layout_t", {size = 16, x = 0, y = 4, z = 8, bits = 20})
+        assert(not ok)
+        local message = tostring(err)
+        assert(message:find("size is 24, expected 16", 1, true), message)
+        assert(message:find("y is at offset 8, expected 4", 1, true), message)
+        assert(message:find("bits is a bit-field", 1, true), message)
+        assert(message:find("no member z", 1, true), message)
+        assert(not message:find("x is", 1, true), message)
+    "#).exec();
+    assert!(result.is_ok(), "layout assertions failed: {:?}", result.err());
+}
+
+#[test]
+fn test_copy_with_offsets() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local buf = ffi.new("uint8_t[8]")
+
+        -- Into the middle of a buffer, from part of a string
+        assert(ffi.copy(buf, 2, "xxabcd", 2, 3) == 3)
+        assert(buf[1] == 0 and buf[2] == string.byte("a") and buf[4] == string.byte("c") and buf[5] == 0)
+
+        -- Without a length the rest of the string is copied and terminated
+        ffi.copy(buf, 5, "yz", 0)
+        assert(buf[5] == string.byte("y") and buf[6] == string.byte("z") and buf[7] == 0)
+
+        -- Between cdata, including overlapping ranges of the same buffer
+        local src = ffi.new("uint8_t[4]", { 1, 2, 3, 4 })
+        ffi.copy(buf, 0, src, 1, 3)
+        assert(buf[0] == 2 and buf[1] == 3 and buf[2] == 4)
+        ffi.copy(buf, 1, buf, 0, 3)
+        assert(buf[1] == 2 and buf[2] == 3 and buf[3] == 4)
+
+        -- Ranges past either end are rejected
+        assert(not pcall(ffi.copy, buf, 6, src, 0, 4))
+        assert(not pcall(ffi.copy, buf, 0, src, 2, 4))
+        assert(not pcall(ffi.copy, buf, 0, "abc", 4))
+
+        -- The original form is unchanged
+        local text = ffi.new("char[8]")
+        ffi.copy(text, "hi")
+        assert(ffi.string(text) == "hi")
+    "#).exec();
+    assert!(result.is_ok(), "Offset copies failed: {:?}", result.err());
+}
+
+#[test]
+fn test_equal_and_hash() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct equal_config { char tag; int value; double scale; unsigned flags : 3; } equal_config;
+            typedef struct equal_other { char tag; int value; double scale; unsigned flags : 3; } equal_other;
+        ]]
+        local a = ffi.new("equal_config", { 1, 2, 0.0, 5 })
+        local b = ffi.new("equal_config")
+        -- Fill padding and the bits next to the bit-field with garbage before setting members
+        ffi.fill(b, ffi.sizeof("equal_config"), 0xFF)
+        b.tag, b.value, b.scale, b.flags = 1, 2, -0.0, 5
+
+        assert(ffi.equal(a, b))
+        assert(ffi.hash(a) == ffi.hash(b))
+
+        b.value = 3
+        assert(not ffi.equal(a, b))
+
+        -- Types must match, even with the same layout
+        assert(not ffi.equal(a, ffi.new("equal_other", { 1, 2, 0.0, 5 })))
+
+        -- Arrays compare element by element
+        assert(ffi.equal(ffi.new("int[3]", { 1, 2, 3 }), ffi.new("int[3]", { 1, 2, 3 })))
+        assert(not ffi.equal(ffi.new("int[3]", { 1, 2, 3 }), ffi.new("int[2]", { 1, 2 })))
+        local nan = ffi.new("double", 0 / 0)
+        assert(not ffi.equal(nan, nan))
+
+        -- Hashes work as table keys for deduplication
+        local seen = {}
+        for _, v in ipairs({ 1, 2, 1 }) do
+            seen[ffi.hash(ffi.new("equal_config", { 0, v }))] = true
+        end
+        local count = 0
+        for _ in pairs(seen) do count = count + 1 end
+        assert(count == 2)
+    "#).exec();
+    assert!(result.is_ok(), "Equal and hash failed: {:?}", result.err());
+}
+
+#[test]
+fn test_const_views() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct { int id; int samples[4]; struct { int x, y; } origin; } view_record;
+        ]]
+        local record = ffi.new("view_record", { id = 1, samples = { 1, 2, 3, 4 }, origin = { 5, 6 } })
+        local view = ffi.const_view(record)
+
+        -- Reads see the same memory, including later changes by the owner
+        record.id = 2
+        assert(view.id == 2 and view.samples[3] == 4 and view.origin.y == 6)
+
+        -- Writes fail at any depth
+        local function rejected(f)
+            local ok, err = pcall(f)
+            return not ok and tostring(err):find("const") ~= nil
+        end
+        assert(rejected(function() view.id = 3 end))
+        assert(rejected(function() view.samples[0] = 9 end))
+        assert(rejected(function() view.origin.x = 9 end))
+        assert(rejected(function() ffi.fill(view, ffi.sizeof("view_record")) end))
+        assert(rejected(function() ffi.copy(view.samples, "abcd") end))
+        assert(record.samples[0] == 1 and record.origin.x == 5)
+
+        -- Pointers and arrays become views of const elements
+        local buf = ffi.new("uint8_t[4]", { 1, 2, 3, 4 })
+        local ro = ffi.const_view(ffi.cast("uint8_t*", buf))
+        assert(ro[2] == 3)
+        assert(rejected(function() ro[2] = 0 end))
+        assert(rejected(function() ffi.const_view(buf)[0] = 0 end))
+        assert(ffi.istype("const uint8_t*", ro))
+
+        -- A const struct cdata refuses field assignment too
+        local fixed = ffi.new("const view_record", { id = 7 })
+        assert(rejected(function() fixed.id = 8 end))
+    "#).exec();
+    assert!(result.is_ok(), "Const views failed: {:?}", result.err());
+}
+
+#[cfg(feature = "canaries")]
+#[test]
+fn test_canaries_catch_overruns() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local buf = ffi.new("uint8_t[4]")
+        assert(ffi.checkcanaries() >= 1)
+
+        -- An off-by-one write through a pointer made from the bare address, which has no bounds
+        local p = ffi.cast("uint8_t*", ffi.tonumber(ffi.address(buf)))
+        local canary = p[4]
+        p[4] = 0
+        local ok, err = pcall(ffi.checkcanaries)
+        assert(not ok and tostring(err):find("4%-byte block .*%(0 before it, 1 after it%)"))
+        local ok, err = pcall(ffi.free, buf)
+        assert(not ok and tostring(err):find("overwritten"))
+
+        -- Repaired, the buffer frees normally
+        p[4] = canary
+        ffi.checkcanaries()
+        ffi.free(buf)
+    "#).exec();
+    assert!(result.is_ok(), "Canary check failed: {:?}", result.err());
+}
+
+#[test]
+fn test_deref_pointers() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ typedef struct { int x, y; } deref_point; ]]
+        local value = ffi.new("int[1]", { 42 })
+        assert(ffi.deref(ffi.cast("int*", value)) == 42)
+
+        -- Aggregates are views of the pointed-to memory
+        local point = ffi.new("deref_point", { 1, 2 })
+        local view = ffi.deref(ffi.addressof(point))
+        view.y = 5
+        assert(point.y == 5)
+
+        local function fails(pattern, ...)
+            local ok, err = pcall(ffi.deref, ...)
+            return not ok and tostring(err):find(pattern) ~= nil
+        end
+        assert(fails("NULL", ffi.cast("int*", 0)))
+        assert(fails("Cannot dereference", ffi.nullptr))
+        assert(fails("not a pointer", point))
+    "#).exec();
+    assert!(result.is_ok(), "Deref failed: {:?}", result.err());
+}
+
+#[test]
+fn test_null_dereference_raises_errors() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct { int x, y; } nullcheck_point;
+            int abs(int);
+        ]]
+        local function null_error(f)
+            local ok, err = pcall(f)
+            return not ok and tostring(err):find("NULL") ~= nil
+        end
+
+        local p = ffi.cast("int*", 0)
+        assert(null_error(function() return p[0] end))
+        assert(null_error(function() p[1] = 5 end))
+
+        local s = ffi.cast("nullcheck_point*", 0)
+        assert(null_error(function() return s[0].x end))
+        assert(null_error(function() s[0].y = 1 end))
+
+        -- So is a struct cast at address zero
+        local at_zero = ffi.cast("nullcheck_point", 0)
+        assert(null_error(function() return at_zero.x end))
+
+        -- NULL pointers themselves are still fine to pass and compare
+        assert(ffi.isnull(p) and ffi.C.abs(-3) == 3)
+    "#).exec();
+    assert!(result.is_ok(), "NULL checks failed: {:?}", result.err());
+}
+
+#[test]
+fn test_whole_aggregate_assignment() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            typedef struct assign_point { int x, y; } assign_point;
+            typedef struct { assign_point inner; int arr[3]; union { int i; float f; } u; } assign_outer;
+        ]]
+        local a = ffi.new("assign_outer")
+        local b = ffi.new("assign_outer", { { 1, 2 }, { 3, 4, 5 }, { 9 } })
+
+        a.inner = b.inner
+        a.arr = b.arr
+        a.u = b.u
+        assert(a.inner.y == 2 and a.arr[2] == 5 and a.u.i == 9)
+
+        -- The copy is independent of its source
+        b.inner.x = 10
+        assert(a.inner.x == 1)
+
+        -- Whole elements and const sources
+        local list = ffi.new("assign_outer[2]")
+        list[1] = b
+        assert(list[1].arr[0] == 3)
+        a.inner = ffi.const_view(b).inner
+        a.arr = ffi.new("const assign_outer", { arr = { 7, 8, 9 } }).arr
+        assert(a.inner.x == 10 and a.arr[1] == 8)
+
+        -- Incompatible types or sizes are rejected
+        assert(not pcall(function() a.arr = ffi.new("int[2]") end))
+        assert(not pcall(function() a.inner = ffi.addressof(b.inner) end))
+        assert(not pcall(function() a.inner = ffi.new("struct { int x, y, z; }") end))
+    "#).exec();
+    assert!(result.is_ok(), "Whole aggregate assignment failed: {:?}", result.err());
+}
+
+#[test]
+fn test_gcc_qualifier_spellings() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            __extension__ typedef long long ext_llong_t;
+            typedef _Atomic(unsigned short) atomic_ushort_t;
+            struct GccQualified {
+                _Atomic int counter;
+                char * __restrict__ name;
+                __extension__ union { int i; float f; } value;
+                __const char *label;
+                __volatile__ long ticks;
+            };
+            static __inline__ int inline_helper(int a);
+            void *memcpy(void * __restrict dst, const void * restrict src, size_t n);
+        ]]
+        local ptr_size = ffi.sizeof("void*")
+        assert(ffi.sizeof("ext_llong_t") == 8)
+        assert(ffi.sizeof("atomic_ushort_t") == 2)
+        assert(ffi.sizeof("_Atomic(double)") == 8)
+        assert(ffi.sizeof("char * __restrict") == ptr_size)
+        assert(ffi.offsetof("struct GccQualified", "name") == ptr_size)
+        assert(ffi.offsetof("struct GccQualified", "value") == 2 * ptr_size)
+        assert(ffi.istype("const char*", ffi.new("struct GccQualified").label))
+
+        local src = ffi.new("int[2]", {1, 2})
+        local dst = ffi.new("int[2]")
+        ffi.C.memcpy(dst, src, ffi.sizeof("int[2]"))
+        assert(dst[1] == 2)
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "GCC qualifier spellings failed: {:?}", result.err());
+}
+
+#[test]
+fn test_msvc_decorations() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            typedef int BOOL;
+            typedef void *HANDLE;
+            typedef unsigned long DWORD;
+            typedef BOOL (WINAPI *PHANDLER_ROUTINE)(_In_ DWORD dwCtrlType);
+            typedef struct __declspec(align(8)) _MSVC_RECORD {
+                _Field_size_(count) HANDLE *items;
+                DWORD count;
+            } MSVC_RECORD;
+            __declspec(dllimport) BOOL WINAPI MsvcCloseHandle(_In_ HANDLE hObject);
+            _Check_return_ _Success_(return != 0) BOOL __stdcall MsvcRead(
+                _In_ HANDLE hFile,
+                _Out_writes_bytes_to_opt_(nNumberOfBytesToRead, *lpNumberOfBytesRead) void *lpBuffer,
+                _In_ DWORD nNumberOfBytesToRead,
+                _Out_opt_ DWORD *lpNumberOfBytesRead
+            );
+            __attribute__((visibility("default"))) int labs_like(int x) __attribute__((const));
+            int abs(_In_ int x);
+        ]]
+        local ptr_size = ffi.sizeof("void*")
+        assert(ffi.sizeof("PHANDLER_ROUTINE") == ptr_size)
+        assert(ffi.offsetof("MSVC_RECORD", "count") == ptr_size)
+        assert(ffi.sizeof("void (__cdecl *)(int)") == ptr_size)
+        assert(ffi.C.abs(-3) == 3)
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "MSVC decorations failed: {:?}", result.err());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_cpp_symbol_candidates() {
+    let lua = create_lua_with_ffi();
+
+    let message = lua
+        .load(
+            r#"
+        local lib = ffi.load("libstdc++.so.6")
+        local ok, err = pcall(function() return lib.terminate end)
+        assert(not ok)
+        return tostring(err)
+    "#,
+        )
+        .eval::<String>()
+        .expect("lookup should fail with a message");
+
+    assert!(message.contains("Symbol not found: terminate"), "unexpected message: {}", message);
+    if cfg!(feature = "demangle") {
+        assert!(message.contains("std::terminate()"), "missing demangled candidate: {}", message);
+    } else {
+        assert!(message.contains("_ZSt9terminatev"), "missing mangled candidate: {}", message);
+    }
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "demangle"))]
+fn test_demangled_symbol_lookup() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        local lib = ffi.load("libstdc++.so.6")
+        assert(lib["std::terminate()"] ~= nil)
+        assert(lib["std::terminate"] ~= nil)
+        assert(not pcall(function() return lib["std::no_such_function()"] end))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "demangled lookup failed: {:?}", result.err());
+}
+
+#[test]
+fn test_typo_suggestions() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        ffi.cdef[[
+            struct SuggestPoint { int x; int y; };
+        ]]
+        local ok, err = pcall(ffi.typeof, "unit32_t")
+        assert(not ok and tostring(err):find("Unknown type: unit32_t (did you mean: uint32_t", 1, true), tostring(err))
+
+        ok, err = pcall(ffi.sizeof, "struct SugestPoint")
+        assert(not ok and tostring(err):find("did you mean: struct SuggestPoint", 1, true), tostring(err))
+
+        ok, err = pcall(ffi.typeof, "completely_unrelated_name")
+        assert(not ok and not tostring(err):find("did you mean", 1, true), tostring(err))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "type suggestions failed: {:?}", result.err());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_symbol_typo_suggestions() {
+    let lua = create_lua_with_ffi();
+
+    let result: Result<(), _> = lua
+        .load(
+            r#"
+        local ok, err = pcall(function() return ffi.C.prinft end)
+        assert(not ok and tostring(err):find("Symbol not found: prinft (did you mean: printf", 1, true), tostring(err))
+    "#,
+        )
+        .exec();
+
+    assert!(result.is_ok(), "symbol suggestions failed: {:?}", result.err());
+}
+
+#[test]
+fn test_structured_errors() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.clearerr()
+        assert(ffi.lasterror() == nil)
+
+        local ok, err = pcall(ffi.typeof, "no_such_type_t")
+        assert(not ok and err:kind() == "UNKNOWN_TYPE", tostring(err))
+        assert(err:code() ~= 0 and err:ctype() == "no_such_type_t" and err:symbol() == nil)
+        assert(err:message():find("Unknown type: no_such_type_t", 1, true), err:message())
+        assert(tostring(err):find("Unknown type", 1, true))
+
+        ok, err = pcall(function() return ffi.C.luaffi_surely_missing_symbol end)
+        assert(not ok and err:kind() == "SYMBOL_NOT_FOUND" and err:symbol() == "luaffi_surely_missing_symbol")
+
+        ok, err = pcall(function() return ffi.cast("int*", 0)[0] end)
+        assert(not ok and err:kind() == "NULL_POINTER" and err:ctype() == "int *", err:ctype())
+
+        ok, err = pcall(ffi.cdef, "this is not C", { strict = true })
+        assert(not ok and err:kind() == "PARSE_ERROR")
+
+        -- The last error is kept until cleared
+        assert(ffi.lasterror():kind() == "PARSE_ERROR")
+        ffi.clearerr()
+        assert(ffi.lasterror() == nil)
+
+        -- Errors from outside the FFI are plain runtime errors
+        ok, err = pcall(ffi.new)
+        assert(not ok and err:kind() == "RUNTIME" and err:code() == 0)
+    "#).exec();
+    assert!(result.is_ok(), "structured errors failed: {:?}", result.err());
+}
+
+#[test]
+fn test_library_has_symbol() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ int abs(int); ]]
+        assert(ffi.C:has("abs") == true)
+        assert(ffi.C:has("luaffi_surely_missing_symbol") == false)
+        assert(ffi.C.abs(-2) == 2)
+
+        -- Missing symbols still raise by default
+        assert(not pcall(function() return ffi.C.luaffi_surely_missing_symbol end))
+    "#).exec();
+    assert!(result.is_ok(), "has-symbol checks failed: {:?}", result.err());
+}
+
+#[test]
+fn test_optional_functions() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[
+            int luaffi_missing_weak(int) __attribute__((weak));
+            __attribute__((weak)) int abs(int);
+        ]]
+        -- Present symbols resolve as usual
+        assert(ffi.C.abs(-3) == 3)
+
+        -- Missing ones become stubs that raise a catchable error when called
+        local stub = ffi.C.luaffi_missing_weak
+        assert(stub ~= nil and not ffi.C:has("luaffi_missing_weak"))
+        local ok, err = pcall(stub, 1)
+        assert(not ok and err:kind() == "SYMBOL_NOT_FOUND" and err:symbol() == "luaffi_missing_weak", tostring(err))
+
+        -- Or return zero, for a whole block
+        ffi.cdef([[
+            double luaffi_missing_zero(double);
+            void luaffi_missing_void(void);
+            typedef struct { int a, b; } optional_pair;
+            optional_pair luaffi_missing_pair(void);
+        ]], { optional = "zero" })
+        assert(ffi.C.luaffi_missing_zero(2.5) == 0)
+        assert(ffi.C.luaffi_missing_void() == nil)
+        local pair = ffi.C.luaffi_missing_pair()
+        assert(pair.a == 0 and pair.b == 0)
+
+        -- Redeclaring without the attribute makes a function required again
+        ffi.cdef[[ int luaffi_missing_weak(int); ]]
+        assert(not pcall(function() return ffi.C.luaffi_missing_weak end))
+
+        assert(not pcall(ffi.cdef, "int f(void);", { optional = "maybe" }))
+    "#).exec();
+    assert!(result.is_ok(), "optional functions failed: {:?}", result.err());
+}
+
+#[test]
+fn test_symbol_addresses() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ int abs(int); ]]
+        local abs = ffi.symaddr(ffi.C, "abs")
+        assert(ffi.typeof(abs) == ffi.typeof("int (*)(int)"), ffi.typeof(abs))
+        assert(not ffi.isnull(abs) and abs(-4) == 4)
+        assert(abs == ffi.cast("void*", ffi.C.abs))
+        assert(ffi.tonumber(abs) > 0)
+
+        -- Undeclared symbols come back as void*
+        local strlen = ffi.symaddr(ffi.C, "strlen")
+        assert(ffi.istype("void*", strlen) and not ffi.isnull(strlen))
+
+        local ok, err = pcall(ffi.symaddr, ffi.C, "luaffi_surely_missing_symbol")
+        assert(not ok and err:kind() == "SYMBOL_NOT_FOUND")
+    "#).exec();
+    assert!(result.is_ok(), "symbol addresses failed: {:?}", result.err());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_library_path_and_handle() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        ffi.cdef[[ void *dlsym(void *handle, const char *symbol); ]]
+        local libm = ffi.load("libm.so.6")
+        assert(libm:path():find("libm", 1, true) and libm:path():sub(1, 1) == "/", libm:path())
+        assert(ffi.C:path() ~= nil)
+
+        -- The handle goes straight to C APIs that take one
+        local handle = libm:handle()
+        assert(ffi.istype("void*", handle) and not ffi.isnull(handle))
+        assert(not ffi.isnull(ffi.C.dlsym(handle, "cos")))
+    "#).exec();
+    assert!(result.is_ok(), "library introspection failed: {:?}", result.err());
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn test_library_version() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        assert(ffi.load("libm.so.6"):version() == "6")
+        assert(ffi.C:version() == nil)
+
+        -- Or from a function the library reports its version with
+        ffi.cdef[[
+            const char *gnu_get_libc_version(void);
+            int abs(int);
+        ]]
+        local version = ffi.C:version("gnu_get_libc_version")
+        assert(type(version) == "string" and version:match("^%d+%.%d+"), tostring(version))
+        local ok, err = pcall(ffi.C.version, ffi.C, "abs")
+        assert(not ok and tostring(err):find("without parameters"), tostring(err))
+    "#).exec();
+    assert!(result.is_ok(), "library versions failed: {:?}", result.err());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_whereis() {
+    let lua = create_lua_with_ffi();
+    let result: Result<(), _> = lua.load(r#"
+        local path, symbol = ffi.whereis("strlen")
+        assert(path:find("libc", 1, true), path)
+
+        path, symbol = ffi.whereis(ffi.C.abs)
+        assert(path:find("libc", 1, true) and symbol == "abs", tostring(path) .. " " .. tostring(symbol))
+
+        -- Functions the loader picks an implementation for report that implementation's module
+        local libm = ffi.load("libm.so.6")
+        path = ffi.whereis(libm.cos)
+        assert(path:find("libm", 1, true) and ffi.whereis(ffi.symaddr(libm, "cos")) == path, path)
+
+        assert(ffi.whereis("luaffi_surely_missing_symbol") == nil)
+        assert(ffi.whereis(ffi.new("int[1]")) == nil)
+    "#).exec();
+    assert!(result.is_ok(), "whereis failed: {:?}", result.err());
+}
@@ -0,0 +1,90 @@
+#![cfg(feature = "serde")]
+
+use luaffi::serde_cdata::{Seed, Serializable};
+use mlua::prelude::*;
+use serde::de::DeserializeSeed;
+
+fn create_lua_with_ffi() -> Lua {
+    let lua = Lua::new();
+    lua.globals().set("ffi", luaffi::lua_module(&lua).unwrap()).unwrap();
+    lua
+}
+
+#[test]
+fn test_struct_round_trip() {
+    let lua = create_lua_with_ffi();
+    lua.load(
+        r#"
+        ffi.cdef[[
+            enum serde_shape { SERDE_CIRCLE = 1, SERDE_SQUARE = 2 };
+            typedef struct { int x, y; } serde_point;
+            typedef struct {
+                char name[8];
+                serde_point corners[2];
+                enum serde_shape shape;
+                double scale;
+                unsigned int flags : 3;
+                bool visible;
+                uint64_t id;
+            } serde_shape_t;
+        ]]
+        shape = ffi.new("serde_shape_t", {
+            name = "box", corners = { { 1, 2 }, { 3, 4 } }, shape = 2, scale = 1.5,
+            flags = 5, visible = true, id = -1,
+        })
+    "#,
+    )
+    .exec()
+    .unwrap();
+
+    let shape: LuaAnyUserData = lua.globals().get("shape").unwrap();
+    let json = serde_json::to_value(Serializable::new(&shape).unwrap()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "name": "box",
+            "corners": [{ "x": 1, "y": 2 }, { "x": 3, "y": 4 }],
+            "shape": "SERDE_SQUARE",
+            "scale": 1.5,
+            "flags": 5,
+            "visible": true,
+            "id": u64::MAX,
+        })
+    );
+
+    let copy = Seed::new(&lua, "serde_shape_t").unwrap().deserialize(json).unwrap();
+    lua.globals().set("copy", copy).unwrap();
+    lua.load(
+        r#"
+        assert(ffi.string(copy.name) == "box")
+        assert(copy.corners[1].y == 4 and copy.shape == 2 and copy.scale == 1.5)
+        assert(copy.flags == 5 and copy.visible == true and copy.id == shape.id)
+    "#,
+    )
+    .exec()
+    .unwrap();
+}
+
+#[test]
+fn test_deserialize_errors() {
+    let lua = create_lua_with_ffi();
+    lua.load("ffi.cdef[[ struct serde_pair { int a; int *b; }; enum serde_color { SERDE_RED }; ]]")
+        .exec()
+        .unwrap();
+
+    // Missing members are zero
+    let pair = Seed::new(&lua, "struct serde_pair").unwrap().deserialize(serde_json::json!({ "a": 7 })).unwrap();
+    lua.globals().set("pair", pair).unwrap();
+    lua.load("assert(pair.a == 7 and ffi.isnull(pair.b))").exec().unwrap();
+
+    let error = Seed::new(&lua, "struct serde_pair").unwrap().deserialize(serde_json::json!({ "c": 1 })).unwrap_err();
+    assert!(error.to_string().contains("no member named 'c'"), "{}", error);
+    let error = Seed::new(&lua, "struct serde_pair").unwrap().deserialize(serde_json::json!({ "b": 1 })).unwrap_err();
+    assert!(error.to_string().contains("Cannot deserialize"), "{}", error);
+    let error = Seed::new(&lua, "enum serde_color").unwrap().deserialize(serde_json::json!("SERDE_BLUE")).unwrap_err();
+    assert!(error.to_string().contains("not an enumerator"), "{}", error);
+
+    // Pointers have no portable value
+    let pair: LuaAnyUserData = lua.globals().get("pair").unwrap();
+    assert!(serde_json::to_string(&Serializable::new(&pair).unwrap()).is_err());
+}